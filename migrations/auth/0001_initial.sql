@@ -0,0 +1,84 @@
+-- The auth schema as it stood when versioned migrations were introduced.
+--
+-- Databases created before then already have some or all of these tables
+-- (possibly without some of the columns added to `keys` over time), so
+-- everything here tolerates existing objects.
+
+CREATE TABLE IF NOT EXISTS users (
+    uname TEXT PRIMARY KEY,
+    hash  TEXT
+);
+
+CREATE TABLE IF NOT EXISTS keys (
+    id           BIGSERIAL,
+    key_hash     TEXT,
+    uname        TEXT REFERENCES users,
+    issued       TIMESTAMP,
+    last_used    TIMESTAMP,
+    addr         TEXT,
+    user_agent   TEXT,
+    impersonator TEXT
+);
+
+ALTER TABLE keys ADD COLUMN IF NOT EXISTS id BIGSERIAL;
+ALTER TABLE keys ADD COLUMN IF NOT EXISTS issued TIMESTAMP;
+ALTER TABLE keys ADD COLUMN IF NOT EXISTS addr TEXT;
+ALTER TABLE keys ADD COLUMN IF NOT EXISTS user_agent TEXT;
+ALTER TABLE keys ADD COLUMN IF NOT EXISTS key_hash TEXT;
+ALTER TABLE keys ADD COLUMN IF NOT EXISTS impersonator TEXT;
+
+CREATE TABLE IF NOT EXISTS failed_logins (
+    uname TEXT,
+    addr  TEXT,
+    time  TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS totp (
+    uname     TEXT PRIMARY KEY REFERENCES users,
+    secret    TEXT,
+    confirmed BOOL,
+    last_step BIGINT
+);
+
+CREATE TABLE IF NOT EXISTS recovery_codes (
+    uname TEXT REFERENCES users,
+    hash  TEXT
+);
+
+CREATE TABLE IF NOT EXISTS totp_challenges (
+    token  TEXT,
+    uname  TEXT REFERENCES users,
+    issued TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS reset_tokens (
+    token_hash TEXT,
+    uname      TEXT REFERENCES users,
+    addr       TEXT,
+    issued     TIMESTAMP,
+    used       BOOL
+);
+
+CREATE TABLE IF NOT EXISTS invites (
+    uname      TEXT PRIMARY KEY REFERENCES users,
+    token_hash TEXT,
+    issued     TIMESTAMP,
+    accepted   TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS api_tokens (
+    id         BIGSERIAL,
+    uname      TEXT REFERENCES users,
+    name       TEXT,
+    token_hash TEXT,
+    actions    TEXT[],
+    created    TIMESTAMP,
+    expires    DATE,
+    last_used  TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS grants (
+    uname      TEXT REFERENCES users,
+    permission TEXT,
+    PRIMARY KEY (uname, permission)
+);
@@ -0,0 +1,22 @@
+-- Every server process keeps a copy of the permissions granted to individual
+-- users (see `src/perm.rs`) in memory. Any change to them sends a
+-- notification on the `camp_cache` channel, so that other processes using
+-- the same database know to reread them (see `src/notify.rs`); otherwise a
+-- revoked permission would stay in force elsewhere until the next resync.
+--
+-- The payload is the same as the data DB's (see
+-- `migrations/data/0004_cache_notifications.sql`).
+
+CREATE OR REPLACE FUNCTION camp_notify_cache() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify('camp_cache', json_build_object(
+        'schema', TG_TABLE_SCHEMA,
+        'table',  TG_TABLE_NAME,
+        'from',   current_setting('application_name')
+    )::text);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER grants_notify_cache AFTER INSERT OR UPDATE OR DELETE OR TRUNCATE
+    ON grants FOR EACH STATEMENT EXECUTE FUNCTION camp_notify_cache();
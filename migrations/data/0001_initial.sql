@@ -0,0 +1,156 @@
+-- The data schema as it stood when versioned migrations were introduced.
+--
+-- Databases created before then already have some or all of these tables,
+-- so everything here tolerates existing objects.
+
+-- Three tables of course info: courses, chapters, and custom "chapters".
+
+CREATE TABLE IF NOT EXISTS courses (
+    id    BIGSERIAL PRIMARY KEY,
+    sym   TEXT UNIQUE NOT NULL,
+    title TEXT NOT NULL,
+    book  TEXT,
+    level REAL
+);
+
+CREATE TABLE IF NOT EXISTS chapters (
+    id          BIGSERIAL PRIMARY KEY,
+    course      BIGINT REFERENCES courses(id),
+    sequence    SMALLINT,
+    title       TEXT,   /* default is generated 'Chapter N' title */
+    subject     TEXT,   /* default is blank */
+    weight      REAL    /* default is 1.0 */
+);
+
+CREATE TABLE IF NOT EXISTS custom_chapters (
+    id      BIGSERIAL PRIMARY KEY,
+    uname   TEXT,   /* REFERENCES user(uname), when 'users' table available */
+    title   TEXT NOT NULL,
+    weight  REAL    /* default should be 1.0 */
+);
+
+/*
+Four tables for users: users, teachers, students, parents.
+
+Admin and Boss roles require nothing beyond data in the users table.
+Extra info for Teachers and Students in their respective tables; the
+parents table links each Parent to their Students.
+*/
+
+CREATE TABLE IF NOT EXISTS users (
+    uname TEXT PRIMARY KEY,
+    role  TEXT NOT NULL,
+    salt  TEXT,
+    email TEXT
+);
+
+CREATE TABLE IF NOT EXISTS teachers (
+    uname TEXT UNIQUE REFERENCES users(uname),
+    name  TEXT
+);
+
+CREATE TABLE IF NOT EXISTS students (
+    uname   TEXT UNIQUE REFERENCES users(uname),
+    last    TEXT,
+    rest    TEXT,
+    teacher TEXT REFERENCES teachers(uname),
+    parent  TEXT,     /* parent email address */
+    fall_exam TEXT,
+    spring_exam TEXT,
+    fall_exam_fraction REAL,
+    spring_exam_fraction REAL,
+    fall_notices SMALLINT,
+    spring_notices SMALLINT
+);
+
+CREATE TABLE IF NOT EXISTS parents (
+    uname   TEXT REFERENCES users(uname),
+    student TEXT REFERENCES students(uname),
+    UNIQUE (uname, student)
+);
+
+/*
+Two tables of calendar info.
+
+calendar holds just a list of "working days" as used by the autopacer.
+dates holds special dates (so far this is just the ends of the Spring
+and Fall Semesters).
+*/
+
+CREATE TABLE IF NOT EXISTS calendar ( day DATE UNIQUE NOT NULL );
+
+CREATE TABLE IF NOT EXISTS dates (
+    name TEXT PRIMARY KEY,
+    day DATE NOT NULL
+);
+
+-- Student pace goals.
+
+CREATE TABLE IF NOT EXISTS goals (
+    id          BIGSERIAL PRIMARY KEY,
+    uname       TEXT REFERENCES students(uname),
+    sym         TEXT REFERENCES courses(sym),
+    seq         SMALLINT,
+    custom      BIGINT REFERENCES custom_chapters(id),
+    review      BOOL,
+    incomplete  BOOL,
+    due         DATE,
+    done        DATE,
+    tries       SMALLINT,
+    score       TEXT
+);
+
+-- Report writing extraness.
+
+CREATE TABLE IF NOT EXISTS nmr (
+    id      BIGINT PRIMARY KEY REFERENCES goals(id),
+    status  TEXT    /* one of { NULL, 'M', 'R' } */
+);
+
+CREATE TABLE IF NOT EXISTS facts (
+    uname   TEXT REFERENCES students(uname),
+    add     TEXT,
+    sub     TEXT,
+    mul     TEXT,
+    div     TEXT
+);
+
+CREATE TABLE IF NOT EXISTS social (
+    uname   TEXT REFERENCES students(uname),
+    term    TEXT,
+    trait   TEXT,
+    score   TEXT    /* 1- (worst) to 3+ (best) */
+);
+
+CREATE TABLE IF NOT EXISTS completion (
+    uname   TEXT REFERENCES students(uname),
+    term    TEXT,
+    courses TEXT REFERENCES courses(sym),
+    year    INT
+);
+
+CREATE TABLE IF NOT EXISTS drafts (
+    uname   TEXT REFERENCES students(uname),
+    term    TEXT,
+    draft   TEXT
+);
+
+CREATE TABLE IF NOT EXISTS reports (
+    uname   TEXT REFERENCES students(uname),
+    term    TEXT,
+    doc     bytea
+);
+
+-- Who changed what, and when. (See `crate::audit`.)
+
+CREATE TABLE IF NOT EXISTS audit (
+    id      BIGSERIAL PRIMARY KEY,
+    at      TIMESTAMP NOT NULL DEFAULT now(),
+    actor   TEXT NOT NULL,
+    role    TEXT NOT NULL,
+    action  TEXT NOT NULL,
+    target  TEXT,
+    student TEXT,
+    before  TEXT,
+    after   TEXT
+);
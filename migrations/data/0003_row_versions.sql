@@ -0,0 +1,18 @@
+-- Row versions, for optimistic concurrency: every change to a Goal, a
+-- Student, or a Student's report sidecar bumps its version, and a change
+-- made from data read at an older version is refused instead of silently
+-- overwriting whatever happened in between.
+
+ALTER TABLE goals    ADD COLUMN version BIGINT NOT NULL DEFAULT 1;
+ALTER TABLE students ADD COLUMN version BIGINT NOT NULL DEFAULT 1;
+
+-- A sidecar is spread across several tables (facts, social, completion,
+-- nmr), so its version is kept here, per Student per academic year. A
+-- sidecar with no row here hasn't been written yet, and is at version 0.
+
+CREATE TABLE sidecars (
+    uname   TEXT REFERENCES students(uname),
+    year    INT NOT NULL,
+    version BIGINT NOT NULL,
+    PRIMARY KEY (uname, year)
+);
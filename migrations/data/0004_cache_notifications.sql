@@ -0,0 +1,39 @@
+-- Every server process keeps copies of the users, courses, custom chapters,
+-- calendar, and special dates in memory. Any change to the tables they come
+-- from sends a notification on the `camp_cache` channel, so that other
+-- processes using the same database know to reread them (see
+-- `src/notify.rs`).
+--
+-- The payload says which table changed, in which schema, and the
+-- `application_name` of the connection that changed it, so a process can
+-- ignore its own changes.
+
+CREATE OR REPLACE FUNCTION camp_notify_cache() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify('camp_cache', json_build_object(
+        'schema', TG_TABLE_SCHEMA,
+        'table',  TG_TABLE_NAME,
+        'from',   current_setting('application_name')
+    )::text);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER users_notify_cache AFTER INSERT OR UPDATE OR DELETE OR TRUNCATE
+    ON users FOR EACH STATEMENT EXECUTE FUNCTION camp_notify_cache();
+CREATE TRIGGER teachers_notify_cache AFTER INSERT OR UPDATE OR DELETE OR TRUNCATE
+    ON teachers FOR EACH STATEMENT EXECUTE FUNCTION camp_notify_cache();
+CREATE TRIGGER students_notify_cache AFTER INSERT OR UPDATE OR DELETE OR TRUNCATE
+    ON students FOR EACH STATEMENT EXECUTE FUNCTION camp_notify_cache();
+CREATE TRIGGER parents_notify_cache AFTER INSERT OR UPDATE OR DELETE OR TRUNCATE
+    ON parents FOR EACH STATEMENT EXECUTE FUNCTION camp_notify_cache();
+CREATE TRIGGER courses_notify_cache AFTER INSERT OR UPDATE OR DELETE OR TRUNCATE
+    ON courses FOR EACH STATEMENT EXECUTE FUNCTION camp_notify_cache();
+CREATE TRIGGER chapters_notify_cache AFTER INSERT OR UPDATE OR DELETE OR TRUNCATE
+    ON chapters FOR EACH STATEMENT EXECUTE FUNCTION camp_notify_cache();
+CREATE TRIGGER custom_chapters_notify_cache AFTER INSERT OR UPDATE OR DELETE OR TRUNCATE
+    ON custom_chapters FOR EACH STATEMENT EXECUTE FUNCTION camp_notify_cache();
+CREATE TRIGGER calendar_notify_cache AFTER INSERT OR UPDATE OR DELETE OR TRUNCATE
+    ON calendar FOR EACH STATEMENT EXECUTE FUNCTION camp_notify_cache();
+CREATE TRIGGER dates_notify_cache AFTER INSERT OR UPDATE OR DELETE OR TRUNCATE
+    ON dates FOR EACH STATEMENT EXECUTE FUNCTION camp_notify_cache();
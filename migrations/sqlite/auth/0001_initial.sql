@@ -0,0 +1,78 @@
+-- The auth schema for the SQLite backend (see `crate::db`); equivalent to
+-- `migrations/auth/0001_initial.sql`.
+--
+-- SQLite has no date or array types: timestamps are stored as text
+-- ("YYYY-MM-DD HH:MM:SS"), dates as text ("YYYY-MM-DD"), and lists of
+-- text as JSON arrays.
+
+CREATE TABLE users (
+    uname TEXT PRIMARY KEY,
+    hash  TEXT
+);
+
+CREATE TABLE keys (
+    id           INTEGER PRIMARY KEY AUTOINCREMENT,
+    key_hash     TEXT,
+    uname        TEXT REFERENCES users,
+    issued       TEXT,
+    last_used    TEXT,
+    addr         TEXT,
+    user_agent   TEXT,
+    impersonator TEXT
+);
+
+CREATE TABLE failed_logins (
+    uname TEXT,
+    addr  TEXT,
+    time  TEXT
+);
+
+CREATE TABLE totp (
+    uname     TEXT PRIMARY KEY REFERENCES users,
+    secret    TEXT,
+    confirmed INTEGER,
+    last_step INTEGER
+);
+
+CREATE TABLE recovery_codes (
+    uname TEXT REFERENCES users,
+    hash  TEXT
+);
+
+CREATE TABLE totp_challenges (
+    token  TEXT,
+    uname  TEXT REFERENCES users,
+    issued TEXT
+);
+
+CREATE TABLE reset_tokens (
+    token_hash TEXT,
+    uname      TEXT REFERENCES users,
+    addr       TEXT,
+    issued     TEXT,
+    used       INTEGER
+);
+
+CREATE TABLE invites (
+    uname      TEXT PRIMARY KEY REFERENCES users,
+    token_hash TEXT,
+    issued     TEXT,
+    accepted   TEXT
+);
+
+CREATE TABLE api_tokens (
+    id         INTEGER PRIMARY KEY AUTOINCREMENT,
+    uname      TEXT REFERENCES users,
+    name       TEXT,
+    token_hash TEXT,
+    actions    TEXT,
+    created    TEXT,
+    expires    TEXT,
+    last_used  TEXT
+);
+
+CREATE TABLE grants (
+    uname      TEXT REFERENCES users,
+    permission TEXT,
+    PRIMARY KEY (uname, permission)
+);
@@ -0,0 +1,4 @@
+-- SQLite has no notifications, and an SQLite database is only ever used by
+-- a single server process, so there are no other processes' cached copies
+-- of anything to keep up to date. (See `migrations/auth/` for what this
+-- does in Postgres.)
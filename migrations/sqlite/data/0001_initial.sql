@@ -0,0 +1,133 @@
+-- The data schema for the SQLite backend (see `crate::db`); equivalent to
+-- `migrations/data/0001_initial.sql`.
+--
+-- SQLite has no date types: dates are stored as text ("YYYY-MM-DD"), and
+-- timestamps as text ("YYYY-MM-DD HH:MM:SS").
+
+CREATE TABLE courses (
+    id    INTEGER PRIMARY KEY AUTOINCREMENT,
+    sym   TEXT UNIQUE NOT NULL,
+    title TEXT NOT NULL,
+    book  TEXT,
+    level REAL
+);
+
+CREATE TABLE chapters (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    course      INTEGER REFERENCES courses(id),
+    sequence    INTEGER,
+    title       TEXT,
+    subject     TEXT,
+    weight      REAL
+);
+
+CREATE TABLE custom_chapters (
+    id      INTEGER PRIMARY KEY AUTOINCREMENT,
+    uname   TEXT,
+    title   TEXT NOT NULL,
+    weight  REAL
+);
+
+CREATE TABLE users (
+    uname TEXT PRIMARY KEY,
+    role  TEXT NOT NULL,
+    salt  TEXT,
+    email TEXT
+);
+
+CREATE TABLE teachers (
+    uname TEXT UNIQUE REFERENCES users(uname),
+    name  TEXT
+);
+
+CREATE TABLE students (
+    uname   TEXT UNIQUE REFERENCES users(uname),
+    last    TEXT,
+    rest    TEXT,
+    teacher TEXT REFERENCES teachers(uname),
+    parent  TEXT,
+    fall_exam TEXT,
+    spring_exam TEXT,
+    fall_exam_fraction REAL,
+    spring_exam_fraction REAL,
+    fall_notices INTEGER,
+    spring_notices INTEGER
+);
+
+CREATE TABLE parents (
+    uname   TEXT REFERENCES users(uname),
+    student TEXT REFERENCES students(uname),
+    UNIQUE (uname, student)
+);
+
+CREATE TABLE calendar ( day TEXT UNIQUE NOT NULL );
+
+CREATE TABLE dates (
+    name TEXT PRIMARY KEY,
+    day TEXT NOT NULL
+);
+
+CREATE TABLE goals (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    uname       TEXT REFERENCES students(uname),
+    sym         TEXT REFERENCES courses(sym),
+    seq         INTEGER,
+    custom      INTEGER REFERENCES custom_chapters(id),
+    review      INTEGER,
+    incomplete  INTEGER,
+    due         TEXT,
+    done        TEXT,
+    tries       INTEGER,
+    score       TEXT
+);
+
+CREATE TABLE nmr (
+    id      INTEGER PRIMARY KEY REFERENCES goals(id),
+    status  TEXT
+);
+
+CREATE TABLE facts (
+    uname   TEXT REFERENCES students(uname),
+    "add"   TEXT,
+    sub     TEXT,
+    mul     TEXT,
+    div     TEXT
+);
+
+CREATE TABLE social (
+    uname   TEXT REFERENCES students(uname),
+    term    TEXT,
+    trait   TEXT,
+    score   TEXT
+);
+
+CREATE TABLE completion (
+    uname   TEXT REFERENCES students(uname),
+    term    TEXT,
+    courses TEXT REFERENCES courses(sym),
+    year    INTEGER
+);
+
+CREATE TABLE drafts (
+    uname   TEXT REFERENCES students(uname),
+    term    TEXT,
+    draft   TEXT
+);
+
+CREATE TABLE reports (
+    uname   TEXT REFERENCES students(uname),
+    term    TEXT,
+    doc     BLOB
+);
+
+CREATE TABLE audit (
+    id      INTEGER PRIMARY KEY AUTOINCREMENT,
+    at      TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    actor   TEXT NOT NULL,
+    role    TEXT NOT NULL,
+    action  TEXT NOT NULL,
+    target  TEXT,
+    student TEXT,
+    before  TEXT,
+    after   TEXT
+);
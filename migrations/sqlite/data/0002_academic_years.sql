@@ -0,0 +1,25 @@
+-- Tag each year's Goals and report data with the academic year it belongs
+-- to (by starting year, as in `completion`), so that nothing has to be
+-- deleted at the end of the year.
+--
+-- Existing rows belong to the year of the calendar currently loaded (or to
+-- year 0 if there isn't one, which is also what `Glob::academic_year()` says
+-- in that case).
+
+ALTER TABLE goals   ADD COLUMN year INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE facts   ADD COLUMN year INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE social  ADD COLUMN year INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE drafts  ADD COLUMN year INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE reports ADD COLUMN year INTEGER NOT NULL DEFAULT 0;
+
+UPDATE goals   SET year = COALESCE((SELECT CAST(strftime('%Y', MIN(day)) AS INTEGER) FROM calendar), 0);
+UPDATE facts   SET year = COALESCE((SELECT CAST(strftime('%Y', MIN(day)) AS INTEGER) FROM calendar), 0);
+UPDATE social  SET year = COALESCE((SELECT CAST(strftime('%Y', MIN(day)) AS INTEGER) FROM calendar), 0);
+UPDATE drafts  SET year = COALESCE((SELECT CAST(strftime('%Y', MIN(day)) AS INTEGER) FROM calendar), 0);
+UPDATE reports SET year = COALESCE((SELECT CAST(strftime('%Y', MIN(day)) AS INTEGER) FROM calendar), 0);
+
+CREATE INDEX goals_uname_year   ON goals (uname, year);
+CREATE INDEX facts_uname_year   ON facts (uname, year);
+CREATE INDEX social_uname_year  ON social (uname, year);
+CREATE INDEX drafts_uname_year  ON drafts (uname, year);
+CREATE INDEX reports_uname_year ON reports (uname, year);
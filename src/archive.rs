@@ -0,0 +1,960 @@
+/*!
+Exporting everything in both databases to a single, portable archive, and
+restoring from one.
+
+An archive is a zip file holding
+
+  * `manifest.json`, a [`Manifest`] describing the rest of the archive;
+  * one JSON file per table (`auth/users.json`, `data/goals.json`, and so
+    on), each an array of objects keyed by column name; and
+  * the report PDFs from `reports.doc`, each in a file of its own under
+    `data/reports/`, whose name is what appears in the `doc` field of the
+    corresponding row.
+
+Dates are written as `"YYYY-MM-DD"` and timestamps as
+`"YYYY-MM-DD HH:MM:SS"` (to the second), so an archive looks the same
+whichever [backend](crate::db) it came from, and can be restored into
+either.
+
+An archive can only be restored into empty databases at the same schema
+version (see [`crate::migrate`]) as the ones it was made from. [`FORMAT`]
+changes whenever the layout of the archive itself does.
+
+`camp export` and `camp import` do this from the command line, and Admins
+can download an archive from the Admin page.
+*/
+use std::io::{Cursor, Read, Seek, Write};
+
+use serde::{Deserialize, Serialize};
+use time::{
+    format_description::FormatItem, macros::format_description, OffsetDateTime, PrimitiveDateTime,
+};
+use zip::{write::FileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::{
+    auth,
+    db::{Row, ToValue, Transaction, Value},
+    migrate::{self, State},
+    store::Store,
+    UnifiedError, DATE_FMT,
+};
+
+/// Version of the archive layout.
+pub const FORMAT: u32 = 1;
+
+const MANIFEST: &str = "manifest.json";
+const TIMESTAMP_FMT: &[FormatItem] =
+    format_description!("[year]-[month]-[day] [hour]:[minute]:[second]");
+
+/// How a column's values are written to (and read back from) an archive.
+#[derive(Clone, Copy, Debug)]
+enum Kind {
+    Bool,
+    Int,
+    Float,
+    Text,
+    Date,
+    Timestamp,
+    TextArray,
+    /// Binary data, stored as a file of its own with the given extension.
+    File(&'static str),
+}
+
+use Kind::*;
+
+/// A table that gets archived.
+struct Table {
+    name: &'static str,
+    columns: &'static [(&'static str, Kind)],
+    /// Whether the `id` column is filled from a sequence (which needs to be
+    /// moved past the restored values, in Postgres).
+    serial: bool,
+}
+
+/// The auth DB's tables, in an order they can be restored in.
+static AUTH_TABLES: &[Table] = &[
+    Table {
+        name: "users",
+        columns: &[("uname", Text), ("hash", Text)],
+        serial: false,
+    },
+    Table {
+        name: "keys",
+        columns: &[
+            ("id", Int),
+            ("key_hash", Text),
+            ("uname", Text),
+            ("issued", Timestamp),
+            ("last_used", Timestamp),
+            ("addr", Text),
+            ("user_agent", Text),
+            ("impersonator", Text),
+        ],
+        serial: true,
+    },
+    Table {
+        name: "failed_logins",
+        columns: &[("uname", Text), ("addr", Text), ("time", Timestamp)],
+        serial: false,
+    },
+    Table {
+        name: "totp",
+        columns: &[
+            ("uname", Text),
+            ("secret", Text),
+            ("confirmed", Bool),
+            ("last_step", Int),
+        ],
+        serial: false,
+    },
+    Table {
+        name: "recovery_codes",
+        columns: &[("uname", Text), ("hash", Text)],
+        serial: false,
+    },
+    Table {
+        name: "totp_challenges",
+        columns: &[("token", Text), ("uname", Text), ("issued", Timestamp)],
+        serial: false,
+    },
+    Table {
+        name: "reset_tokens",
+        columns: &[
+            ("token_hash", Text),
+            ("uname", Text),
+            ("addr", Text),
+            ("issued", Timestamp),
+            ("used", Bool),
+        ],
+        serial: false,
+    },
+    Table {
+        name: "invites",
+        columns: &[
+            ("uname", Text),
+            ("token_hash", Text),
+            ("issued", Timestamp),
+            ("accepted", Timestamp),
+        ],
+        serial: false,
+    },
+    Table {
+        name: "api_tokens",
+        columns: &[
+            ("id", Int),
+            ("uname", Text),
+            ("name", Text),
+            ("token_hash", Text),
+            ("actions", TextArray),
+            ("created", Timestamp),
+            ("expires", Date),
+            ("last_used", Timestamp),
+        ],
+        serial: true,
+    },
+    Table {
+        name: "grants",
+        columns: &[("uname", Text), ("permission", Text)],
+        serial: false,
+    },
+];
+
+/// The data DB's tables, in an order they can be restored in.
+static DATA_TABLES: &[Table] = &[
+    Table {
+        name: "courses",
+        columns: &[
+            ("id", Int),
+            ("sym", Text),
+            ("title", Text),
+            ("book", Text),
+            ("level", Float),
+        ],
+        serial: true,
+    },
+    Table {
+        name: "chapters",
+        columns: &[
+            ("id", Int),
+            ("course", Int),
+            ("sequence", Int),
+            ("title", Text),
+            ("subject", Text),
+            ("weight", Float),
+        ],
+        serial: true,
+    },
+    Table {
+        name: "custom_chapters",
+        columns: &[
+            ("id", Int),
+            ("uname", Text),
+            ("title", Text),
+            ("weight", Float),
+        ],
+        serial: true,
+    },
+    Table {
+        name: "users",
+        columns: &[
+            ("uname", Text),
+            ("role", Text),
+            ("salt", Text),
+            ("email", Text),
+        ],
+        serial: false,
+    },
+    Table {
+        name: "teachers",
+        columns: &[("uname", Text), ("name", Text)],
+        serial: false,
+    },
+    Table {
+        name: "students",
+        columns: &[
+            ("uname", Text),
+            ("last", Text),
+            ("rest", Text),
+            ("teacher", Text),
+            ("parent", Text),
+            ("fall_exam", Text),
+            ("spring_exam", Text),
+            ("fall_exam_fraction", Float),
+            ("spring_exam_fraction", Float),
+            ("fall_notices", Int),
+            ("spring_notices", Int),
+            ("version", Int),
+        ],
+        serial: false,
+    },
+    Table {
+        name: "parents",
+        columns: &[("uname", Text), ("student", Text)],
+        serial: false,
+    },
+    Table {
+        name: "calendar",
+        columns: &[("day", Date)],
+        serial: false,
+    },
+    Table {
+        name: "dates",
+        columns: &[("name", Text), ("day", Date)],
+        serial: false,
+    },
+    Table {
+        name: "goals",
+        columns: &[
+            ("id", Int),
+            ("uname", Text),
+            ("sym", Text),
+            ("seq", Int),
+            ("custom", Int),
+            ("review", Bool),
+            ("incomplete", Bool),
+            ("due", Date),
+            ("done", Date),
+            ("tries", Int),
+            ("score", Text),
+            ("year", Int),
+            ("version", Int),
+        ],
+        serial: true,
+    },
+    Table {
+        name: "nmr",
+        columns: &[("id", Int), ("status", Text)],
+        serial: false,
+    },
+    Table {
+        name: "facts",
+        columns: &[
+            ("uname", Text),
+            ("add", Text),
+            ("sub", Text),
+            ("mul", Text),
+            ("div", Text),
+            ("year", Int),
+        ],
+        serial: false,
+    },
+    Table {
+        name: "social",
+        columns: &[
+            ("uname", Text),
+            ("term", Text),
+            ("trait", Text),
+            ("score", Text),
+            ("year", Int),
+        ],
+        serial: false,
+    },
+    Table {
+        name: "completion",
+        columns: &[
+            ("uname", Text),
+            ("term", Text),
+            ("courses", Text),
+            ("year", Int),
+        ],
+        serial: false,
+    },
+    Table {
+        name: "drafts",
+        columns: &[
+            ("uname", Text),
+            ("term", Text),
+            ("draft", Text),
+            ("year", Int),
+        ],
+        serial: false,
+    },
+    Table {
+        name: "reports",
+        columns: &[
+            ("uname", Text),
+            ("term", Text),
+            ("doc", File("pdf")),
+            ("year", Int),
+        ],
+        serial: false,
+    },
+    Table {
+        name: "sidecars",
+        columns: &[("uname", Text), ("year", Int), ("version", Int)],
+        serial: false,
+    },
+    Table {
+        name: "audit",
+        columns: &[
+            ("id", Int),
+            ("at", Timestamp),
+            ("actor", Text),
+            ("role", Text),
+            ("action", Text),
+            ("target", Text),
+            ("student", Text),
+            ("before", Text),
+            ("after", Text),
+        ],
+        serial: true,
+    },
+];
+
+/// A single table's entry in the [`Manifest`].
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TableEntry {
+    pub name: String,
+    /// Name of the JSON file in the archive holding the table's rows.
+    pub file: String,
+    pub rows: usize,
+}
+
+/// A single database's entry in the [`Manifest`].
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DbEntry {
+    /// Version of the last migration applied to the database.
+    pub schema_version: i32,
+    pub tables: Vec<TableEntry>,
+}
+
+/// What's in an archive.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Manifest {
+    /// The [`FORMAT`] of the archive.
+    pub format: u32,
+    /// Version of `camp` that made the archive.
+    pub camp_version: String,
+    /// When the archive was made ("YYYY-MM-DD HH:MM:SS", UTC).
+    pub created: String,
+    pub auth: DbEntry,
+    pub data: DbEntry,
+}
+
+/**
+The version of the last migration applied to the database `which`, which
+must have all of (and only) the migrations this version of `camp` knows
+about.
+*/
+fn schema_version(which: &str, statuses: &[migrate::Status]) -> Result<i32, String> {
+    for s in statuses.iter() {
+        if s.state != State::Applied {
+            return Err(format!(
+                "The {} DB's migration {} ({:?}) is {}; see `camp migrate status`.",
+                which, s.version, &s.name, &s.state
+            ));
+        }
+    }
+
+    Ok(statuses.last().map(|s| s.version).unwrap_or(0))
+}
+
+/// Ensure `row` (from `SELECT *`) doesn't have any columns `table` doesn't
+/// know about, which would otherwise silently go missing.
+fn check_columns(table: &Table, row: &Row) -> Result<(), String> {
+    for col in row.columns().iter() {
+        if !table.columns.iter().any(|(name, _)| name == col) {
+            return Err(format!(
+                "The {:?} table has a column {:?} that this version of camp doesn't know how to archive.",
+                table.name, col
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn cell_to_json(row: &Row, col: &str, kind: Kind) -> Result<serde_json::Value, String> {
+    let v = match kind {
+        Bool => row.try_get::<_, Option<bool>>(col)?.into(),
+        Int => row.try_get::<_, Option<i64>>(col)?.into(),
+        Float => row.try_get::<_, Option<f64>>(col)?.into(),
+        Text => row.try_get::<_, Option<String>>(col)?.into(),
+        Date => match row.try_get::<_, Option<time::Date>>(col)? {
+            Some(d) => d
+                .format(DATE_FMT)
+                .map_err(|e| format!("Error formatting date {:?}: {}", &d, &e))?
+                .into(),
+            None => serde_json::Value::Null,
+        },
+        Timestamp => match row.try_get::<_, Option<PrimitiveDateTime>>(col)? {
+            Some(t) => t
+                .format(TIMESTAMP_FMT)
+                .map_err(|e| format!("Error formatting timestamp {:?}: {}", &t, &e))?
+                .into(),
+            None => serde_json::Value::Null,
+        },
+        TextArray => row.try_get::<_, Option<Vec<String>>>(col)?.into(),
+        File(_) => {
+            return Err(format!("Column {:?} should be archived as a file.", col));
+        }
+    };
+    Ok(v)
+}
+
+fn json_to_value<R: Read + Seek>(
+    v: &serde_json::Value,
+    kind: Kind,
+    zip: &mut ZipArchive<R>,
+) -> Result<Value, String> {
+    if v.is_null() {
+        return Ok(Value::Null);
+    }
+    let bad = || format!("{} is not a valid {:?} value.", v, kind);
+
+    let val = match kind {
+        Bool => Value::Bool(v.as_bool().ok_or_else(bad)?),
+        Int => Value::Int(v.as_i64().ok_or_else(bad)?),
+        Float => Value::Float(v.as_f64().ok_or_else(bad)?),
+        Text => Value::Text(v.as_str().ok_or_else(bad)?.to_owned()),
+        Date => {
+            let s = v.as_str().ok_or_else(bad)?;
+            Value::Date(time::Date::parse(s, DATE_FMT).map_err(|_| bad())?)
+        }
+        Timestamp => {
+            let s = v.as_str().ok_or_else(bad)?;
+            Value::Timestamp(PrimitiveDateTime::parse(s, TIMESTAMP_FMT).map_err(|_| bad())?)
+        }
+        TextArray => Value::TextArray(serde_json::from_value(v.clone()).map_err(|_| bad())?),
+        File(_) => Value::Bytes(read_file(zip, v.as_str().ok_or_else(bad)?)?),
+    };
+    Ok(val)
+}
+
+fn read_file<R: Read + Seek>(zip: &mut ZipArchive<R>, name: &str) -> Result<Vec<u8>, String> {
+    let mut f = zip
+        .by_name(name)
+        .map_err(|e| format!("Error finding {:?} in archive: {}", name, &e))?;
+    let mut buff: Vec<u8> = Vec::with_capacity(f.size() as usize);
+    f.read_to_end(&mut buff)
+        .map_err(|e| format!("Error reading {:?} from archive: {}", name, &e))?;
+    Ok(buff)
+}
+
+fn write_file<W: Write + Seek>(
+    zip: &mut ZipWriter<W>,
+    name: &str,
+    bytes: &[u8],
+) -> Result<(), String> {
+    let opts = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    zip.start_file(name, opts)
+        .map_err(|e| format!("Error adding {:?} to archive: {}", name, &e))?;
+    zip.write_all(bytes)
+        .map_err(|e| format!("Error writing {:?} to archive: {}", name, &e))
+}
+
+/// Write every table in `tables` (of the database `which`) to `zip`.
+async fn export_tables<W: Write + Seek>(
+    which: &str,
+    tables: &[Table],
+    t: &Transaction<'_>,
+    zip: &mut ZipWriter<W>,
+) -> Result<Vec<TableEntry>, UnifiedError> {
+    let mut entries: Vec<TableEntry> = Vec::with_capacity(tables.len());
+
+    for table in tables.iter() {
+        let rows = t
+            .query(&format!("SELECT * FROM {}", table.name), &[])
+            .await?;
+        if let Some(row) = rows.first() {
+            check_columns(table, row)?;
+        }
+
+        let mut objects: Vec<serde_json::Value> = Vec::with_capacity(rows.len());
+        for (n, row) in rows.iter().enumerate() {
+            let mut obj = serde_json::Map::with_capacity(table.columns.len());
+            for (col, kind) in table.columns.iter() {
+                let v = match kind {
+                    File(ext) => match row.try_get::<_, Option<&[u8]>>(*col)? {
+                        Some(bytes) => {
+                            let fname = format!("{}/{}/{}.{}", which, table.name, n, ext);
+                            write_file(zip, &fname, bytes)?;
+                            serde_json::Value::from(fname)
+                        }
+                        None => serde_json::Value::Null,
+                    },
+                    _ => cell_to_json(row, col, *kind)?,
+                };
+                obj.insert(col.to_string(), v);
+            }
+            objects.push(serde_json::Value::Object(obj));
+        }
+
+        let file = format!("{}/{}.json", which, table.name);
+        let json = serde_json::to_vec(&objects)
+            .map_err(|e| format!("Error serializing {} table: {}", table.name, &e))?;
+        write_file(zip, &file, &json)?;
+
+        entries.push(TableEntry {
+            name: table.name.to_owned(),
+            file,
+            rows: rows.len(),
+        });
+    }
+
+    Ok(entries)
+}
+
+/**
+Restore every table in `tables` (of the database `which`) from `zip`,
+as described by `entry`.
+
+Every table must be empty beforehand.
+*/
+async fn import_tables<R: Read + Seek>(
+    which: &str,
+    tables: &[Table],
+    entry: &DbEntry,
+    t: &Transaction<'_>,
+    zip: &mut ZipArchive<R>,
+) -> Result<(), UnifiedError> {
+    for table in tables.iter() {
+        let row = t
+            .query_one(&format!("SELECT COUNT(*) AS n FROM {}", table.name), &[])
+            .await?;
+        if row.try_get::<_, i64>("n")? != 0 {
+            return Err(format!(
+                "The {} DB's {:?} table isn't empty; an archive can only be restored into empty databases.",
+                which, table.name
+            )
+            .into());
+        }
+
+        let tab_entry = entry
+            .tables
+            .iter()
+            .find(|e| e.name == table.name)
+            .ok_or_else(|| format!("The archive has no {} {:?} table.", which, table.name))?;
+        let objects: Vec<serde_json::Map<String, serde_json::Value>> =
+            serde_json::from_slice(&read_file(zip, &tab_entry.file)?)
+                .map_err(|e| format!("Error reading {:?}: {}", &tab_entry.file, &e))?;
+        if objects.len() != tab_entry.rows {
+            return Err(format!(
+                "The manifest says {:?} has {} rows, but it has {}.",
+                &tab_entry.file,
+                tab_entry.rows,
+                objects.len()
+            )
+            .into());
+        }
+
+        let columns: Vec<String> = table
+            .columns
+            .iter()
+            .map(|(col, _)| format!("\"{}\"", col))
+            .collect();
+        let params: Vec<String> = (1..=columns.len()).map(|n| format!("${}", n)).collect();
+        let stmt = format!(
+            "INSERT INTO {} ({}) VALUES ({})",
+            table.name,
+            columns.join(", "),
+            params.join(", ")
+        );
+
+        for (n, obj) in objects.iter().enumerate() {
+            let mut values: Vec<Value> = Vec::with_capacity(table.columns.len());
+            for (col, kind) in table.columns.iter() {
+                let v = obj.get(*col).unwrap_or(&serde_json::Value::Null);
+                let val = json_to_value(v, *kind, zip).map_err(|e| {
+                    format!("{:?} row {}, column {:?}: {}", &tab_entry.file, n, col, &e)
+                })?;
+                values.push(val);
+            }
+            let refs: Vec<&(dyn ToValue + Sync)> =
+                values.iter().map(|v| v as &(dyn ToValue + Sync)).collect();
+            t.execute(&stmt, &refs)
+                .await
+                .map_err(|e| format!("Error restoring {:?} row {}: {}", &tab_entry.file, n, &e))?;
+        }
+
+        if table.serial {
+            t.sync_id_sequence(table.name).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/**
+Write the entire contents of both databases to a new archive, returning
+the bytes of the zip file.
+
+Each database's tables are all read from one snapshot of it (see
+[`TransactionConnection::read_snapshot`](crate::db::TransactionConnection::read_snapshot)),
+so that tables exported while others are writing still agree with each
+other (no Goals whose Student isn't there, say).
+*/
+pub async fn export(auth: &auth::Db, data: &Store) -> Result<Vec<u8>, UnifiedError> {
+    log::trace!("archive::export( [ auth::Db ], [ Store ] ) called.");
+
+    let auth_version = schema_version("auth", &auth.migration_status().await?)?;
+    let data_version = schema_version("data", &data.migration_status().await?)?;
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+
+    let mut auth_client = auth.connect().await?;
+    let t = auth_client.transaction().await?;
+    t.read_snapshot().await?;
+    let auth_tables = export_tables("auth", AUTH_TABLES, &t, &mut zip).await?;
+    t.rollback().await?;
+
+    let mut data_client = data.connect().await?;
+    let t = data_client.transaction().await?;
+    t.read_snapshot().await?;
+    let data_tables = export_tables("data", DATA_TABLES, &t, &mut zip).await?;
+    t.rollback().await?;
+
+    let now = OffsetDateTime::now_utc();
+    let manifest = Manifest {
+        format: FORMAT,
+        camp_version: crate::VERSION.to_owned(),
+        created: PrimitiveDateTime::new(now.date(), now.time())
+            .format(TIMESTAMP_FMT)
+            .map_err(|e| format!("Error formatting current time: {}", &e))?,
+        auth: DbEntry {
+            schema_version: auth_version,
+            tables: auth_tables,
+        },
+        data: DbEntry {
+            schema_version: data_version,
+            tables: data_tables,
+        },
+    };
+    let manifest_json = serde_json::to_vec_pretty(&manifest)
+        .map_err(|e| format!("Error serializing manifest: {}", &e))?;
+    write_file(&mut zip, MANIFEST, &manifest_json)?;
+
+    let cursor = zip
+        .finish()
+        .map_err(|e| format!("Error finishing archive: {}", &e))?;
+    Ok(cursor.into_inner())
+}
+
+/**
+Restore the contents of both databases from an `archive` made by
+[`export`], returning its manifest.
+
+Any pending migrations are applied first. Both databases must then be at
+the schema versions the archive was made at, and must be empty; nothing is
+written unless the whole archive can be restored. (The exception: if the
+auth DB is restored but committing the data DB's changes fails, the auth
+DB keeps its.)
+*/
+pub async fn import(
+    auth: &auth::Db,
+    data: &Store,
+    archive: &[u8],
+) -> Result<Manifest, UnifiedError> {
+    log::trace!(
+        "archive::import( [ auth::Db ], [ Store ], [ {} bytes ] ) called.",
+        archive.len()
+    );
+
+    let mut zip = ZipArchive::new(Cursor::new(archive))
+        .map_err(|e| format!("Error opening archive: {}", &e))?;
+    let manifest: Manifest = serde_json::from_slice(&read_file(&mut zip, MANIFEST)?)
+        .map_err(|e| format!("Error reading archive manifest: {}", &e))?;
+    if manifest.format != FORMAT {
+        return Err(format!(
+            "The archive is in format {}, but this version of camp ({}) reads format {}.",
+            manifest.format,
+            crate::VERSION,
+            FORMAT
+        )
+        .into());
+    }
+
+    auth.ensure_db_schema().await?;
+    data.ensure_db_schema().await?;
+    for (which, archived, current) in [
+        (
+            "auth",
+            manifest.auth.schema_version,
+            schema_version("auth", &auth.migration_status().await?)?,
+        ),
+        (
+            "data",
+            manifest.data.schema_version,
+            schema_version("data", &data.migration_status().await?)?,
+        ),
+    ] {
+        if archived != current {
+            return Err(format!(
+                "The archive was made from a {} DB at schema version {}, but this version of camp ({}) is at version {}.",
+                which, archived, crate::VERSION, current
+            )
+            .into());
+        }
+    }
+
+    let mut auth_client = auth.connect().await?;
+    let auth_t = auth_client.transaction().await?;
+    let mut data_client = data.connect().await?;
+    let data_t = data_client.transaction().await?;
+
+    import_tables("auth", AUTH_TABLES, &manifest.auth, &auth_t, &mut zip).await?;
+    import_tables("data", DATA_TABLES, &manifest.data, &data_t, &mut zip).await?;
+
+    auth_t.commit().await?;
+    data_t.commit().await.map_err(|e| {
+        format!(
+            "Error committing data DB changes ({}); the auth DB has been restored, but the data DB hasn't.",
+            &e
+        )
+    })?;
+
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::{Dialect, MEMORY},
+        tests::{ensure_logging, test_data_db},
+    };
+
+    use serial_test::serial;
+
+    const AUTH_ROWS: &str = "
+INSERT INTO users (uname, hash) VALUES ('boss', 'h1'), ('tea', 'h2');
+INSERT INTO keys (key_hash, uname, issued, last_used, addr, user_agent)
+    VALUES ('k', 'boss', '2023-01-10 13:14:15', '2023-01-10 13:20:00', '127.0.0.1', 'test');
+INSERT INTO totp (uname, secret, confirmed, last_step) VALUES ('boss', 'secret', 1, 42);
+INSERT INTO api_tokens (uname, name, token_hash, actions, created, expires)
+    VALUES ('boss', 'script', 'th', '[\"find-student\",\"populate-goals\"]',
+        '2023-01-10 13:14:15', '2023-06-30');
+INSERT INTO grants (uname, permission) VALUES ('tea', 'export-archive');";
+
+    const DATA_ROWS: &str = "
+INSERT INTO courses (sym, title, book, level) VALUES ('pa', 'Pre-Algebra', 'Book', 9.5);
+INSERT INTO chapters (course, sequence, title, weight) VALUES (1, 1, 'Fractions', 1.5);
+INSERT INTO users (uname, role, salt, email)
+    VALUES ('tea', 'Teacher', 's', 'tea@school.com'), ('stu', 'Student', 's', 'stu@school.com');
+INSERT INTO teachers (uname, name) VALUES ('tea', 'Ms Tea');
+INSERT INTO students (uname, last, rest, teacher, parent, fall_exam_fraction,
+        spring_exam_fraction, fall_notices, spring_notices)
+    VALUES ('stu', 'Dent', 'Stu', 'tea', 'parent@home.com', 0.2, 0.2, 0, 1);
+INSERT INTO calendar (day) VALUES ('2023-01-10'), ('2023-01-11');
+INSERT INTO goals (uname, sym, seq, review, incomplete, due, done, tries, score, year)
+    VALUES ('stu', 'pa', 1, 0, 1, '2023-01-10', '2023-01-11', 2, '95%', 2022);
+INSERT INTO reports (uname, term, doc, year) VALUES ('stu', 'fall', X'00FF2550', 2022);
+INSERT INTO audit (at, actor, role, action, target)
+    VALUES ('2023-01-10 13:14:15', 'tea', 'Teacher', 'add-goal', 'stu');";
+
+    /// A fresh pair of in-memory databases, with schemas.
+    async fn empty_dbs() -> (auth::Db, Store) {
+        let auth = auth::Db::new(MEMORY.to_owned());
+        let data = Store::new(MEMORY.to_owned());
+        auth.ensure_db_schema().await.unwrap();
+        data.ensure_db_schema().await.unwrap();
+        (auth, data)
+    }
+
+    async fn populated_dbs() -> (auth::Db, Store) {
+        let (auth, data) = empty_dbs().await;
+        auth.connect()
+            .await
+            .unwrap()
+            .batch_execute(AUTH_ROWS)
+            .await
+            .unwrap();
+        data.connect()
+            .await
+            .unwrap()
+            .batch_execute(DATA_ROWS)
+            .await
+            .unwrap();
+        (auth, data)
+    }
+
+    /// The names and contents of every file in an archive but the manifest.
+    fn contents(archive: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let mut zip = ZipArchive::new(Cursor::new(archive)).unwrap();
+        let mut names: Vec<String> = zip
+            .file_names()
+            .filter(|name| *name != MANIFEST)
+            .map(|name| name.to_owned())
+            .collect();
+        names.sort();
+        names
+            .into_iter()
+            .map(|name| {
+                let bytes = read_file(&mut zip, &name).unwrap();
+                (name, bytes)
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        ensure_logging();
+        let (auth, data) = populated_dbs().await;
+        let archive = export(&auth, &data).await.unwrap();
+
+        let (new_auth, new_data) = empty_dbs().await;
+        let manifest = import(&new_auth, &new_data, &archive).await.unwrap();
+        assert_eq!(manifest.format, FORMAT);
+        let goals = manifest
+            .data
+            .tables
+            .iter()
+            .find(|t| t.name == "goals")
+            .unwrap();
+        assert_eq!(goals.rows, 1);
+
+        let restored = export(&new_auth, &new_data).await.unwrap();
+        assert_eq!(contents(&archive), contents(&restored));
+
+        let pdf = new_data
+            .connect()
+            .await
+            .unwrap()
+            .query_one("SELECT doc FROM reports", &[])
+            .await
+            .unwrap()
+            .try_get::<_, Vec<u8>>("doc")
+            .unwrap();
+        assert_eq!(pdf, vec![0x00, 0xff, 0x25, 0x50]);
+    }
+
+    #[tokio::test]
+    async fn import_refuses_nonempty() {
+        ensure_logging();
+        let (auth, data) = populated_dbs().await;
+        let archive = export(&auth, &data).await.unwrap();
+        assert!(import(&auth, &data, &archive).await.is_err());
+
+        // Nothing from the failed attempt was written.
+        let n: i64 = auth
+            .connect()
+            .await
+            .unwrap()
+            .query_one("SELECT COUNT(*) AS n FROM users", &[])
+            .await
+            .unwrap()
+            .try_get("n")
+            .unwrap();
+        assert_eq!(n, 2);
+    }
+
+    #[tokio::test]
+    async fn import_refuses_other_format() {
+        ensure_logging();
+        let manifest = Manifest {
+            format: FORMAT + 1,
+            camp_version: "0.0.0".to_owned(),
+            created: "2023-01-10 13:14:15".to_owned(),
+            auth: DbEntry {
+                schema_version: 1,
+                tables: Vec::new(),
+            },
+            data: DbEntry {
+                schema_version: 2,
+                tables: Vec::new(),
+            },
+        };
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        write_file(&mut zip, MANIFEST, &serde_json::to_vec(&manifest).unwrap()).unwrap();
+        let archive = zip.finish().unwrap().into_inner();
+
+        let (auth, data) = empty_dbs().await;
+        let err = import(&auth, &data, &archive).await.unwrap_err();
+        assert!(err.to_string().contains("format"));
+    }
+
+    /// Every column of every table in the current schema is archived.
+    #[tokio::test]
+    async fn tables_match_schema() {
+        ensure_logging();
+        let (auth, data) = empty_dbs().await;
+
+        for (db_client, tables) in [
+            (auth.connect().await.unwrap(), AUTH_TABLES),
+            (data.connect().await.unwrap(), DATA_TABLES),
+        ] {
+            for table in tables.iter() {
+                let rows = db_client
+                    .query("SELECT name FROM pragma_table_info($1)", &[&table.name])
+                    .await
+                    .unwrap();
+                let mut in_db: Vec<String> =
+                    rows.iter().map(|r| r.try_get("name").unwrap()).collect();
+                let mut archived: Vec<String> =
+                    table.columns.iter().map(|(c, _)| c.to_string()).collect();
+                in_db.sort();
+                archived.sort();
+                assert_eq!(in_db, archived, "columns of {:?}", table.name);
+            }
+        }
+    }
+
+    /// In Postgres, the export's reads can't be changed out from under it.
+    #[tokio::test]
+    #[serial]
+    async fn export_reads_one_snapshot() {
+        ensure_logging();
+        let data = Store::new(test_data_db());
+        data.ensure_db_schema().await.unwrap();
+        let mut client = data.connect().await.unwrap();
+        let t = client.transaction().await.unwrap();
+        t.read_snapshot().await.unwrap();
+
+        if t.dialect() == Dialect::Postgres {
+            let level: String = t
+                .query_one("SHOW transaction_isolation", &[])
+                .await
+                .unwrap()
+                .try_get(0)
+                .unwrap();
+            assert_eq!(&level, "repeatable read");
+            assert!(t
+                .execute("INSERT INTO calendar (day) VALUES ('2023-01-10')", &[])
+                .await
+                .is_err());
+        }
+        t.rollback().await.unwrap();
+        drop(client);
+
+        data.nuke_database().await.unwrap();
+    }
+}
@@ -0,0 +1,127 @@
+/*!
+Who changed what, and when.
+
+Every write made through one of the `/admin`, `/boss`, or `/teacher` API
+endpoints is recorded in an append-only table in the data database: the
+uname and role of the user who made it, the `x-camp-action`, the entity it
+was aimed at (and the Student it concerns, if any), what that entity looked
+like before, and the body of the request.
+
+Admins and Bosses can query the log by actor, Student, or date range, and
+export the results as CSV.
+*/
+use std::io::Write;
+
+use serde::Serialize;
+use time::Date;
+
+use crate::perm::Endpoint;
+
+/// Whether `action` at `endpoint` writes anything (and should therefore be
+/// recorded): anything not explicitly read-only (see
+/// [`Endpoint::is_read_only`]) is assumed to.
+pub fn is_write(endpoint: Endpoint, action: &str) -> bool {
+    !endpoint.is_read_only(action)
+}
+
+/// A new entry for the audit log.
+#[derive(Debug)]
+pub struct Record<'a> {
+    /// uname of the user who performed the action
+    pub actor: &'a str,
+    pub role: &'a str,
+    /// value of the `x-camp-action` header
+    pub action: &'a str,
+    /// The entity acted upon, like `"goal 1234"` or `"course pa1"`, or a
+    /// uname for actions on users.
+    pub target: Option<&'a str>,
+    /// uname of the Student the action concerns, if any
+    pub student: Option<&'a str>,
+    /// JSON representation of the target before the action
+    pub before: Option<&'a str>,
+    /// Body of the request.
+    pub after: Option<&'a str>,
+}
+
+/// An entry read back from the audit log.
+#[derive(Debug, Serialize)]
+pub struct Entry {
+    pub id: i64,
+    /// "YYYY-MM-DD HH:MM:SS"
+    pub at: String,
+    pub actor: String,
+    pub role: String,
+    pub action: String,
+    pub target: Option<String>,
+    pub student: Option<String>,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+/// Which entries to read from the audit log; `None` fields match anything.
+#[derive(Debug, Default)]
+pub struct Query {
+    pub actor: Option<String>,
+    pub student: Option<String>,
+    /// First day to include.
+    pub from: Option<Date>,
+    /// Last day to include.
+    pub to: Option<Date>,
+}
+
+/// Write `entries` to `w` as CSV, with a header row.
+pub fn write_csv<W: Write>(entries: &[Entry], w: W) -> Result<(), String> {
+    let mut csv_writer = csv::Writer::from_writer(w);
+    for e in entries.iter() {
+        csv_writer
+            .serialize(e)
+            .map_err(|err| format!("Error writing audit entry {} as CSV: {}", &e.id, &err))?;
+    }
+    csv_writer
+        .flush()
+        .map_err(|e| format!("Error flushing CSV writer: {}", &e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes() {
+        assert!(is_write(Endpoint::Teacher, "update-goal"));
+        assert!(is_write(Endpoint::Admin, "delete-user"));
+        assert!(!is_write(Endpoint::Teacher, "populate-goals"));
+        assert!(!is_write(Endpoint::Admin, "export-audit"));
+        assert!(is_write(Endpoint::Teacher, "populate-something-new"));
+    }
+
+    #[test]
+    fn csv_export() {
+        let entries = vec![Entry {
+            id: 1,
+            at: "2026-01-05 10:15:00".to_owned(),
+            actor: "jenny".to_owned(),
+            role: "Teacher".to_owned(),
+            action: "update-goal".to_owned(),
+            target: Some("goal 12".to_owned()),
+            student: Some("frog".to_owned()),
+            before: Some(r#"{"done": null}"#.to_owned()),
+            after: None,
+        }];
+
+        let mut buff: Vec<u8> = Vec::new();
+        write_csv(&entries, &mut buff).unwrap();
+        let text = String::from_utf8(buff).unwrap();
+        let mut lines = text.lines();
+        assert_eq!(
+            lines.next(),
+            Some("id,at,actor,role,action,target,student,before,after")
+        );
+        assert_eq!(
+            lines.next(),
+            Some(
+                r#"1,2026-01-05 10:15:00,jenny,Teacher,update-goal,goal 12,frog,"{""done"": null}","#
+            )
+        );
+    }
+}
@@ -2,7 +2,8 @@
 Authentication database connection and methods.
 
 This struct is meant to interface with a database with the following
-schema:
+schema (as defined by the migrations in `migrations/auth/`; see
+[`crate::migrate`]):
 
 ```sql
 CREATE TABLE users (
@@ -11,18 +12,144 @@ CREATE TABLE users (
 );
 
 CREATE TABLE keys (
-    key       TEXT,
-    uname     TEXT REFERENCES users,
-    last_used TIMESTAMP
+    id           BIGSERIAL,
+    key_hash     TEXT,
+    uname        TEXT REFERENCES users,
+    issued       TIMESTAMP,
+    last_used    TIMESTAMP,
+    addr         TEXT,
+    user_agent   TEXT,
+    impersonator TEXT
+);
+
+CREATE TABLE failed_logins (
+    uname TEXT,
+    addr  TEXT,
+    time  TIMESTAMP
+);
+
+CREATE TABLE totp (
+    uname     TEXT PRIMARY KEY REFERENCES users,
+    secret    TEXT,
+    confirmed BOOL,
+    last_step BIGINT
+);
+
+CREATE TABLE recovery_codes (
+    uname TEXT REFERENCES users,
+    hash  TEXT
+);
+
+CREATE TABLE totp_challenges (
+    token  TEXT, -- hashed
+    uname  TEXT REFERENCES users,
+    issued TIMESTAMP
+);
+
+CREATE TABLE reset_tokens (
+    token_hash TEXT,
+    uname      TEXT REFERENCES users,
+    addr       TEXT,
+    issued     TIMESTAMP,
+    used       BOOL
+);
+
+CREATE TABLE invites (
+    uname      TEXT PRIMARY KEY REFERENCES users,
+    token_hash TEXT,
+    issued     TIMESTAMP,
+    accepted   TIMESTAMP
+);
+
+CREATE TABLE api_tokens (
+    id         BIGSERIAL,
+    uname      TEXT REFERENCES users,
+    name       TEXT,
+    token_hash TEXT,
+    actions    TEXT[],
+    created    TIMESTAMP,
+    expires    DATE,
+    last_used  TIMESTAMP
+);
+
+CREATE TABLE grants (
+    uname      TEXT REFERENCES users,
+    permission TEXT,
+    PRIMARY KEY (uname, permission)
 );
 ```
 
 Additionally, each `uname` should have a short `salt` string associated with
 it (stored separately somewhere) for use in password hashing.
+
+Passwords are hashed with Argon2id and stored in `users.hash` as PHC-format
+strings (`$argon2id$v=19$...`). Older versions of this system stored a
+single unkeyed [`blake3`] hash of the password and salt; these legacy hashes
+are still accepted, and are transparently replaced with Argon2id hashes the
+next time the user successfully logs in.
+
+Keys are bearer credentials, so only their hashes are stored (in
+`keys.key_hash`); a key is looked up by hashing the presented value. (Older
+versions of this system stored keys in the clear in a `keys.key` column;
+[`Db::ensure_db_schema`] hashes any such keys and drops that column.) The
+same goes for the tokens in `totp_challenges` and `reset_tokens`.
+
+Password reset tokens are not keys: they can't be used to authenticate
+anything but a single password reset, they expire sooner, and redeeming one
+invalidates all of that user's other outstanding tokens. Issued tokens are
+remembered for a while even after they're used up, in order to limit how
+often they can be requested. (See [`ResetPolicy`].)
+
+New users are given an unguessable password nobody knows, and may be sent
+an invitation instead: a longer-lived token, one per user, with which they
+can set their own password. Issuing a new invitation replaces any previous
+one. Accepted invitations are kept around so their status can be reported.
+
+For scripted access, a user may also be issued named API tokens. These are
+presented in place of a key, but don't expire with disuse; instead each may
+have an expiration date. Each token is only good for the `x-camp-action`
+values it was issued for. API tokens all start with [`API_TOKEN_PREFIX`],
+so they can be told apart from keys.
+
+An Admin may also be issued a key for another user, in order to see what
+that user sees. Such keys have the Admin's uname recorded in
+`keys.impersonator`; [`Db::check_key`] reports them as
+`AuthResult::ReadOnly`, and they should only be honored for requests that
+don't change anything.
+
+Extra permissions granted to individual users (beyond what their roles
+allow; see [`crate::perm`]) are stored in `grants`.
+
+Failed password attempts are recorded in `failed_logins`, both by `uname`
+and by client address, and are used to throttle further attempts. (See
+[`LoginThrottle`].)
+
+Users may also enroll a TOTP secret (see [`crate::totp`]) as a second
+factor. An enrollment isn't in force until the user has proven they can
+generate a code from it; from then on, a password check only earns a short-
+lived entry in `totp_challenges`, which must be redeemed along with a
+current code (or one of the single-use recovery codes, stored hashed in
+`recovery_codes`) before a key is issued.
 */
+use std::collections::HashMap;
+
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
 use blake3::Hasher;
-use rand::{distributions, Rng};
-use tokio_postgres::{types::Type, Client, NoTls, Transaction};
+use rand::{distributions, Rng, RngCore};
+use serde::Serialize;
+use time::Date;
+use once_cell::sync::OnceCell;
+
+use crate::{
+    db::{self, Backend, Client, Transaction},
+    migrate,
+    perm::Permission,
+    pool::PoolConfig,
+    totp,
+};
 
 // Defaults for [`Db`] key generation; new `Db`s will be instantiated with
 // these values, which can be changed later.
@@ -30,35 +157,44 @@ const DEFAULT_KEY_LENGTH: usize = 32;
 const DEFAULT_KEY_CHARS: &str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
 const DEFAULT_KEY_LIFE_SECONDS: u64 = 20 * 60; // 20 minutes
 
-/*
-The method [`Db::ensure_db_schema`] will (attempt to) ensure the backing
-Postgres store contains the necessary tables.
-
-It does this by executing each query in `SCHEMA_TEST`. Any query not returning
-a row indicates that the associated table doesn't exist; it then executes the
-corresponding query in `SCHEMA` to create the appropriate table.
-*/
-static SCHEMA_TEST: &[&str] = &[
-    "SELECT FROM information_schema.tables WHERE table_name = 'users'",
-    "SELECT FROM information_schema.tables WHERE table_name = 'keys'",
-];
-
-static SCHEMA: &[&str] = &[
-    "CREATE TABLE users (
-        uname TEXT PRIMARY KEY,
-        hash  TEXT
-    )",
-    "CREATE TABLE keys (
-        key TEXT,
-        uname TEXT REFERENCES users,
-        last_used TIMESTAMP
-    )",
-];
-
-/// Used to hash passwords with the [`blake3`] algorithm, both when storing
-/// hashed password text in the database and when comparing provided passwords
-/// with stored hashes.
-fn hash_with_salt(pwd: &str, salt: &[u8]) -> String {
+// Defaults for [`LoginThrottle`].
+const DEFAULT_FREE_ATTEMPTS: u32 = 3;
+const DEFAULT_BACKOFF_SECONDS: u64 = 2;
+const DEFAULT_MAX_ATTEMPTS: u32 = 10;
+const DEFAULT_ADDR_MAX_ATTEMPTS: u32 = 100;
+const DEFAULT_LOCKOUT_SECONDS: u64 = 15 * 60; // 15 minutes
+
+/// Every API token starts with this, and no key does.
+pub const API_TOKEN_PREFIX: &str = "camp_";
+const API_TOKEN_LENGTH: usize = 40;
+
+/// How long an invitation to set a password is good for, unless configured
+/// otherwise.
+pub const DEFAULT_INVITE_LIFE_SECONDS: u64 = 7 * 24 * 60 * 60; // one week
+
+// Defaults for [`ResetPolicy`].
+const DEFAULT_RESET_TOKEN_LIFE_SECONDS: u64 = 15 * 60; // 15 minutes
+const DEFAULT_RESET_WINDOW_SECONDS: u64 = 60 * 60; // 1 hour
+const DEFAULT_RESET_MAX_PER_UNAME: u32 = 3;
+const DEFAULT_RESET_MAX_PER_ADDR: u32 = 10;
+
+// Second-factor parameters.
+const TOTP_ISSUER: &str = "CAMP";
+const TOTP_SKEW_STEPS: u64 = 1;
+const TOTP_CHALLENGE_LIFE: &str = "300 seconds"; // 5 minutes
+const N_RECOVERY_CODES: usize = 10;
+const RECOVERY_CODE_LENGTH: usize = 10;
+// No 0/o, 1/l/i to confuse when copying codes off of paper.
+const RECOVERY_CODE_CHARS: &str = "abcdefghjkmnpqrstuvwxyz23456789";
+
+// Number of random bytes in the salt embedded in each Argon2id PHC string.
+const ARGON2_SALT_LENGTH: usize = 16;
+
+/// The hashing scheme used by this system before it switched to Argon2id.
+///
+/// This is only used to verify (and then replace) hashes still stored in
+/// the legacy format; new hashes should never be generated this way.
+fn legacy_hash_with_salt(pwd: &str, salt: &[u8]) -> String {
     let mut hasher = Hasher::new();
     hasher.update(pwd.as_bytes());
     hasher.update(salt);
@@ -66,22 +202,140 @@ fn hash_with_salt(pwd: &str, salt: &[u8]) -> String {
     String::from(hash.to_hex().as_str())
 }
 
+/// Legacy hashes are bare hex strings; anything in PHC format starts with `$`.
+fn is_legacy_hash(stored_hash: &str) -> bool {
+    !stored_hash.starts_with('$')
+}
+
+/// The bytes actually fed to Argon2: the password followed by the user's
+/// salt from the data DB (in addition to the random salt Argon2 embeds in
+/// its output).
+fn password_input(pwd: &str, salt: &str) -> Vec<u8> {
+    let mut input = Vec::with_capacity(pwd.len() + salt.len());
+    input.extend_from_slice(pwd.as_bytes());
+    input.extend_from_slice(salt.as_bytes());
+    input
+}
+
+/// Hash a single password with Argon2id, returning a PHC-format string.
+fn argon2_hash(input: &[u8]) -> Result<String, DbError> {
+    let mut salt_bytes = [0u8; ARGON2_SALT_LENGTH];
+    rand::thread_rng().fill_bytes(&mut salt_bytes);
+    let phc_salt = SaltString::encode_b64(&salt_bytes)
+        .map_err(|e| format!("Error encoding password salt: {}", &e))?;
+    let hash = Argon2::default()
+        .hash_password(input, &phc_salt)
+        .map_err(|e| format!("Error hashing password: {}", &e))?;
+    Ok(hash.to_string())
+}
+
 /**
-Errors returned by this module. Some are passed along from the underlying
-[`tokio_postgres`] crate; some are sui-generis strings.
+Hash the given `(password, salt)` pairs for storage in the database.
+
+Argon2id is deliberately expensive, so the work is shunted off to a
+blocking thread instead of stalling the async runtime.
 */
+async fn hash_passwords(passwords: &[&str], salts: &[&str]) -> Result<Vec<String>, DbError> {
+    let inputs: Vec<Vec<u8>> = std::iter::zip(passwords, salts)
+        .map(|(pwd, salt)| password_input(pwd, salt))
+        .collect();
+
+    tokio::task::spawn_blocking(move || {
+        inputs
+            .iter()
+            .map(|input| argon2_hash(input))
+            .collect::<Result<Vec<String>, DbError>>()
+    })
+    .await
+    .map_err(|e| format!("Password hashing task failed: {}", &e))?
+}
+
+/// Convenience wrapper around `hash_passwords()` to hash just one password.
+async fn hash_password(password: &str, salt: &str) -> Result<String, DbError> {
+    hash_passwords(&[password], &[salt])
+        .await?
+        .pop()
+        .ok_or_else(|| DbError("Password hashing returned no hash.".to_owned()))
+}
+
+/// Keys (and TOTP challenge tokens) are long random strings, so, as with
+/// recovery codes, a fast hash is adequate.
+fn hash_key(key: &str) -> String {
+    String::from(blake3::hash(key.as_bytes()).to_hex().as_str())
+}
+
+/// Generate a fresh recovery code, formatted `xxxxx-xxxxx` for legibility.
+fn generate_recovery_code() -> String {
+    let chars: Vec<char> = RECOVERY_CODE_CHARS.chars().collect();
+    let dist = distributions::Slice::new(&chars).unwrap();
+    let mut code: String = rand::thread_rng()
+        .sample_iter(&dist)
+        .take(RECOVERY_CODE_LENGTH)
+        .collect();
+    code.insert(RECOVERY_CODE_LENGTH / 2, '-');
+    code
+}
+
+/// Recovery codes are random enough that a fast hash is adequate. Dashes,
+/// whitespace, and case are ignored.
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    String::from(blake3::hash(normalized.as_bytes()).to_hex().as_str())
+}
+
+/// Possible outcomes of comparing a supplied password to a stored hash.
 #[derive(Debug, PartialEq)]
-pub struct DbError(String);
+enum PasswordCheck {
+    Match,
+    /// The password matched, but the stored hash is in the legacy format and
+    /// should be replaced.
+    LegacyMatch,
+    NoMatch,
+}
 
-impl From<tokio_postgres::error::Error> for DbError {
-    fn from(e: tokio_postgres::error::Error) -> DbError {
-        use std::fmt::Write;
+/// Compare the supplied `password` and `salt` with the `stored_hash`, which
+/// may be either an Argon2id PHC string or a legacy blake3 hash.
+async fn verify_password(
+    password: &str,
+    salt: &str,
+    stored_hash: String,
+) -> Result<PasswordCheck, DbError> {
+    if is_legacy_hash(&stored_hash) {
+        if legacy_hash_with_salt(password, salt.as_bytes()) == stored_hash {
+            return Ok(PasswordCheck::LegacyMatch);
+        } else {
+            return Ok(PasswordCheck::NoMatch);
+        }
+    }
 
-        let mut s = format!("DB: {}", &e);
-        if let Some(dbe) = e.as_db_error() {
-            write!(&mut s, "; {}", dbe).unwrap();
+    let input = password_input(password, salt);
+    tokio::task::spawn_blocking(move || {
+        let parsed = PasswordHash::new(&stored_hash)
+            .map_err(|e| format!("Unable to parse stored password hash: {}", &e))?;
+        match Argon2::default().verify_password(&input, &parsed) {
+            Ok(()) => Ok(PasswordCheck::Match),
+            Err(argon2::password_hash::Error::Password) => Ok(PasswordCheck::NoMatch),
+            Err(e) => Err(DbError(format!("Error verifying password hash: {}", &e))),
         }
-        DbError(s)
+    })
+    .await
+    .map_err(|e| format!("Password verification task failed: {}", &e))?
+}
+
+/**
+Errors returned by this module. Some are passed along from the underlying
+[`db`] backend; some are sui-generis strings.
+*/
+#[derive(Debug, PartialEq)]
+pub struct DbError(String);
+
+impl From<db::Error> for DbError {
+    fn from(e: db::Error) -> DbError {
+        DbError(format!("DB: {}", &e))
     }
 }
 
@@ -123,17 +377,169 @@ pub enum AuthResult {
     NoSuchUser,
     BadPassword,
     InvalidKey,
+    /// Too many recent failed attempts for this user (or from this client
+    /// address); the password wasn't even checked.
+    Locked,
+    /// The second-factor (TOTP or recovery) code was wrong.
+    BadCode,
+    /// The API token is good, but not for the requested action.
+    OutOfScope,
+    /// The key is good, but was issued to the Admin with the given uname
+    /// for viewing as this user, so it's only good for read-only requests.
+    ReadOnly(String),
+}
+
+/**
+Identifies where a login attempt (and so any key issued as a result) came
+from, so a user's active sessions can be told apart.
+*/
+#[derive(Clone, Debug, Default)]
+pub struct ClientInfo {
+    pub addr: String,
+    pub user_agent: String,
+}
+
+/// An active session (that is, an unexpired key), as reported by
+/// [`Db::list_sessions`]. The key itself is never revealed.
+#[derive(Debug, Serialize)]
+pub struct Session {
+    pub id: i64,
+    /// `None` for keys issued before this was recorded.
+    pub issued: Option<String>,
+    pub last_used: String,
+    pub addr: Option<String>,
+    pub user_agent: Option<String>,
+    /// uname of the Admin viewing as this user, if this is such a session
+    pub impersonator: Option<String>,
+}
+
+/// The state of a user's invitation, as reported by [`Db::invite_statuses`].
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InviteStatus {
+    Pending,
+    Accepted,
+    Expired,
+}
+
+/// An API token, as reported by [`Db::list_api_tokens`]. The token itself
+/// is never revealed after it's issued.
+#[derive(Debug, Serialize)]
+pub struct ApiToken {
+    pub id: i64,
+    pub uname: String,
+    pub name: String,
+    pub actions: Vec<String>,
+    pub created: String,
+    pub expires: Option<String>,
+    pub last_used: Option<String>,
+}
+
+/**
+Everything a user needs to set up an authenticator app, returned by
+[`Db::begin_totp_enrollment`].
+
+The recovery codes are only stored hashed, so this is the only time they
+can be shown.
+*/
+#[derive(Debug, Serialize)]
+pub struct TotpEnrollment {
+    pub secret: String,
+    pub uri: String,
+    pub recovery_codes: Vec<String>,
+}
+
+/**
+Parameters governing how failed password attempts are throttled.
+
+Every failed attempt is recorded. Once a user has more than
+`free_attempts` failures within the last `lockout_seconds`, each further
+attempt must wait `backoff_seconds`, doubling with each additional failure.
+At `max_attempts` failures the account is locked until the failures age out
+of the window (or an Admin unlocks it). Separately, a client address with
+`addr_max_attempts` failures in the window (across any unames) is locked out.
+
+A successful login clears that user's failures.
+*/
+#[derive(Clone, Debug)]
+pub struct LoginThrottle {
+    pub free_attempts: u32,
+    pub backoff_seconds: u64,
+    pub max_attempts: u32,
+    pub addr_max_attempts: u32,
+    pub lockout_seconds: u64,
+}
+
+impl Default for LoginThrottle {
+    fn default() -> Self {
+        Self {
+            free_attempts: DEFAULT_FREE_ATTEMPTS,
+            backoff_seconds: DEFAULT_BACKOFF_SECONDS,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            addr_max_attempts: DEFAULT_ADDR_MAX_ATTEMPTS,
+            lockout_seconds: DEFAULT_LOCKOUT_SECONDS,
+        }
+    }
+}
+
+impl LoginThrottle {
+    /**
+    Determine whether another attempt should be allowed, given the number of
+    recent failures for the uname (and the time in seconds since the most
+    recent of them) and the number of recent failures from the address.
+    */
+    pub(crate) fn is_locked(&self, n_uname: i64, since_last: Option<f64>, n_addr: i64) -> bool {
+        if n_uname >= self.max_attempts as i64 || n_addr >= self.addr_max_attempts as i64 {
+            return true;
+        }
+        if n_uname < self.free_attempts as i64 {
+            return false;
+        }
+
+        let n_extra = (n_uname - self.free_attempts as i64) as u32;
+        let wait = self
+            .backoff_seconds
+            .saturating_mul(2u64.saturating_pow(n_extra))
+            .min(self.lockout_seconds);
+        match since_last {
+            Some(secs) => secs < wait as f64,
+            None => false,
+        }
+    }
+}
+
+/**
+Parameters governing password reset tokens.
+
+A token is good for `token_life_seconds` after it's issued, and only once.
+No more than `max_per_uname` tokens will be issued for any one user, nor
+`max_per_addr` in response to requests from any one client address, within
+any `window_seconds`.
+*/
+#[derive(Clone, Debug)]
+pub struct ResetPolicy {
+    pub token_life_seconds: u64,
+    pub window_seconds: u64,
+    pub max_per_uname: u32,
+    pub max_per_addr: u32,
+}
+
+impl Default for ResetPolicy {
+    fn default() -> Self {
+        Self {
+            token_life_seconds: DEFAULT_RESET_TOKEN_LIFE_SECONDS,
+            window_seconds: DEFAULT_RESET_WINDOW_SECONDS,
+            max_per_uname: DEFAULT_RESET_MAX_PER_UNAME,
+            max_per_addr: DEFAULT_RESET_MAX_PER_ADDR,
+        }
+    }
 }
 
 /**
 The endpoint for interacting with the underlying Postgres store.
 
-As it stands, each "interaction" with the database will open (and when
-finished close) a new connection. This is generally regarded as less
-performant and involves more out-of-band data exchange than reusing
-connections or using connection pools, but it is _way simpler_ to implement,
-so unless and until there's a burning need to change it, it stays
-inefficient but simple.
+Each "interaction" with the database borrows a connection from a pool
+(see [`crate::pool`]), which it returns when finished.
 
 Some methods take as one of their arguments an [`&Transaction`](Transaction). These are
 meant to be used in operations that may require multiple queries or
@@ -146,9 +552,14 @@ caller holds on to the [`Transaction`] in use.
 */
 pub struct Db {
     connection_string: String,
+    pool_config: PoolConfig,
+    backend: OnceCell<Box<dyn Backend>>,
     key_chars: Vec<char>,
     key_length: usize,
     key_life: String,
+    invite_life: String,
+    throttle: LoginThrottle,
+    reset_policy: ResetPolicy,
 }
 
 impl Db {
@@ -158,12 +569,18 @@ impl Db {
         let key_chars: Vec<char> = DEFAULT_KEY_CHARS.chars().collect();
         let key_length = DEFAULT_KEY_LENGTH;
         let key_life = format!("{} seconds", &DEFAULT_KEY_LIFE_SECONDS);
+        let invite_life = format!("{} seconds", &DEFAULT_INVITE_LIFE_SECONDS);
 
         Self {
             connection_string,
+            pool_config: PoolConfig::default(),
+            backend: OnceCell::new(),
             key_chars,
             key_length,
             key_life,
+            invite_life,
+            throttle: LoginThrottle::default(),
+            reset_policy: ResetPolicy::default(),
         }
     }
 
@@ -179,6 +596,22 @@ impl Db {
     pub fn set_key_life(&mut self, seconds: u64) {
         self.key_life = format!("{} seconds", &seconds);
     }
+    pub fn set_invite_life(&mut self, seconds: u64) {
+        self.invite_life = format!("{} seconds", &seconds);
+    }
+    pub fn set_login_throttle(&mut self, throttle: LoginThrottle) {
+        self.throttle = throttle;
+    }
+    pub fn set_reset_policy(&mut self, policy: ResetPolicy) {
+        self.reset_policy = policy;
+    }
+    pub fn reset_policy(&self) -> &ResetPolicy {
+        &self.reset_policy
+    }
+    /// Has no effect once a connection has been made.
+    pub fn set_pool_config(&mut self, cfg: PoolConfig) {
+        self.pool_config = cfg;
+    }
 
     /// Generate a new authentication key based on the current values of
     /// `self.key_chars` and `self.key_length`.
@@ -191,7 +624,8 @@ impl Db {
     }
 
     /**
-    Open and return a connection to this database.
+    Return a connection to this database (see [`crate::db`]); with Postgres,
+    it's pooled, and goes back into the pool when dropped.
 
     This is necessary for methods that require an
     [`&Transaction`](Transaction) as an argument.
@@ -202,16 +636,14 @@ impl Db {
             &self.connection_string
         );
 
-        match tokio_postgres::connect(&self.connection_string, NoTls).await {
-            Ok((client, connection)) => {
+        let backend = self
+            .backend
+            .get_or_try_init(|| db::open(&self.connection_string, &self.pool_config))
+            .map_err(|e| format!("Connection error: {}", &e))?;
+
+        match backend.connect().await {
+            Ok(client) => {
                 log::trace!("    ...connection successful.");
-                tokio::spawn(async move {
-                    if let Err(e) = connection.await {
-                        log::error!("Auth DB connection error: {}", &e);
-                    } else {
-                        log::trace!("tokio connection runtime drops.");
-                    }
-                });
                 Ok(client)
             }
             Err(e) => {
@@ -222,26 +654,109 @@ impl Db {
     }
 
     /**
-    Ensure that the database has all the tables required for use.
+    Start listening for notifications on `channel` (see [`db::Listener`]),
+    on a connection of its own.
+
+    Returns `Ok(None)` if the database isn't Postgres, which is the only
+    backend with notifications.
+    */
+    pub async fn listen(&self, channel: &str) -> Result<Option<db::Listener>, DbError> {
+        log::trace!("Db::listen( {:?} ) called.", channel);
+
+        db::listen(&self.connection_string, &self.pool_config, channel)
+            .await
+            .map_err(|e| format!("Unable to listen: {}", &e).into())
+    }
+
+    /**
+    Ensure that the database has all the tables required for use, by
+    applying any pending [migrations](crate::migrate).
     */
     pub async fn ensure_db_schema(&self) -> Result<(), DbError> {
         log::trace!("Db::ensure_db_schema() called.");
+
+        self.migrate(false).await.map(|_| ())
+    }
+
+    /**
+    Apply any pending [migrations](crate::migrate) to the auth DB (and hash
+    any keys still stored in the clear), in a single transaction, returning
+    the versions applied.
+
+    If `dry_run` is `true`, the transaction is rolled back instead of
+    committed.
+    */
+    pub async fn migrate(&self, dry_run: bool) -> Result<Vec<i32>, DbError> {
+        log::trace!("Db::migrate( {:?} ) called.", &dry_run);
+
         let mut client = self.connect().await?;
         let t = client
             .transaction()
             .await
             .map_err(|e| format!("Auth DB unable to begin transaction: {}", &e))?;
-        for (test_stmt, create_stmt) in std::iter::zip(SCHEMA_TEST, SCHEMA) {
-            if t.query_opt(test_stmt.to_owned(), &[]).await?.is_none() {
-                log::info!("{:?} returned no results.", &test_stmt);
-                log::info!("Attempting to insert table.");
-                t.execute(create_stmt.to_owned(), &[]).await?;
-            }
+
+        let applied = migrate::run(&t, migrate::auth_migrations(t.dialect()))
+            .await
+            .map_err(|e| format!("Auth DB: {}", &e))?;
+        self.migrate_plaintext_keys(&t).await?;
+
+        if dry_run {
+            t.rollback()
+                .await
+                .map_err(|e| format!("Error rolling back transaction: {}", &e))?;
+        } else {
+            t.commit()
+                .await
+                .map_err(|e| format!("Error committing transaction: {}", &e))?;
         }
 
-        t.commit()
+        Ok(applied)
+    }
+
+    /// Report the state of each of the auth DB's [migrations](crate::migrate).
+    pub async fn migration_status(&self) -> Result<Vec<migrate::Status>, DbError> {
+        log::trace!("Db::migration_status() called.");
+
+        let mut client = self.connect().await?;
+        let t = client
+            .transaction()
+            .await
+            .map_err(|e| format!("Auth DB unable to begin transaction: {}", &e))?;
+
+        migrate::status(&t, migrate::auth_migrations(t.dialect()))
             .await
-            .map_err(|e| format!("Error committing transaction: {}", &e).into())
+            .map_err(|e| format!("Auth DB: {}", &e).into())
+    }
+
+    /**
+    Replace any keys stored in the clear (in the legacy `keys.key` column)
+    with their hashes, then drop that column.
+
+    Only Postgres databases old enough to have stored keys in the clear
+    have this column.
+    */
+    async fn migrate_plaintext_keys(&self, t: &Transaction<'_>) -> Result<(), DbError> {
+        if !t.column_exists("keys", "key").await? {
+            return Ok(());
+        }
+
+        log::info!("Hashing plaintext keys.");
+        let rows = t
+            .query("SELECT id, key FROM keys WHERE key IS NOT NULL", &[])
+            .await?;
+        for row in rows.iter() {
+            let id: i64 = row.try_get("id")?;
+            let key: String = row.try_get("key")?;
+            t.execute(
+                "UPDATE keys SET key_hash = $1 WHERE id = $2",
+                &[&hash_key(&key), &id],
+            )
+            .await?;
+        }
+        t.execute("ALTER TABLE keys DROP COLUMN key", &[]).await?;
+        log::info!("Hashed {} plaintext keys.", rows.len());
+
+        Ok(())
     }
 
     /**
@@ -285,16 +800,12 @@ impl Db {
 
         let owned_unames: Vec<String> = unames.iter().map(|s| String::from(*s)).collect();
 
-        let hashes: Vec<String> = std::iter::zip(passwords, salts)
-            .map(|(pwd, salt)| hash_with_salt(pwd, salt.as_bytes()))
-            .collect();
+        let hashes = hash_passwords(passwords, salts).await?;
 
-        let preexisting_user_query = t
-            .prepare_typed(
-                "SELECT uname FROM users WHERE uname = ANY($1)",
-                &[Type::TEXT_ARRAY],
-            )
-            .await?;
+        let preexisting_user_query = format!(
+            "SELECT uname FROM users WHERE {}",
+            t.dialect().in_array("uname", "$1")
+        );
 
         let preexisting_user_rows = t
             .query(&preexisting_user_query, &[&owned_unames])
@@ -312,17 +823,11 @@ impl Db {
             return Err(DbError(estr));
         }
 
-        let s_add_user = t
-            .prepare_typed(
-                "INSERT INTO users (uname, hash) VALUES ($1, $2)",
-                &[Type::TEXT, Type::TEXT],
-            )
-            .await
-            .map_err(|e| format!("Unable to prepare statement to insert new users: {}", &e))?;
+        let s_add_user = "INSERT INTO users (uname, hash) VALUES ($1, $2)";
 
         let mut n_inserted: u64 = 0;
         for (uname, hash) in std::iter::zip(unames, hashes) {
-            match t.execute(&s_add_user, &[&uname, &hash]).await {
+            match t.execute(s_add_user, &[&uname, &hash]).await {
                 Ok(n) => {
                     n_inserted += n;
                 }
@@ -369,406 +874,2347 @@ impl Db {
         log::trace!("Db::delete_users( {:?} ) called", &unames);
 
         let owned_unames: Vec<String> = unames.iter().map(|s| String::from(*s)).collect();
+        let in_unames = t.dialect().in_array("uname", "$1");
 
         let n_keys = t
-            .execute("DELETE FROM keys WHERE uname = ANY($1)", &[&owned_unames])
+            .execute(
+                &format!("DELETE FROM keys WHERE {}", &in_unames),
+                &[&owned_unames],
+            )
             .await?;
         log::trace!("Deleted {} keys.", &n_keys);
 
+        t.execute(
+            &format!("DELETE FROM failed_logins WHERE {}", &in_unames),
+            &[&owned_unames],
+        )
+        .await?;
+        for table in [
+            "totp",
+            "recovery_codes",
+            "totp_challenges",
+            "reset_tokens",
+            "invites",
+            "api_tokens",
+            "grants",
+        ] {
+            t.execute(
+                format!("DELETE FROM {} WHERE {}", table, &in_unames).as_str(),
+                &[&owned_unames],
+            )
+            .await?;
+        }
+
         let n_users = t
-            .execute("DELETE FROM users WHERE uname = ANY($1)", &[&owned_unames])
+            .execute(
+                &format!("DELETE FROM users WHERE {}", &in_unames),
+                &[&owned_unames],
+            )
             .await?;
         log::trace!("Deleted {} users.", &n_users);
 
         Ok(n_users)
     }
 
-    pub async fn check_password(
+    /**
+    Compare the supplied `password` against the hash stored for `uname`.
+
+    Returns `AuthResult::Ok`, `AuthResult::BadPassword`, or
+    `AuthResult::NoSuchUser`. If the stored hash is in the legacy format and
+    the password matches, it is replaced with an Argon2id hash; failure to
+    do so is logged, but doesn't prevent the user from authenticating.
+    */
+    async fn verify_stored_password(
         &self,
+        client: &Client,
         uname: &str,
         password: &str,
         salt: &str,
     ) -> Result<AuthResult, DbError> {
-        log::trace!(
-            "Db::check_password( {:?}, {:?}, {:?} ) called.",
-            uname,
-            password,
-            salt
-        );
-
-        let current_hash = hash_with_salt(password, salt.as_bytes());
-
-        let client = self.connect().await?;
-
-        match client
+        let stored_hash: String = match client
             .query_opt("SELECT hash FROM users WHERE uname = $1", &[&uname])
             .await
         {
             Err(e) => {
                 let estr = format!("Error querying user {:?}: {}", uname, &e);
                 log::error!("{}", &estr);
-                Err(DbError(estr))
+                return Err(DbError(estr));
             }
             Ok(None) => {
                 log::trace!("User {:?} doesn't exist.", uname);
-                Ok(AuthResult::NoSuchUser)
+                return Ok(AuthResult::NoSuchUser);
             }
-            Ok(Some(row)) => {
-                let stored_hash: String = row.get("hash");
-                if stored_hash == current_hash {
-                    Ok(AuthResult::Ok)
-                } else {
-                    Ok(AuthResult::BadPassword)
+            Ok(Some(row)) => row.get("hash"),
+        };
+
+        match verify_password(password, salt, stored_hash).await? {
+            PasswordCheck::Match => Ok(AuthResult::Ok),
+            PasswordCheck::NoMatch => Ok(AuthResult::BadPassword),
+            PasswordCheck::LegacyMatch => {
+                log::info!("Upgrading legacy password hash for {:?}.", uname);
+                let new_hash = hash_password(password, salt).await?;
+                if let Err(e) = client
+                    .execute(
+                        "UPDATE users SET hash = $1 WHERE uname = $2",
+                        &[&new_hash, &uname],
+                    )
+                    .await
+                {
+                    log::error!(
+                        "Error replacing legacy password hash for {:?}: {}",
+                        uname,
+                        &e
+                    );
                 }
+                Ok(AuthResult::Ok)
             }
         }
     }
 
-    /// Issue a key without checking whether a password is valid first.
-    ///
-    /// This is so an authentication token (that is, a key) can be sent via
-    /// email in order to facilitate password reset.
-    pub async fn issue_key(&self, uname: &str) -> Result<AuthResult, DbError> {
-        log::trace!("Db::issue_key( {:?} ) called.", uname);
-
-        let key = self.generate_key();
-        let client = self.connect().await?;
-        if let Err(e) = client
-            .execute(
-                "INSERT INTO keys (uname, key, last_used)
-            VALUES ($1, $2, CURRENT_TIMESTAMP)",
-                &[&uname, &key],
+    /**
+    Determine whether password attempts for `uname` from `addr` are
+    currently locked out by too many recent failures.
+    */
+    async fn is_locked(
+        &self,
+        client: &Transaction<'_>,
+        uname: &str,
+        addr: &str,
+    ) -> Result<bool, DbError> {
+        let lockout = format!("{} seconds", &self.throttle.lockout_seconds);
+        let since_last = client
+            .dialect()
+            .seconds_since("MAX(time) FILTER (WHERE uname = $1)");
+        let row = client
+            .query_one(
+                &format!(
+                    "SELECT
+                        COUNT(*) FILTER (WHERE uname = $1) AS n_uname,
+                        {} AS since_last,
+                        COUNT(*) FILTER (WHERE addr = $2) AS n_addr
+                    FROM failed_logins
+                    WHERE {} > CURRENT_TIMESTAMP",
+                    since_last,
+                    client.dialect().add_interval("time", "$3")
+                ),
+                &[&uname, &addr, &lockout],
             )
-            .await
-        {
-            return Err(e.into());
+            .await?;
+
+        let n_uname: i64 = row.try_get("n_uname")?;
+        let since_last: Option<f64> = row.try_get("since_last")?;
+        let n_addr: i64 = row.try_get("n_addr")?;
+
+        Ok(self.throttle.is_locked(n_uname, since_last, n_addr))
+    }
+
+    /**
+    Count an attempt (at a password or a second-factor code) for `uname`
+    from `addr` against the login throttle, returning `false` (and counting
+    nothing) if there have already been too many recent failures.
+
+    The attempt is recorded as a failure up front, in the same transaction
+    as the check, with `failed_logins` locked in between, so simultaneous
+    attempts can't all pass the check before any of them is counted. One
+    that succeeds clears it again (along with `uname`'s other failures).
+    */
+    async fn begin_attempt(
+        &self,
+        client: &mut Client,
+        uname: &str,
+        addr: &str,
+    ) -> Result<bool, DbError> {
+        let t = client.transaction().await?;
+        t.lock_table("failed_logins").await?;
+
+        if self.is_locked(&t, uname, addr).await? {
+            t.rollback().await?;
+            return Ok(false);
         }
+        self.insert_failed_login(&t, uname, addr).await?;
+        t.commit().await?;
 
-        Ok(AuthResult::Key(key))
+        Ok(true)
     }
 
     /**
-    Check whether the provided `(uname, password, salt)` combination is valid,
-    and issue a new key on success.
+    Check the supplied password subject to the login throttle, recording the
+    attempt.
+
+    Returns `AuthResult::Locked` without checking the password at all if
+    there have been too many recent failures.
     */
-    pub async fn check_password_and_issue_key(
+    async fn throttled_verify(
         &self,
+        client: &mut Client,
         uname: &str,
         password: &str,
         salt: &str,
+        addr: &str,
     ) -> Result<AuthResult, DbError> {
-        log::trace!(
-            "Db::check_password_and_issue_key( {:?}, {:?}, {:?} ) called.",
-            uname,
-            password,
-            salt
-        );
-
-        let current_hash = hash_with_salt(password, salt.as_bytes());
-
-        let client = self.connect().await?;
-
-        match client
-            .query_opt("SELECT hash FROM users WHERE uname = $1", &[&uname])
-            .await
-        {
-            Err(e) => {
-                let estr = format!("Error querying user {:?}: {}", uname, &e);
-                log::error!("{}", &estr);
-                return Err(DbError(estr));
-            }
-            Ok(None) => {
-                log::trace!("User {:?} doesn't exist.", uname);
-                return Ok(AuthResult::NoSuchUser);
-            }
-            Ok(Some(row)) => {
-                let stored_hash: String = row.get("hash");
-                if stored_hash != current_hash {
-                    return Ok(AuthResult::BadPassword);
-                }
-            }
+        if !self.begin_attempt(client, uname, addr).await? {
+            log::warn!(
+                "Login attempt for {:?} from {:?} refused: too many failures.",
+                uname,
+                addr
+            );
+            return Ok(AuthResult::Locked);
         }
 
-        let key = self.generate_key();
-        if let Err(e) = client
-            .execute(
-                "INSERT INTO keys (uname, key, last_used)
-            VALUES ($1, $2, CURRENT_TIMESTAMP)",
-                &[&uname, &key],
-            )
-            .await
-        {
-            return Err(e.into());
+        let res = self
+            .verify_stored_password(client, uname, password, salt)
+            .await?;
+        if let AuthResult::Ok = res {
+            client
+                .execute("DELETE FROM failed_logins WHERE uname = $1", &[&uname])
+                .await?;
         }
 
-        log::trace!("Returning new key: {:?}", &key);
-        Ok(AuthResult::Key(key))
+        Ok(res)
+    }
+
+    async fn insert_failed_login(
+        &self,
+        t: &Transaction<'_>,
+        uname: &str,
+        addr: &str,
+    ) -> Result<(), DbError> {
+        t.execute(
+            "INSERT INTO failed_logins (uname, addr, time)
+            VALUES ($1, $2, CURRENT_TIMESTAMP)",
+            &[&uname, &addr],
+        )
+        .await?;
+        Ok(())
     }
 
     /**
-    Checks to see if the provided `key` was issued to the provided `uname`
-    and is still valid.
+    Record a failed login attempt that didn't get as far as checking a
+    password (for example, because `uname` doesn't belong to any user).
 
-    Also updates the key's `last_used` time to the current time on success.
+    This still counts against the client address.
     */
-    pub async fn check_key(&self, uname: &str, key: &str) -> Result<AuthResult, DbError> {
-        log::trace!("Db::check_key( {:?}, {:?} ) called.", uname, key);
+    pub async fn record_failed_login(&self, uname: &str, addr: &str) -> Result<(), DbError> {
+        log::trace!("Db::record_failed_login( {:?}, {:?} ) called.", uname, addr);
 
-        let client = self.connect().await?;
-        let key = match client
-            .query_opt(
-                "SELECT key FROM keys
-                WHERE uname = $1
-                AND key = $2
-                AND last_used + ($3 || ' ')::INTERVAL > now()",
-                &[&uname, &key, &self.key_life],
+        let mut client = self.connect().await?;
+        let t = client.transaction().await?;
+        self.insert_failed_login(&t, uname, addr).await?;
+        t.commit().await?;
+        Ok(())
+    }
+
+    /// Clear all recorded failed login attempts for `uname`, lifting any
+    /// lockout. Returns the number of failures cleared.
+    pub async fn unlock_user(&self, uname: &str) -> Result<u64, DbError> {
+        log::trace!("Db::unlock_user( {:?} ) called.", uname);
+
+        let client = self.connect().await?;
+        let n = client
+            .execute("DELETE FROM failed_logins WHERE uname = $1", &[&uname])
+            .await?;
+        log::trace!("Cleared {} failed logins.", &n);
+
+        Ok(n)
+    }
+
+    /// Delete any failed login records too old to count against anyone.
+    pub async fn cull_old_login_failures(&self) -> Result<usize, DbError> {
+        log::trace!("Db::cull_old_login_failures() called.");
+
+        let lockout = format!("{} seconds", &self.throttle.lockout_seconds);
+        let client = self.connect().await?;
+        let n_culled = client
+            .execute(
+                &format!(
+                    "DELETE FROM failed_logins
+                    WHERE {} < CURRENT_TIMESTAMP",
+                    client.dialect().add_interval("time", "$1")
+                ),
+                &[&lockout],
+            )
+            .await?;
+        log::trace!("Deleted {} failed logins.", &n_culled);
+
+        Ok(n_culled as usize)
+    }
+
+    /**
+    Check the provided password without checking (or affecting) the login
+    throttle.
+
+    This is meant for internal checks (like ensuring the existence of the
+    default Admin); login attempts from users should go through
+    [`Db::check_login`] or [`Db::check_password_and_issue_key`].
+    */
+    pub async fn check_password(
+        &self,
+        uname: &str,
+        password: &str,
+        salt: &str,
+    ) -> Result<AuthResult, DbError> {
+        log::trace!(
+            "Db::check_password( {:?}, {:?}, {:?} ) called.",
+            uname,
+            password,
+            salt
+        );
+
+        let client = self.connect().await?;
+        self.verify_stored_password(&client, uname, password, salt)
+            .await
+    }
+
+    /**
+    Check whether the provided `(uname, password, salt)` combination is
+    valid for a login attempt from client address `addr`, without issuing a
+    key.
+
+    Subject to the login throttle; may return `AuthResult::Locked`.
+    */
+    pub async fn check_login(
+        &self,
+        uname: &str,
+        password: &str,
+        salt: &str,
+        addr: &str,
+    ) -> Result<AuthResult, DbError> {
+        log::trace!(
+            "Db::check_login( {:?}, {:?}, {:?}, {:?} ) called.",
+            uname,
+            password,
+            salt,
+            addr
+        );
+
+        let mut client = self.connect().await?;
+        self.throttled_verify(&mut client, uname, password, salt, addr)
+            .await
+    }
+
+    /// Generate and store a new key for `uname`, recording where the request
+    /// for it came from.
+    async fn insert_key(
+        &self,
+        client: &Client,
+        uname: &str,
+        info: &ClientInfo,
+    ) -> Result<String, DbError> {
+        let key = self.generate_key();
+        client
+            .execute(
+                "INSERT INTO keys (uname, key_hash, issued, last_used, addr, user_agent)
+                VALUES ($1, $2, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP, $3, $4)",
+                &[&uname, &hash_key(&key), &info.addr, &info.user_agent],
+            )
+            .await?;
+
+        Ok(key)
+    }
+
+    /// Issue a key without checking whether a password is valid first.
+    ///
+    /// This is so an authentication token (that is, a key) can be sent via
+    /// email in order to facilitate password reset, and so a key can be
+    /// issued after a login has passed checks beyond the password.
+    pub async fn issue_key(&self, uname: &str, info: &ClientInfo) -> Result<AuthResult, DbError> {
+        log::trace!("Db::issue_key( {:?}, {:?} ) called.", uname, info);
+
+        let client = self.connect().await?;
+        let key = self.insert_key(&client, uname, info).await?;
+
+        Ok(AuthResult::Key(key))
+    }
+
+    /**
+    Issue a key for `uname` to the Admin `impersonator` (at the client
+    described by `info`), so they can see what `uname` sees.
+
+    [`Db::check_key`] will report the key as `AuthResult::ReadOnly`.
+    */
+    pub async fn issue_impersonation_key(
+        &self,
+        uname: &str,
+        impersonator: &str,
+        info: &ClientInfo,
+    ) -> Result<AuthResult, DbError> {
+        log::trace!(
+            "Db::issue_impersonation_key( {:?}, {:?}, {:?} ) called.",
+            uname,
+            impersonator,
+            info
+        );
+
+        let client = self.connect().await?;
+        let key = self.generate_key();
+        client
+            .execute(
+                "INSERT INTO keys (uname, key_hash, issued, last_used, addr, user_agent, impersonator)
+                VALUES ($1, $2, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP, $3, $4, $5)",
+                &[&uname, &hash_key(&key), &info.addr, &info.user_agent, &impersonator],
+            )
+            .await?;
+
+        Ok(AuthResult::Key(key))
+    }
+
+    /**
+    Check whether the provided `(uname, password, salt)` combination is valid
+    for a login attempt from the client described by `info`, and issue a
+    new key on success.
+
+    Subject to the login throttle; may return `AuthResult::Locked`.
+    */
+    pub async fn check_password_and_issue_key(
+        &self,
+        uname: &str,
+        password: &str,
+        salt: &str,
+        info: &ClientInfo,
+    ) -> Result<AuthResult, DbError> {
+        log::trace!(
+            "Db::check_password_and_issue_key( {:?}, {:?}, {:?}, {:?} ) called.",
+            uname,
+            password,
+            salt,
+            info
+        );
+
+        let mut client = self.connect().await?;
+
+        match self
+            .throttled_verify(&mut client, uname, password, salt, &info.addr)
+            .await?
+        {
+            AuthResult::Ok => { /* Proceed to issue a key. */ }
+            x => {
+                return Ok(x);
+            }
+        }
+
+        let key = self.insert_key(&client, uname, info).await?;
+
+        log::trace!("Returning new key for {:?}.", uname);
+        Ok(AuthResult::Key(key))
+    }
+
+    /**
+    Checks to see if the provided `key` was issued to the provided `uname`
+    and is still valid.
+
+    Also updates the key's `last_used` time to the current time on success.
+
+    Keys issued by [`Db::issue_impersonation_key`] yield
+    `AuthResult::ReadOnly` instead of `AuthResult::Ok`.
+    */
+    pub async fn check_key(&self, uname: &str, key: &str) -> Result<AuthResult, DbError> {
+        log::trace!("Db::check_key( {:?}, [ key ] ) called.", uname);
+
+        let client = self.connect().await?;
+        let rows = client
+            .query(
+                &format!(
+                    "UPDATE keys SET last_used = CURRENT_TIMESTAMP
+                    WHERE uname = $1
+                    AND key_hash = $2
+                    AND {} > CURRENT_TIMESTAMP
+                    RETURNING impersonator",
+                    client.dialect().add_interval("last_used", "$3")
+                ),
+                &[&uname, &hash_key(key), &self.key_life],
+            )
+            .await?;
+
+        match rows.first() {
+            None => Ok(AuthResult::InvalidKey),
+            Some(row) => match row.try_get::<_, Option<String>>("impersonator")? {
+                None => Ok(AuthResult::Ok),
+                Some(admin) => Ok(AuthResult::ReadOnly(admin)),
+            },
+        }
+    }
+
+    /// Delete any keys that have been unused for longer than `self.key_life`
+    /// (and any second-factor challenges that have expired).
+    pub async fn cull_old_keys(&self) -> Result<usize, DbError> {
+        log::trace!("Db::cull_old_keys() called.");
+
+        let client = self.connect().await?;
+        let n_culled = client
+            .execute(
+                &format!(
+                    "DELETE FROM keys
+                    WHERE {} < CURRENT_TIMESTAMP",
+                    client.dialect().add_interval("last_used", "$1")
+                ),
+                &[&self.key_life],
+            )
+            .await?;
+        log::trace!("Deleted {} keys.", &n_culled);
+
+        let n_challenges = client
+            .execute(
+                &format!(
+                    "DELETE FROM totp_challenges
+                    WHERE {} < CURRENT_TIMESTAMP",
+                    client.dialect().add_interval("issued", "$1")
+                ),
+                &[&TOTP_CHALLENGE_LIFE],
+            )
+            .await?;
+        log::trace!("Deleted {} TOTP challenges.", &n_challenges);
+
+        Ok(n_culled as usize)
+    }
+
+    /// List `uname`'s active (unexpired) sessions, most recently used first.
+    pub async fn list_sessions(&self, uname: &str) -> Result<Vec<Session>, DbError> {
+        log::trace!("Db::list_sessions( {:?} ) called.", uname);
+
+        let client = self.connect().await?;
+        let rows = client
+            .query(
+                &format!(
+                    "SELECT id,
+                        {} AS issued,
+                        {} AS last_used,
+                        addr, user_agent, impersonator
+                    FROM keys
+                    WHERE uname = $1
+                    AND {} > CURRENT_TIMESTAMP
+                    ORDER BY last_used DESC",
+                    client.dialect().timestamp_text("issued"),
+                    client.dialect().timestamp_text("last_used"),
+                    client.dialect().add_interval("last_used", "$2")
+                ),
+                &[&uname, &self.key_life],
+            )
+            .await?;
+
+        let mut sessions: Vec<Session> = Vec::with_capacity(rows.len());
+        for row in rows.iter() {
+            sessions.push(Session {
+                id: row.try_get("id")?,
+                issued: row.try_get("issued")?,
+                last_used: row.try_get("last_used")?,
+                addr: row.try_get("addr")?,
+                user_agent: row.try_get("user_agent")?,
+                impersonator: row.try_get("impersonator")?,
+            });
+        }
+
+        Ok(sessions)
+    }
+
+    /// Revoke the single session (key) of `uname` with the given `id`.
+    /// Returns whether there was such a session.
+    pub async fn revoke_session(&self, uname: &str, id: i64) -> Result<bool, DbError> {
+        log::trace!("Db::revoke_session( {:?}, {} ) called.", uname, &id);
+
+        let client = self.connect().await?;
+        let n = client
+            .execute(
+                "DELETE FROM keys WHERE uname = $1 AND id = $2",
+                &[&uname, &id],
+            )
+            .await?;
+
+        Ok(n > 0)
+    }
+
+    /// Revoke all of `uname`'s sessions (keys), logging them out everywhere.
+    /// Returns the number of keys revoked.
+    pub async fn revoke_all_sessions(&self, uname: &str) -> Result<u64, DbError> {
+        log::trace!("Db::revoke_all_sessions( {:?} ) called.", uname);
+
+        let client = self.connect().await?;
+        let n = client
+            .execute("DELETE FROM keys WHERE uname = $1", &[&uname])
+            .await?;
+        log::trace!("Revoked {} keys.", &n);
+
+        Ok(n)
+    }
+
+    /// Whether `uname` has a confirmed TOTP enrollment.
+    pub async fn totp_enabled(&self, uname: &str) -> Result<bool, DbError> {
+        log::trace!("Db::totp_enabled( {:?} ) called.", uname);
+
+        let client = self.connect().await?;
+        let row = client
+            .query_opt(
+                "SELECT 1 FROM totp WHERE uname = $1 AND confirmed",
+                &[&uname],
+            )
+            .await?;
+        Ok(row.is_some())
+    }
+
+    /**
+    Generate a new TOTP secret and set of recovery codes for `uname`.
+
+    The enrollment doesn't take effect until a code generated from the new
+    secret is presented (either to [`Db::confirm_totp`] or as part of a
+    login through [`Db::check_totp_and_issue_key`]). Any previous
+    unconfirmed enrollment is replaced; a confirmed one must first be
+    removed with [`Db::disable_totp`].
+    */
+    pub async fn begin_totp_enrollment(&self, uname: &str) -> Result<TotpEnrollment, DbError> {
+        log::trace!("Db::begin_totp_enrollment( {:?} ) called.", uname);
+
+        let secret = totp::generate_secret();
+        let recovery_codes: Vec<String> = (0..N_RECOVERY_CODES)
+            .map(|_| generate_recovery_code())
+            .collect();
+        let hashes: Vec<String> = recovery_codes
+            .iter()
+            .map(|c| hash_recovery_code(c))
+            .collect();
+
+        let mut client = self.connect().await?;
+        let t = client.transaction().await?;
+
+        if t.query_opt(
+            "SELECT 1 FROM totp WHERE uname = $1 AND confirmed",
+            &[&uname],
+        )
+        .await?
+        .is_some()
+        {
+            return Err(DbError(format!(
+                "{:?} already has two-factor authentication enabled.",
+                uname
+            )));
+        }
+
+        t.execute("DELETE FROM totp WHERE uname = $1", &[&uname])
+            .await?;
+        t.execute("DELETE FROM recovery_codes WHERE uname = $1", &[&uname])
+            .await?;
+        t.execute(
+            "INSERT INTO totp (uname, secret, confirmed, last_step)
+            VALUES ($1, $2, FALSE, 0)",
+            &[&uname, &secret],
+        )
+        .await?;
+        for hash in hashes.iter() {
+            t.execute(
+                "INSERT INTO recovery_codes (uname, hash) VALUES ($1, $2)",
+                &[&uname, hash],
+            )
+            .await?;
+        }
+
+        t.commit().await?;
+
+        let uri = totp::provisioning_uri(TOTP_ISSUER, uname, &secret);
+        Ok(TotpEnrollment {
+            secret,
+            uri,
+            recovery_codes,
+        })
+    }
+
+    /**
+    Check a second-factor `code` for `uname`.
+
+    A TOTP code is accepted if it's current and newer than the last one
+    accepted (so a code can't be replayed, even by simultaneous logins: the
+    `UPDATE` that records it is the check); accepting one also confirms a
+    pending enrollment. Once the enrollment is confirmed, a recovery code
+    is accepted as well (and is then used up).
+
+    Returns `AuthResult::Ok` or `AuthResult::BadCode`.
+    */
+    async fn verify_totp_code(
+        &self,
+        client: &Client,
+        uname: &str,
+        code: &str,
+    ) -> Result<AuthResult, DbError> {
+        let row = match client
+            .query_opt(
+                "SELECT secret, confirmed FROM totp WHERE uname = $1",
+                &[&uname],
+            )
+            .await?
+        {
+            Some(row) => row,
+            None => {
+                log::trace!("{:?} has no TOTP enrollment.", uname);
+                return Ok(AuthResult::BadCode);
+            }
+        };
+        let secret: String = row.try_get("secret")?;
+        let confirmed: bool = row.try_get("confirmed")?;
+
+        if let Some(step) = totp::verify(&secret, code, totp::current_step(), TOTP_SKEW_STEPS)? {
+            let step = step as i64;
+            let n = client
+                .execute(
+                    "UPDATE totp SET confirmed = TRUE, last_step = $1
+                    WHERE uname = $2 AND (last_step IS NULL OR last_step < $1)",
+                    &[&step, &uname],
+                )
+                .await?;
+            if n == 1 {
+                return Ok(AuthResult::Ok);
+            }
+            log::warn!("Replayed TOTP code for {:?} refused.", uname);
+            return Ok(AuthResult::BadCode);
+        }
+
+        if confirmed {
+            let hash = hash_recovery_code(code);
+            let n = client
+                .execute(
+                    "DELETE FROM recovery_codes WHERE uname = $1 AND hash = $2",
+                    &[&uname, &hash],
+                )
+                .await?;
+            if n > 0 {
+                log::info!("{:?} used a recovery code.", uname);
+                return Ok(AuthResult::Ok);
+            }
+        }
+
+        Ok(AuthResult::BadCode)
+    }
+
+    /**
+    Confirm a pending TOTP enrollment for an already-authenticated user by
+    checking a code generated from the new secret.
+
+    Returns `AuthResult::Ok` or `AuthResult::BadCode`.
+    */
+    pub async fn confirm_totp(&self, uname: &str, code: &str) -> Result<AuthResult, DbError> {
+        log::trace!("Db::confirm_totp( {:?}, {:?} ) called.", uname, code);
+
+        let client = self.connect().await?;
+        self.verify_totp_code(&client, uname, code).await
+    }
+
+    /**
+    Remove `uname`'s TOTP enrollment, recovery codes, and any outstanding
+    login challenges. Returns whether there was an enrollment to remove.
+    */
+    pub async fn disable_totp(&self, uname: &str) -> Result<bool, DbError> {
+        log::trace!("Db::disable_totp( {:?} ) called.", uname);
+
+        let mut client = self.connect().await?;
+        let t = client.transaction().await?;
+        t.execute("DELETE FROM totp_challenges WHERE uname = $1", &[&uname])
+            .await?;
+        t.execute("DELETE FROM recovery_codes WHERE uname = $1", &[&uname])
+            .await?;
+        let n = t
+            .execute("DELETE FROM totp WHERE uname = $1", &[&uname])
+            .await?;
+        t.commit().await?;
+
+        Ok(n > 0)
+    }
+
+    /**
+    Issue a token recording that `uname` has passed the password check, to
+    be redeemed (along with a second-factor code) through
+    [`Db::check_totp_and_issue_key`].
+    */
+    pub async fn issue_totp_challenge(&self, uname: &str) -> Result<String, DbError> {
+        log::trace!("Db::issue_totp_challenge( {:?} ) called.", uname);
+
+        let token = self.generate_key();
+        let client = self.connect().await?;
+        client
+            .execute(
+                "INSERT INTO totp_challenges (token, uname, issued)
+                VALUES ($1, $2, CURRENT_TIMESTAMP)",
+                &[&hash_key(&token), &uname],
+            )
+            .await?;
+
+        Ok(token)
+    }
+
+    /**
+    Redeem a challenge `token` (from [`Db::issue_totp_challenge`]) with a
+    second-factor `code` from the client described by `info`, issuing a new
+    key on success.
+
+    Returns `AuthResult::InvalidKey` if the token is unknown or expired.
+    Wrong codes count as failed logins, so this is subject to the login
+    throttle and may return `AuthResult::Locked`.
+    */
+    pub async fn check_totp_and_issue_key(
+        &self,
+        uname: &str,
+        token: &str,
+        code: &str,
+        info: &ClientInfo,
+    ) -> Result<AuthResult, DbError> {
+        log::trace!(
+            "Db::check_totp_and_issue_key( {:?}, [ token ], {:?}, {:?} ) called.",
+            uname,
+            code,
+            info
+        );
+
+        let addr = info.addr.as_str();
+        let mut client = self.connect().await?;
+
+        if client
+            .query_opt(
+                &format!(
+                    "SELECT 1 FROM totp_challenges
+                    WHERE token = $1
+                    AND uname = $2
+                    AND {} > CURRENT_TIMESTAMP",
+                    client.dialect().add_interval("issued", "$3")
+                ),
+                &[&hash_key(token), &uname, &TOTP_CHALLENGE_LIFE],
+            )
+            .await?
+            .is_none()
+        {
+            return Ok(AuthResult::InvalidKey);
+        }
+
+        if !self.begin_attempt(&mut client, uname, addr).await? {
+            log::warn!(
+                "Second-factor attempt for {:?} from {:?} refused: too many failures.",
+                uname,
+                addr
+            );
+            return Ok(AuthResult::Locked);
+        }
+
+        match self.verify_totp_code(&client, uname, code).await? {
+            AuthResult::Ok => { /* Proceed to issue a key. */ }
+            x => {
+                return Ok(x);
+            }
+        }
+
+        client
+            .execute("DELETE FROM totp_challenges WHERE uname = $1", &[&uname])
+            .await?;
+        client
+            .execute("DELETE FROM failed_logins WHERE uname = $1", &[&uname])
+            .await?;
+
+        let key = self.insert_key(&client, uname, info).await?;
+
+        Ok(AuthResult::Key(key))
+    }
+
+    /**
+    Within the transaction `t`, replace `uname`'s password hash with
+    `new_hash` and revoke all of their keys.
+    */
+    async fn store_password_hash(
+        &self,
+        t: &Transaction<'_>,
+        uname: &str,
+        new_hash: &str,
+    ) -> Result<(), DbError> {
+        match t
+            .execute(
+                "UPDATE users SET hash = $1
+                WHERE uname = $2",
+                &[&new_hash, &uname],
+            )
+            .await
+        {
+            Err(e) => {
+                return Err(DbError(format!(
+                    "Error setting password for {:?}: {}",
+                    uname, &e
+                )));
+            }
+            Ok(0) => {
+                return Err(DbError(format!("No user {:?} in auth DB.", uname)));
+            }
+            Ok(1) => {}
+            Ok(n) => {
+                log::warn!(
+                    "Updating password for {:?} updated {} records in the auth DB.",
+                    uname,
+                    &n
+                );
+            }
+        }
+
+        let n_keys = t
+            .execute("DELETE FROM keys WHERE uname = $1", &[&uname])
+            .await?;
+        log::trace!("Revoked {} keys for {:?}.", &n_keys, uname);
+
+        Ok(())
+    }
+
+    /**
+    Set `uname`'s password.
+
+    This also revokes all of `uname`'s existing keys, so any sessions opened
+    with the old password stop working.
+    */
+    pub async fn set_password(
+        &self,
+        uname: &str,
+        password: &str,
+        salt: &str,
+    ) -> Result<(), DbError> {
+        log::trace!(
+            "Db::set_password( &T, {:?}, ... , {:?} ) called.",
+            uname,
+            salt
+        );
+
+        let new_hash = hash_password(password, salt).await?;
+        let mut client = self.connect().await?;
+        let t = client.transaction().await?;
+
+        self.store_password_hash(&t, uname, &new_hash).await?;
+
+        t.commit()
+            .await
+            .map_err(|e| format!("Error committing password change: {}", &e).into())
+    }
+
+    /**
+    Issue a password reset token for `uname` at the request of client
+    address `addr`.
+
+    Returns `AuthResult::Key(token)`, or `AuthResult::Locked` if too many
+    tokens have recently been requested for `uname` or from `addr`.
+
+    The recent tokens are counted and the new one inserted in one
+    transaction, with `reset_tokens` locked in between, so simultaneous
+    requests can't all get in under the limits.
+    */
+    pub async fn issue_reset_token(&self, uname: &str, addr: &str) -> Result<AuthResult, DbError> {
+        log::trace!("Db::issue_reset_token( {:?}, {:?} ) called.", uname, addr);
+
+        let window = format!("{} seconds", &self.reset_policy.window_seconds);
+        let mut client = self.connect().await?;
+        let t = client.transaction().await?;
+        t.lock_table("reset_tokens").await?;
+
+        let row = t
+            .query_one(
+                &format!(
+                    "SELECT
+                        COUNT(*) FILTER (WHERE uname = $1) AS n_uname,
+                        COUNT(*) FILTER (WHERE addr = $2) AS n_addr
+                    FROM reset_tokens
+                    WHERE {} > CURRENT_TIMESTAMP",
+                    t.dialect().add_interval("issued", "$3")
+                ),
+                &[&uname, &addr, &window],
+            )
+            .await?;
+        let n_uname: i64 = row.try_get("n_uname")?;
+        let n_addr: i64 = row.try_get("n_addr")?;
+        if n_uname >= self.reset_policy.max_per_uname as i64
+            || n_addr >= self.reset_policy.max_per_addr as i64
+        {
+            log::warn!(
+                "Password reset for {:?} requested from {:?} refused: too many requests.",
+                uname,
+                addr
+            );
+            t.rollback().await?;
+            return Ok(AuthResult::Locked);
+        }
+
+        let token = self.generate_key();
+        t.execute(
+            "INSERT INTO reset_tokens (token_hash, uname, addr, issued, used)
+            VALUES ($1, $2, $3, CURRENT_TIMESTAMP, FALSE)",
+            &[&hash_key(&token), &uname, &addr],
+        )
+        .await?;
+        t.commit().await?;
+
+        Ok(AuthResult::Key(token))
+    }
+
+    /**
+    Redeem the password reset `token` to set `uname`'s password.
+
+    On success, all of `uname`'s other outstanding reset tokens and keys are
+    invalidated. Returns `AuthResult::Ok`, or `AuthResult::InvalidKey` if
+    the token is unknown, used, or expired.
+    */
+    pub async fn reset_password(
+        &self,
+        uname: &str,
+        token: &str,
+        password: &str,
+        salt: &str,
+    ) -> Result<AuthResult, DbError> {
+        log::trace!(
+            "Db::reset_password( {:?}, [ token ], ... , {:?} ) called.",
+            uname,
+            salt
+        );
+
+        let life = format!("{} seconds", &self.reset_policy.token_life_seconds);
+        let mut client = self.connect().await?;
+        let t = client.transaction().await?;
+
+        let n_redeemed = t
+            .execute(
+                &format!(
+                    "UPDATE reset_tokens SET used = TRUE
+                    WHERE uname = $1
+                    AND token_hash = $2
+                    AND NOT used
+                    AND {} > CURRENT_TIMESTAMP",
+                    t.dialect().add_interval("issued", "$3")
+                ),
+                &[&uname, &hash_key(token), &life],
+            )
+            .await?;
+        if n_redeemed == 0 {
+            return Ok(AuthResult::InvalidKey);
+        }
+        t.execute(
+            "UPDATE reset_tokens SET used = TRUE WHERE uname = $1",
+            &[&uname],
+        )
+        .await?;
+
+        let new_hash = hash_password(password, salt).await?;
+        self.store_password_hash(&t, uname, &new_hash).await?;
+
+        t.commit()
+            .await
+            .map_err(|e| format!("Error committing password reset: {}", &e))?;
+
+        Ok(AuthResult::Ok)
+    }
+
+    /**
+    Issue an invitation token with which `uname` can set their own password,
+    replacing any invitation they've previously been sent.
+    */
+    pub async fn issue_invite(&self, uname: &str) -> Result<String, DbError> {
+        log::trace!("Db::issue_invite( {:?} ) called.", uname);
+
+        let token = self.generate_key();
+        let client = self.connect().await?;
+        client
+            .execute(
+                "INSERT INTO invites (uname, token_hash, issued, accepted)
+                VALUES ($1, $2, CURRENT_TIMESTAMP, NULL)
+                ON CONFLICT (uname) DO UPDATE
+                SET token_hash = EXCLUDED.token_hash,
+                    issued = EXCLUDED.issued,
+                    accepted = NULL",
+                &[&uname, &hash_key(&token)],
+            )
+            .await?;
+
+        Ok(token)
+    }
+
+    /**
+    Redeem `uname`'s invitation `token` by setting their password.
+
+    Returns `AuthResult::Ok`, or `AuthResult::InvalidKey` if the token
+    doesn't match an outstanding, unexpired invitation.
+    */
+    pub async fn accept_invite(
+        &self,
+        uname: &str,
+        token: &str,
+        password: &str,
+        salt: &str,
+    ) -> Result<AuthResult, DbError> {
+        log::trace!(
+            "Db::accept_invite( {:?}, [ token ], ... , {:?} ) called.",
+            uname,
+            salt
+        );
+
+        let mut client = self.connect().await?;
+        let t = client.transaction().await?;
+
+        let n_accepted = t
+            .execute(
+                &format!(
+                    "UPDATE invites SET accepted = CURRENT_TIMESTAMP
+                    WHERE uname = $1
+                    AND token_hash = $2
+                    AND accepted IS NULL
+                    AND {} > CURRENT_TIMESTAMP",
+                    t.dialect().add_interval("issued", "$3")
+                ),
+                &[&uname, &hash_key(token), &self.invite_life],
+            )
+            .await?;
+        if n_accepted == 0 {
+            return Ok(AuthResult::InvalidKey);
+        }
+
+        let new_hash = hash_password(password, salt).await?;
+        self.store_password_hash(&t, uname, &new_hash).await?;
+
+        t.commit()
+            .await
+            .map_err(|e| format!("Error committing invitation acceptance: {}", &e))?;
+
+        Ok(AuthResult::Ok)
+    }
+
+    /// Report the status of every user who has been sent an invitation.
+    pub async fn invite_statuses(&self) -> Result<HashMap<String, InviteStatus>, DbError> {
+        log::trace!("Db::invite_statuses() called.");
+
+        let client = self.connect().await?;
+        let rows = client
+            .query(
+                &format!(
+                    "SELECT uname,
+                        accepted IS NOT NULL AS accepted,
+                        {} < CURRENT_TIMESTAMP AS expired
+                    FROM invites",
+                    client.dialect().add_interval("issued", "$1")
+                ),
+                &[&self.invite_life],
+            )
+            .await?;
+
+        let mut statuses = HashMap::with_capacity(rows.len());
+        for row in rows.iter() {
+            let uname: String = row.try_get("uname")?;
+            let status = if row.try_get("accepted")? {
+                InviteStatus::Accepted
+            } else if row.try_get("expired")? {
+                InviteStatus::Expired
+            } else {
+                InviteStatus::Pending
+            };
+            statuses.insert(uname, status);
+        }
+
+        Ok(statuses)
+    }
+
+    /**
+    Issue `uname` a new API token called `name`, good only for the given
+    `x-camp-action` values, and (if `expires` is supplied) only through
+    that date.
+
+    Returns the token; this is the only time it's available.
+    */
+    pub async fn issue_api_token(
+        &self,
+        uname: &str,
+        name: &str,
+        actions: &[String],
+        expires: Option<Date>,
+    ) -> Result<String, DbError> {
+        log::trace!(
+            "Db::issue_api_token( {:?}, {:?}, {:?}, {:?} ) called.",
+            uname,
+            name,
+            actions,
+            &expires
+        );
+
+        let dist = distributions::Slice::new(&self.key_chars).unwrap();
+        let rng = rand::thread_rng();
+        let token: String = API_TOKEN_PREFIX
+            .chars()
+            .chain(rng.sample_iter(&dist).take(API_TOKEN_LENGTH).copied())
+            .collect();
+
+        let client = self.connect().await?;
+        client
+            .execute(
+                "INSERT INTO api_tokens (uname, name, token_hash, actions, created, expires)
+                VALUES ($1, $2, $3, $4, CURRENT_TIMESTAMP, $5)",
+                &[&uname, &name, &hash_key(&token), &actions, &expires],
+            )
+            .await?;
+
+        Ok(token)
+    }
+
+    /**
+    Check whether `token` is a current API token of `uname`'s that's good
+    for `action`, and if so, note that it's been used.
+
+    Returns `AuthResult::Ok`, `AuthResult::InvalidKey`, or
+    `AuthResult::OutOfScope`.
+    */
+    pub async fn check_api_token(
+        &self,
+        uname: &str,
+        token: &str,
+        action: &str,
+    ) -> Result<AuthResult, DbError> {
+        log::trace!(
+            "Db::check_api_token( {:?}, [ token ], {:?} ) called.",
+            uname,
+            action
+        );
+
+        let client = self.connect().await?;
+        let row = client
+            .query_opt(
+                "SELECT id, name, actions FROM api_tokens
+                WHERE uname = $1
+                AND token_hash = $2
+                AND (expires IS NULL OR expires >= CURRENT_DATE)",
+                &[&uname, &hash_key(token)],
+            )
+            .await?;
+        let row = match row {
+            Some(row) => row,
+            None => {
+                return Ok(AuthResult::InvalidKey);
+            }
+        };
+
+        let id: i64 = row.try_get("id")?;
+        let actions: Vec<String> = row.try_get("actions")?;
+        if !actions.iter().any(|a| a == action) {
+            let name: String = row.try_get("name")?;
+            log::warn!(
+                "API token {:?} of {:?} used for out-of-scope action {:?}.",
+                &name,
+                uname,
+                action
+            );
+            return Ok(AuthResult::OutOfScope);
+        }
+
+        client
+            .execute(
+                "UPDATE api_tokens SET last_used = CURRENT_TIMESTAMP WHERE id = $1",
+                &[&id],
+            )
+            .await?;
+
+        Ok(AuthResult::Ok)
+    }
+
+    /// List all API tokens, whether expired or not.
+    pub async fn list_api_tokens(&self) -> Result<Vec<ApiToken>, DbError> {
+        log::trace!("Db::list_api_tokens() called.");
+
+        let client = self.connect().await?;
+        let rows = client
+            .query(
+                &format!(
+                    "SELECT id, uname, name, actions,
+                        {} AS expires,
+                        {} AS created,
+                        {} AS last_used
+                    FROM api_tokens
+                    ORDER BY uname, name",
+                    client.dialect().date_text("expires"),
+                    client.dialect().timestamp_text("created"),
+                    client.dialect().timestamp_text("last_used")
+                ),
+                &[],
+            )
+            .await?;
+
+        let mut tokens: Vec<ApiToken> = Vec::with_capacity(rows.len());
+        for row in rows.iter() {
+            tokens.push(ApiToken {
+                id: row.try_get("id")?,
+                uname: row.try_get("uname")?,
+                name: row.try_get("name")?,
+                actions: row.try_get("actions")?,
+                created: row.try_get("created")?,
+                expires: row.try_get("expires")?,
+                last_used: row.try_get("last_used")?,
+            });
+        }
+
+        Ok(tokens)
+    }
+
+    /// Revoke the API token with the given `id`. Returns whether there was
+    /// such a token.
+    pub async fn revoke_api_token(&self, id: i64) -> Result<bool, DbError> {
+        log::trace!("Db::revoke_api_token( {} ) called.", &id);
+
+        let client = self.connect().await?;
+        let n = client
+            .execute("DELETE FROM api_tokens WHERE id = $1", &[&id])
+            .await?;
+
+        Ok(n > 0)
+    }
+
+    /**
+    Retrieve the extra permissions granted to every user, keyed by uname.
+
+    Any stored permission that no longer parses (because the action it
+    names has been removed, say) is logged and skipped.
+    */
+    pub async fn get_grants(&self) -> Result<HashMap<String, Vec<Permission>>, DbError> {
+        log::trace!("Db::get_grants() called.");
+
+        let client = self.connect().await?;
+        let rows = client
+            .query("SELECT uname, permission FROM grants ORDER BY uname, permission", &[])
+            .await?;
+
+        let mut grants: HashMap<String, Vec<Permission>> = HashMap::new();
+        for row in rows.iter() {
+            let uname: String = row.try_get("uname")?;
+            let perm_str: &str = row.try_get("permission")?;
+            match perm_str.parse::<Permission>() {
+                Ok(perm) => grants.entry(uname).or_default().push(perm),
+                Err(e) => {
+                    log::warn!(
+                        "Ignoring invalid permission {:?} granted to {:?}: {}",
+                        perm_str,
+                        &uname,
+                        &e
+                    );
+                }
+            }
+        }
+
+        Ok(grants)
+    }
+
+    /// Grant `uname` the extra permission `perm`. Returns `false` if they
+    /// already had it.
+    pub async fn add_grant(&self, uname: &str, perm: &Permission) -> Result<bool, DbError> {
+        log::trace!("Db::add_grant( {:?}, {} ) called.", uname, perm);
+
+        let client = self.connect().await?;
+        let n = client
+            .execute(
+                "INSERT INTO grants (uname, permission) VALUES ($1, $2)
+                ON CONFLICT DO NOTHING",
+                &[&uname, &perm.to_string()],
+            )
+            .await?;
+
+        Ok(n > 0)
+    }
+
+    /// Remove the extra permission `perm` from `uname`. Returns whether they
+    /// had it.
+    pub async fn remove_grant(&self, uname: &str, perm: &Permission) -> Result<bool, DbError> {
+        log::trace!("Db::remove_grant( {:?}, {} ) called.", uname, perm);
+
+        let client = self.connect().await?;
+        let n = client
+            .execute(
+                "DELETE FROM grants WHERE uname = $1 AND permission = $2",
+                &[&uname, &perm.to_string()],
+            )
+            .await?;
+
+        Ok(n > 0)
+    }
+
+    /// Delete any reset tokens too old to count toward the request limits
+    /// (and thus also too old to use).
+    pub async fn cull_old_reset_tokens(&self) -> Result<usize, DbError> {
+        log::trace!("Db::cull_old_reset_tokens() called.");
+
+        let horizon = format!(
+            "{} seconds",
+            self.reset_policy
+                .window_seconds
+                .max(self.reset_policy.token_life_seconds)
+        );
+        let client = self.connect().await?;
+        let n_culled = client
+            .execute(
+                &format!(
+                    "DELETE FROM reset_tokens
+                    WHERE {} < CURRENT_TIMESTAMP",
+                    client.dialect().add_interval("issued", "$1")
+                ),
+                &[&horizon],
+            )
+            .await?;
+        log::trace!("Deleted {} reset tokens.", &n_culled);
+
+        Ok(n_culled as usize)
+    }
+
+    /**
+    Drop all database tables.
+
+    This is largely for cleanup after testing.
+    */
+    pub async fn nuke_database(&self) -> Result<(), DbError> {
+        log::trace!("Db::nuke_database() called");
+        let mut client = self.connect().await?;
+        let t = client
+            .transaction()
+            .await
+            .map_err(|e| format!("Auth DB Unable to begin transaction: {}", &e))?;
+
+        let mut n_rows: u64 = 0;
+        for table in [
+            "grants",
+            "api_tokens",
+            "invites",
+            "reset_tokens",
+            "totp_challenges",
+            "recovery_codes",
+            "totp",
+        ] {
+            n_rows += t
+                .execute(format!("DROP TABLE {}", table).as_str(), &[])
+                .await
+                .map_err(|e| format!("Error dropping {} table: {}", table, &e))?;
+        }
+        n_rows += t
+            .execute("DROP TABLE failed_logins", &[])
+            .await
+            .map_err(|e| format!("Error dropping failed_logins table: {}", &e))?;
+        n_rows += t
+            .execute("DROP TABLE keys", &[])
+            .await
+            .map_err(|e| format!("Error dropping keys table: {}", &e))?;
+        n_rows += t
+            .execute("DROP TABLE users", &[])
+            .await
+            .map_err(|e| format!("Error dropping users table: {}", &e))?;
+        n_rows += t
+            .execute("DROP TABLE schema_version", &[])
+            .await
+            .map_err(|e| format!("Error dropping schema_version table: {}", &e))?;
+
+        t.commit()
+            .await
+            .map_err(|e| format!("Error committing nuclear transaction: {}", &e))?;
+
+        log::trace!("Nuked {} rows.", &n_rows);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Dialect;
+    use crate::tests::{backend_tests, ensure_logging, test_auth_db, TestDbs};
+    use crate::UnifiedError;
+
+    use serial_test::serial;
+
+    static USERS: &[&str] = &["dan", "griffin", "krista"];
+    static PASSWORDS: &[&str] = &["booga", "purple", "aqua"];
+    static SALTS: &[&str] = &["asdf", "hjkl", "qwer"];
+    static TEST_ADDR: &str = "127.0.0.1";
+
+    fn test_client() -> ClientInfo {
+        ClientInfo {
+            addr: TEST_ADDR.to_owned(),
+            user_agent: "camp test".to_owned(),
+        }
+    }
+
+    #[tokio::test]
+    #[ignore]
+    #[serial]
+    async fn reset_db() {
+        ensure_logging();
+        let db = Db::new(test_auth_db());
+        db.nuke_database().await.unwrap();
+    }
+
+    async fn populate_db(dbs: TestDbs) -> Result<(), UnifiedError> {
+        ensure_logging();
+
+        let db = Db::new(dbs.auth());
+        db.ensure_db_schema().await?;
+        let mut client = db.connect().await?;
+        let t = client.transaction().await?;
+
+        let n_users: usize = USERS.len();
+        assert_eq!(
+            db.add_users(&t, USERS, PASSWORDS, SALTS).await?,
+            n_users as u64
+        );
+        t.commit().await?;
+
+        for n in 0..n_users {
+            let (uname, pwd, salt) = (USERS[n], PASSWORDS[n], SALTS[n]);
+            assert_eq!(db.check_password(uname, pwd, salt).await?, AuthResult::Ok);
+        }
+
+        assert_eq!(
+            db.check_password(USERS[1], "mama moo moo", SALTS[1])
+                .await?,
+            AuthResult::BadPassword
+        );
+        assert_eq!(
+            db.check_password(USERS[1], PASSWORDS[1], "not a real salt")
+                .await?,
+            AuthResult::BadPassword
+        );
+
+        let t = client.transaction().await?;
+        db.delete_users(&t, &USERS[1..]).await?;
+        t.commit().await?;
+
+        assert_eq!(
+            db.check_password(USERS[1], PASSWORDS[1], SALTS[1])
+                .await
+                .unwrap(),
+            AuthResult::NoSuchUser
+        );
+
+        db.nuke_database().await.unwrap();
+
+        match db.check_password(USERS[1], PASSWORDS[1], SALTS[1]).await {
+            Err(_) => { /* this is okay */ }
+            x @ _ => {
+                panic!("Expected Err(_), got {:?}", &x);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn upgrade_legacy_hashes(dbs: TestDbs) -> Result<(), UnifiedError> {
+        ensure_logging();
+
+        let db = Db::new(dbs.auth());
+        db.ensure_db_schema().await?;
+        let client = db.connect().await?;
+
+        let legacy = legacy_hash_with_salt(PASSWORDS[0], SALTS[0].as_bytes());
+        client
+            .execute(
+                "INSERT INTO users (uname, hash) VALUES ($1, $2)",
+                &[&USERS[0], &legacy],
             )
+            .await?;
+
+        assert_eq!(
+            db.check_password(USERS[0], "not the password", SALTS[0])
+                .await?,
+            AuthResult::BadPassword
+        );
+        let row = client
+            .query_one("SELECT hash FROM users WHERE uname = $1", &[&USERS[0]])
+            .await?;
+        let stored: String = row.get("hash");
+        assert_eq!(stored, legacy);
+
+        assert_eq!(
+            db.check_password(USERS[0], PASSWORDS[0], SALTS[0]).await?,
+            AuthResult::Ok
+        );
+        let row = client
+            .query_one("SELECT hash FROM users WHERE uname = $1", &[&USERS[0]])
+            .await?;
+        let stored: String = row.get("hash");
+        assert!(stored.starts_with("$argon2id$"));
+
+        assert_eq!(
+            db.check_password(USERS[0], PASSWORDS[0], SALTS[0]).await?,
+            AuthResult::Ok
+        );
+        assert_eq!(
+            db.check_password(USERS[0], PASSWORDS[0], SALTS[1]).await?,
+            AuthResult::BadPassword
+        );
+
+        db.nuke_database().await?;
+        Ok(())
+    }
+
+    async fn issue_keys(dbs: TestDbs) -> Result<(), UnifiedError> {
+        use std::time::Duration;
+        use tokio::time::sleep;
+
+        ensure_logging();
+
+        let mut db = Db::new(dbs.auth());
+        db.ensure_db_schema().await.unwrap();
+        let mut client = db.connect().await?;
+        let t = client.transaction().await?;
+
+        db.add_users(&t, USERS, PASSWORDS, SALTS).await.unwrap();
+        t.commit().await?;
+
+        let key = match db
+            .check_password_and_issue_key(USERS[0], PASSWORDS[0], SALTS[0], &test_client())
             .await?
         {
-            None => {
-                return Ok(AuthResult::InvalidKey);
+            AuthResult::Key(k) => k,
+            x @ _ => {
+                panic!("Expected AuthResult::Key(_), got {:?}", &x);
+            }
+        };
+
+        assert_eq!(db.check_key(USERS[0], &key).await.unwrap(), AuthResult::Ok);
+        assert_eq!(
+            db.check_key(USERS[1], &key).await.unwrap(),
+            AuthResult::InvalidKey
+        );
+        assert_eq!(
+            db.check_key(USERS[0], "wrong_key").await.unwrap(),
+            AuthResult::InvalidKey
+        );
+
+        db.set_key_life(1_u64);
+        let key = match db
+            .check_password_and_issue_key(USERS[1], PASSWORDS[1], SALTS[1], &test_client())
+            .await
+            .unwrap()
+        {
+            AuthResult::Key(k) => k,
+            x @ _ => {
+                panic!("Expected AuthResult::Key(_), got {:?}", &x);
+            }
+        };
+        assert_eq!(db.check_key(USERS[1], &key).await.unwrap(), AuthResult::Ok);
+        sleep(Duration::from_millis(1500)).await;
+        assert_eq!(
+            db.check_key(USERS[1], &key).await.unwrap(),
+            AuthResult::InvalidKey
+        );
+        db.cull_old_keys().await.unwrap();
+
+        db.nuke_database().await?;
+        Ok(())
+    }
+
+    async fn second_factor(dbs: TestDbs) -> Result<(), UnifiedError> {
+        ensure_logging();
+
+        let db = Db::new(dbs.auth());
+        db.ensure_db_schema().await?;
+        let mut client = db.connect().await?;
+        let t = client.transaction().await?;
+        db.add_users(&t, USERS, PASSWORDS, SALTS).await?;
+        t.commit().await?;
+
+        let enrollment = db.begin_totp_enrollment(USERS[0]).await?;
+        assert_eq!(enrollment.recovery_codes.len(), N_RECOVERY_CODES);
+        assert!(enrollment.uri.starts_with("otpauth://totp/"));
+        assert!(!db.totp_enabled(USERS[0]).await?);
+        // Recovery codes don't work until the enrollment is confirmed.
+        assert_eq!(
+            db.confirm_totp(USERS[0], &enrollment.recovery_codes[0])
+                .await?,
+            AuthResult::BadCode
+        );
+
+        let token = db.issue_totp_challenge(USERS[0]).await?;
+        assert_eq!(
+            db.check_totp_and_issue_key(USERS[0], &token, "000000x", &test_client())
+                .await?,
+            AuthResult::BadCode
+        );
+        let code = totp::generate(&enrollment.secret, totp::current_step())?;
+        match db
+            .check_totp_and_issue_key(USERS[0], &token, &code, &test_client())
+            .await?
+        {
+            AuthResult::Key(k) => {
+                assert_eq!(db.check_key(USERS[0], &k).await?, AuthResult::Ok);
+            }
+            x => panic!("Expected AuthResult::Key(_), got {:?}", &x),
+        }
+        assert!(db.totp_enabled(USERS[0]).await?);
+        assert!(db.begin_totp_enrollment(USERS[0]).await.is_err());
+
+        // Challenges are single-use, and codes can't be replayed.
+        assert_eq!(
+            db.check_totp_and_issue_key(USERS[0], &token, &code, &test_client())
+                .await?,
+            AuthResult::InvalidKey
+        );
+        let token = db.issue_totp_challenge(USERS[0]).await?;
+        assert_eq!(
+            db.check_totp_and_issue_key(USERS[0], &token, &code, &test_client())
+                .await?,
+            AuthResult::BadCode
+        );
+        // Neither can recovery codes, but they're case- and dash-insensitive.
+        let recovery = enrollment.recovery_codes[3].replace('-', "").to_uppercase();
+        assert!(matches!(
+            db.check_totp_and_issue_key(USERS[0], &token, &recovery, &test_client())
+                .await?,
+            AuthResult::Key(_)
+        ));
+        let token = db.issue_totp_challenge(USERS[0]).await?;
+        assert_eq!(
+            db.check_totp_and_issue_key(USERS[0], &token, &recovery, &test_client())
+                .await?,
+            AuthResult::BadCode
+        );
+        // A challenge is only good for the user it was issued to.
+        assert_eq!(
+            db.check_totp_and_issue_key(USERS[1], &token, &recovery, &test_client())
+                .await?,
+            AuthResult::InvalidKey
+        );
+
+        assert!(db.disable_totp(USERS[0]).await?);
+        assert!(!db.totp_enabled(USERS[0]).await?);
+        assert!(!db.disable_totp(USERS[0]).await?);
+
+        db.nuke_database().await?;
+        Ok(())
+    }
+
+    async fn sessions(dbs: TestDbs) -> Result<(), UnifiedError> {
+        ensure_logging();
+
+        let db = Db::new(dbs.auth());
+        db.ensure_db_schema().await?;
+        let mut client = db.connect().await?;
+        let t = client.transaction().await?;
+        db.add_users(&t, USERS, PASSWORDS, SALTS).await?;
+        t.commit().await?;
+
+        let laptop = ClientInfo {
+            addr: "10.0.0.2".to_owned(),
+            user_agent: "laptop".to_owned(),
+        };
+        let mut keys: Vec<String> = Vec::new();
+        for info in [&test_client(), &laptop, &laptop] {
+            match db
+                .check_password_and_issue_key(USERS[0], PASSWORDS[0], SALTS[0], info)
+                .await?
+            {
+                AuthResult::Key(k) => keys.push(k),
+                x => panic!("Expected AuthResult::Key(_), got {:?}", &x),
             }
-            Some(row) => {
-                let key: String = row.get("key");
-                key
+        }
+        db.issue_key(USERS[1], &test_client()).await?;
+
+        let sessions = db.list_sessions(USERS[0]).await?;
+        assert_eq!(sessions.len(), 3);
+        assert_eq!(
+            sessions
+                .iter()
+                .filter(|s| s.addr.as_deref() == Some("10.0.0.2"))
+                .count(),
+            2
+        );
+        assert!(sessions.iter().all(|s| s.issued.is_some()));
+
+        // Revoking the first key's session leaves the others alone.
+        let first = sessions
+            .iter()
+            .find(|s| s.user_agent.as_deref() == Some("camp test"))
+            .unwrap();
+        assert!(!db.revoke_session(USERS[1], first.id).await?);
+        assert!(db.revoke_session(USERS[0], first.id).await?);
+        assert_eq!(db.check_key(USERS[0], &keys[0]).await?, AuthResult::InvalidKey);
+        assert_eq!(db.check_key(USERS[0], &keys[1]).await?, AuthResult::Ok);
+
+        assert_eq!(db.revoke_all_sessions(USERS[0]).await?, 2);
+        assert_eq!(db.check_key(USERS[0], &keys[2]).await?, AuthResult::InvalidKey);
+        assert_eq!(db.list_sessions(USERS[1]).await?.len(), 1);
+
+        // Changing a password logs the user out everywhere.
+        db.set_password(USERS[1], "new password", SALTS[1]).await?;
+        assert!(db.list_sessions(USERS[1]).await?.is_empty());
+
+        db.nuke_database().await?;
+        Ok(())
+    }
+
+    async fn impersonation_keys(dbs: TestDbs) -> Result<(), UnifiedError> {
+        ensure_logging();
+
+        let db = Db::new(dbs.auth());
+        db.ensure_db_schema().await?;
+        let mut client = db.connect().await?;
+        let t = client.transaction().await?;
+        db.add_users(&t, USERS, PASSWORDS, SALTS).await?;
+        t.commit().await?;
+
+        let key = match db
+            .issue_impersonation_key(USERS[1], USERS[0], &test_client())
+            .await?
+        {
+            AuthResult::Key(k) => k,
+            x => panic!("Expected AuthResult::Key(_), got {:?}", &x),
+        };
+        assert_eq!(
+            db.check_key(USERS[1], &key).await?,
+            AuthResult::ReadOnly(USERS[0].to_owned())
+        );
+        assert_eq!(db.check_key(USERS[0], &key).await?, AuthResult::InvalidKey);
+
+        let sessions = db.list_sessions(USERS[1]).await?;
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].impersonator.as_deref(), Some(USERS[0]));
+
+        db.nuke_database().await?;
+        Ok(())
+    }
+
+    async fn reset_tokens(dbs: TestDbs) -> Result<(), UnifiedError> {
+        ensure_logging();
+
+        let mut db = Db::new(dbs.auth());
+        db.set_reset_policy(ResetPolicy {
+            max_per_uname: 3,
+            max_per_addr: 4,
+            ..ResetPolicy::default()
+        });
+        db.ensure_db_schema().await?;
+        let mut client = db.connect().await?;
+        let t = client.transaction().await?;
+        db.add_users(&t, USERS, PASSWORDS, SALTS).await?;
+        t.commit().await?;
+
+        let mut tokens: Vec<String> = Vec::new();
+        for _ in 0..3 {
+            match db.issue_reset_token(USERS[0], TEST_ADDR).await? {
+                AuthResult::Key(k) => tokens.push(k),
+                x => panic!("Expected AuthResult::Key(_), got {:?}", &x),
             }
+        }
+        assert_eq!(
+            db.issue_reset_token(USERS[0], "10.0.0.2").await?,
+            AuthResult::Locked
+        );
+        // Reset tokens aren't keys.
+        assert_eq!(
+            db.check_key(USERS[0], &tokens[0]).await?,
+            AuthResult::InvalidKey
+        );
+
+        let key = match db.issue_key(USERS[0], &test_client()).await? {
+            AuthResult::Key(k) => k,
+            x => panic!("Expected AuthResult::Key(_), got {:?}", &x),
+        };
+        assert_eq!(
+            db.reset_password(USERS[1], &tokens[1], "wrong user", SALTS[1])
+                .await?,
+            AuthResult::InvalidKey
+        );
+        assert_eq!(
+            db.reset_password(USERS[0], &tokens[1], "new password", SALTS[0])
+                .await?,
+            AuthResult::Ok
+        );
+        assert_eq!(
+            db.check_password(USERS[0], "new password", SALTS[0]).await?,
+            AuthResult::Ok
+        );
+        assert_eq!(db.check_key(USERS[0], &key).await?, AuthResult::InvalidKey);
+        // Redeeming one token uses them all up.
+        for token in tokens.iter() {
+            assert_eq!(
+                db.reset_password(USERS[0], token, "again", SALTS[0]).await?,
+                AuthResult::InvalidKey
+            );
+        }
+
+        // The per-address limit counts requests for any user.
+        assert!(matches!(
+            db.issue_reset_token(USERS[1], TEST_ADDR).await?,
+            AuthResult::Key(_)
+        ));
+        assert_eq!(
+            db.issue_reset_token(USERS[2], TEST_ADDR).await?,
+            AuthResult::Locked
+        );
+
+        // Expired tokens can't be redeemed.
+        let token = match db.issue_reset_token(USERS[2], "10.0.0.3").await? {
+            AuthResult::Key(k) => k,
+            x => panic!("Expected AuthResult::Key(_), got {:?}", &x),
         };
         client
             .execute(
-                "UPDATE keys SET last_used = CURRENT_TIMESTAMP
-                WHERE key = $1",
-                &[&key],
+                &format!(
+                    "UPDATE reset_tokens SET issued = {} WHERE uname = $1",
+                    client.dialect().add_interval("issued", "'-1 day'")
+                ),
+                &[&USERS[2]],
+            )
+            .await?;
+        assert_eq!(
+            db.reset_password(USERS[2], &token, "too late", SALTS[2])
+                .await?,
+            AuthResult::InvalidKey
+        );
+        assert_eq!(db.cull_old_reset_tokens().await?, 1);
+
+        db.nuke_database().await?;
+        Ok(())
+    }
+
+    async fn invites(dbs: TestDbs) -> Result<(), UnifiedError> {
+        ensure_logging();
+
+        let db = Db::new(dbs.auth());
+        db.ensure_db_schema().await?;
+        let mut client = db.connect().await?;
+        let t = client.transaction().await?;
+        db.add_users(&t, USERS, PASSWORDS, SALTS).await?;
+        t.commit().await?;
+
+        assert!(db.invite_statuses().await?.is_empty());
+
+        let old_token = db.issue_invite(USERS[0]).await?;
+        let token = db.issue_invite(USERS[0]).await?;
+        let other_token = db.issue_invite(USERS[1]).await?;
+        let statuses = db.invite_statuses().await?;
+        assert_eq!(statuses.len(), 2);
+        assert_eq!(statuses.get(USERS[0]), Some(&InviteStatus::Pending));
+
+        // Reissuing an invitation replaces the old one.
+        assert_eq!(
+            db.accept_invite(USERS[0], &old_token, "new password", SALTS[0])
+                .await?,
+            AuthResult::InvalidKey
+        );
+        assert_eq!(
+            db.accept_invite(USERS[0], &other_token, "new password", SALTS[0])
+                .await?,
+            AuthResult::InvalidKey
+        );
+        assert_eq!(
+            db.accept_invite(USERS[0], &token, "new password", SALTS[0])
+                .await?,
+            AuthResult::Ok
+        );
+        assert_eq!(
+            db.check_password(USERS[0], "new password", SALTS[0]).await?,
+            AuthResult::Ok
+        );
+        // It only works once.
+        assert_eq!(
+            db.accept_invite(USERS[0], &token, "newer password", SALTS[0])
+                .await?,
+            AuthResult::InvalidKey
+        );
+        assert_eq!(
+            db.invite_statuses().await?.get(USERS[0]),
+            Some(&InviteStatus::Accepted)
+        );
+
+        client
+            .execute(
+                &format!(
+                    "UPDATE invites SET issued = {} WHERE uname = $1",
+                    client.dialect().add_interval("issued", "'-30 days'")
+                ),
+                &[&USERS[1]],
             )
             .await?;
+        assert_eq!(
+            db.invite_statuses().await?.get(USERS[1]),
+            Some(&InviteStatus::Expired)
+        );
+        assert_eq!(
+            db.accept_invite(USERS[1], &other_token, "too late", SALTS[1])
+                .await?,
+            AuthResult::InvalidKey
+        );
 
-        Ok(AuthResult::Ok)
+        // Deleting a user removes their invitation.
+        let t = client.transaction().await?;
+        db.delete_users(&t, &[USERS[1]]).await?;
+        t.commit().await?;
+        assert_eq!(db.invite_statuses().await?.len(), 1);
+
+        db.nuke_database().await?;
+        Ok(())
     }
 
-    /// Delete any keys that have been unused for longer than `self.key_life`.
-    pub async fn cull_old_keys(&self) -> Result<usize, DbError> {
-        log::trace!("Db::cull_old_keys() called.");
+    async fn grants(dbs: TestDbs) -> Result<(), UnifiedError> {
+        ensure_logging();
 
-        let client = self.connect().await?;
-        let n_culled = client
+        let db = Db::new(dbs.auth());
+        db.ensure_db_schema().await?;
+        let mut client = db.connect().await?;
+        let t = client.transaction().await?;
+        db.add_users(&t, USERS, PASSWORDS, SALTS).await?;
+        t.commit().await?;
+
+        let fix_typos: Permission = "admin:update-course".parse().unwrap();
+        let teach: Permission = "teacher:*".parse().unwrap();
+        assert!(db.add_grant(USERS[0], &fix_typos).await?);
+        assert!(!db.add_grant(USERS[0], &fix_typos).await?);
+        assert!(db.add_grant(USERS[0], &teach).await?);
+        assert!(db.add_grant(USERS[1], &teach).await?);
+        // Something an older version might have left behind.
+        client
             .execute(
-                "DELETE FROM keys
-                WHERE last_used + ($1 || ' ')::INTERVAL < now()",
-                &[&self.key_life],
+                "INSERT INTO grants (uname, permission) VALUES ($1, 'admin:gone')",
+                &[&USERS[1]],
             )
             .await?;
-        log::trace!("Deleted {} keys.", &n_culled);
-
-        Ok(n_culled as usize)
-    }
 
-    pub async fn set_password(
-        &self,
-        uname: &str,
-        password: &str,
-        salt: &str,
-    ) -> Result<(), DbError> {
-        log::trace!(
-            "Db::set_password( &T, {:?}, ... , {:?} ) called.",
-            uname,
-            salt
+        let grants = db.get_grants().await?;
+        assert_eq!(grants.len(), 2);
+        assert_eq!(
+            grants.get(USERS[0]).unwrap(),
+            &vec![fix_typos.clone(), teach.clone()]
         );
+        assert_eq!(grants.get(USERS[1]).unwrap(), &vec![teach.clone()]);
 
-        let new_hash = hash_with_salt(password, salt.as_bytes());
-        let client = self.connect().await?;
+        assert!(db.remove_grant(USERS[0], &fix_typos).await?);
+        assert!(!db.remove_grant(USERS[0], &fix_typos).await?);
+        let t = client.transaction().await?;
+        db.delete_users(&t, &[USERS[1]]).await?;
+        t.commit().await?;
+        let grants = db.get_grants().await?;
+        assert_eq!(grants.len(), 1);
+        assert_eq!(grants.get(USERS[0]).unwrap(), &vec![teach]);
 
-        match client
-            .execute(
-                "UPDATE users SET hash = $1
-                WHERE uname = $2",
-                &[&new_hash, &uname],
-            )
-            .await
-        {
-            Err(e) => Err(DbError(format!(
-                "Error setting password for {:?}: {}",
-                uname, &e
-            ))),
-            Ok(0) => Err(DbError(format!("No user {:?} in auth DB.", uname))),
-            Ok(1) => Ok(()),
-            Ok(n) => {
-                log::warn!(
-                    "Updating password for {:?} updated {} records in the auth DB.",
-                    uname,
-                    &n
-                );
-                Ok(())
-            }
-        }
+        db.nuke_database().await?;
+        Ok(())
     }
 
-    /**
-    Drop both database tables.
+    async fn api_tokens(dbs: TestDbs) -> Result<(), UnifiedError> {
+        ensure_logging();
 
-    This is largely for cleanup after testing.
-    */
-    pub async fn nuke_database(&self) -> Result<(), DbError> {
-        log::trace!("Db::nuke_database() called");
-        let mut client = self.connect().await?;
-        let t = client
-            .transaction()
-            .await
-            .map_err(|e| format!("Auth DB Unable to begin transaction: {}", &e))?;
+        let db = Db::new(dbs.auth());
+        db.ensure_db_schema().await?;
+        let mut client = db.connect().await?;
+        let t = client.transaction().await?;
+        db.add_users(&t, USERS, PASSWORDS, SALTS).await?;
+        t.commit().await?;
 
-        let mut n_rows: u64 = 0;
-        n_rows += t
-            .execute("DROP TABLE keys", &[])
-            .await
-            .map_err(|e| format!("Error dropping keys table: {}", &e))?;
-        n_rows += t
-            .execute("DROP TABLE users", &[])
-            .await
-            .map_err(|e| format!("Error dropping users table: {}", &e))?;
+        let actions = vec!["populate-users".to_owned(), "add-user".to_owned()];
+        let token = db
+            .issue_api_token(USERS[0], "roster sync", &actions, None)
+            .await?;
+        assert!(token.starts_with(API_TOKEN_PREFIX));
+        let yesterday = time::OffsetDateTime::now_utc().date().previous_day().unwrap();
+        let expired = db
+            .issue_api_token(USERS[0], "old", &actions, Some(yesterday))
+            .await?;
 
-        t.commit()
-            .await
-            .map_err(|e| format!("Error committing nuclear transaction: {}", &e))?;
+        assert_eq!(
+            db.check_api_token(USERS[0], &token, "add-user").await?,
+            AuthResult::Ok
+        );
+        assert_eq!(
+            db.check_api_token(USERS[0], &token, "delete-user").await?,
+            AuthResult::OutOfScope
+        );
+        assert_eq!(
+            db.check_api_token(USERS[1], &token, "add-user").await?,
+            AuthResult::InvalidKey
+        );
+        assert_eq!(
+            db.check_api_token(USERS[0], &expired, "add-user").await?,
+            AuthResult::InvalidKey
+        );
+        // API tokens aren't keys.
+        assert_eq!(db.check_key(USERS[0], &token).await?, AuthResult::InvalidKey);
+
+        let tokens = db.list_api_tokens().await?;
+        assert_eq!(tokens.len(), 2);
+        let listed = tokens.iter().find(|t| t.name == "roster sync").unwrap();
+        assert_eq!(&listed.actions, &actions);
+        assert!(listed.expires.is_none());
+        assert!(listed.last_used.is_some());
+        let old = tokens.iter().find(|t| t.name == "old").unwrap();
+        assert!(old.last_used.is_none());
+
+        // Tokens survive a password change, but not revocation.
+        db.set_password(USERS[0], "new password", SALTS[0]).await?;
+        assert_eq!(
+            db.check_api_token(USERS[0], &token, "populate-users").await?,
+            AuthResult::Ok
+        );
+        assert!(db.revoke_api_token(listed.id).await?);
+        assert!(!db.revoke_api_token(listed.id).await?);
+        assert_eq!(
+            db.check_api_token(USERS[0], &token, "populate-users").await?,
+            AuthResult::InvalidKey
+        );
 
-        log::trace!("Nuked {} rows.", &n_rows);
+        db.nuke_database().await?;
         Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::tests::ensure_logging;
-    use crate::UnifiedError;
+    async fn hashed_keys(dbs: TestDbs) -> Result<(), UnifiedError> {
+        ensure_logging();
 
-    use serial_test::serial;
+        // Set up the tables as an older version would have left them, with
+        // a key stored in the clear. (Other tests that fail partway through
+        // may have left current tables behind, so clear those out first.)
+        let db = Db::new(dbs.auth());
+        let client = db.connect().await?;
+        // Only Postgres databases ever stored keys in the clear.
+        if client.dialect() != Dialect::Postgres {
+            return Ok(());
+        }
+        client
+            .batch_execute(
+                "DROP TABLE IF EXISTS grants, api_tokens, invites, reset_tokens, totp_challenges,
+                    recovery_codes, totp, failed_logins, keys, users;
+                CREATE TABLE users (uname TEXT PRIMARY KEY, hash TEXT);
+                CREATE TABLE keys (
+                    key TEXT,
+                    uname TEXT REFERENCES users,
+                    last_used TIMESTAMP
+                );",
+            )
+            .await?;
+        client
+            .execute(
+                "INSERT INTO users (uname, hash) VALUES ($1, 'x')",
+                &[&USERS[0]],
+            )
+            .await?;
+        client
+            .execute(
+                "INSERT INTO keys (key, uname, last_used)
+                VALUES ('oldplaintextkey', $1, CURRENT_TIMESTAMP)",
+                &[&USERS[0]],
+            )
+            .await?;
 
-    static USERS: &[&str] = &["dan", "griffin", "krista"];
-    static PASSWORDS: &[&str] = &["booga", "purple", "aqua"];
-    static SALTS: &[&str] = &["asdf", "hjkl", "qwer"];
+        db.ensure_db_schema().await?;
+        assert_eq!(
+            db.check_key(USERS[0], "oldplaintextkey").await?,
+            AuthResult::Ok
+        );
+        // Running it again is harmless.
+        db.ensure_db_schema().await?;
+        assert_eq!(
+            db.check_key(USERS[0], "oldplaintextkey").await?,
+            AuthResult::Ok
+        );
 
-    static TEST_CONNECTION: &str =
-        "host=localhost user=camp_test password='camp_test' dbname=camp_auth_test";
+        let key = match db.issue_key(USERS[0], &test_client()).await? {
+            AuthResult::Key(k) => k,
+            x => panic!("Expected AuthResult::Key(_), got {:?}", &x),
+        };
+        assert_eq!(db.check_key(USERS[0], &key).await?, AuthResult::Ok);
+        let n_plain = client
+            .query_one(
+                "SELECT COUNT(*) AS n FROM keys
+                WHERE key_hash = $1 OR key_hash = 'oldplaintextkey'",
+                &[&key],
+            )
+            .await?;
+        assert_eq!(n_plain.get::<_, i64>("n"), 0);
 
-    #[tokio::test]
-    #[ignore]
-    #[serial]
-    async fn reset_db() {
-        ensure_logging();
-        let db = Db::new(TEST_CONNECTION.to_owned());
-        db.nuke_database().await.unwrap();
+        db.nuke_database().await?;
+        Ok(())
     }
 
-    #[tokio::test]
-    #[serial]
-    async fn populate_db() -> Result<(), UnifiedError> {
+    #[test]
+    fn throttle_backoff() {
+        let throttle = LoginThrottle {
+            free_attempts: 2,
+            backoff_seconds: 5,
+            max_attempts: 6,
+            addr_max_attempts: 20,
+            lockout_seconds: 60,
+        };
+
+        assert!(!throttle.is_locked(0, None, 0));
+        assert!(!throttle.is_locked(1, Some(0.0), 1));
+        // Third failure onward must wait 5, 10, 20... seconds.
+        assert!(throttle.is_locked(2, Some(4.0), 2));
+        assert!(!throttle.is_locked(2, Some(6.0), 2));
+        assert!(throttle.is_locked(4, Some(19.0), 4));
+        assert!(!throttle.is_locked(4, Some(21.0), 4));
+        // Hard limits.
+        assert!(throttle.is_locked(6, Some(59.0), 6));
+        assert!(throttle.is_locked(0, None, 20));
+    }
+
+    async fn lockout(dbs: TestDbs) -> Result<(), UnifiedError> {
         ensure_logging();
 
-        let db = Db::new(TEST_CONNECTION.to_owned());
+        let mut db = Db::new(dbs.auth());
+        db.set_login_throttle(LoginThrottle {
+            free_attempts: 10,
+            backoff_seconds: 1,
+            max_attempts: 3,
+            addr_max_attempts: 5,
+            lockout_seconds: 60,
+        });
         db.ensure_db_schema().await?;
         let mut client = db.connect().await?;
         let t = client.transaction().await?;
-
-        let n_users: usize = USERS.len();
-        assert_eq!(
-            db.add_users(&t, USERS, PASSWORDS, SALTS).await?,
-            n_users as u64
-        );
+        db.add_users(&t, USERS, PASSWORDS, SALTS).await?;
         t.commit().await?;
 
-        for n in 0..n_users {
-            let (uname, pwd, salt) = (USERS[n], PASSWORDS[n], SALTS[n]);
-            assert_eq!(db.check_password(uname, pwd, salt).await?, AuthResult::Ok);
+        for _ in 0..3 {
+            assert_eq!(
+                db.check_login(USERS[0], "wrong", SALTS[0], TEST_ADDR).await?,
+                AuthResult::BadPassword
+            );
         }
-
+        // Even the right password is refused now.
         assert_eq!(
-            db.check_password(USERS[1], "mama moo moo", SALTS[1])
+            db.check_login(USERS[0], PASSWORDS[0], SALTS[0], TEST_ADDR)
                 .await?,
-            AuthResult::BadPassword
+            AuthResult::Locked
         );
         assert_eq!(
-            db.check_password(USERS[1], PASSWORDS[1], "not a real salt")
+            db.check_password_and_issue_key(USERS[0], PASSWORDS[0], SALTS[0], &test_client())
                 .await?,
-            AuthResult::BadPassword
+            AuthResult::Locked
+        );
+        // Other users from other addresses are unaffected.
+        assert_eq!(
+            db.check_login(USERS[1], PASSWORDS[1], SALTS[1], "10.0.0.1")
+                .await?,
+            AuthResult::Ok
         );
 
-        let t = client.transaction().await?;
-        db.delete_users(&t, &USERS[1..]).await?;
-        t.commit().await?;
+        assert_eq!(db.unlock_user(USERS[0]).await?, 3);
+        assert_eq!(
+            db.check_login(USERS[0], PASSWORDS[0], SALTS[0], TEST_ADDR)
+                .await?,
+            AuthResult::Ok
+        );
 
+        // Enough failures from one address lock out every uname from it.
+        for _ in 0..5 {
+            db.record_failed_login("nobody", TEST_ADDR).await?;
+        }
         assert_eq!(
-            db.check_password(USERS[1], PASSWORDS[1], SALTS[1])
-                .await
-                .unwrap(),
-            AuthResult::NoSuchUser
+            db.check_login(USERS[2], PASSWORDS[2], SALTS[2], TEST_ADDR)
+                .await?,
+            AuthResult::Locked
+        );
+        assert_eq!(
+            db.check_login(USERS[2], PASSWORDS[2], SALTS[2], "10.0.0.1")
+                .await?,
+            AuthResult::Ok
         );
 
-        db.nuke_database().await.unwrap();
+        db.nuke_database().await?;
+        Ok(())
+    }
 
-        match db.check_password(USERS[1], PASSWORDS[1], SALTS[1]).await {
-            Err(_) => { /* this is okay */ }
-            x @ _ => {
-                panic!("Expected Err(_), got {:?}", &x);
+    async fn simultaneous_guesses(dbs: TestDbs) -> Result<(), UnifiedError> {
+        ensure_logging();
+
+        let mut db = Db::new(dbs.auth());
+        db.set_login_throttle(LoginThrottle {
+            free_attempts: 10,
+            backoff_seconds: 1,
+            max_attempts: 3,
+            addr_max_attempts: 50,
+            lockout_seconds: 60,
+        });
+        db.ensure_db_schema().await?;
+        let mut client = db.connect().await?;
+        let t = client.transaction().await?;
+        db.add_users(&t, USERS, PASSWORDS, SALTS).await?;
+        t.commit().await?;
+
+        // Guesses made all at once get no more tries than one at a time.
+        let guesses = (0..10).map(|_| db.check_login(USERS[0], "wrong", SALTS[0], TEST_ADDR));
+        let results = futures::future::join_all(guesses).await;
+        let mut n_bad = 0;
+        for res in results.into_iter() {
+            match res? {
+                AuthResult::BadPassword => n_bad += 1,
+                AuthResult::Locked => {}
+                x => panic!("Expected BadPassword or Locked, got {:?}", &x),
             }
         }
+        assert_eq!(n_bad, 3);
 
+        db.nuke_database().await?;
         Ok(())
     }
 
-    #[tokio::test]
-    #[serial]
-    async fn issue_keys() -> Result<(), UnifiedError> {
-        use std::time::Duration;
-        use tokio::time::sleep;
-
+    async fn simultaneous_codes(dbs: TestDbs) -> Result<(), UnifiedError> {
         ensure_logging();
 
-        let mut db = Db::new(TEST_CONNECTION.to_owned());
-        db.ensure_db_schema().await.unwrap();
+        let db = Db::new(dbs.auth());
+        db.ensure_db_schema().await?;
         let mut client = db.connect().await?;
         let t = client.transaction().await?;
-
-        db.add_users(&t, USERS, PASSWORDS, SALTS).await.unwrap();
+        db.add_users(&t, USERS, PASSWORDS, SALTS).await?;
         t.commit().await?;
 
-        let key = match db
-            .check_password_and_issue_key(USERS[0], PASSWORDS[0], SALTS[0])
-            .await?
-        {
-            AuthResult::Key(k) => k,
-            x @ _ => {
-                panic!("Expected AuthResult::Key(_), got {:?}", &x);
+        // A code submitted twice at once is only accepted once.
+        let enrollment = db.begin_totp_enrollment(USERS[0]).await?;
+        let code = totp::generate(&enrollment.secret, totp::current_step())?;
+        let clients = [db.connect().await?, db.connect().await?];
+        let checks = clients
+            .iter()
+            .map(|client| db.verify_totp_code(client, USERS[0], &code));
+        let results = futures::future::join_all(checks).await;
+        let mut n_ok = 0;
+        for res in results.into_iter() {
+            match res? {
+                AuthResult::Ok => n_ok += 1,
+                AuthResult::BadCode => {}
+                x => panic!("Expected Ok or BadCode, got {:?}", &x),
             }
-        };
+        }
+        assert_eq!(n_ok, 1);
+        assert!(db.totp_enabled(USERS[0]).await?);
 
-        assert_eq!(db.check_key(USERS[0], &key).await.unwrap(), AuthResult::Ok);
-        assert_eq!(
-            db.check_key(USERS[1], &key).await.unwrap(),
-            AuthResult::InvalidKey
-        );
-        assert_eq!(
-            db.check_key(USERS[0], "wrong_key").await.unwrap(),
-            AuthResult::InvalidKey
-        );
+        db.nuke_database().await?;
+        Ok(())
+    }
 
-        db.set_key_life(1_u64);
-        let key = match db
-            .check_password_and_issue_key(USERS[1], PASSWORDS[1], SALTS[1])
-            .await
-            .unwrap()
-        {
-            AuthResult::Key(k) => k,
-            x @ _ => {
-                panic!("Expected AuthResult::Key(_), got {:?}", &x);
+    async fn simultaneous_reset_requests(dbs: TestDbs) -> Result<(), UnifiedError> {
+        ensure_logging();
+
+        let mut db = Db::new(dbs.auth());
+        db.set_reset_policy(ResetPolicy {
+            max_per_uname: 3,
+            max_per_addr: 50,
+            ..ResetPolicy::default()
+        });
+        db.ensure_db_schema().await?;
+        let mut client = db.connect().await?;
+        let t = client.transaction().await?;
+        db.add_users(&t, USERS, PASSWORDS, SALTS).await?;
+        t.commit().await?;
+
+        // Requests made all at once get no more tokens than one at a time.
+        let requests = (0..10).map(|_| db.issue_reset_token(USERS[0], TEST_ADDR));
+        let results = futures::future::join_all(requests).await;
+        let mut n_issued = 0;
+        for res in results.into_iter() {
+            match res? {
+                AuthResult::Key(_) => n_issued += 1,
+                AuthResult::Locked => {}
+                x => panic!("Expected Key(_) or Locked, got {:?}", &x),
             }
-        };
-        assert_eq!(db.check_key(USERS[1], &key).await.unwrap(), AuthResult::Ok);
-        sleep(Duration::from_millis(1500)).await;
-        assert_eq!(
-            db.check_key(USERS[1], &key).await.unwrap(),
-            AuthResult::InvalidKey
-        );
-        db.cull_old_keys().await.unwrap();
+        }
+        assert_eq!(n_issued, 3);
 
         db.nuke_database().await?;
         Ok(())
     }
+
+    backend_tests!(
+        populate_db,
+        upgrade_legacy_hashes,
+        issue_keys,
+        second_factor,
+        sessions,
+        impersonation_keys,
+        reset_tokens,
+        invites,
+        grants,
+        api_tokens,
+        hashed_keys,
+        lockout,
+        simultaneous_guesses,
+        simultaneous_codes,
+        simultaneous_reset_requests,
+    );
 }
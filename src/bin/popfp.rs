@@ -91,7 +91,7 @@ fn csv_file_to_staff<R: Read>(r: R) -> Result<(Vec<User>, Vec<String>), String>
                     .ok_or_else(|| format!("Line {}: no name for teacher.", &n))?;
                 bu.into_teacher(name.to_owned())
             }
-            Role::Student => {
+            Role::Student | Role::Parent => {
                 return Err(format!("Line {} should not contain a student.", &n));
             }
         };
@@ -13,26 +13,30 @@ use std::{
     path::{Path, PathBuf},
     str::FromStr,
     sync::Arc,
+    time::Duration,
 };
 
 use rand::{distributions, Rng};
 use serde::Deserialize;
 use time::Date;
-use tokio::sync::RwLock;
-use tokio_postgres::types::{ToSql, Type};
+use arc_swap::ArcSwap;
+use tokio::sync::{Mutex, MutexGuard, RwLock};
+use crate::db::ToValue;
 
 use crate::{
     academic_year_from_start_year,
     auth,
     auth::AuthResult,
-    course::{Chapter, Course},
+    course::{Chapter, Course, Custom},
     hist::HistEntry,
     inter,
     MiniString,
-    pace::{Goal, Pace, Source, Term},
+    pace::{CustomCh, Goal, Pace, Source, Term},
+    perm,
+    pool::PoolConfig,
     SMALLSTORE,
-    store::Store,
-    user::{Role, Student, User},
+    store::{Store, Versioned},
+    user::{Parent, Role, Student, User},
     UnifiedError,
 };
 
@@ -84,6 +88,32 @@ fn bad_uname(uname: &str) -> bool {
     false
 }
 
+/**
+Generate a uname for a new Parent from their email address: the alphanumeric
+characters before the `@`, with a number appended if necessary to make it
+distinct from every uname for which `taken` returns true.
+*/
+fn parent_uname<F: Fn(&str) -> bool>(email: &str, taken: F) -> String {
+    let local = email.split('@').next().unwrap_or("");
+    let mut base: String = local
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase();
+    if base.is_empty() {
+        base.push_str("parent");
+    }
+    if !taken(&base) {
+        return base;
+    }
+
+    // The iterator is unbounded, so this will always find something.
+    (2..)
+        .map(|n| format!("{}{}", &base, n))
+        .find(|uname| !taken(uname))
+        .unwrap()
+}
+
 static BAD_UNAME_MSG: &str =
     "A uname can only contain alphanumeric ASCII characters: a-z, A-Z, or 0-9.";
 
@@ -96,6 +126,26 @@ massaged into data structures that are required for the system's
 operation, but less amenable to being read directly from a textual
 configuration file.
 
+Any of these can also be set (or overridden) by an environment variable
+named after it, in uppercase and prefixed with `CAMP_`
+(`CAMP_SENDGRID_AUTH_STRING`, say), or read from a file (like a mounted
+secret) named by the same variable with `_FILE` on the end
+(`CAMP_SENDGRID_AUTH_STRING_FILE`). A tenant's configuration (see
+[`crate::tenant`]) can also be overridden by variables prefixed with
+`CAMP_<ID>_` instead, where `<ID>` is its id in uppercase (with any `-`s
+changed to `_`s). From most to least important:
+
+  * `PORT` (for `port` only)
+  * `CAMP_<ID>_<KEY>` or `CAMP_<ID>_<KEY>_FILE` (for tenants only)
+  * `CAMP_<KEY>` or `CAMP_<KEY>_FILE`
+  * the configuration file
+  * the default value
+
+A variable and its `_FILE` variant can't both be set. Lists (like
+`social_traits`) can be given as TOML arrays or as comma-separated values.
+On startup, where each setting came from is logged, along with its value,
+unless it's a secret (a password, token, or connection string).
+
 This struct and its members are only `pub` so that the configuration
 documentation will show up with `cargo doc`.
 */
@@ -103,11 +153,15 @@ documentation will show up with `cargo doc`.
 pub struct ConfigFile {
     /// Base URI of the system, the one that should serve the login page.
     pub uri: Option<String>,
+    /// Name of the school, shown at the top of Student and Parent views.
+    pub school_name: Option<String>,
     /// Connection string for the authorization database [`auth::Db`]. See
-    /// [`tokio_postgres::config::Config`] for the appropriate format(s).
+    /// [`tokio_postgres::config::Config`] for the appropriate format(s), or
+    /// use `sqlite:path/to/file.db` to keep the database in a SQLite file
+    /// instead (see [`db::open`](crate::db::open)).
     pub auth_db_connect_string: Option<String>,
     /// Connection string for the data database [`store::Store`](crate::store::Store).
-    /// See again the `tokio::postgres` documentation.
+    /// See again the `tokio::postgres` documentation; `sqlite:` works here, too.
     pub data_db_connect_string: Option<String>,
     /// User name of the default Admin user account who should be guaranteed
     /// to exist.
@@ -145,6 +199,325 @@ pub struct ConfigFile {
     /// "From" format for Pandoc requests. This is largely for experimenting
     /// with different extensions.
     pub pandoc_format: Option<String>,
+    /// Number of failed login attempts a user gets before further attempts
+    /// start being delayed. (See [`auth::LoginThrottle`].)
+    pub login_free_attempts: Option<u32>,
+    /// Delay (in seconds) imposed after the first failed attempt beyond
+    /// `login_free_attempts`; doubles with each subsequent failure.
+    pub login_backoff_seconds: Option<u64>,
+    /// Number of failed login attempts that will lock a user's account.
+    pub login_max_attempts: Option<u32>,
+    /// Number of failed login attempts (for any unames) that will lock out
+    /// a single client address.
+    pub login_addr_max_attempts: Option<u32>,
+    /// Failed login attempts older than this many seconds no longer count
+    /// toward a lockout.
+    pub login_lockout_seconds: Option<u64>,
+    /// If `true`, take each client's address (for login throttling, and to
+    /// record with sessions) from the last entry of the `X-Forwarded-For`
+    /// header, which the proxy in front of the server (Cloud Run's, say)
+    /// adds. Only turn this on if every request comes through such a proxy;
+    /// otherwise clients could set the header themselves. Off by default, in
+    /// which case the address of the connecting socket is used.
+    pub trust_proxy_headers: Option<bool>,
+    /// If `true`, Admins and Bosses must enroll in (and thereafter use)
+    /// TOTP two-factor authentication to log in. Otherwise it's optional.
+    pub require_2fa: Option<bool>,
+    /// Number of seconds a password reset token remains valid.
+    /// (See [`auth::ResetPolicy`].)
+    pub reset_token_life_seconds: Option<u64>,
+    /// Maximum number of password reset emails that will be sent for a
+    /// single user per hour.
+    pub reset_max_per_uname: Option<u32>,
+    /// Maximum number of password reset emails that will be sent in
+    /// response to requests from a single client address per hour.
+    pub reset_max_per_addr: Option<u32>,
+    /// Number of seconds an invitation to set a password remains valid.
+    pub invite_life_seconds: Option<u64>,
+    /// Maximum number of connections to hold open to each database.
+    /// (See [`PoolConfig`].)
+    pub db_pool_size: Option<usize>,
+    /// Number of seconds to wait to connect to a database (or for a pooled
+    /// connection to become free) before giving up.
+    pub db_timeout_seconds: Option<u64>,
+    /// If `true` (the default), check that each pooled database connection
+    /// can still run a query before reusing it.
+    pub db_health_check: Option<bool>,
+    /// Number of seconds between complete rereadings of everything cached
+    /// from the databases, in case a change made by another server process
+    /// went unnoticed (see [`crate::notify`]). `0` means never.
+    pub cache_resync_seconds: Option<u64>,
+    /// If `true`, reload the templates whenever anything in `templates_dir`
+    /// changes (see [`inter::watch_templates`]). This is for working on the
+    /// templates; it's off by default.
+    pub dev_mode: Option<bool>,
+}
+
+/// The kind of value a [`ConfigFile`] setting takes, which determines how
+/// it's read from an environment variable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    Text,
+    Integer,
+    Boolean,
+    /// A TOML array of strings, or a comma-separated list.
+    List,
+}
+
+/// A [`ConfigFile`] setting.
+struct Key {
+    name: &'static str,
+    kind: Kind,
+    /// Secrets (passwords, tokens, and connection strings, which may have
+    /// passwords in them) are never logged.
+    secret: bool,
+}
+
+const fn key(name: &'static str, kind: Kind, secret: bool) -> Key {
+    Key { name, kind, secret }
+}
+
+/// Every [`ConfigFile`] setting, in the order they're logged.
+const KEYS: &[Key] = &[
+    key("uri", Kind::Text, false),
+    key("school_name", Kind::Text, false),
+    key("auth_db_connect_string", Kind::Text, true),
+    key("data_db_connect_string", Kind::Text, true),
+    key("admin_uname", Kind::Text, false),
+    key("admin_password", Kind::Text, true),
+    key("admin_email", Kind::Text, false),
+    key("sendgrid_auth_string", Kind::Text, true),
+    key("social_traits", Kind::List, false),
+    key("host", Kind::Text, false),
+    key("port", Kind::Integer, false),
+    key("templates_dir", Kind::Text, false),
+    key("pandoc_uri", Kind::Text, false),
+    key("pandoc_auth", Kind::Text, true),
+    key("pandoc_format", Kind::Text, false),
+    key("login_free_attempts", Kind::Integer, false),
+    key("login_backoff_seconds", Kind::Integer, false),
+    key("login_max_attempts", Kind::Integer, false),
+    key("login_addr_max_attempts", Kind::Integer, false),
+    key("login_lockout_seconds", Kind::Integer, false),
+    key("trust_proxy_headers", Kind::Boolean, false),
+    key("require_2fa", Kind::Boolean, false),
+    key("reset_token_life_seconds", Kind::Integer, false),
+    key("reset_max_per_uname", Kind::Integer, false),
+    key("reset_max_per_addr", Kind::Integer, false),
+    key("invite_life_seconds", Kind::Integer, false),
+    key("db_pool_size", Kind::Integer, false),
+    key("db_timeout_seconds", Kind::Integer, false),
+    key("db_health_check", Kind::Boolean, false),
+    key("cache_resync_seconds", Kind::Integer, false),
+    key("dev_mode", Kind::Boolean, false),
+];
+
+/// Where the value of a configuration setting came from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Origin {
+    /// It wasn't set anywhere, so it has its default value.
+    Default,
+    /// The configuration file.
+    File,
+    /// The named environment variable.
+    Env(String),
+    /// The file named by the named environment variable.
+    EnvFile(String),
+}
+
+impl std::fmt::Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Origin::Default => write!(f, "default"),
+            Origin::File => write!(f, "config file"),
+            Origin::Env(var) => write!(f, "${}", var),
+            Origin::EnvFile(var) => write!(f, "file named by ${}", var),
+        }
+    }
+}
+
+/// Where one setting's value came from, and what it was (unless it's a
+/// secret).
+#[derive(Clone, Debug)]
+pub struct Setting {
+    pub key: &'static str,
+    pub origin: Origin,
+    /// The value as it would be written in TOML, or `None` if it wasn't set
+    /// or is a secret.
+    pub shown: Option<String>,
+}
+
+/**
+The prefixes of the environment variables that can override the settings
+of a configuration file, most important first: `CAMP_<ID>_` (with `<ID>`
+the uppercased `tenant` id, and any `-`s changed to `_`s) if it's for a
+tenant (see [`crate::tenant`]), then `CAMP_`.
+*/
+fn env_prefixes(tenant: Option<&str>) -> Vec<String> {
+    let mut prefixes = Vec::with_capacity(2);
+    if let Some(id) = tenant {
+        prefixes.push(format!("CAMP_{}_", id.to_ascii_uppercase().replace('-', "_")));
+    }
+    prefixes.push("CAMP_".to_owned());
+    prefixes
+}
+
+/// Interpret `text` (from an environment variable) as the value of a setting
+/// of the given `kind`.
+fn env_value(text: &str, kind: Kind) -> Result<toml::Value, String> {
+    match kind {
+        Kind::Text => Ok(toml::Value::String(text.to_owned())),
+        Kind::Integer => text
+            .trim()
+            .parse::<i64>()
+            .map(toml::Value::Integer)
+            .map_err(|_| format!("should be a whole number, not {:?}", text)),
+        Kind::Boolean => match text.trim().to_ascii_lowercase().as_str() {
+            "true" => Ok(toml::Value::Boolean(true)),
+            "false" => Ok(toml::Value::Boolean(false)),
+            _ => Err(format!("should be true or false, not {:?}", text)),
+        },
+        Kind::List => {
+            let text = text.trim();
+            if text.starts_with('[') {
+                let wrapped: toml::value::Table = toml::from_str(&format!("list = {}", text))
+                    .map_err(|e| format!("isn't a TOML array: {}", &e))?;
+                Ok(wrapped["list"].clone())
+            } else {
+                Ok(toml::Value::Array(
+                    text.split(',')
+                        .map(str::trim)
+                        .filter(|s| !s.is_empty())
+                        .map(|s| toml::Value::String(s.to_owned()))
+                        .collect(),
+                ))
+            }
+        }
+    }
+}
+
+/**
+Override the settings in `table` (the contents of a configuration file) with
+any set in the environment, which is read with `var`, and report where each
+setting came from.
+
+For each setting `key`, and each of the `prefixes` in turn, the variable
+`<prefix><KEY>` holds the value itself, and `<prefix><KEY>_FILE` names a
+file that holds it (like a mounted secret; a single trailing newline is
+ignored). The first prefix with either one set wins. It's an error for
+both to be set.
+
+`PORT`, if it's set (and is a port number), beats all of these.
+*/
+fn apply_env<F>(
+    table: &mut toml::value::Table,
+    prefixes: &[String],
+    var: F,
+) -> Result<Vec<Setting>, String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut settings: Vec<Setting> = Vec::with_capacity(KEYS.len());
+
+    for k in KEYS.iter() {
+        let mut origin = if table.contains_key(k.name) {
+            Origin::File
+        } else {
+            Origin::Default
+        };
+
+        if k.name == "port" {
+            if let Some(text) = var("PORT") {
+                match text.parse::<u16>() {
+                    Ok(n) => {
+                        table.insert(k.name.to_owned(), toml::Value::Integer(n.into()));
+                        settings.push(Setting {
+                            key: k.name,
+                            origin: Origin::Env("PORT".to_owned()),
+                            shown: Some(n.to_string()),
+                        });
+                        continue;
+                    }
+                    Err(e) => {
+                        log::warn!(
+                            "Unable to parse $PORT {:?}: {}; using default or configured value.",
+                            &text,
+                            &e
+                        );
+                    }
+                }
+            }
+        }
+
+        for prefix in prefixes.iter() {
+            let name = format!("{}{}", prefix, k.name.to_ascii_uppercase());
+            let file_name = format!("{}_FILE", &name);
+            let (text, used, from_file) = match (var(&name), var(&file_name)) {
+                (None, None) => {
+                    continue;
+                }
+                (Some(_), Some(_)) => {
+                    return Err(format!(
+                        "Both ${} and ${} are set; only one of them may be.",
+                        &name, &file_name
+                    ));
+                }
+                (Some(text), None) => (text, name, false),
+                (None, Some(path)) => {
+                    let text = std::fs::read_to_string(&path).map_err(|e| {
+                        format!("Unable to read {:?} (named by ${}): {}", &path, &file_name, &e)
+                    })?;
+                    let text = text
+                        .strip_suffix('\n')
+                        .map(|t| t.strip_suffix('\r').unwrap_or(t))
+                        .unwrap_or(&text)
+                        .to_owned();
+                    (text, file_name, true)
+                }
+            };
+
+            let value = env_value(&text, k.kind).map_err(|e| format!("${}: {}.", &used, &e))?;
+            table.insert(k.name.to_owned(), value);
+            origin = if from_file {
+                Origin::EnvFile(used)
+            } else {
+                Origin::Env(used)
+            };
+            break;
+        }
+
+        let shown = match (k.secret, table.get(k.name)) {
+            (false, Some(val)) => Some(val.to_string()),
+            _ => None,
+        };
+        settings.push(Setting {
+            key: k.name,
+            origin,
+            shown,
+        });
+    }
+
+    Ok(settings)
+}
+
+/// Set the port of `addr` from the `PORT` environment variable, if it's set
+/// (and makes sense).
+pub(crate) fn port_from_env(addr: &mut SocketAddr) {
+    if let Ok(port_str) = std::env::var("PORT") {
+        match port_str.parse::<u16>() {
+            Ok(n) => {
+                log::info!("Using value of $PORT: {}", &n);
+                addr.set_port(n);
+            }
+            Err(e) => {
+                log::warn!(
+                    "Unable to parse $PORT {:?}: {}; using default or configured value.",
+                    &port_str,
+                    &e
+                )
+            }
+        }
+    }
 }
 
 /**
@@ -155,6 +528,7 @@ If I were more clever, it probably wouldn't need to exist.
 #[derive(Debug)]
 pub struct Cfg {
     pub uri: String,
+    pub school_name: String,
     pub auth_db_connect_string: String,
     pub data_db_connect_string: String,
     pub default_admin_uname: String,
@@ -167,12 +541,23 @@ pub struct Cfg {
     pub pandoc_uri: hyper::Uri,
     pub pandoc_auth: String,
     pub pandoc_format: Option<String>,
+    pub login_throttle: auth::LoginThrottle,
+    pub trust_proxy_headers: bool,
+    pub require_2fa: bool,
+    pub reset_policy: auth::ResetPolicy,
+    pub invite_life_seconds: Option<u64>,
+    pub pool_config: PoolConfig,
+    pub cache_resync_seconds: u64,
+    pub dev_mode: bool,
+    /// Where each setting came from (see [`Cfg::log_settings`]).
+    pub settings: Vec<Setting>,
 }
 
 impl std::default::Default for Cfg {
     fn default() -> Self {
         Self {
             uri: "localhost:8001/".to_owned(),
+            school_name: "Camelot Academy".to_owned(),
             auth_db_connect_string:
                 "host=localhost user=camp_test password='camp_test' dbname=camp_auth_test"
                     .to_owned(),
@@ -198,25 +583,66 @@ impl std::default::Default for Cfg {
             pandoc_uri: "http://localhost:8002/".parse().unwrap(),
             pandoc_auth: "".to_owned(),
             pandoc_format: None,
+            login_throttle: auth::LoginThrottle::default(),
+            trust_proxy_headers: false,
+            require_2fa: false,
+            reset_policy: auth::ResetPolicy::default(),
+            invite_life_seconds: None,
+            pool_config: PoolConfig::default(),
+            cache_resync_seconds: 300,
+            dev_mode: false,
+            settings: Vec::new(),
         }
     }
 }
 
 impl Cfg {
-    #[allow(clippy::field_reassign_with_default)]
+    /// Read the configuration file at `path`, overriding its settings with
+    /// any `CAMP_*` environment variables (see [`ConfigFile`]).
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, String> {
-        let path = path.as_ref();
+        Self::from_file_and_env(path.as_ref(), &env_prefixes(None), |name| {
+            std::env::var(name).ok()
+        })
+    }
+
+    /// Read the configuration file at `path` of the tenant with the given
+    /// `id`, overriding its settings with any `CAMP_<ID>_*` or `CAMP_*`
+    /// environment variables (see [`ConfigFile`]).
+    pub fn for_tenant<P: AsRef<Path>>(path: P, id: &str) -> Result<Self, String> {
+        Self::from_file_and_env(path.as_ref(), &env_prefixes(Some(id)), |name| {
+            std::env::var(name).ok()
+        })
+    }
+
+    fn from_file_and_env<F>(path: &Path, prefixes: &[String], var: F) -> Result<Self, String>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
         let file_contents = std::fs::read_to_string(path)
             .map_err(|e| format!("Unable to read config file: {}", &e))?;
-        let cf: ConfigFile = toml::from_str(&file_contents)
+        let mut table: toml::value::Table = toml::from_str(&file_contents)
+            .map_err(|e| format!("Unable to deserialize config file: {}", &e))?;
+        let settings = apply_env(&mut table, prefixes, var)?;
+        let cf: ConfigFile = toml::Value::Table(table)
+            .try_into()
             .map_err(|e| format!("Unable to deserialize config file: {}", &e))?;
 
+        let mut c = Self::from_config_file(cf)?;
+        c.settings = settings;
+        Ok(c)
+    }
+
+    #[allow(clippy::field_reassign_with_default)]
+    fn from_config_file(cf: ConfigFile) -> Result<Self, String> {
         let mut c = Self::default();
         c.sendgrid_auth_string = cf.sendgrid_auth_string;
 
         if let Some(s) = cf.uri {
             c.uri = s;
         }
+        if let Some(s) = cf.school_name {
+            c.school_name = s;
+        }
         if let Some(s) = cf.auth_db_connect_string {
             c.auth_db_connect_string = s;
         }
@@ -245,22 +671,6 @@ impl Cfg {
             c.addr.set_port(n);
         }
 
-        if let Ok(port_str) = std::env::var("PORT") {
-            match port_str.parse::<u16>() {
-                Ok(n) => {
-                    log::info!("Using value of $PORT: {}", &n);
-                    c.addr.set_port(n);
-                }
-                Err(e) => {
-                    log::warn!(
-                        "Unable to parse $PORT {:?}: {}; using default or configured value.",
-                        &port_str,
-                        &e
-                    )
-                }
-            }
-        }
-
         if let Some(s) = cf.templates_dir {
             c.templates_dir = PathBuf::from(&s);
         }
@@ -274,8 +684,145 @@ impl Cfg {
         }
         c.pandoc_format = cf.pandoc_format;
 
+        if let Some(n) = cf.login_free_attempts {
+            c.login_throttle.free_attempts = n;
+        }
+        if let Some(n) = cf.login_backoff_seconds {
+            c.login_throttle.backoff_seconds = n;
+        }
+        if let Some(n) = cf.login_max_attempts {
+            c.login_throttle.max_attempts = n;
+        }
+        if let Some(n) = cf.login_addr_max_attempts {
+            c.login_throttle.addr_max_attempts = n;
+        }
+        if let Some(n) = cf.login_lockout_seconds {
+            c.login_throttle.lockout_seconds = n;
+        }
+        if let Some(b) = cf.trust_proxy_headers {
+            c.trust_proxy_headers = b;
+        }
+        if let Some(b) = cf.require_2fa {
+            c.require_2fa = b;
+        }
+        if let Some(n) = cf.reset_token_life_seconds {
+            c.reset_policy.token_life_seconds = n;
+        }
+        if let Some(n) = cf.reset_max_per_uname {
+            c.reset_policy.max_per_uname = n;
+        }
+        if let Some(n) = cf.reset_max_per_addr {
+            c.reset_policy.max_per_addr = n;
+        }
+        c.invite_life_seconds = cf.invite_life_seconds;
+        if let Some(n) = cf.db_pool_size {
+            if n == 0 {
+                return Err("db_pool_size must be at least 1.".to_owned());
+            }
+            c.pool_config.max_size = n;
+        }
+        if let Some(n) = cf.db_timeout_seconds {
+            c.pool_config.timeout_seconds = n;
+        }
+        if let Some(b) = cf.db_health_check {
+            c.pool_config.health_check = b;
+        }
+        if let Some(n) = cf.cache_resync_seconds {
+            c.cache_resync_seconds = n;
+        }
+        if let Some(b) = cf.dev_mode {
+            c.dev_mode = b;
+        }
+
         Ok(c)
     }
+
+    /**
+    Log where each setting came from, and its value (unless it's a secret;
+    see [`ConfigFile`]).
+
+    `label` says which configuration this is.
+    */
+    pub fn log_settings(&self, label: &str) {
+        let mut text = format!("Configuration of {}:", label);
+        for s in self.settings.iter() {
+            match (&s.origin, &s.shown) {
+                (Origin::Default, _) => write!(&mut text, "\n    {} (default)", s.key),
+                (origin, Some(val)) => write!(&mut text, "\n    {} = {} ({})", s.key, val, origin),
+                (origin, None) => write!(&mut text, "\n    {} = [secret] ({})", s.key, origin),
+            }
+            .unwrap();
+        }
+        log::info!("{}", &text);
+    }
+
+    /// A handle to the auth DB described by this configuration.
+    pub fn auth_db(&self) -> auth::Db {
+        let mut auth_db = auth::Db::new(self.auth_db_connect_string.clone());
+        self.set_auth_policies(&mut auth_db);
+        auth_db.set_pool_config(self.pool_config.clone());
+        auth_db
+    }
+
+    /// Set the login, password reset, and invitation policies of `auth_db`
+    /// to the ones in this configuration.
+    fn set_auth_policies(&self, auth_db: &mut auth::Db) {
+        auth_db.set_login_throttle(self.login_throttle.clone());
+        auth_db.set_reset_policy(self.reset_policy.clone());
+        auth_db.set_invite_life(
+            self.invite_life_seconds
+                .unwrap_or(auth::DEFAULT_INVITE_LIFE_SECONDS),
+        );
+    }
+
+    /**
+    Check whether a server running with this configuration can switch to
+    `new` without restarting.
+
+    It can't if `new` names different databases. Otherwise this returns the
+    names of the settings that differ but are only read at startup, so
+    won't take effect until the next restart.
+    */
+    pub fn restart_only_changes(&self, new: &Cfg) -> Result<Vec<&'static str>, String> {
+        if new.auth_db_connect_string != self.auth_db_connect_string {
+            return Err(
+                "auth_db_connect_string can't be changed without restarting the server.".to_owned(),
+            );
+        }
+        if new.data_db_connect_string != self.data_db_connect_string {
+            return Err(
+                "data_db_connect_string can't be changed without restarting the server.".to_owned(),
+            );
+        }
+
+        let mut changed: Vec<&'static str> = Vec::new();
+        if new.addr != self.addr {
+            changed.push("host/port");
+        }
+        if new.default_admin_uname != self.default_admin_uname
+            || new.default_admin_password != self.default_admin_password
+            || new.default_admin_email != self.default_admin_email
+        {
+            changed.push("admin_uname/admin_password/admin_email");
+        }
+        if new.pool_config != self.pool_config {
+            changed.push("db_pool_size/db_timeout_seconds/db_health_check");
+        }
+        if new.cache_resync_seconds != self.cache_resync_seconds {
+            changed.push("cache_resync_seconds");
+        }
+        if new.dev_mode != self.dev_mode {
+            changed.push("dev_mode");
+        }
+        Ok(changed)
+    }
+
+    /// A handle to the data DB described by this configuration.
+    pub fn data_db(&self) -> Store {
+        let mut data_db = Store::new(self.data_db_connect_string.clone());
+        data_db.set_pool_config(self.pool_config.clone());
+        data_db
+    }
 }
 
 /**
@@ -291,23 +838,122 @@ As the `Glob` holds so much information (and in particular handles to both
 databases), operations involving "checks" or data from multiple sources or
 of multiple kinds are often most easily conducted "through" it. This is
 reflected in its profusion of methods.
+
+Handlers share it through a [`GlobCell`], which hands out immutable
+snapshots of it.
 */
+#[derive(Clone)]
 pub struct Glob {
     auth: Arc<RwLock<auth::Db>>,
     data: Arc<RwLock<Store>>,
     pub uri: String,
+    /// Name of the school this `Glob` serves (see [`crate::tenant`]).
+    pub school_name: String,
     pub sendgrid_auth: String,
     pub calendar: Vec<Date>,
     pub dates: HashMap<String, Date>,
     pub courses: HashMap<i64, Course>,
     pub course_syms: HashMap<String, i64>,
+    pub custom_chapters: HashMap<i64, Custom>,
     pub users: HashMap<String, User>,
+    /// Extra permissions granted to individual users.
+    pub grants: perm::Grants,
     pub addr: SocketAddr,
     pub pwd_chars: Vec<char>,
     pub pandoc_uri: hyper::Uri,
     pub pandoc_auth: String,
     pub social_traits: Vec<String>,
     pub pandoc_format: Option<String>,
+    pub require_2fa: bool,
+    /// Whether to believe the `X-Forwarded-For` header (see
+    /// [`ConfigFile::trust_proxy_headers`]).
+    pub trust_proxy_headers: bool,
+    /// How often to reread everything cached here from the databases, if at
+    /// all (see [`crate::notify`]).
+    pub cache_resync: Option<Duration>,
+    /// The configuration this was loaded (or last reloaded) from.
+    cfg: Arc<Cfg>,
+}
+
+/**
+The [`Glob`] shared by the request handlers (and anything else), as a
+succession of immutable snapshots.
+
+Readers [`load()`](GlobCell::load) the current snapshot, which never waits,
+and can keep using it for as long as they need a consistent view of things,
+even after a newer one has been published. Writers take turns (see
+[`write()`](GlobCell::write)); each makes its changes to a copy of the
+current snapshot, which replaces it when the writer is done. Readers are
+never held up by writers, however long those take.
+
+The database handles aren't copied; every snapshot shares the same ones.
+*/
+pub struct GlobCell {
+    current: ArcSwap<Glob>,
+    turn: Mutex<()>,
+}
+
+impl GlobCell {
+    pub fn new(glob: Glob) -> GlobCell {
+        GlobCell {
+            current: ArcSwap::from_pointee(glob),
+            turn: Mutex::new(()),
+        }
+    }
+
+    /// The current snapshot of the `Glob`.
+    pub fn load(&self) -> Arc<Glob> {
+        self.current.load_full()
+    }
+
+    /**
+    Wait for any other writers to finish, then return a copy of the current
+    `Glob` to change. The changes are published as the new current snapshot
+    when the returned [`GlobWriter`] is dropped.
+
+    Any database work that doesn't need the new snapshot should be done
+    before calling this, so as not to hold up other writers.
+    */
+    pub async fn write(&self) -> GlobWriter<'_> {
+        let turn = self.turn.lock().await;
+        let glob = Glob::clone(&self.current.load());
+        GlobWriter {
+            cell: self,
+            glob: Some(glob),
+            _turn: turn,
+        }
+    }
+}
+
+/// A copy of the current [`Glob`] being changed; see [`GlobCell::write`].
+pub struct GlobWriter<'a> {
+    cell: &'a GlobCell,
+    // Only `None` once it's been published.
+    glob: Option<Glob>,
+    // Dropped (letting the next writer go) only after publishing.
+    _turn: MutexGuard<'a, ()>,
+}
+
+impl std::ops::Deref for GlobWriter<'_> {
+    type Target = Glob;
+
+    fn deref(&self) -> &Glob {
+        self.glob.as_ref().unwrap()
+    }
+}
+
+impl std::ops::DerefMut for GlobWriter<'_> {
+    fn deref_mut(&mut self) -> &mut Glob {
+        self.glob.as_mut().unwrap()
+    }
+}
+
+impl Drop for GlobWriter<'_> {
+    fn drop(&mut self) {
+        if let Some(glob) = self.glob.take() {
+            self.cell.current.store(Arc::new(glob));
+        }
+    }
 }
 
 impl<'a> Glob {
@@ -320,6 +966,36 @@ impl<'a> Glob {
         self.data.clone()
     }
 
+    /// The configuration this was loaded (or last reloaded) from.
+    pub fn cfg(&self) -> &Cfg {
+        &self.cfg
+    }
+
+    /**
+    Start using the settings in `cfg` in place of the ones this was loaded
+    with.
+
+    `cfg` should already have been checked with
+    [`Cfg::restart_only_changes`]; the settings that only take effect on
+    restart are left alone.
+    */
+    pub async fn reconfigure(&mut self, cfg: Cfg) {
+        log::trace!("Glob::reconfigure( ... ) called.");
+
+        self.uri = cfg.uri.clone();
+        self.school_name = cfg.school_name.clone();
+        self.sendgrid_auth = cfg.sendgrid_auth_string.clone();
+        self.social_traits = cfg.social_traits.clone();
+        self.pandoc_uri = cfg.pandoc_uri.clone();
+        self.pandoc_auth = cfg.pandoc_auth.clone();
+        self.pandoc_format = cfg.pandoc_format.clone();
+        self.require_2fa = cfg.require_2fa;
+        self.trust_proxy_headers = cfg.trust_proxy_headers;
+        cfg.set_auth_policies(&mut *self.auth.write().await);
+
+        self.cfg = Arc::new(cfg);
+    }
+
     /// Generate a random password (for inserting a new user).
     fn random_password(&self, length: usize) -> String {
         let dist = distributions::Slice::new(&self.pwd_chars).unwrap();
@@ -343,6 +1019,55 @@ impl<'a> Glob {
         Ok(())
     }
 
+    /// Reread just the user `uname` from the database, for when only that
+    /// one user has changed.
+    pub async fn refresh_user(&mut self, uname: &str) -> Result<(), String> {
+        log::trace!("Glob::refresh_user( {:?} ) called.", uname);
+        let u = self
+            .data
+            .read()
+            .await
+            .get_user_by_uname(uname)
+            .await
+            .map_err(|e| format!("Error retrieving {:?} from Data DB: {}", uname, &e))?;
+        match u {
+            Some(u) => {
+                self.users.insert(uname.to_owned(), u);
+            }
+            None => {
+                self.users.remove(uname);
+            }
+        }
+        Ok(())
+    }
+
+    /// Reread everything this `Glob` caches from the databases, for when
+    /// they may have been changed by something other than this process.
+    pub async fn refresh_all(&mut self) -> Result<(), String> {
+        log::trace!("Glob::refresh_all() called.");
+        self.refresh_courses().await?;
+        self.refresh_custom_chapters().await?;
+        self.refresh_users().await?;
+        self.refresh_grants().await?;
+        self.refresh_calendar().await?;
+        self.refresh_dates().await
+    }
+
+    /// Retrieve all extra permissions from the auth database and replace the
+    /// current `.grants` with them.
+    pub async fn refresh_grants(&mut self) -> Result<(), String> {
+        log::trace!("Glob::refresh_grants() called.");
+        let new_grants = self
+            .auth
+            .read()
+            .await
+            .get_grants()
+            .await
+            .map_err(|e| format!("Error retrieving permissions from Auth DB: {}", &e))?;
+        self.grants = perm::Grants::new(new_grants);
+        Ok(())
+    }
+
     /// Retrieve all `Course` data from the database and replace the contents
     /// of the current `.courses` map with it.
     pub async fn refresh_courses(&mut self) -> Result<(), String> {
@@ -364,6 +1089,21 @@ impl<'a> Glob {
         Ok(())
     }
 
+    /// Retrieve all [`Custom`] chapters from the database and replace the
+    /// contents of the current `.custom_chapters` map with them.
+    pub async fn refresh_custom_chapters(&mut self) -> Result<(), String> {
+        log::trace!("Glob::refresh_custom_chapters() called.");
+        let new_customs = self
+            .data
+            .read()
+            .await
+            .get_custom_chapters()
+            .await
+            .map_err(|e| format!("Error retrieving custom chapters from Data DB: {}", &e))?;
+        self.custom_chapters = new_customs;
+        Ok(())
+    }
+
     /// Refresh the internal list of instructional days from the values stored
     /// in the database.
     pub async fn refresh_calendar(&mut self) -> Result<(), String> {
@@ -412,6 +1152,17 @@ impl<'a> Glob {
         }
     }
 
+    /// Return every academic year (by starting year) that has any data
+    /// recorded, along with the current one, in ascending order.
+    pub async fn academic_years(&self) -> Result<Vec<i32>, UnifiedError> {
+        let mut years = self.data.read().await.get_academic_years().await?;
+        let current = self.academic_year();
+        if let Err(n) = years.binary_search(&current) {
+            years.insert(n, current);
+        }
+        Ok(years)
+    }
+
     /// Retrieve a reference to a given [`Course`] by its symbol.
     /// (This is slightly complicated because they are not indexed
     /// internally by course symbol.)
@@ -422,6 +1173,11 @@ impl<'a> Glob {
         }
     }
 
+    /// Retrieve a reference to the [`Custom`] chapter with the given `id`.
+    pub fn custom_chapter(&self, id: i64) -> Option<&Custom> {
+        self.custom_chapters.get(&id)
+    }
+
     /**
     Check to see if any of a Course's data has prohibited characters.
 
@@ -460,6 +1216,17 @@ impl<'a> Glob {
         Ok(())
     }
 
+    /// Check to see if a [`Custom`] chapter's title has "forbidden"
+    /// characters.
+    ///
+    /// (See [`Glob::check_course_for_bad_chars`].)
+    pub fn check_custom_for_bad_chars(cust: &Custom) -> Result<(), String> {
+        if has_bad_chars(&cust.title) {
+            return Err(format!("Custom chapter titles {}", BAD_CHARS_MSG));
+        }
+        Ok(())
+    }
+
     /// Insert the given user into both the auth and the data databases.
     ///
     /// This takes advantage of the fact that it's necessary to insert into
@@ -504,6 +1271,11 @@ impl<'a> Glob {
                 // was a vector of length exactly 1.
                 studs.pop().unwrap().base.salt
             }
+            User::Parent(p) => {
+                self.check_children(p)?;
+                data.insert_parent(&t, &p.base.uname, &p.base.email, &p.children)
+                    .await?
+            }
         };
 
         let new_password = self.random_password(32);
@@ -526,12 +1298,32 @@ impl<'a> Glob {
         Ok(())
     }
 
+    /// Ensure all of Parent `p`'s children are Students.
+    fn check_children(&self, p: &Parent) -> Result<(), UnifiedError> {
+        for uname in p.children.iter() {
+            match self.users.get(uname) {
+                Some(User::Student(_)) => { /* This is what we want. */ }
+                _ => {
+                    return Err(format!("{:?} is not a Student.", uname).into());
+                }
+            }
+        }
+        Ok(())
+    }
+
     /**
     Insert multiple students at once, with data supplied in CSV format.
 
     For CSV file format, see [`Pace::from_csv`].
+
+    Each distinct parent email address gets a Parent account linked to all
+    the Students who list it. If a Parent with that email address already
+    exists (say, with an older sibling), the new Students are added to that
+    Parent's account instead.
+
+    Returns the unames of the inserted students and of any new Parents.
     */
-    pub async fn upload_students(&self, csv_data: &str) -> Result<(), UnifiedError> {
+    pub async fn upload_students(&self, csv_data: &str) -> Result<Vec<String>, UnifiedError> {
         log::trace!(
             "Glob::upload_students( [ {} bytes of CSV body ] ) called.",
             &csv_data.len()
@@ -579,14 +1371,62 @@ impl<'a> Glob {
         let n_studs = data.insert_students(&data_t, &mut students).await?;
         log::trace!("Inserted {} Students into store.", &n_studs);
 
-        let passwords: Vec<String> = students.iter().map(|_| self.random_password(32)).collect();
+        // Siblings share a parent email address, and so share a Parent.
+        let mut families: Vec<(&str, Vec<String>)> = Vec::new();
+        for s in students.iter() {
+            let email = s.parent.trim();
+            if email.is_empty() {
+                continue;
+            }
+            match families
+                .iter_mut()
+                .find(|(e, _)| e.eq_ignore_ascii_case(email))
+            {
+                Some((_, kids)) => kids.push(s.base.uname.clone()),
+                None => families.push((email, vec![s.base.uname.clone()])),
+            }
+        }
+
+        // (uname, salt) of each newly-created Parent
+        let mut new_parents: Vec<(String, String)> = Vec::new();
+        for (email, kids) in families.iter() {
+            let extant = self.users.values().find_map(|u| match u {
+                User::Parent(p) if p.base.email.eq_ignore_ascii_case(email) => {
+                    Some(p.base.uname.as_str())
+                }
+                _ => None,
+            });
+
+            match extant {
+                Some(uname) => {
+                    data.link_parent(&data_t, uname, kids).await?;
+                }
+                None => {
+                    let uname = parent_uname(email, |u| {
+                        self.users.contains_key(u)
+                            || students.iter().any(|s| s.base.uname == u)
+                            || new_parents.iter().any(|(p, _)| p == u)
+                    });
+                    let salt = data.insert_parent(&data_t, &uname, email, kids).await?;
+                    new_parents.push((uname, salt));
+                }
+            }
+        }
+        log::trace!("Inserted {} Parents into store.", new_parents.len());
+
+        let n_users = students.len() + new_parents.len();
+        let passwords: Vec<String> = (0..n_users).map(|_| self.random_password(32)).collect();
         let pword_refs: Vec<&str> = passwords.iter().map(|s| s.as_str()).collect();
-        let mut uname_refs: Vec<&str> = Vec::with_capacity(students.len());
-        let mut salt_refs: Vec<&str> = Vec::with_capacity(students.len());
+        let mut uname_refs: Vec<&str> = Vec::with_capacity(n_users);
+        let mut salt_refs: Vec<&str> = Vec::with_capacity(n_users);
         for s in students.iter() {
             uname_refs.push(&s.base.uname);
             salt_refs.push(&s.base.salt);
         }
+        for (uname, salt) in new_parents.iter() {
+            uname_refs.push(uname);
+            salt_refs.push(salt);
+        }
 
         {
             let auth = self.auth.read().await;
@@ -605,7 +1445,9 @@ impl<'a> Glob {
         )
         })?;
 
-        Ok(())
+        let mut unames: Vec<String> = students.into_iter().map(|s| s.base.uname).collect();
+        unames.extend(new_parents.into_iter().map(|(uname, _)| uname));
+        Ok(unames)
     }
 
     /// Update the user data associated with `u.uname()` with the other data in `u`.
@@ -672,7 +1514,17 @@ impl<'a> Glob {
                 s.fall_notices = old_u.fall_notices;
                 s.spring_notices = old_u.spring_notices;
 
-                data.update_student(&t, &s).await?;
+                if data.update_student(&t, &s).await? == Versioned::Conflict {
+                    return Err(UnifiedError::Conflict(format!(
+                        "{:?} has been changed by someone else since you loaded it.",
+                        &s.base.uname
+                    )));
+                }
+            }
+            User::Parent(p) => {
+                self.check_children(p)?;
+                data.update_parent(&t, &p.base.uname, &p.base.email, &p.children)
+                    .await?;
             }
         }
 
@@ -866,7 +1718,7 @@ impl<'a> Glob {
         let mut client = data_read.connect().await?;
         let t = client.transaction().await?;
 
-        let sym_ref: [&(dyn ToSql + Sync); 1] = [&sym];
+        let sym_ref: [&(dyn ToValue + Sync); 1] = [&sym];
 
         let (goal_rows, hist_rows) = tokio::try_join!(
             t.query("SELECT DISTINCT uname FROM goals WHERE sym = $1", &sym_ref[..]),
@@ -929,6 +1781,7 @@ impl<'a> Glob {
         {
             let mut unk_users: HashSet<String> = HashSet::new();
             let mut unk_courses: HashSet<String> = HashSet::new();
+            let mut unk_customs: HashSet<i64> = HashSet::new();
             for g in goals.iter() {
                 match self.users.get(&g.uname) {
                     Some(User::Student(_)) => { /* This is what we hope is true! */ }
@@ -942,13 +1795,15 @@ impl<'a> Glob {
                             unk_courses.insert(bch.sym.clone());
                         }
                     }
-                    _ => {
-                        return Err("Custom Courses not yet supported.".to_owned().into());
+                    Source::Custom(CustomCh(id)) => {
+                        if !self.custom_chapters.contains_key(&id) {
+                            unk_customs.insert(id);
+                        }
                     }
                 }
             }
 
-            if !(unk_users.is_empty() && unk_courses.is_empty()) {
+            if !(unk_users.is_empty() && unk_courses.is_empty() && unk_customs.is_empty()) {
                 let mut estr = String::new();
                 if !unk_users.is_empty() {
                     writeln!(
@@ -972,18 +1827,49 @@ impl<'a> Glob {
                             .map_err(|e| format!("Error preparing error message: {}!!!", &e))?;
                     }
                 }
+                if !unk_customs.is_empty() {
+                    writeln!(
+                        &mut estr,
+                        "The following ids do not belong to known custom chapters:"
+                    )
+                    .map_err(|e| format!("Error preparing error message: {}!!!", &e))?;
+                    for id in unk_customs.iter() {
+                        writeln!(&mut estr, "{}", id)
+                            .map_err(|e| format!("Error preparing error message: {}!!!", &e))?;
+                    }
+                }
 
                 return Err(estr.into());
             }
         }
 
-        let n_inserted = self.data.read().await.insert_goals(goals).await?;
+        let n_inserted = self
+            .data
+            .read()
+            .await
+            .insert_goals(goals, self.academic_year())
+            .await?;
         Ok(n_inserted)
     }
 
     /// Return the [`Pace`] calendar data for the Student with the given `uname`.
     pub async fn get_pace_by_student(&self, uname: &str) -> Result<Pace, UnifiedError> {
-        log::trace!("Glob::get_pace_by_student( {:?} ) called.", uname);
+        self.get_pace_by_student_for_year(uname, self.academic_year())
+            .await
+    }
+
+    /// Return the [`Pace`] calendar data for the Student with the given
+    /// `uname` from the academic year starting in `year`.
+    pub async fn get_pace_by_student_for_year(
+        &self,
+        uname: &str,
+        year: i32,
+    ) -> Result<Pace, UnifiedError> {
+        log::trace!(
+            "Glob::get_pace_by_student_for_year( {:?}, {} ) called.",
+            uname,
+            year
+        );
 
         let stud = match self.users.get(uname) {
             Some(User::Student(s)) => s.clone(),
@@ -1002,7 +1888,12 @@ impl<'a> Glob {
             }
         };
 
-        let goals = self.data.read().await.get_goals_by_student(uname).await?;
+        let goals = self
+            .data
+            .read()
+            .await
+            .get_goals_by_student(uname, year)
+            .await?;
 
         let p = Pace::new(stud, teach, goals, self)?;
         Ok(p)
@@ -1010,7 +1901,22 @@ impl<'a> Glob {
 
     /// Get [`Pace`]s for all Students who have the Teacher with the given `uname`.
     pub async fn get_paces_by_teacher(&self, tuname: &str) -> Result<Vec<Pace>, UnifiedError> {
-        log::trace!("Glob::get_paces_by_teacher( {:?} ) called.", tuname);
+        self.get_paces_by_teacher_for_year(tuname, self.academic_year())
+            .await
+    }
+
+    /// Get [`Pace`]s from the academic year starting in `year` for all
+    /// Students who (currently) have the Teacher with the given `uname`.
+    pub async fn get_paces_by_teacher_for_year(
+        &self,
+        tuname: &str,
+        year: i32,
+    ) -> Result<Vec<Pace>, UnifiedError> {
+        log::trace!(
+            "Glob::get_paces_by_teacher_for_year( {:?}, {} ) called.",
+            tuname,
+            year
+        );
 
         let teach = match self.users.get(tuname) {
             Some(User::Teacher(t)) => t.clone(),
@@ -1021,7 +1927,12 @@ impl<'a> Glob {
 
         let students = self.get_students_by_teacher(tuname);
 
-        let mut goals = self.data.read().await.get_goals_by_teacher(tuname).await?;
+        let mut goals = self
+            .data
+            .read()
+            .await
+            .get_goals_by_teacher(tuname, year)
+            .await?;
 
         let mut goal_map: HashMap<String, Vec<Goal>> = HashMap::with_capacity(students.len());
 
@@ -1071,13 +1982,16 @@ impl<'a> Glob {
     pub async fn get_reports_archive_by_teacher(
         &self,
         tuname: &str,
+        year: i32,
         term: Term,
     ) -> Result<Option<Vec<u8>>, UnifiedError> {
         use std::io::Write;
         use zip::{write::FileOptions, CompressionMethod, ZipWriter};
         log::trace!(
-            "Glob::get_reports_archive_by_teacher( {:?} ) called.",
-            tuname
+            "Glob::get_reports_archive_by_teacher( {:?}, {}, {:?} ) called.",
+            tuname,
+            year,
+            &term
         );
 
         /*
@@ -1098,7 +2012,7 @@ impl<'a> Glob {
 
         let term_str = term.as_str();
         let stud_refs = self.get_students_by_teacher(tuname);
-        let params: Vec<[&(dyn ToSql + Sync); 2]> = stud_refs
+        let params: Vec<[&(dyn ToValue + Sync); 3]> = stud_refs
             .iter()
             .map(|u| match u {
                 User::Student(s) => Some(s),
@@ -1106,9 +2020,10 @@ impl<'a> Glob {
             })
             .filter(|s| s.is_some())
             .map(|s| {
-                let p: [&(dyn ToSql + Sync); 2] = [
+                let p: [&(dyn ToValue + Sync); 3] = [
                     &s.unwrap().base.uname,
-                    &term_str
+                    &term_str,
+                    &year,
                 ];
                 p
             })
@@ -1124,17 +2039,14 @@ impl<'a> Glob {
         let reader = data.read().await;
         let mut client = reader.connect().await?;
         let t = client.transaction().await?;
-        let stmt = t.prepare_typed(
-            "SELECT doc FROM reports WHERE uname = $1 AND term = $2",
-            &[Type::TEXT, Type::TEXT]
-        ).await?;
+        let stmt = "SELECT doc FROM reports WHERE uname = $1 AND term = $2 AND year = $3";
 
         let mut uname_n: usize = 0;
-        let mut fut = t.query_opt(&stmt, &params[uname_n]);
+        let mut fut = t.query_opt(stmt, &params[uname_n]);
         uname_n += 1;
         while uname_n < params.len() {
             if let Ok(Some(row)) = fut.await {
-                fut = t.query_opt(&stmt, &params[uname_n]);
+                fut = t.query_opt(stmt, &params[uname_n]);
                 if let Ok(doc) = row.try_get("doc") {
                     zip.start_file(format!("{}.pdf", stud_refs[uname_n - 1].uname()), zip_opts)
                         .map_err(|e| {
@@ -1154,7 +2066,7 @@ impl<'a> Glob {
                     }
                 }
             } else {
-                fut = t.query_opt(&stmt, &params[uname_n]);
+                fut = t.query_opt(stmt, &params[uname_n]);
             }
             uname_n += 1;
         }
@@ -1320,59 +2232,6 @@ impl<'a> Glob {
 
         Ok(())
     }
-
-    /**
-    Delete all Student Goals, sidecar info, and report data (but _not_
-    course completion data).
-
-    This is meant to clear the database out between academic years. This
-    does _not_ remove any Students from the database.
-    */
-    pub async fn yearly_data_nuke(&self) -> Result<(), UnifiedError> {
-        log::trace!("Glob::yearly_data_nuke() called.");
-    
-        let data_arc = self.data();
-        let data = data_arc.read().await;
-        let mut client = data.connect().await?;
-        let t = client.transaction().await?;
-    
-        let _ = tokio::try_join!(
-            Store::yearly_clear_sidecars(&t),
-            Store::yearly_clear_goals(&t),
-        ).map_err(|e| format!(
-            "Error clearing yearly data from database: {}", &e
-        ))?;
-
-        t.commit().await?;
-    
-        Ok(())
-    }
-
-/*     pub async fn yearly_data_nuke(&mut self) -> Result<(), UnifiedError> {
-        log::trace!("Glob::yearly_data_nuke() called.");
-
-        let data_arc = self.data();
-        let data = data_arc.read().await;
-        let mut client = data.connect().await?;
-        let t = client.transaction().await?;
-
-        let unames = data.delete_students(&t).await?;
-        let uname_refs: Vec<&str> = unames.iter().map(|s| s.as_str()).collect();
-
-        let auth_arc = self.auth();
-        let auth = auth_arc.read().await;
-        let mut auth_client = auth.connect().await?;
-        let auth_t = auth_client.transaction().await?;
-
-        auth.delete_users(&auth_t, &uname_refs).await?;
-
-        t.commit().await?;
-
-        auth_t.commit().await.map_err(|e| format!(
-            "Error removing tranche of {} users from the Auth DB ({}); Auth and Data DBs may be out of synch. The database may need manual attention from someone who understands databases. In any case, it is recommended to log back in before you continue.",
-            &uname_refs.len(), &e
-        ).into())
-    } */
 }
 
 async fn insert_default_admin_into_data_db(cfg: &Cfg, data: &Store) -> Result<User, UnifiedError> {
@@ -1414,15 +2273,20 @@ async fn insert_default_admin_into_auth_db(
 }
 
 /// Loads system configuration and ensures all appropriate database tables
-/// exist.
+/// exist (by applying any pending [migrations](crate::migrate)).
 ///
 /// Also assures existence of default admin.
 pub async fn load_configuration<P: AsRef<Path>>(path: P) -> Result<Glob, UnifiedError> {
     let cfg = Cfg::from_file(path.as_ref())?;
-    log::info!("Configuration file read:\n{:#?}", &cfg);
+    cfg.log_settings(&path.as_ref().display().to_string());
+    load_cfg(cfg).await
+}
 
+/// Like [`load_configuration`], but with a configuration that's already
+/// been read.
+pub async fn load_cfg(cfg: Cfg) -> Result<Glob, UnifiedError> {
     log::trace!("Checking state of auth DB...");
-    let auth_db = auth::Db::new(cfg.auth_db_connect_string.clone());
+    let auth_db = cfg.auth_db();
     if let Err(e) = auth_db.ensure_db_schema().await {
         let estr = format!("Unable to ensure state of auth DB: {}", &e);
         return Err(estr.into());
@@ -1430,9 +2294,19 @@ pub async fn load_configuration<P: AsRef<Path>>(path: P) -> Result<Glob, Unified
     log::trace!("...auth DB okay.");
     let n_old_keys = auth_db.cull_old_keys().await?;
     log::info!("Removed {} expired keys from Auth DB.", &n_old_keys);
+    let n_old_failures = auth_db.cull_old_login_failures().await?;
+    log::info!(
+        "Removed {} stale failed login records from Auth DB.",
+        &n_old_failures
+    );
+    let n_old_resets = auth_db.cull_old_reset_tokens().await?;
+    log::info!(
+        "Removed {} stale password reset tokens from Auth DB.",
+        &n_old_resets
+    );
 
     log::trace!("Checking state of data DB...");
-    let data_db = Store::new(cfg.data_db_connect_string.clone());
+    let data_db = cfg.data_db();
     if let Err(e) = data_db.ensure_db_schema().await {
         let estr = format!("Unable to ensure state of data DB: {}", &e);
         return Err(estr.into());
@@ -1519,29 +2393,46 @@ pub async fn load_configuration<P: AsRef<Path>>(path: P) -> Result<Glob, Unified
     log::trace!("Default Admin OK in auth DB.");
 
     let mut glob = Glob {
-        uri: cfg.uri,
+        uri: cfg.uri.clone(),
+        school_name: cfg.school_name.clone(),
         auth: Arc::new(RwLock::new(auth_db)),
         data: Arc::new(RwLock::new(data_db)),
-        sendgrid_auth: cfg.sendgrid_auth_string,
+        sendgrid_auth: cfg.sendgrid_auth_string.clone(),
         dates: HashMap::new(),
         calendar: Vec::new(),
         courses: HashMap::new(),
         course_syms: HashMap::new(),
+        custom_chapters: HashMap::new(),
         users: HashMap::new(),
+        grants: perm::Grants::default(),
         addr: cfg.addr,
         pwd_chars: DEFAULT_PASSWORD_CHARS.chars().collect(),
-        pandoc_uri: cfg.pandoc_uri,
-        pandoc_auth: cfg.pandoc_auth,
-        social_traits: cfg.social_traits,
-        pandoc_format: cfg.pandoc_format,
+        pandoc_uri: cfg.pandoc_uri.clone(),
+        pandoc_auth: cfg.pandoc_auth.clone(),
+        social_traits: cfg.social_traits.clone(),
+        pandoc_format: cfg.pandoc_format.clone(),
+        require_2fa: cfg.require_2fa,
+        trust_proxy_headers: cfg.trust_proxy_headers,
+        cache_resync: match cfg.cache_resync_seconds {
+            0 => None,
+            n => Some(Duration::from_secs(n)),
+        },
+        cfg: Arc::new(cfg),
     };
 
     glob.refresh_courses().await?;
+    glob.refresh_custom_chapters().await?;
     log::info!("Retrieved {} courses from data DB.", glob.courses.len());
 
     glob.refresh_users().await?;
     log::info!("Retrieved {} users from data DB.", glob.users.len());
 
+    glob.refresh_grants().await?;
+    log::info!(
+        "Retrieved extra permissions for {} users from auth DB.",
+        glob.grants.all().len()
+    );
+
     glob.refresh_calendar().await?;
     log::info!(
         "Retrieved {} instructional days from data DB.",
@@ -1552,42 +2443,285 @@ pub async fn load_configuration<P: AsRef<Path>>(path: P) -> Result<Glob, Unified
     log::info!("Retrieved {} special dates from data DB.", glob.dates.len());
     log::debug!("special dates:\n{:#?}\n", &glob.dates);
 
-    inter::init(&cfg.templates_dir)?;
+    inter::init(&glob.cfg.templates_dir)?;
 
     Ok(glob)
 }
 
 #[cfg(test)]
 mod tests {
+    use std::{collections::HashMap, path::Path};
+
     use crate::pace::{Pace, Source};
-    use crate::tests::ensure_logging;
+    use crate::tests::{ensure_logging, init_env, insert_test_goals, teardown_env};
     use crate::*;
 
-    use serial_test::serial;
+    #[test]
+    fn parent_unames() {
+        let taken = ["rlparker", "parent", "kim", "kim2"];
+        let is_taken = |u: &str| taken.contains(&u);
+
+        assert_eq!(&config::parent_uname("R.L.Parker@gmail.com", |_| false), "rlparker");
+        assert_eq!(&config::parent_uname("r.l.parker@gmail.com", is_taken), "rlparker2");
+        assert_eq!(&config::parent_uname("kim@holt-info.com", is_taken), "kim3");
+        assert_eq!(&config::parent_uname("@nowhere.org", is_taken), "parent2");
+    }
 
-    static CONFIG: &str = "fakeprod_data/config.toml";
+    #[test]
+    fn env_overrides() {
+        use config::{Cfg, Origin};
+
+        let dir = std::env::temp_dir().join(format!("camp_env_overrides_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let secret = dir.join("sendgrid");
+        std::fs::write(&secret, "Bearer from a file\n").unwrap();
+
+        let vars: HashMap<String, String> = [
+            ("CAMP_SENDGRID_AUTH_STRING_FILE", secret.to_str().unwrap()),
+            ("CAMP_SCHOOL_NAME", "Everyone's School"),
+            ("CAMP_NORTH_SCHOOL_NAME", "North School"),
+            ("CAMP_SOCIAL_TRAITS", "Leadership, Behavior"),
+            ("CAMP_REQUIRE_2FA", "TRUE"),
+            ("CAMP_LOGIN_MAX_ATTEMPTS", "3"),
+        ]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        let var = |name: &str| vars.get(name).cloned();
+
+        let cfg = Cfg::from_file_and_env(
+            Path::new("test/env/config.toml"),
+            &config::env_prefixes(Some("north")),
+            var,
+        )
+        .unwrap();
+        assert_eq!(&cfg.sendgrid_auth_string, "Bearer from a file");
+        assert_eq!(&cfg.school_name, "North School");
+        assert_eq!(&cfg.social_traits, &["Leadership", "Behavior"]);
+        assert!(cfg.require_2fa);
+        assert_eq!(cfg.login_throttle.max_attempts, 3);
+        assert_eq!(&cfg.default_admin_password, "pwd");
+
+        let setting = |key: &str| cfg.settings.iter().find(|s| s.key == key).unwrap();
+        assert_eq!(
+            setting("sendgrid_auth_string").origin,
+            Origin::EnvFile("CAMP_SENDGRID_AUTH_STRING_FILE".to_owned())
+        );
+        assert_eq!(
+            setting("school_name").origin,
+            Origin::Env("CAMP_NORTH_SCHOOL_NAME".to_owned())
+        );
+        assert_eq!(setting("admin_uname").origin, Origin::File);
+        assert_eq!(setting("admin_uname").shown.as_deref(), Some("\"root\""));
+        assert_eq!(setting("pandoc_uri").origin, Origin::Default);
+        // Secrets aren't shown, wherever they come from.
+        assert_eq!(setting("sendgrid_auth_string").shown, None);
+        assert_eq!(setting("admin_password").shown, None);
+
+        // Without the tenant's prefix, the general one wins.
+        let cfg = Cfg::from_file_and_env(
+            Path::new("test/env/config.toml"),
+            &config::env_prefixes(None),
+            var,
+        )
+        .unwrap();
+        assert_eq!(&cfg.school_name, "Everyone's School");
+
+        // $PORT beats everything.
+        let cfg = Cfg::from_file_and_env(
+            Path::new("test/env/config.toml"),
+            &config::env_prefixes(None),
+            |name: &str| match name {
+                "PORT" => Some("8123".to_owned()),
+                "CAMP_PORT" => Some("9000".to_owned()),
+                _ => None,
+            },
+        )
+        .unwrap();
+        assert_eq!(cfg.addr.port(), 8123);
+        assert!(cfg
+            .settings
+            .iter()
+            .any(|s| s.key == "port" && s.origin == Origin::Env("PORT".to_owned())));
+
+        let mut bad = vars.clone();
+        bad.insert("CAMP_SENDGRID_AUTH_STRING".to_owned(), "Bearer too".to_owned());
+        let res = Cfg::from_file_and_env(
+            Path::new("test/env/config.toml"),
+            &config::env_prefixes(None),
+            |name: &str| bad.get(name).cloned(),
+        );
+        assert!(res.is_err());
+
+        let mut bad = vars.clone();
+        bad.insert("CAMP_PORT".to_owned(), "eighty".to_owned());
+        let res = Cfg::from_file_and_env(
+            Path::new("test/env/config.toml"),
+            &config::env_prefixes(None),
+            |name: &str| bad.get(name).cloned(),
+        );
+        assert!(res.unwrap_err().contains("CAMP_PORT"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Every `ConfigFile` setting can be set from the environment.
+    #[test]
+    fn env_keys() {
+        let mut table = toml::value::Table::new();
+        let vars: HashMap<String, String> = config::KEYS
+            .iter()
+            .map(|k| {
+                let val = match k.kind {
+                    config::Kind::Text => "text",
+                    config::Kind::Integer => "1",
+                    config::Kind::Boolean => "true",
+                    config::Kind::List => "one, two",
+                };
+                (format!("CAMP_{}", k.name.to_ascii_uppercase()), val.to_owned())
+            })
+            .collect();
+        let settings = config::apply_env(&mut table, &config::env_prefixes(None), |name| {
+            vars.get(name).cloned()
+        })
+        .unwrap();
+        assert!(settings
+            .iter()
+            .all(|s| matches!(s.origin, config::Origin::Env(_))));
+
+        // If a setting is added to `ConfigFile` but not to `KEYS`, this stops
+        // compiling; if it's missing from `KEYS`, it's `None`.
+        let config::ConfigFile {
+            uri,
+            school_name,
+            auth_db_connect_string,
+            data_db_connect_string,
+            admin_uname,
+            admin_password,
+            admin_email,
+            sendgrid_auth_string,
+            social_traits,
+            host,
+            port,
+            templates_dir,
+            pandoc_uri,
+            pandoc_auth,
+            pandoc_format,
+            login_free_attempts,
+            login_backoff_seconds,
+            login_max_attempts,
+            login_addr_max_attempts,
+            login_lockout_seconds,
+            trust_proxy_headers,
+            require_2fa,
+            reset_token_life_seconds,
+            reset_max_per_uname,
+            reset_max_per_addr,
+            invite_life_seconds,
+            db_pool_size,
+            db_timeout_seconds,
+            db_health_check,
+            cache_resync_seconds,
+            dev_mode,
+        } = toml::Value::Table(table).try_into().unwrap();
+        assert_eq!(&sendgrid_auth_string, "text");
+        assert_eq!(social_traits, Some(vec!["one".to_owned(), "two".to_owned()]));
+        assert!([
+            uri,
+            school_name,
+            auth_db_connect_string,
+            data_db_connect_string,
+            admin_uname,
+            admin_password,
+            admin_email,
+            host,
+            templates_dir,
+            pandoc_uri,
+            pandoc_auth,
+            pandoc_format
+        ]
+        .iter()
+        .all(Option::is_some));
+        assert!([
+            login_free_attempts,
+            login_max_attempts,
+            login_addr_max_attempts,
+            reset_max_per_uname,
+            reset_max_per_addr
+        ]
+        .iter()
+        .all(Option::is_some));
+        assert!([
+            login_backoff_seconds,
+            login_lockout_seconds,
+            reset_token_life_seconds,
+            invite_life_seconds,
+            db_timeout_seconds,
+            cache_resync_seconds
+        ]
+        .iter()
+        .all(Option::is_some));
+        assert!([trust_proxy_headers, require_2fa, db_health_check, dev_mode]
+            .iter()
+            .all(Option::is_some));
+        assert!(port.is_some() && db_pool_size.is_some());
+    }
 
     #[tokio::test]
-    #[serial]
-    async fn get_one_pace() -> Result<(), UnifiedError> {
+    async fn glob_snapshots() {
+        use std::{sync::Arc, time::Duration};
+
+        use crate::config::GlobCell;
+
         ensure_logging();
 
-        let glob = config::load_configuration(CONFIG).await?;
+        let glob = config::load_configuration("test/env/config.toml").await.unwrap();
+        let school = glob.school_name.clone();
+        let cell = GlobCell::new(glob);
+        let before = cell.load();
+
+        let mut writer = cell.write().await;
+        writer.school_name = "Changed Academy".to_owned();
+        // Readers still see (and can still get) the old snapshot, while any
+        // other writer has to wait its turn.
+        assert_eq!(&cell.load().school_name, &school);
+        assert!(tokio::time::timeout(Duration::from_millis(50), cell.write())
+            .await
+            .is_err());
+
+        drop(writer);
+        assert_eq!(&cell.load().school_name, "Changed Academy");
+        assert_eq!(&before.school_name, &school);
+        assert!(Arc::ptr_eq(&before.data(), &cell.load().data()));
+    }
+
+    #[tokio::test]
+    async fn get_one_pace() -> Result<(), UnifiedError> {
+        let glob = init_env().await?;
+        insert_test_goals(&glob).await?;
 
-        let p = glob.get_pace_by_student("eparker").await?;
+        let p = glob.get_pace_by_student("dval").await?;
         println!("{:#?}", &p);
+        assert!(!p.goals.is_empty());
 
+        teardown_env(glob).await?;
         Ok(())
     }
 
     #[tokio::test]
-    #[serial]
     async fn autopace() -> Result<(), UnifiedError> {
-        ensure_logging();
+        let mut glob = init_env().await?;
 
-        let glob = config::load_configuration(CONFIG).await?;
+        let weekdays: Vec<Date> = (0..270)
+            .map(|n| time::macros::date!(2022 - 09 - 01) + time::Duration::days(n))
+            .filter(|d| !matches!(d.weekday(), time::Weekday::Saturday | time::Weekday::Sunday))
+            .collect();
+        glob.data().read().await.set_calendar(&weekdays).await?;
+        glob.refresh_calendar().await?;
+        // The academic year follows the calendar, so the Goals go in after.
+        insert_test_goals(&glob).await?;
 
-        let mut p: Pace = glob.get_pace_by_student("wholt").await?;
+        let mut p: Pace = glob.get_pace_by_student("dval").await?;
         p.autopace(&glob.calendar)?;
         for g in p.goals.iter() {
             let source = match &g.source {
@@ -1601,9 +2735,13 @@ mod tests {
                 None => "None".to_string(),
                 Some(d) => format!("{}", &d),
             };
+            if let Some(d) = &g.due {
+                assert!(weekdays.contains(d));
+            }
             println!("{}: {} {} {:?}", &g.id, &crs.title, &chp.title, &datestr);
         }
 
+        teardown_env(glob).await?;
         Ok(())
     }
 }
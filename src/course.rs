@@ -145,9 +145,9 @@ fn read_toml_csv_split<R: Read>(r: R) -> Result<(usize, String, usize, String),
 }
 
 /// Represents the material covered by a "custom" [`Goal`](crate::pace::Goal)
-/// not represented by a Chapter in any current Courses in the database.
-///
-/// This is currently not supported, but may be in the future.
+/// not represented by a Chapter in any current Courses in the database: a
+/// one-off project, say, or a unit from an outside workbook.
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Custom {
     /// Database primary key.
     pub id: i64,
@@ -255,7 +255,7 @@ A `Course` represents the requirements for a single academic year-long course
 of Mathematics. This is almost universally some chunk of chapters (or partial
 chapters) from a single textbook.
 */
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Course {
     pub id: i64,
     pub sym: String,
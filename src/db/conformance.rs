@@ -0,0 +1,410 @@
+/*!
+Behavior every [`Backend`] must share.
+
+Each check here is run twice: once against a fresh in-memory database
+([`MEMORY`]), which needs no server and so runs in parallel with everything
+else, and once (serially) against the data database the rest of the tests
+use (see [`crate::tests::test_data_db`]), which is Postgres unless
+`CAMP_TEST_DATA_DB` says otherwise.
+
+The checks create (and drop) their own `conformance_` tables, so they don't
+disturb any `camp` data that might be in the test database.
+*/
+use time::{macros::datetime, Date, PrimitiveDateTime};
+
+use super::*;
+
+const SETUP: &str = "DROP TABLE IF EXISTS conformance_child;
+DROP TABLE IF EXISTS conformance_parent;
+DROP TABLE IF EXISTS conformance_values;
+CREATE TABLE conformance_parent (
+    id   BIGINT PRIMARY KEY,
+    name TEXT UNIQUE NOT NULL
+);
+CREATE TABLE conformance_child (
+    id     BIGINT PRIMARY KEY,
+    parent BIGINT NOT NULL REFERENCES conformance_parent(id),
+    note   TEXT
+);";
+
+const TEARDOWN: &str = "DROP TABLE IF EXISTS conformance_child;
+DROP TABLE IF EXISTS conformance_parent;
+DROP TABLE IF EXISTS conformance_values;";
+
+async fn setup(backend: &dyn Backend) -> Result<Client, Error> {
+    let client = backend.connect().await?;
+    client.batch_execute(SETUP).await?;
+    Ok(client)
+}
+
+async fn count(client: &Client, table: &str) -> Result<i64, Error> {
+    let row = client
+        .query_one(&format!("SELECT COUNT(*) AS n FROM {}", table), &[])
+        .await?;
+    row.try_get("n")
+}
+
+/// Every type `camp` stores comes back out as it went in.
+async fn values_round_trip(backend: &dyn Backend) -> Result<(), Error> {
+    let client = setup(backend).await?;
+    let create = match client.dialect() {
+        Dialect::Postgres => {
+            "CREATE TABLE conformance_values (
+                flag BOOLEAN, n BIGINT, small SMALLINT, x DOUBLE PRECISION,
+                s TEXT, raw BYTEA, day DATE, at TIMESTAMP, empty TEXT
+            )"
+        }
+        Dialect::Sqlite => {
+            "CREATE TABLE conformance_values (
+                flag INTEGER, n INTEGER, small INTEGER, x REAL,
+                s TEXT, raw BLOB, day TEXT, at TEXT, empty TEXT
+            )"
+        }
+    };
+    client.batch_execute(create).await?;
+
+    let day = Date::from_calendar_date(2023, time::Month::January, 10).unwrap();
+    let at = datetime!(2023-01-10 13:14:15);
+    let raw: Vec<u8> = vec![0, 1, 2, 255];
+    let empty: Option<String> = None;
+    let n = client
+        .execute(
+            "INSERT INTO conformance_values
+                (flag, n, small, x, s, raw, day, at, empty)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+            &[
+                &true,
+                &(i64::MAX - 1),
+                &-7_i16,
+                &2.5_f64,
+                &"Ťëxt, 'quoted'",
+                &raw,
+                &day,
+                &at,
+                &empty,
+            ],
+        )
+        .await?;
+    assert_eq!(n, 1);
+
+    let row = client
+        .query_one("SELECT * FROM conformance_values", &[])
+        .await?;
+    assert!(row.try_get::<_, bool>("flag")?);
+    assert_eq!(row.try_get::<_, i64>("n")?, i64::MAX - 1);
+    assert_eq!(row.try_get::<_, i16>("small")?, -7);
+    assert_eq!(row.try_get::<_, f64>("x")?, 2.5);
+    assert_eq!(row.try_get::<_, &str>("s")?, "Ťëxt, 'quoted'");
+    assert_eq!(row.try_get::<_, &[u8]>("raw")?, &raw[..]);
+    assert_eq!(row.try_get::<_, Date>("day")?, day);
+    assert_eq!(row.try_get::<_, PrimitiveDateTime>("at")?, at);
+    assert_eq!(row.try_get::<_, Option<String>>("empty")?, None);
+    assert!(row.try_get::<_, i64>("s").is_err());
+
+    // Dates and timestamps compare correctly against parameters.
+    let matched = client
+        .query(
+            "SELECT n FROM conformance_values WHERE day = $1 AND at > $2",
+            &[&day, &datetime!(2023-01-10 13:00:00)],
+        )
+        .await?;
+    assert_eq!(matched.len(), 1);
+
+    // As do the expressions built by the [`Dialect`] helpers.
+    let dialect = client.dialect();
+    let row = client
+        .query_one(
+            &format!(
+                "SELECT {} AS later, {} AS day_text FROM conformance_values",
+                dialect.timestamp_text(&dialect.add_interval("at", "$1")),
+                dialect.date_text("day"),
+            ),
+            &[&"+11 hours"],
+        )
+        .await?;
+    assert_eq!(row.try_get::<_, &str>("later")?, "2023-01-11 00:14:15");
+    assert_eq!(row.try_get::<_, &str>("day_text")?, "2023-01-10");
+
+    client.batch_execute(TEARDOWN).await
+}
+
+/// Committed writes stay; rolled-back or abandoned ones don't.
+async fn transactions(backend: &dyn Backend) -> Result<(), Error> {
+    let mut client = setup(backend).await?;
+    let insert = "INSERT INTO conformance_parent (id, name) VALUES ($1, $2)";
+
+    let t = client.transaction().await?;
+    t.execute(insert, &[&1_i64, &"committed"]).await?;
+    assert_eq!(
+        t.query("SELECT id FROM conformance_parent", &[])
+            .await?
+            .len(),
+        1
+    );
+    t.commit().await?;
+    assert_eq!(count(&client, "conformance_parent").await?, 1);
+
+    let t = client.transaction().await?;
+    t.execute(insert, &[&2_i64, &"rolled back"]).await?;
+    t.rollback().await?;
+    assert_eq!(count(&client, "conformance_parent").await?, 1);
+
+    {
+        let t = client.transaction().await?;
+        t.execute(insert, &[&3_i64, &"dropped"]).await?;
+    }
+    assert_eq!(count(&client, "conformance_parent").await?, 1);
+
+    // A failed statement spoils nothing that has already been committed,
+    // and rolling back after it discards the rest of the transaction.
+    let t = client.transaction().await?;
+    t.execute(insert, &[&4_i64, &"before the failure"]).await?;
+    assert!(t.execute(insert, &[&1_i64, &"duplicate"]).await.is_err());
+    t.rollback().await?;
+    let names: Vec<String> = client
+        .query("SELECT name FROM conformance_parent", &[])
+        .await?
+        .iter()
+        .map(|row| row.try_get("name"))
+        .collect::<Result<_, _>>()?;
+    assert_eq!(names, vec!["committed".to_owned()]);
+
+    client.batch_execute(TEARDOWN).await
+}
+
+/// Primary keys and `UNIQUE` columns reject duplicates, and `ON CONFLICT`
+/// clauses handle them.
+async fn uniqueness(backend: &dyn Backend) -> Result<(), Error> {
+    let client = setup(backend).await?;
+    let insert = "INSERT INTO conformance_parent (id, name) VALUES ($1, $2)";
+
+    client.execute(insert, &[&1_i64, &"one"]).await?;
+    assert!(client.execute(insert, &[&1_i64, &"uno"]).await.is_err());
+    assert!(client.execute(insert, &[&2_i64, &"one"]).await.is_err());
+    assert!(client.execute(insert, &[&2_i64, &None::<&str>]).await.is_err());
+
+    let n = client
+        .execute(
+            "INSERT INTO conformance_parent (id, name) VALUES ($1, $2)
+                ON CONFLICT DO NOTHING",
+            &[&1_i64, &"uno"],
+        )
+        .await?;
+    assert_eq!(n, 0);
+
+    let n = client
+        .execute(
+            "INSERT INTO conformance_parent (id, name) VALUES ($1, $2)
+                ON CONFLICT (id) DO UPDATE SET name = $2",
+            &[&1_i64, &"uno"],
+        )
+        .await?;
+    assert_eq!(n, 1);
+    let row = client
+        .query_one("SELECT name FROM conformance_parent WHERE id = $1", &[&1_i64])
+        .await?;
+    assert_eq!(row.try_get::<_, &str>("name")?, "uno");
+
+    client.batch_execute(TEARDOWN).await
+}
+
+/// Rows can't refer to rows that don't exist, or be left referring to rows
+/// that have been deleted.
+async fn foreign_keys(backend: &dyn Backend) -> Result<(), Error> {
+    let client = setup(backend).await?;
+
+    client
+        .execute(
+            "INSERT INTO conformance_parent (id, name) VALUES ($1, $2)",
+            &[&1_i64, &"parent"],
+        )
+        .await?;
+    let insert_child = "INSERT INTO conformance_child (id, parent) VALUES ($1, $2)";
+    client.execute(insert_child, &[&10_i64, &1_i64]).await?;
+    assert!(client.execute(insert_child, &[&11_i64, &2_i64]).await.is_err());
+
+    assert!(client
+        .execute("DELETE FROM conformance_parent WHERE id = $1", &[&1_i64])
+        .await
+        .is_err());
+    assert_eq!(count(&client, "conformance_parent").await?, 1);
+
+    client
+        .execute("DELETE FROM conformance_child WHERE parent = $1", &[&1_i64])
+        .await?;
+    let n = client
+        .execute("DELETE FROM conformance_parent WHERE id = $1", &[&1_i64])
+        .await?;
+    assert_eq!(n, 1);
+
+    client.batch_execute(TEARDOWN).await
+}
+
+/// `execute()` reports how many rows were affected.
+async fn affected_rows(backend: &dyn Backend) -> Result<(), Error> {
+    let client = setup(backend).await?;
+
+    for (id, name) in [(1_i64, "a"), (2, "b"), (3, "c")] {
+        client
+            .execute(
+                "INSERT INTO conformance_parent (id, name) VALUES ($1, $2)",
+                &[&id, &name],
+            )
+            .await?;
+    }
+    let n = client
+        .execute(
+            "UPDATE conformance_parent SET name = name || '!' WHERE id > $1",
+            &[&1_i64],
+        )
+        .await?;
+    assert_eq!(n, 2);
+    let n = client
+        .execute("DELETE FROM conformance_parent WHERE id > $1", &[&5_i64])
+        .await?;
+    assert_eq!(n, 0);
+
+    client.batch_execute(TEARDOWN).await
+}
+
+/// [`Dialect::in_array`] matches against a list parameter.
+async fn list_parameters(backend: &dyn Backend) -> Result<(), Error> {
+    let client = setup(backend).await?;
+    let dialect = client.dialect();
+
+    for (id, name) in [(1_i64, "a"), (2, "b"), (3, "c")] {
+        client
+            .execute(
+                "INSERT INTO conformance_parent (id, name) VALUES ($1, $2)",
+                &[&id, &name],
+            )
+            .await?;
+    }
+    let rows = client
+        .query(
+            &format!(
+                "SELECT id FROM conformance_parent WHERE {} ORDER BY id",
+                dialect.in_array("name", "$1")
+            ),
+            &[&vec!["c", "a", "z"]],
+        )
+        .await?;
+    let ids: Vec<i64> = rows
+        .iter()
+        .map(|row| row.try_get("id"))
+        .collect::<Result<_, _>>()?;
+    assert_eq!(ids, vec![1, 3]);
+
+    client.batch_execute(TEARDOWN).await
+}
+
+/// The operations each backend implements its own way work alike.
+async fn backend_operations(backend: &dyn Backend) -> Result<(), Error> {
+    let mut client = setup(backend).await?;
+    let dialect = client.dialect();
+
+    assert!(client.table_exists("conformance_parent").await?);
+    assert!(!client.table_exists("conformance_values").await?);
+    assert!(client.column_exists("conformance_child", "note").await?);
+    assert!(!client.column_exists("conformance_child", "nope").await?);
+    assert!(!client.column_exists("conformance_values", "note").await?);
+
+    client
+        .execute(
+            "INSERT INTO conformance_parent (id, name) VALUES ($1, $2)",
+            &[&1_i64, &"a"],
+        )
+        .await?;
+    let json = client
+        .row_json("conformance_parent", "id", &1_i64)
+        .await?
+        .unwrap();
+    assert_eq!(
+        serde_json::from_str::<serde_json::Value>(&json).unwrap(),
+        serde_json::json!({ "id": 1, "name": "a" })
+    );
+    assert_eq!(client.row_json("conformance_parent", "id", &2_i64).await?, None);
+
+    let t = client.transaction().await?;
+    t.lock_id(12345).await?;
+    t.lock_table("conformance_parent").await?;
+    t.execute(
+        "INSERT INTO conformance_parent (id, name) VALUES ($1, $2)",
+        &[&2_i64, &"b"],
+    )
+    .await?;
+    t.commit().await?;
+    assert_eq!(count(&client, "conformance_parent").await?, 2);
+
+    let t = client.transaction().await?;
+    t.read_snapshot().await?;
+    let row = t
+        .query_one("SELECT COUNT(*) AS n FROM conformance_parent", &[])
+        .await?;
+    assert_eq!(row.try_get::<_, i64>("n")?, 2);
+    t.rollback().await?;
+
+    // And so do the rest of the [`Dialect`] helpers.
+    let row = client
+        .query_one(
+            &format!(
+                "SELECT {} AS since, {} AS next, {} IS NULL AS none",
+                dialect.seconds_since(&dialect.add_interval("CURRENT_TIMESTAMP", "$1")),
+                dialect.date_text(&dialect.next_day(&dialect.typed("$2", "DATE"))),
+                dialect.typed("$3", "TEXT"),
+            ),
+            &[
+                &"-1 hours",
+                &Date::from_calendar_date(2023, time::Month::December, 31).unwrap(),
+                &None::<&str>,
+            ],
+        )
+        .await?;
+    assert!((row.try_get::<_, f64>("since")? - 3600.0).abs() < 60.0);
+    assert_eq!(row.try_get::<_, &str>("next")?, "2024-01-01");
+    assert!(row.try_get::<_, bool>("none")?);
+
+    client.batch_execute(TEARDOWN).await
+}
+
+macro_rules! conformance_tests {
+    ($($check:ident),* $(,)?) => {
+        mod memory {
+            use super::*;
+
+            $(
+                #[tokio::test]
+                async fn $check() {
+                    let backend = open(MEMORY, &PoolConfig::default()).unwrap();
+                    super::$check(backend.as_ref()).await.unwrap();
+                }
+            )*
+        }
+
+        mod configured {
+            use super::*;
+            use crate::tests::test_data_db;
+
+            use serial_test::serial;
+
+            $(
+                #[tokio::test]
+                #[serial]
+                async fn $check() {
+                    let backend = open(&test_data_db(), &PoolConfig::default()).unwrap();
+                    super::$check(backend.as_ref()).await.unwrap();
+                }
+            )*
+        }
+    };
+}
+
+conformance_tests!(
+    values_round_trip,
+    transactions,
+    uniqueness,
+    foreign_keys,
+    affected_rows,
+    list_parameters,
+    backend_operations,
+);
@@ -0,0 +1,758 @@
+/*!
+Storage backends for the auth and data databases.
+
+[`auth::Db`](crate::auth::Db) and [`Store`](crate::store::Store) don't talk
+to a particular database directly; they run their SQL through the traits in
+this module, which are implemented for
+
+  * Postgres (the production backend; see [`crate::pool`]), and
+  * an embedded SQLite database, selected by a connection string of the form
+    `sqlite:path/to/file.db` (or `sqlite::memory:` for one that only lives
+    as long as the process). This is meant for local development and
+    testing, so that the whole thing can run without a Postgres server.
+
+Which backend is used is decided by [`open`] from the connection string.
+
+The two speak slightly different dialects of SQL. Most of the statements
+`camp` uses are common to both; where they aren't, the calling code uses one
+of the [`Dialect`] methods that produce an equivalent expression in each.
+Operations that work differently in each backend, rather than just being
+spelled differently (locking, snapshots, looking at the schema), are
+methods of [`Connection`] and [`TransactionConnection`] that each backend
+implements its own way.
+
+Query parameters are passed as `&[&(dyn ToValue + Sync)]` and results come
+back as [`Row`]s of [`Value`]s, whose columns can be read into Rust types
+with [`Row::try_get`], much as with `tokio_postgres`.
+*/
+use std::{
+    fmt::{Debug, Display, Formatter},
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use time::{format_description::FormatItem, macros::format_description, Date, PrimitiveDateTime};
+
+use crate::pool::PoolConfig;
+
+#[cfg(test)]
+mod conformance;
+mod postgres;
+mod sqlite;
+
+pub use postgres::Listener;
+
+/// Connection strings starting with this select the SQLite backend.
+pub const SQLITE_PREFIX: &str = "sqlite:";
+/**
+Connection string for a SQLite database held entirely in memory. Every
+backend opened with it gets its own empty database, which goes away when the
+backend is dropped, so tests using it don't need a server and can't see each
+other's data.
+*/
+pub const MEMORY: &str = "sqlite::memory:";
+
+const DATE_FMT: &[FormatItem] = format_description!("[year]-[month]-[day]");
+const TIMESTAMP_FMT: &[FormatItem] =
+    format_description!("[year]-[month]-[day] [hour]:[minute]:[second]");
+
+/// Errors returned by backend operations.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Error(String);
+
+impl Error {
+    pub fn display(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", &self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<String> for Error {
+    fn from(s: String) -> Error {
+        Error(s)
+    }
+}
+
+impl From<&str> for Error {
+    fn from(s: &str) -> Error {
+        Error(String::from(s))
+    }
+}
+
+impl From<Error> for String {
+    fn from(e: Error) -> String {
+        e.0
+    }
+}
+
+/// The flavor of SQL a backend speaks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dialect {
+    Postgres,
+    Sqlite,
+}
+
+impl Dialect {
+    /**
+    SQL expression for the timestamp `ts` plus the span of time `span`,
+    which should be an expression (usually a parameter) with a text value
+    like `'1200 seconds'`.
+    */
+    pub fn add_interval(self, ts: &str, span: &str) -> String {
+        match self {
+            Dialect::Postgres => format!("({} + ({} || ' ')::INTERVAL)", ts, span),
+            Dialect::Sqlite => format!("datetime({}, {})", ts, span),
+        }
+    }
+
+    /// SQL expression testing whether `expr` is one of the elements of
+    /// `array`, a parameter holding a list of strings.
+    pub fn in_array(self, expr: &str, array: &str) -> String {
+        match self {
+            Dialect::Postgres => format!("{} = ANY({})", expr, array),
+            Dialect::Sqlite => format!("{} IN (SELECT value FROM json_each({}))", expr, array),
+        }
+    }
+
+    /// SQL expression for the timestamp `ts` as text ("YYYY-MM-DD HH:MM:SS").
+    pub fn timestamp_text(self, ts: &str) -> String {
+        match self {
+            Dialect::Postgres => format!("to_char({}, 'YYYY-MM-DD HH24:MI:SS')", ts),
+            Dialect::Sqlite => format!("strftime('%Y-%m-%d %H:%M:%S', {})", ts),
+        }
+    }
+
+    /// SQL expression for the (floating-point) number of seconds since the
+    /// timestamp `ts`.
+    pub fn seconds_since(self, ts: &str) -> String {
+        match self {
+            Dialect::Postgres => format!("EXTRACT(EPOCH FROM (now() - {}))::FLOAT8", ts),
+            Dialect::Sqlite => format!("(julianday('now') - julianday({})) * 86400.0", ts),
+        }
+    }
+
+    /**
+    The parameter `param` where its type, `sql_type`, can't be inferred from
+    how it's used (as in `$1 IS NULL`); Postgres needs to be told.
+    */
+    pub fn typed(self, param: &str, sql_type: &str) -> String {
+        match self {
+            Dialect::Postgres => format!("{}::{}", param, sql_type),
+            Dialect::Sqlite => param.to_owned(),
+        }
+    }
+
+    /// SQL expression for the day after the date `d`.
+    pub fn next_day(self, d: &str) -> String {
+        match self {
+            Dialect::Postgres => format!("({} + 1)", d),
+            Dialect::Sqlite => format!("date({}, '+1 day')", d),
+        }
+    }
+
+    /// SQL expression for the date `d` as text ("YYYY-MM-DD").
+    pub fn date_text(self, d: &str) -> String {
+        match self {
+            Dialect::Postgres => format!("to_char({}, 'YYYY-MM-DD')", d),
+            Dialect::Sqlite => d.to_owned(),
+        }
+    }
+}
+
+/// A single value passed to or returned from the database.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Text(String),
+    Bytes(Vec<u8>),
+    Date(Date),
+    Timestamp(PrimitiveDateTime),
+    TextArray(Vec<String>),
+    /// A value of a type this module doesn't handle; holds the type's name.
+    Unsupported(String),
+}
+
+impl Value {
+    /// Date formatted as text, the way it's stored in SQLite.
+    fn date_string(d: &Date) -> String {
+        // Formatting with a hard-coded, valid description can't fail.
+        d.format(DATE_FMT).unwrap()
+    }
+
+    /// Timestamp formatted as text, the way it's stored in SQLite.
+    fn timestamp_string(t: &PrimitiveDateTime) -> String {
+        t.format(TIMESTAMP_FMT).unwrap()
+    }
+
+    /// An array of strings encoded as a JSON array, the way it's stored in
+    /// SQLite.
+    fn array_string(a: &[String]) -> String {
+        serde_json::to_string(a).unwrap()
+    }
+}
+
+/// Types that can be passed as query parameters.
+pub trait ToValue: Debug {
+    fn to_value(&self) -> Value;
+}
+
+impl ToValue for Value {
+    fn to_value(&self) -> Value {
+        self.clone()
+    }
+}
+
+impl ToValue for bool {
+    fn to_value(&self) -> Value {
+        Value::Bool(*self)
+    }
+}
+
+impl ToValue for i16 {
+    fn to_value(&self) -> Value {
+        Value::Int(*self as i64)
+    }
+}
+
+impl ToValue for i32 {
+    fn to_value(&self) -> Value {
+        Value::Int(*self as i64)
+    }
+}
+
+impl ToValue for i64 {
+    fn to_value(&self) -> Value {
+        Value::Int(*self)
+    }
+}
+
+impl ToValue for f32 {
+    fn to_value(&self) -> Value {
+        Value::Float(*self as f64)
+    }
+}
+
+impl ToValue for f64 {
+    fn to_value(&self) -> Value {
+        Value::Float(*self)
+    }
+}
+
+impl ToValue for str {
+    fn to_value(&self) -> Value {
+        Value::Text(self.to_owned())
+    }
+}
+
+impl ToValue for String {
+    fn to_value(&self) -> Value {
+        Value::Text(self.clone())
+    }
+}
+
+impl ToValue for [u8] {
+    fn to_value(&self) -> Value {
+        Value::Bytes(self.to_vec())
+    }
+}
+
+impl ToValue for Vec<u8> {
+    fn to_value(&self) -> Value {
+        Value::Bytes(self.clone())
+    }
+}
+
+impl ToValue for Date {
+    fn to_value(&self) -> Value {
+        Value::Date(*self)
+    }
+}
+
+impl ToValue for PrimitiveDateTime {
+    fn to_value(&self) -> Value {
+        Value::Timestamp(*self)
+    }
+}
+
+impl ToValue for [String] {
+    fn to_value(&self) -> Value {
+        Value::TextArray(self.to_vec())
+    }
+}
+
+impl ToValue for Vec<String> {
+    fn to_value(&self) -> Value {
+        Value::TextArray(self.clone())
+    }
+}
+
+impl ToValue for [&str] {
+    fn to_value(&self) -> Value {
+        Value::TextArray(self.iter().map(|s| s.to_string()).collect())
+    }
+}
+
+impl ToValue for Vec<&str> {
+    fn to_value(&self) -> Value {
+        self.as_slice().to_value()
+    }
+}
+
+impl<T: ToValue> ToValue for Option<T> {
+    fn to_value(&self) -> Value {
+        match self {
+            Some(x) => x.to_value(),
+            None => Value::Null,
+        }
+    }
+}
+
+impl<T: ToValue + ?Sized> ToValue for &T {
+    fn to_value(&self) -> Value {
+        (*self).to_value()
+    }
+}
+
+/// Types that a column of a [`Row`] can be read as.
+pub trait FromValue<'a>: Sized {
+    fn from_value(v: &'a Value) -> Result<Self, String>;
+}
+
+fn wrong_type<T>(v: &Value, want: &str) -> Result<T, String> {
+    Err(format!("can't read {:?} as {}", v, want))
+}
+
+impl<'a> FromValue<'a> for Value {
+    fn from_value(v: &'a Value) -> Result<Self, String> {
+        Ok(v.clone())
+    }
+}
+
+impl<'a> FromValue<'a> for bool {
+    fn from_value(v: &'a Value) -> Result<Self, String> {
+        match v {
+            Value::Bool(b) => Ok(*b),
+            Value::Int(n) => Ok(*n != 0),
+            _ => wrong_type(v, "bool"),
+        }
+    }
+}
+
+impl<'a> FromValue<'a> for i64 {
+    fn from_value(v: &'a Value) -> Result<Self, String> {
+        match v {
+            Value::Int(n) => Ok(*n),
+            _ => wrong_type(v, "i64"),
+        }
+    }
+}
+
+impl<'a> FromValue<'a> for i32 {
+    fn from_value(v: &'a Value) -> Result<Self, String> {
+        let n = i64::from_value(v)?;
+        i32::try_from(n).map_err(|_| format!("{} out of range for i32", n))
+    }
+}
+
+impl<'a> FromValue<'a> for i16 {
+    fn from_value(v: &'a Value) -> Result<Self, String> {
+        let n = i64::from_value(v)?;
+        i16::try_from(n).map_err(|_| format!("{} out of range for i16", n))
+    }
+}
+
+impl<'a> FromValue<'a> for f64 {
+    fn from_value(v: &'a Value) -> Result<Self, String> {
+        match v {
+            Value::Float(x) => Ok(*x),
+            Value::Int(n) => Ok(*n as f64),
+            _ => wrong_type(v, "f64"),
+        }
+    }
+}
+
+impl<'a> FromValue<'a> for f32 {
+    fn from_value(v: &'a Value) -> Result<Self, String> {
+        f64::from_value(v).map(|x| x as f32)
+    }
+}
+
+impl<'a> FromValue<'a> for &'a str {
+    fn from_value(v: &'a Value) -> Result<Self, String> {
+        match v {
+            Value::Text(s) => Ok(s.as_str()),
+            _ => wrong_type(v, "text"),
+        }
+    }
+}
+
+impl<'a> FromValue<'a> for String {
+    fn from_value(v: &'a Value) -> Result<Self, String> {
+        <&str>::from_value(v).map(String::from)
+    }
+}
+
+impl<'a> FromValue<'a> for &'a [u8] {
+    fn from_value(v: &'a Value) -> Result<Self, String> {
+        match v {
+            Value::Bytes(b) => Ok(b.as_slice()),
+            _ => wrong_type(v, "bytes"),
+        }
+    }
+}
+
+impl<'a> FromValue<'a> for Vec<u8> {
+    fn from_value(v: &'a Value) -> Result<Self, String> {
+        <&[u8]>::from_value(v).map(|b| b.to_vec())
+    }
+}
+
+impl<'a> FromValue<'a> for Date {
+    fn from_value(v: &'a Value) -> Result<Self, String> {
+        match v {
+            Value::Date(d) => Ok(*d),
+            Value::Text(s) => {
+                Date::parse(s, DATE_FMT).map_err(|e| format!("bad date {:?}: {}", s, &e))
+            }
+            _ => wrong_type(v, "date"),
+        }
+    }
+}
+
+impl<'a> FromValue<'a> for PrimitiveDateTime {
+    fn from_value(v: &'a Value) -> Result<Self, String> {
+        match v {
+            Value::Timestamp(t) => Ok(*t),
+            Value::Text(s) => {
+                // Ignore any fractional seconds.
+                let whole = s.split('.').next().unwrap_or_default();
+                PrimitiveDateTime::parse(whole, TIMESTAMP_FMT)
+                    .map_err(|e| format!("bad timestamp {:?}: {}", s, &e))
+            }
+            _ => wrong_type(v, "timestamp"),
+        }
+    }
+}
+
+impl<'a> FromValue<'a> for Vec<String> {
+    fn from_value(v: &'a Value) -> Result<Self, String> {
+        match v {
+            Value::TextArray(a) => Ok(a.clone()),
+            Value::Text(s) => {
+                serde_json::from_str(s).map_err(|e| format!("bad array {:?}: {}", s, &e))
+            }
+            _ => wrong_type(v, "text array"),
+        }
+    }
+}
+
+impl<'a, T: FromValue<'a>> FromValue<'a> for Option<T> {
+    fn from_value(v: &'a Value) -> Result<Self, String> {
+        match v {
+            Value::Null => Ok(None),
+            _ => T::from_value(v).map(Some),
+        }
+    }
+}
+
+/// Ways of specifying a column of a [`Row`]: by position or by name.
+pub trait RowIndex: Display {
+    fn index(&self, columns: &[String]) -> Option<usize>;
+}
+
+impl RowIndex for usize {
+    fn index(&self, columns: &[String]) -> Option<usize> {
+        if *self < columns.len() {
+            Some(*self)
+        } else {
+            None
+        }
+    }
+}
+
+impl RowIndex for str {
+    fn index(&self, columns: &[String]) -> Option<usize> {
+        columns.iter().position(|c| c == self)
+    }
+}
+
+impl<T: RowIndex + ?Sized> RowIndex for &T {
+    fn index(&self, columns: &[String]) -> Option<usize> {
+        (*self).index(columns)
+    }
+}
+
+/// A single row returned from a query.
+#[derive(Clone, Debug)]
+pub struct Row {
+    columns: Arc<[String]>,
+    values: Vec<Value>,
+}
+
+impl Row {
+    pub(crate) fn new(columns: Arc<[String]>, values: Vec<Value>) -> Row {
+        Row { columns, values }
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Names of the row's columns, in order.
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    /// Read the value in column `idx` as a `T`.
+    pub fn try_get<'a, I, T>(&'a self, idx: I) -> Result<T, Error>
+    where
+        I: RowIndex,
+        T: FromValue<'a>,
+    {
+        let n = idx
+            .index(&self.columns)
+            .ok_or_else(|| Error(format!("No column {} in row.", &idx)))?;
+        T::from_value(&self.values[n])
+            .map_err(|e| Error(format!("Error reading column {}: {}", &idx, &e)))
+    }
+
+    /// The row as a JSON object, keyed by column name.
+    pub fn to_json(&self) -> String {
+        let map: serde_json::Map<String, serde_json::Value> = self
+            .columns
+            .iter()
+            .zip(self.values.iter())
+            .map(|(col, v)| {
+                let jv = match v {
+                    Value::Null | Value::Unsupported(_) => serde_json::Value::Null,
+                    Value::Bool(b) => serde_json::Value::from(*b),
+                    Value::Int(n) => serde_json::Value::from(*n),
+                    Value::Float(x) => serde_json::Value::from(*x),
+                    Value::Text(s) => serde_json::Value::from(s.as_str()),
+                    Value::Bytes(b) => serde_json::Value::from(b.as_slice()),
+                    Value::Date(d) => serde_json::Value::from(Value::date_string(d)),
+                    Value::Timestamp(t) => serde_json::Value::from(Value::timestamp_string(t)),
+                    Value::TextArray(a) => serde_json::Value::from(a.as_slice()),
+                };
+                (col.clone(), jv)
+            })
+            .collect();
+        serde_json::Value::Object(map).to_string()
+    }
+
+    /// Read the value in column `idx` as a `T`, panicking if it can't be.
+    pub fn get<'a, I, T>(&'a self, idx: I) -> T
+    where
+        I: RowIndex,
+        T: FromValue<'a>,
+    {
+        match self.try_get(idx) {
+            Ok(x) => x,
+            Err(e) => panic!("{}", &e),
+        }
+    }
+}
+
+/// Operations common to plain connections and transactions.
+#[async_trait]
+pub trait Connection: Send + Sync {
+    fn dialect(&self) -> Dialect;
+
+    /// Run a single statement, returning the number of rows affected.
+    async fn execute(&self, sql: &str, params: &[&(dyn ToValue + Sync)]) -> Result<u64, Error>;
+
+    /// Run a single statement, returning the rows it produces.
+    async fn query(&self, sql: &str, params: &[&(dyn ToValue + Sync)])
+        -> Result<Vec<Row>, Error>;
+
+    /// Run one or more statements separated by semicolons (with no
+    /// parameters).
+    async fn batch_execute(&self, sql: &str) -> Result<(), Error>;
+
+    /// Whether there's a table named `table`.
+    async fn table_exists(&self, table: &str) -> Result<bool, Error>;
+
+    /// Whether there's a table named `table` with a column named `column`.
+    async fn column_exists(&self, table: &str, column: &str) -> Result<bool, Error>;
+
+    /// The row of `table` whose `key_col` is `key` (if there is one), as a
+    /// JSON object keyed by column name.
+    async fn row_json(
+        &self,
+        table: &str,
+        key_col: &str,
+        key: &(dyn ToValue + Sync),
+    ) -> Result<Option<String>, Error>;
+
+    /// Run a statement that should return exactly one row.
+    async fn query_one(&self, sql: &str, params: &[&(dyn ToValue + Sync)]) -> Result<Row, Error> {
+        match self.query_opt(sql, params).await? {
+            Some(row) => Ok(row),
+            None => Err(Error::from("query returned no rows")),
+        }
+    }
+
+    /// Run a statement that should return at most one row.
+    async fn query_opt(
+        &self,
+        sql: &str,
+        params: &[&(dyn ToValue + Sync)],
+    ) -> Result<Option<Row>, Error> {
+        let mut rows = self.query(sql, params).await?;
+        match rows.len() {
+            0 => Ok(None),
+            1 => Ok(rows.pop()),
+            _ => Err(Error::from("query returned more than one row")),
+        }
+    }
+}
+
+/// A connection to the database, from which transactions can be started.
+#[async_trait]
+pub trait ClientConnection: Connection {
+    async fn transaction<'a>(&'a mut self) -> Result<Transaction<'a>, Error>;
+}
+
+/**
+A transaction in progress. Dropping it without calling `commit()` rolls it
+back.
+*/
+#[async_trait]
+pub trait TransactionConnection: Connection {
+    async fn commit(self: Box<Self>) -> Result<(), Error>;
+    async fn rollback(self: Box<Self>) -> Result<(), Error>;
+
+    /**
+    Keep other transactions from writing to `table` (or locking it this way)
+    until this one ends, so that what this one reads from it stays true
+    until it's done writing.
+    */
+    async fn lock_table(&self, table: &str) -> Result<(), Error>;
+
+    /// Wait for, and then hold until this transaction ends, the lock named
+    /// `id`, which is only meaningful to other transactions taking it.
+    async fn lock_id(&self, id: i64) -> Result<(), Error>;
+
+    /// Make every statement in this transaction read from the same snapshot
+    /// of the database, and (where the backend can) refuse to write.
+    async fn read_snapshot(&self) -> Result<(), Error>;
+
+    /// After rows have been inserted into `table` with explicit `id`s, make
+    /// sure new ones are given `id`s past them.
+    async fn sync_id_sequence(&self, table: &str) -> Result<(), Error>;
+}
+
+pub type Client = Box<dyn ClientConnection>;
+pub type Transaction<'a> = Box<dyn TransactionConnection + 'a>;
+
+/// A database from which connections can be had.
+#[async_trait]
+pub trait Backend: Send + Sync {
+    fn dialect(&self) -> Dialect;
+    async fn connect(&self) -> Result<Client, Error>;
+}
+
+/**
+Open the database described by `connection_string` with the appropriate
+backend: SQLite if it starts with `sqlite:`, otherwise Postgres. (See the
+[module-level documentation](self).)
+
+No connection is made until one is requested.
+*/
+pub fn open(connection_string: &str, pool_config: &PoolConfig) -> Result<Box<dyn Backend>, Error> {
+    match connection_string.strip_prefix(SQLITE_PREFIX) {
+        Some(path) => Ok(Box::new(sqlite::Sqlite::new(path, pool_config))),
+        None => {
+            let pool = pool_config.build(connection_string)?;
+            Ok(Box::new(postgres::Postgres::new(pool)))
+        }
+    }
+}
+
+/**
+Start listening on `channel` of the database described by
+`connection_string` (see [`Listener`]).
+
+Only Postgres has notifications; for an SQLite database this returns
+`Ok(None)`.
+*/
+pub async fn listen(
+    connection_string: &str,
+    pool_config: &PoolConfig,
+    channel: &str,
+) -> Result<Option<Listener>, Error> {
+    if connection_string.starts_with(SQLITE_PREFIX) {
+        return Ok(None);
+    }
+    Listener::connect(connection_string, pool_config, channel)
+        .await
+        .map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_values() {
+        let row = Row::new(
+            vec![
+                "flag".to_owned(),
+                "n".to_owned(),
+                "day".to_owned(),
+                "list".to_owned(),
+                "nothing".to_owned(),
+            ]
+            .into(),
+            vec![
+                Value::Int(1),
+                Value::Int(300),
+                Value::Text("2023-01-10".to_owned()),
+                Value::Text(r#"["a","b"]"#.to_owned()),
+                Value::Null,
+            ],
+        );
+
+        assert!(row.get::<_, bool>("flag"));
+        assert_eq!(row.get::<_, i16>(1), 300);
+        assert!(row.try_get::<_, i16>("flag").is_ok());
+        assert_eq!(
+            row.get::<_, Date>("day"),
+            Date::from_calendar_date(2023, time::Month::January, 10).unwrap()
+        );
+        assert_eq!(
+            row.get::<_, Vec<String>>("list"),
+            vec!["a".to_owned(), "b".to_owned()]
+        );
+        assert_eq!(row.get::<_, Option<&str>>("nothing"), None);
+        assert!(row.try_get::<_, &str>("nothing").is_err());
+        assert!(row.try_get::<_, &str>("missing").is_err());
+        assert!(row.try_get::<_, i64>(5).is_err());
+    }
+
+    #[test]
+    fn dialects() {
+        assert_eq!(
+            Dialect::Postgres.in_array("uname", "$1"),
+            "uname = ANY($1)"
+        );
+        assert_eq!(
+            Dialect::Sqlite.add_interval("issued", "$2"),
+            "datetime(issued, $2)"
+        );
+    }
+}
@@ -0,0 +1,389 @@
+/*!
+The Postgres [`Backend`], drawing connections from a pool (see
+[`crate::pool`]).
+
+Statements are prepared once per connection and cached; parameter types are
+whatever Postgres infers from the statement, and [`Value`]s are converted to
+match.
+
+A [`Listener`] is the exception: it holds its own connection, outside the
+pool, for as long as it's listening.
+*/
+use std::{error::Error as StdError, fmt::Write, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use bytes::BytesMut;
+use deadpool_postgres::{Object, Pool};
+use futures::{stream, StreamExt};
+use time::{Date, OffsetDateTime, PrimitiveDateTime, UtcOffset};
+use tokio::sync::mpsc;
+use tokio_postgres::{
+    types::{to_sql_checked, FromSql, IsNull, ToSql, Type},
+    AsyncMessage, NoTls,
+};
+
+use super::*;
+
+type BoxedError = Box<dyn StdError + Sync + Send>;
+
+impl From<tokio_postgres::error::Error> for Error {
+    fn from(e: tokio_postgres::error::Error) -> Error {
+        let mut s = e.to_string();
+        if let Some(dbe) = e.as_db_error() {
+            write!(&mut s, "; {}", dbe).unwrap();
+        }
+        Error(s)
+    }
+}
+
+impl ToSql for Value {
+    fn to_sql(&self, ty: &Type, out: &mut BytesMut) -> Result<IsNull, BoxedError> {
+        match (self, ty) {
+            (Value::Null, _) => Ok(IsNull::Yes),
+
+            (Value::Bool(b), &Type::BOOL) => b.to_sql(ty, out),
+            (Value::Bool(b), _) => Value::Int(*b as i64).to_sql(ty, out),
+
+            (Value::Int(n), &Type::INT2) => i16::try_from(*n)?.to_sql(ty, out),
+            (Value::Int(n), &Type::INT4) => i32::try_from(*n)?.to_sql(ty, out),
+            (Value::Int(n), &Type::INT8) => n.to_sql(ty, out),
+            (Value::Int(n), &Type::FLOAT4) => (*n as f32).to_sql(ty, out),
+            (Value::Int(n), &Type::FLOAT8) => (*n as f64).to_sql(ty, out),
+            (Value::Int(n), &Type::BOOL) => (*n != 0).to_sql(ty, out),
+            (Value::Int(n), &Type::TEXT | &Type::VARCHAR) => n.to_string().to_sql(ty, out),
+
+            (Value::Float(x), &Type::FLOAT4) => (*x as f32).to_sql(ty, out),
+            (Value::Float(x), &Type::FLOAT8) => x.to_sql(ty, out),
+
+            (Value::Text(s), &Type::DATE) => Date::parse(s, DATE_FMT)?.to_sql(ty, out),
+            (Value::Text(s), _) => s.to_sql(ty, out),
+
+            (Value::Bytes(b), _) => b.to_sql(ty, out),
+
+            (Value::Date(d), &Type::DATE) => d.to_sql(ty, out),
+            (Value::Date(d), _) => Value::date_string(d).to_sql(ty, out),
+
+            (Value::Timestamp(t), &Type::TIMESTAMP) => t.to_sql(ty, out),
+            (Value::Timestamp(t), &Type::TIMESTAMPTZ) => t.assume_utc().to_sql(ty, out),
+            (Value::Timestamp(t), _) => Value::timestamp_string(t).to_sql(ty, out),
+
+            (Value::TextArray(a), _) => a.to_sql(ty, out),
+
+            (v, ty) => Err(format!("Can't use {:?} as a parameter of type {}.", v, ty).into()),
+        }
+    }
+
+    fn accepts(_: &Type) -> bool {
+        true
+    }
+
+    to_sql_checked!();
+}
+
+impl<'a> FromSql<'a> for Value {
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> Result<Value, BoxedError> {
+        let v = match *ty {
+            Type::BOOL => Value::Bool(bool::from_sql(ty, raw)?),
+            Type::INT2 => Value::Int(i16::from_sql(ty, raw)? as i64),
+            Type::INT4 => Value::Int(i32::from_sql(ty, raw)? as i64),
+            Type::INT8 => Value::Int(i64::from_sql(ty, raw)?),
+            Type::FLOAT4 => Value::Float(f32::from_sql(ty, raw)? as f64),
+            Type::FLOAT8 => Value::Float(f64::from_sql(ty, raw)?),
+            Type::TEXT | Type::VARCHAR | Type::BPCHAR | Type::NAME | Type::UNKNOWN => {
+                Value::Text(String::from_sql(ty, raw)?)
+            }
+            Type::BYTEA => Value::Bytes(Vec::<u8>::from_sql(ty, raw)?),
+            Type::DATE => Value::Date(Date::from_sql(ty, raw)?),
+            Type::TIMESTAMP => Value::Timestamp(PrimitiveDateTime::from_sql(ty, raw)?),
+            Type::TIMESTAMPTZ => {
+                let t = OffsetDateTime::from_sql(ty, raw)?.to_offset(UtcOffset::UTC);
+                Value::Timestamp(PrimitiveDateTime::new(t.date(), t.time()))
+            }
+            Type::TEXT_ARRAY | Type::VARCHAR_ARRAY => {
+                Value::TextArray(Vec::<String>::from_sql(ty, raw)?)
+            }
+            _ => Value::Unsupported(ty.name().to_owned()),
+        };
+        Ok(v)
+    }
+
+    fn from_sql_null(_: &Type) -> Result<Value, BoxedError> {
+        Ok(Value::Null)
+    }
+
+    fn accepts(_: &Type) -> bool {
+        true
+    }
+}
+
+fn convert_rows(rows: Vec<tokio_postgres::Row>) -> Result<Vec<Row>, Error> {
+    let columns: Arc<[String]> = match rows.first() {
+        Some(row) => row.columns().iter().map(|c| c.name().to_owned()).collect(),
+        None => return Ok(Vec::new()),
+    };
+
+    let mut converted = Vec::with_capacity(rows.len());
+    for row in rows.iter() {
+        let mut values = Vec::with_capacity(row.len());
+        for n in 0..row.len() {
+            values.push(row.try_get::<_, Value>(n)?);
+        }
+        converted.push(Row::new(columns.clone(), values));
+    }
+    Ok(converted)
+}
+
+fn to_values(params: &[&(dyn ToValue + Sync)]) -> Vec<Value> {
+    params.iter().map(|p| p.to_value()).collect()
+}
+
+fn as_params(values: &[Value]) -> Vec<&(dyn ToSql + Sync)> {
+    values.iter().map(|v| v as &(dyn ToSql + Sync)).collect()
+}
+
+/*
+The client and transaction types implement the same operations in the same
+way; the only difference is the type of the wrapped object.
+*/
+macro_rules! impl_connection {
+    ($t:ty) => {
+        #[async_trait]
+        impl Connection for $t {
+            fn dialect(&self) -> Dialect {
+                Dialect::Postgres
+            }
+
+            async fn execute(
+                &self,
+                sql: &str,
+                params: &[&(dyn ToValue + Sync)],
+            ) -> Result<u64, Error> {
+                let values = to_values(params);
+                let stmt = self.0.prepare_cached(sql).await?;
+                let n = self.0.execute(&stmt, &as_params(&values)).await?;
+                Ok(n)
+            }
+
+            async fn query(
+                &self,
+                sql: &str,
+                params: &[&(dyn ToValue + Sync)],
+            ) -> Result<Vec<Row>, Error> {
+                let values = to_values(params);
+                let stmt = self.0.prepare_cached(sql).await?;
+                let rows = self.0.query(&stmt, &as_params(&values)).await?;
+                convert_rows(rows)
+            }
+
+            async fn batch_execute(&self, sql: &str) -> Result<(), Error> {
+                self.0.batch_execute(sql).await?;
+                Ok(())
+            }
+
+            async fn table_exists(&self, table: &str) -> Result<bool, Error> {
+                self.query_one(
+                    "SELECT EXISTS (
+                        SELECT 1 FROM information_schema.tables
+                        WHERE table_schema = current_schema() AND table_name = $1
+                    )",
+                    &[&table],
+                )
+                .await?
+                .try_get(0)
+            }
+
+            async fn column_exists(&self, table: &str, column: &str) -> Result<bool, Error> {
+                self.query_one(
+                    "SELECT EXISTS (
+                        SELECT 1 FROM information_schema.columns
+                        WHERE table_schema = current_schema()
+                        AND table_name = $1 AND column_name = $2
+                    )",
+                    &[&table, &column],
+                )
+                .await?
+                .try_get(0)
+            }
+
+            async fn row_json(
+                &self,
+                table: &str,
+                key_col: &str,
+                key: &(dyn ToValue + Sync),
+            ) -> Result<Option<String>, Error> {
+                let stmt = format!(
+                    "SELECT to_jsonb({0})::TEXT AS snap FROM {0} WHERE {1} = $1",
+                    table, key_col
+                );
+                match self.query_opt(&stmt, &[key]).await? {
+                    Some(row) => Ok(Some(row.try_get("snap")?)),
+                    None => Ok(None),
+                }
+            }
+        }
+    };
+}
+
+pub struct PgClient(Object);
+pub struct PgTransaction<'a>(deadpool_postgres::Transaction<'a>);
+
+impl_connection!(PgClient);
+impl_connection!(PgTransaction<'_>);
+
+#[async_trait]
+impl ClientConnection for PgClient {
+    async fn transaction<'a>(&'a mut self) -> Result<Transaction<'a>, Error> {
+        let t = self.0.transaction().await?;
+        Ok(Box::new(PgTransaction(t)))
+    }
+}
+
+#[async_trait]
+impl TransactionConnection for PgTransaction<'_> {
+    async fn commit(self: Box<Self>) -> Result<(), Error> {
+        self.0.commit().await?;
+        Ok(())
+    }
+
+    async fn rollback(self: Box<Self>) -> Result<(), Error> {
+        self.0.rollback().await?;
+        Ok(())
+    }
+
+    async fn lock_table(&self, table: &str) -> Result<(), Error> {
+        self.batch_execute(&format!("LOCK TABLE {} IN SHARE ROW EXCLUSIVE MODE", table))
+            .await
+    }
+
+    async fn lock_id(&self, id: i64) -> Result<(), Error> {
+        self.execute("SELECT pg_advisory_xact_lock($1)", &[&id])
+            .await
+            .map(|_| ())
+    }
+
+    async fn read_snapshot(&self) -> Result<(), Error> {
+        self.batch_execute("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY")
+            .await
+    }
+
+    async fn sync_id_sequence(&self, table: &str) -> Result<(), Error> {
+        self.query(
+            &format!(
+                "SELECT setval(pg_get_serial_sequence('{0}', 'id'), MAX(id)) FROM {0}",
+                table
+            ),
+            &[],
+        )
+        .await
+        .map(|_| ())
+    }
+}
+
+pub struct Postgres {
+    pool: Pool,
+}
+
+impl Postgres {
+    pub fn new(pool: Pool) -> Postgres {
+        Postgres { pool }
+    }
+}
+
+#[async_trait]
+impl Backend for Postgres {
+    fn dialect(&self) -> Dialect {
+        Dialect::Postgres
+    }
+
+    async fn connect(&self) -> Result<Client, Error> {
+        match self.pool.get().await {
+            Ok(client) => Ok(Box::new(PgClient(client))),
+            Err(e) => Err(Error(e.to_string())),
+        }
+    }
+}
+
+/**
+A connection `LISTEN`ing on a channel, which passes along the payload of
+each notification sent on it.
+
+The connection is closed when the `Listener` is dropped.
+*/
+pub struct Listener {
+    // Only held so the connection stays open.
+    _client: tokio_postgres::Client,
+    payloads: mpsc::UnboundedReceiver<String>,
+    application_name: String,
+    schema: String,
+}
+
+impl Listener {
+    /// Connect to the database described by `connection_string` and start
+    /// listening on `channel`.
+    pub async fn connect(
+        connection_string: &str,
+        pool_config: &PoolConfig,
+        channel: &str,
+    ) -> Result<Listener, Error> {
+        let mut pg_config: tokio_postgres::Config = connection_string
+            .parse()
+            .map_err(|e| Error(format!("Unable to parse connection string: {}", &e)))?;
+        pg_config.connect_timeout(Duration::from_secs(pool_config.timeout_seconds));
+        let application_name = crate::pool::application_name(&pg_config);
+        pg_config.application_name(&application_name);
+
+        let (client, mut connection) = pg_config.connect(NoTls).await?;
+
+        // The connection has to be polled for anything to happen on it, and
+        // that's also the only way to get at the notifications.
+        let (tx, payloads) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let mut messages = stream::poll_fn(move |cx| connection.poll_message(cx));
+            while let Some(msg) = messages.next().await {
+                match msg {
+                    Ok(AsyncMessage::Notification(n)) => {
+                        if tx.send(n.payload().to_owned()).is_err() {
+                            break;
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        log::error!("Error on listening connection: {}", &e);
+                        break;
+                    }
+                }
+            }
+        });
+
+        let row = client.query_one("SELECT current_schema()", &[]).await?;
+        let schema: String = row.try_get(0)?;
+        client
+            .batch_execute(&format!("LISTEN \"{}\"", channel.replace('"', "\"\"")))
+            .await?;
+
+        Ok(Listener {
+            _client: client,
+            payloads,
+            application_name,
+            schema,
+        })
+    }
+
+    /// The payload of the next notification, or `None` once the connection
+    /// has been lost.
+    pub async fn recv(&mut self) -> Option<String> {
+        self.payloads.recv().await
+    }
+
+    /// The `application_name` the listening connection (and any others from
+    /// this process to the same database) goes by.
+    pub fn application_name(&self) -> &str {
+        &self.application_name
+    }
+
+    /// The schema tables are found in on this connection (as determined by
+    /// its `search_path`).
+    pub fn schema(&self) -> &str {
+        &self.schema
+    }
+}
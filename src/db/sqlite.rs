@@ -0,0 +1,490 @@
+/*!
+The embedded SQLite [`Backend`].
+
+Each backend holds a single connection to its database file, shared by
+every [`Client`] it hands out. A transaction holds the connection for as
+long as it's open; any other statement (from another client) waits for it
+to finish, up to the pool's `timeout_seconds`. Individual statements run
+synchronously, so statements issued concurrently on the same client or
+transaction just run one after the other.
+
+Statements are written with Postgres-style `$1, $2, ...` placeholders,
+which are rewritten as SQLite's `?1, ?2, ...`. Values SQLite doesn't have
+a type for are stored as text: dates as "YYYY-MM-DD", timestamps as
+"YYYY-MM-DD HH:MM:SS", and arrays of text as JSON arrays.
+*/
+use std::{
+    sync::{Arc, Mutex as SyncMutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use once_cell::sync::OnceCell;
+use rusqlite::types::{Value as SqlValue, ValueRef};
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+use super::*;
+
+/// Path that opens a database that lives only in memory.
+const MEMORY_PATH: &str = ":memory:";
+
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Error {
+        Error(e.to_string())
+    }
+}
+
+/// Rewrite `$n` placeholders as `?n`, leaving quoted strings and
+/// identifiers alone.
+fn translate_placeholders(sql: &str) -> String {
+    let mut out = String::with_capacity(sql.len());
+    let mut quote: Option<char> = None;
+    let mut chars = sql.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match quote {
+            Some(q) => {
+                if c == q {
+                    quote = None;
+                }
+                out.push(c);
+            }
+            None => match c {
+                '\'' | '"' => {
+                    quote = Some(c);
+                    out.push(c);
+                }
+                '$' if chars.peek().map(|d| d.is_ascii_digit()).unwrap_or(false) => {
+                    out.push('?');
+                }
+                _ => out.push(c),
+            },
+        }
+    }
+
+    out
+}
+
+fn to_sqlite(v: Value) -> Result<SqlValue, Error> {
+    let sv = match v {
+        Value::Null => SqlValue::Null,
+        Value::Bool(b) => SqlValue::Integer(b as i64),
+        Value::Int(n) => SqlValue::Integer(n),
+        Value::Float(x) => SqlValue::Real(x),
+        Value::Text(s) => SqlValue::Text(s),
+        Value::Bytes(b) => SqlValue::Blob(b),
+        Value::Date(d) => SqlValue::Text(Value::date_string(&d)),
+        Value::Timestamp(t) => SqlValue::Text(Value::timestamp_string(&t)),
+        Value::TextArray(a) => SqlValue::Text(Value::array_string(&a)),
+        Value::Unsupported(name) => {
+            return Err(Error(format!("Unsupported parameter type {:?}.", &name)));
+        }
+    };
+    Ok(sv)
+}
+
+fn from_sqlite(v: ValueRef) -> Value {
+    match v {
+        ValueRef::Null => Value::Null,
+        ValueRef::Integer(n) => Value::Int(n),
+        ValueRef::Real(x) => Value::Float(x),
+        ValueRef::Text(b) => Value::Text(String::from_utf8_lossy(b).into_owned()),
+        ValueRef::Blob(b) => Value::Bytes(b.to_vec()),
+    }
+}
+
+/// Prepare `sql`, bind `params`, and run it, calling `on_row` with each
+/// row returned. Returns the names of the statement's columns.
+fn run_statement(
+    conn: &rusqlite::Connection,
+    sql: &str,
+    params: &[&(dyn ToValue + Sync)],
+    mut on_row: impl FnMut(Vec<Value>),
+) -> Result<Vec<String>, Error> {
+    let mut stmt = conn.prepare_cached(&translate_placeholders(sql))?;
+    for (n, p) in params.iter().enumerate() {
+        stmt.raw_bind_parameter(n + 1, to_sqlite(p.to_value())?)?;
+    }
+    let columns: Vec<String> = stmt.column_names().iter().map(|c| c.to_string()).collect();
+
+    let mut rows = stmt.raw_query();
+    while let Some(row) = rows.next()? {
+        let mut values = Vec::with_capacity(columns.len());
+        for n in 0..columns.len() {
+            values.push(from_sqlite(row.get_ref(n)?));
+        }
+        on_row(values);
+    }
+
+    Ok(columns)
+}
+
+fn execute(
+    conn: &rusqlite::Connection,
+    sql: &str,
+    params: &[&(dyn ToValue + Sync)],
+) -> Result<u64, Error> {
+    let mut n_rows: u64 = 0;
+    let columns = run_statement(conn, sql, params, |_| n_rows += 1)?;
+    if columns.is_empty() {
+        // Like Postgres, report the number of rows returned by a statement
+        // that returns rows, and the number affected by one that doesn't.
+        Ok(conn.changes())
+    } else {
+        Ok(n_rows)
+    }
+}
+
+fn query(
+    conn: &rusqlite::Connection,
+    sql: &str,
+    params: &[&(dyn ToValue + Sync)],
+) -> Result<Vec<Row>, Error> {
+    let mut values: Vec<Vec<Value>> = Vec::new();
+    let columns = run_statement(conn, sql, params, |row| values.push(row))?;
+    let columns: Arc<[String]> = columns.into();
+    Ok(values
+        .into_iter()
+        .map(|v| Row::new(columns.clone(), v))
+        .collect())
+}
+
+fn batch_execute(conn: &rusqlite::Connection, sql: &str) -> Result<(), Error> {
+    conn.execute_batch(sql)?;
+    Ok(())
+}
+
+fn table_exists(conn: &rusqlite::Connection, table: &str) -> Result<bool, Error> {
+    let rows = query(
+        conn,
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = $1)",
+        &[&table],
+    )?;
+    rows[0].try_get(0)
+}
+
+fn column_exists(conn: &rusqlite::Connection, table: &str, column: &str) -> Result<bool, Error> {
+    let rows = query(
+        conn,
+        "SELECT EXISTS (SELECT 1 FROM pragma_table_info($1) WHERE name = $2)",
+        &[&table, &column],
+    )?;
+    rows[0].try_get(0)
+}
+
+fn row_json(
+    conn: &rusqlite::Connection,
+    table: &str,
+    key_col: &str,
+    key: &(dyn ToValue + Sync),
+) -> Result<Option<String>, Error> {
+    let stmt = format!("SELECT * FROM {} WHERE {} = $1", table, key_col);
+    let rows = query(conn, &stmt, &[key])?;
+    Ok(rows.first().map(Row::to_json))
+}
+
+type Shared = Arc<Mutex<rusqlite::Connection>>;
+
+pub struct SqliteClient {
+    conn: Shared,
+    timeout: Duration,
+}
+
+impl SqliteClient {
+    async fn lock(&self) -> Result<tokio::sync::MutexGuard<'_, rusqlite::Connection>, Error> {
+        tokio::time::timeout(self.timeout, self.conn.lock())
+            .await
+            .map_err(|_| Error::from("Timed out waiting for the database to be free."))
+    }
+}
+
+#[async_trait]
+impl Connection for SqliteClient {
+    fn dialect(&self) -> Dialect {
+        Dialect::Sqlite
+    }
+
+    async fn execute(&self, sql: &str, params: &[&(dyn ToValue + Sync)]) -> Result<u64, Error> {
+        execute(&*self.lock().await?, sql, params)
+    }
+
+    async fn query(
+        &self,
+        sql: &str,
+        params: &[&(dyn ToValue + Sync)],
+    ) -> Result<Vec<Row>, Error> {
+        query(&*self.lock().await?, sql, params)
+    }
+
+    async fn batch_execute(&self, sql: &str) -> Result<(), Error> {
+        batch_execute(&*self.lock().await?, sql)
+    }
+
+    async fn table_exists(&self, table: &str) -> Result<bool, Error> {
+        table_exists(&*self.lock().await?, table)
+    }
+
+    async fn column_exists(&self, table: &str, column: &str) -> Result<bool, Error> {
+        column_exists(&*self.lock().await?, table, column)
+    }
+
+    async fn row_json(
+        &self,
+        table: &str,
+        key_col: &str,
+        key: &(dyn ToValue + Sync),
+    ) -> Result<Option<String>, Error> {
+        row_json(&*self.lock().await?, table, key_col, key)
+    }
+}
+
+#[async_trait]
+impl ClientConnection for SqliteClient {
+    async fn transaction<'a>(&'a mut self) -> Result<Transaction<'a>, Error> {
+        let guard = tokio::time::timeout(self.timeout, self.conn.clone().lock_owned())
+            .await
+            .map_err(|_| Error::from("Timed out waiting for the database to be free."))?;
+        guard.execute_batch("BEGIN IMMEDIATE")?;
+        Ok(Box::new(SqliteTransaction {
+            conn: SyncMutex::new(Some(guard)),
+        }))
+    }
+}
+
+/**
+The connection is held until the transaction is committed or rolled back
+(or dropped, which rolls it back). It's behind a (synchronous) mutex only
+so that the transaction can be shared between threads; it's never held
+across an `.await`.
+*/
+pub struct SqliteTransaction {
+    conn: SyncMutex<Option<OwnedMutexGuard<rusqlite::Connection>>>,
+}
+
+impl SqliteTransaction {
+    fn with_conn<T>(
+        &self,
+        f: impl FnOnce(&rusqlite::Connection) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let guard = self
+            .conn
+            .lock()
+            .map_err(|_| Error::from("Transaction poisoned by an earlier panic."))?;
+        match guard.as_ref() {
+            Some(conn) => f(conn),
+            None => Err(Error::from("Transaction already finished.")),
+        }
+    }
+
+    /// Run `sql` (COMMIT or ROLLBACK) and release the connection. If it
+    /// fails, try to leave the connection out of any transaction anyway.
+    fn finish(&self, sql: &str) -> Result<(), Error> {
+        let conn = match self.conn.lock() {
+            Ok(mut guard) => guard.take(),
+            Err(_) => return Err(Error::from("Transaction poisoned by an earlier panic.")),
+        };
+        match conn {
+            Some(conn) => conn.execute_batch(sql).map_err(|e| {
+                if !conn.is_autocommit() {
+                    let _ = conn.execute_batch("ROLLBACK");
+                }
+                Error::from(e)
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for SqliteTransaction {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish("ROLLBACK") {
+            log::error!("Error rolling back abandoned transaction: {}", &e);
+        }
+    }
+}
+
+#[async_trait]
+impl Connection for SqliteTransaction {
+    fn dialect(&self) -> Dialect {
+        Dialect::Sqlite
+    }
+
+    async fn execute(&self, sql: &str, params: &[&(dyn ToValue + Sync)]) -> Result<u64, Error> {
+        self.with_conn(|c| execute(c, sql, params))
+    }
+
+    async fn query(
+        &self,
+        sql: &str,
+        params: &[&(dyn ToValue + Sync)],
+    ) -> Result<Vec<Row>, Error> {
+        self.with_conn(|c| query(c, sql, params))
+    }
+
+    async fn batch_execute(&self, sql: &str) -> Result<(), Error> {
+        self.with_conn(|c| batch_execute(c, sql))
+    }
+
+    async fn table_exists(&self, table: &str) -> Result<bool, Error> {
+        self.with_conn(|c| table_exists(c, table))
+    }
+
+    async fn column_exists(&self, table: &str, column: &str) -> Result<bool, Error> {
+        self.with_conn(|c| column_exists(c, table, column))
+    }
+
+    async fn row_json(
+        &self,
+        table: &str,
+        key_col: &str,
+        key: &(dyn ToValue + Sync),
+    ) -> Result<Option<String>, Error> {
+        self.with_conn(|c| row_json(c, table, key_col, key))
+    }
+}
+
+#[async_trait]
+impl TransactionConnection for SqliteTransaction {
+    async fn commit(self: Box<Self>) -> Result<(), Error> {
+        self.finish("COMMIT")
+    }
+
+    async fn rollback(self: Box<Self>) -> Result<(), Error> {
+        self.finish("ROLLBACK")
+    }
+
+    // A transaction begins by locking the whole database (see
+    // `SqliteClient::transaction()`), so it's already safe from other
+    // writers, and reads from a single snapshot.
+
+    async fn lock_table(&self, _table: &str) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn lock_id(&self, _id: i64) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn read_snapshot(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    // `AUTOINCREMENT` keys always go past the largest one ever inserted.
+    async fn sync_id_sequence(&self, _table: &str) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+pub struct Sqlite {
+    path: String,
+    timeout: Duration,
+    conn: OnceCell<Shared>,
+}
+
+impl Sqlite {
+    pub fn new(path: &str, pool_config: &PoolConfig) -> Sqlite {
+        Sqlite {
+            path: path.to_owned(),
+            timeout: Duration::from_secs(pool_config.timeout_seconds),
+            conn: OnceCell::new(),
+        }
+    }
+
+    fn open(&self) -> Result<Shared, Error> {
+        let conn = if self.path == MEMORY_PATH {
+            rusqlite::Connection::open_in_memory()
+        } else {
+            rusqlite::Connection::open(&self.path)
+        }
+        .map_err(|e| Error(format!("Unable to open {:?}: {}", &self.path, &e)))?;
+
+        conn.busy_timeout(self.timeout)?;
+        conn.execute_batch("PRAGMA foreign_keys = ON")?;
+        if self.path != MEMORY_PATH {
+            // Lets `camp migrate status` read the file while the server has
+            // it open.
+            conn.query_row("PRAGMA journal_mode = WAL", [], |_| Ok(()))?;
+        }
+
+        Ok(Arc::new(Mutex::new(conn)))
+    }
+}
+
+#[async_trait]
+impl Backend for Sqlite {
+    fn dialect(&self) -> Dialect {
+        Dialect::Sqlite
+    }
+
+    async fn connect(&self) -> Result<Client, Error> {
+        let conn = self.conn.get_or_try_init(|| self.open())?;
+        Ok(Box::new(SqliteClient {
+            conn: conn.clone(),
+            timeout: self.timeout,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn placeholders() {
+        assert_eq!(
+            translate_placeholders("SELECT $1, '$2', \"$3\" WHERE x = $10"),
+            "SELECT ?1, '$2', \"$3\" WHERE x = ?10"
+        );
+    }
+
+    #[tokio::test]
+    async fn transactions() {
+        let db = Sqlite::new(MEMORY_PATH, &PoolConfig::default());
+        let mut client = db.connect().await.unwrap();
+        client
+            .batch_execute("CREATE TABLE t (x INTEGER PRIMARY KEY, y TEXT)")
+            .await
+            .unwrap();
+
+        {
+            let t = client.transaction().await.unwrap();
+            t.execute("INSERT INTO t (x, y) VALUES ($1, $2)", &[&1_i64, &"one"])
+                .await
+                .unwrap();
+            // dropped without committing
+        }
+        assert!(client
+            .query_opt("SELECT y FROM t", &[])
+            .await
+            .unwrap()
+            .is_none());
+
+        let t = client.transaction().await.unwrap();
+        let n = t
+            .execute(
+                "INSERT INTO t (x, y) VALUES ($1, $2), ($3, $2)",
+                &[&1_i64, &"same", &2_i64],
+            )
+            .await
+            .unwrap();
+        assert_eq!(n, 2);
+        assert!(t
+            .execute("INSERT INTO t (x, y) VALUES ($1, NULL)", &[&1_i64])
+            .await
+            .is_err());
+        t.commit().await.unwrap();
+
+        let rows = client
+            .query(
+                &format!(
+                    "SELECT x FROM t WHERE {} ORDER BY x",
+                    Dialect::Sqlite.in_array("y", "$1")
+                ),
+                &[&vec!["same".to_owned(), "other".to_owned()]],
+            )
+            .await
+            .unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1].get::<_, i64>("x"), 2);
+    }
+}
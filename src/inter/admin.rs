@@ -3,75 +3,133 @@ Subcrate for interoperation with Admin users.
 */
 use std::collections::HashMap;
 use std::io::Cursor;
+use std::net::SocketAddr;
 use std::sync::Arc;
 
 use axum::{
-    extract::Extension,
+    extract::{ConnectInfo, Extension},
     http::header::{HeaderMap, HeaderName},
     response::{IntoResponse, Response},
     Json,
 };
 use serde_json::json;
 use time::Date;
-use tokio::sync::RwLock;
 
 use super::*;
-use crate::config::Glob;
+use crate::config::{Glob, GlobCell};
 use crate::hist::HistEntry;
 use crate::course::{Chapter, Course};
-use crate::{auth::AuthResult, user::*, DATE_FMT};
+use crate::tenant::{Tenant, Tenants};
+use crate::{auth::AuthResult, perm, user::*, UnifiedError, DATE_FMT};
 
 /**
 Determine whether the Admin's login credentials check out, then send the
 initial HTML for the Admin view.
 
+If the Admin has two-factor authentication enabled (or it's mandatory),
+serve the second-factor page instead; the login is then completed by
+[`totp_login`].
+
 After receiving this initial load of information, the Admin frontend will
 automatically send another couple of requests to populate additional
 information.
 */
-pub async fn login(base: BaseUser, form: LoginData, glob: Arc<RwLock<Glob>>) -> Response {
+pub async fn login(
+    base: BaseUser,
+    form: LoginData,
+    client: &ClientInfo,
+    glob: Arc<GlobCell>,
+) -> Response {
     log::trace!(
         "admin::login( {:?}, {:?}, [ global state ] ) called.",
         &base,
         &form
     );
 
-    let auth_response = {
-        glob.read()
-            .await
-            .auth()
-            .read()
-            .await
-            .check_password_and_issue_key(&base.uname, &form.password, &base.salt)
-            .await
-    };
+    let glob = glob.load();
+    let auth_response = glob
+        .auth()
+        .read()
+        .await
+        .check_login(&base.uname, &form.password, &base.salt, &client.addr)
+        .await;
 
-    let auth_key = match auth_response {
+    match auth_response {
         Err(e) => {
             log::error!(
-                "Error: auth::Db::check_password_and_issue_key( {:?}, {:?}, [ Glob ]): {}",
+                "Error: auth::Db::check_login( {:?}, {:?}, [ Glob ]): {}",
                 &base,
                 &form,
                 &e
             );
             return html_500();
         }
-        Ok(AuthResult::Key(k)) => k,
+        Ok(AuthResult::Ok) => { /* Password checks out; proceed. */ }
         Ok(AuthResult::BadPassword) => {
             return respond_bad_password(&base.uname);
         }
+        Ok(AuthResult::Locked) => {
+            return respond_locked(&base.uname);
+        }
         Ok(x) => {
             log::warn!(
-                "auth::Db::check_password_and_issue_key( {:?}, {:?}, [ Glob ] ) returned {:?}, which shouldn't happen.",
+                "auth::Db::check_login( {:?}, {:?}, [ Glob ] ) returned {:?}, which shouldn't happen.",
                 &base, &form, &x
             );
             return respond_bad_password(&base.uname);
         }
     };
 
+    if let Some(response) = second_factor_page(&base.uname, &glob).await {
+        return response;
+    }
+
+    let auth_key = match glob.auth().read().await.issue_key(&base.uname, client).await {
+        Ok(AuthResult::Key(k)) => k,
+        Ok(x) => {
+            log::warn!(
+                "auth::Db::issue_key( {:?} ) returned {:?}, which shouldn't happen.",
+                &base.uname,
+                &x
+            );
+            return html_500();
+        }
+        Err(e) => {
+            log::error!("Error: auth::Db::issue_key( {:?} ): {}", &base.uname, &e);
+            return html_500();
+        }
+    };
+
+    serve_view(&base.uname, &auth_key)
+}
+
+/**
+Complete an Admin login by checking the second-factor code, then send the
+initial HTML for the Admin view.
+*/
+pub async fn totp_login(
+    base: BaseUser,
+    form: TotpData,
+    client: &ClientInfo,
+    glob: Arc<GlobCell>,
+) -> Response {
+    log::trace!(
+        "admin::totp_login( {:?}, {:?}, [ global state ] ) called.",
+        &base,
+        &form
+    );
+
+    match check_second_factor(&form, client, &glob.load()).await {
+        Ok(auth_key) => serve_view(&base.uname, &auth_key),
+        Err(response) => response,
+    }
+}
+
+/// Render the initial HTML for the Admin view.
+fn serve_view(uname: &str, auth_key: &str) -> Response {
     let data = json!({
-        "uname": &base.uname,
-        "key": &auth_key
+        "uname": uname,
+        "key": auth_key
     });
 
     serve_template(StatusCode::OK, "admin", &data, vec![])
@@ -89,9 +147,12 @@ A previous layer should have already ensured that the Admin's key
 checks out.
 */
 pub async fn api(
+    ConnectInfo(sock): ConnectInfo<SocketAddr>,
     headers: HeaderMap,
     body: Option<String>,
-    Extension(glob): Extension<Arc<RwLock<Glob>>>,
+    Extension(glob): Extension<Arc<GlobCell>>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+    Extension(tenants): Extension<Arc<Tenants>>,
 ) -> Response {
     let uname: &str = match headers.get("x-camp-uname") {
         Some(uname) => match uname.to_str() {
@@ -105,25 +166,8 @@ pub async fn api(
         }
     };
 
-    let u = {
-        let glob = glob.read().await;
-        if let Some(u) = glob.users.get(uname) {
-            u.clone()
-        } else {
-            return text_500(None);
-        }
-    };
-
-    match u {
-        User::Admin(_) => { /* Okay, request may proceed. */ }
-        _ => {
-            return (
-                StatusCode::FORBIDDEN,
-                "Who is this? What's your operating number?".to_owned(),
-            )
-                .into_response();
-        }
-    };
+    // Whether this user may perform the requested action has already been
+    // checked by the `inter::authorize` layer.
 
     let action = match headers.get("x-camp-action") {
         Some(act) => match act.to_str() {
@@ -137,14 +181,42 @@ pub async fn api(
         }
     };
 
-    match action {
+    let audit = Audit::begin(
+        Endpoint::Admin,
+        action,
+        &headers,
+        body.as_deref(),
+        &glob.load(),
+    )
+    .await;
+
+    let resp = match action {
         "populate-users" => populate_users(glob.clone()).await,
         "populate-admins" => populate_role(glob.clone(), Role::Admin).await,
         "populate-bosses" => populate_role(glob.clone(), Role::Boss).await,
-        "add-user" => add_user(body, glob.clone()).await,
+        "add-user" => add_user(body, &headers, glob.clone()).await,
         "update-user" => update_user(body, glob.clone()).await,
         "delete-user" => delete_user(body, glob.clone()).await,
-        "upload-students" => upload_students(body, glob.clone()).await,
+        "unlock-user" => unlock_user(body, glob.clone()).await,
+        "begin-2fa" => begin_totp_enrollment(uname, glob.clone()).await,
+        "confirm-2fa" => confirm_totp_enrollment(uname, body, glob.clone()).await,
+        "reset-2fa" => reset_totp(body, glob.clone()).await,
+        "list-sessions" => list_sessions(body, glob.clone()).await,
+        "revoke-session" => revoke_session(body, glob.clone()).await,
+        "revoke-sessions" => revoke_all_sessions(body, glob.clone()).await,
+        "populate-tokens" => populate_api_tokens(None, glob.clone()).await,
+        "issue-token" => issue_api_token(body, glob.clone()).await,
+        "revoke-token" => revoke_api_token(body, glob.clone()).await,
+        "impersonate" => {
+            let client = client_info(&headers, &sock, glob.load().trust_proxy_headers);
+            impersonate(uname, body, &client, glob.clone()).await
+        }
+        "populate-grants" => populate_grants(glob.clone()).await,
+        "grant-permission" => change_grant(body, true, glob.clone()).await,
+        "revoke-permission" => change_grant(body, false, glob.clone()).await,
+        "upload-students" => upload_students(body, &headers, glob.clone()).await,
+        "populate-invites" => populate_invites(glob.clone()).await,
+        "resend-invite" => resend_invite(body, glob.clone()).await,
         "populate-courses" => populate_courses(glob.clone()).await,
         "upload-course" => upload_course(body, glob.clone()).await,
         "add-course" => add_course(body, glob.clone()).await,
@@ -160,22 +232,30 @@ pub async fn api(
         "populate-completion" => populate_completion(glob.clone()).await,
         "add-completion" => add_completion(body, &headers, glob.clone()).await,
         "delete-completion" => delete_completion(&headers, glob.clone()).await,
-        "reset-students" => reset_students(glob.clone()).await,
+        "query-audit" => query_audit(body, glob.clone()).await,
+        "export-audit" => export_audit(body, glob.clone()).await,
+        "export-archive" => export_archive(glob.clone()).await,
+        "reload-config" => reload_config(&tenants, &tenant).await,
         x => respond_bad_request(format!(
             "{:?} is not a recognizable x-camp-action value.",
             x
         )),
+    };
+
+    if let Some(audit) = audit {
+        audit.finish(resp.status(), &glob.load()).await;
     }
+    resp
 }
 
 /**
 Generate a response for the frontend to populate data about all users of
 the given [`Role`].
 */
-async fn populate_role(glob: Arc<RwLock<Glob>>, role: Role) -> Response {
+async fn populate_role(glob: Arc<GlobCell>, role: Role) -> Response {
     log::trace!("populate_role( Glob, {:?} ) called.", &role);
 
-    let glob = glob.read().await;
+    let glob = glob.load();
     let users: Vec<&User> = glob
         .users
         .iter()
@@ -203,10 +283,10 @@ Request requirements:
 x-camp-action: populate-users
 ```
 */
-async fn populate_users(glob: Arc<RwLock<Glob>>) -> Response {
+async fn populate_users(glob: Arc<GlobCell>) -> Response {
     log::trace!("populate_all( Glob ) called.");
 
-    let glob = glob.read().await;
+    let glob = glob.load();
     let mut users: Vec<&User> = glob.users.iter().map(|(_, u)| u).collect();
     users.sort_by(|a, b| a.partial_cmp(b).unwrap());
 
@@ -221,10 +301,10 @@ async fn populate_users(glob: Arc<RwLock<Glob>>) -> Response {
         .into_response()
 }
 
-async fn update_completion(uname: &str, glob: Arc<RwLock<Glob>>) -> Response {
+async fn update_completion(uname: &str, glob: Arc<GlobCell>) -> Response {
     log::trace!("update_completion( {:?}, [ Glob ] ) called.", uname);
 
-    let new_hist = match glob.read().await
+    let new_hist = match glob.load()
         .get_student_completion_history(uname).await
     {
         Ok(v) => v,
@@ -272,9 +352,10 @@ Request requirements:
 x-camp-action: add-user
 ```
 With a body that should JSON-deserialize into the [`User`] data
-in question.
+in question. If the request also has an `x-camp-invite: true` header, the
+new user will be sent an invitation email (see [`send_invite`]).
 */
-async fn add_user(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Response {
+async fn add_user(body: Option<String>, headers: &HeaderMap, glob: Arc<GlobCell>) -> Response {
     let body = match body {
         Some(body) => body,
         None => {
@@ -290,141 +371,912 @@ async fn add_user(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Response {
         }
     };
 
+    if let Err(e) = glob.load().insert_user(&u).await {
+        log::error!("Error inserting new user ({:?})into database: {}", &u, &e,);
+        return text_500(Some(format!("Unable to insert User into database: {}", &e)));
+    }
+    if let Err(e) = glob.write().await.refresh_user(u.uname()).await {
+        log::error!("Error rereading {:?} from database: {}", u.uname(), &e);
+        return text_500(Some("Unable to reread user from database.".to_owned()));
+    }
+
+    if wants_invite(headers) {
+        if let Err(e) = send_invite(&u, &glob.load()).await {
+            log::error!("Error sending invitation to {:?}: {}", u.uname(), &e);
+            return text_500(Some(format!(
+                "User {} was added, but the invitation couldn't be sent: {}",
+                u.uname(),
+                &e
+            )));
+        }
+    }
+
+    //populate_role(glob, u.role()).await
+    populate_users(glob).await
+}
+
+/// Whether the request has asked for new users to be sent invitations.
+fn wants_invite(headers: &HeaderMap) -> bool {
+    matches!(get_head("x-camp-invite", headers), Ok("true"))
+}
+
+/**
+Respond to a request to add multiple Students from data in CSV format.
+
+Request requirements:
+```text
+x-camp-action: upload-students
+```
+The request body should be CSV data in the specified format
+(see [`Student::vec_from_csv_reader`]). As with `add-user`, an
+`x-camp-invite: true` header will send each new Student an invitation.
+*/
+async fn upload_students(
+    body: Option<String>,
+    headers: &HeaderMap,
+    glob: Arc<GlobCell>,
+) -> Response {
+    let body = match body {
+        Some(body) => body,
+        None => {
+            return respond_bad_request("Request requires a CSV body.".to_owned());
+        }
+    };
+
+    let unames = match glob.load().upload_students(&body).await {
+        Ok(unames) => unames,
+        Err(e) => {
+            log::error!(
+                "Error uploading new students via CSV: {}\n\nCSV text:\n\n{}\n",
+                &e,
+                &body
+            );
+            return text_500(Some(e.to_string()));
+        }
+    };
+    if let Err(e) = glob.write().await.refresh_users().await {
+        log::error!("Error refreshing user hash from database: {}", &e);
+        return text_500(Some("Unable to reread users from database.".to_owned()));
+    }
+
+    if wants_invite(headers) {
+        let glob = glob.load();
+        let mut failed: Vec<&str> = Vec::new();
+        for uname in unames.iter() {
+            let res = match glob.users.get(uname) {
+                Some(u) => send_invite(u, &glob).await,
+                None => Err("not found after insertion".to_owned()),
+            };
+            if let Err(e) = res {
+                log::error!("Error sending invitation to {:?}: {}", uname, &e);
+                failed.push(uname);
+            }
+        }
+        if !failed.is_empty() {
+            return text_500(Some(format!(
+                "Students were added, but invitations couldn't be sent to: {}",
+                failed.join(", ")
+            )));
+        }
+    }
+
+    populate_users(glob).await
+}
+
+/**
+Respond to a request to update a User's data.
+
+Request requirements:
+```text
+x-camp-action: update-user
+```
+The request body should be a JSON-deserializable `User` struct with the
+`uname` of the user whose data should be updated with the rest of the
+data in the struct.
+
+A Student's data should also carry the `version` it was read at; if the
+Student has changed since, nothing is written, and the current user data
+comes back with a 409.
+
+This action can't change the [`Role`] of a user.
+*/
+async fn update_user(body: Option<String>, glob: Arc<GlobCell>) -> Response {
+    let body = match body {
+        Some(body) => body,
+        None => {
+            return respond_bad_request("Request requires a JSON body.".to_owned());
+        }
+    };
+
+    let u: User = match serde_json::from_str(&body) {
+        Ok(u) => u,
+        Err(e) => {
+            log::error!("Error deserializing JSON {:?} as User: {}", &body, &e);
+            return text_500(Some("Unable to deserialize User struct.".to_owned()));
+        }
+    };
+
+    let conflict = match glob.load().update_user(&u).await {
+        Ok(()) => false,
+        Err(UnifiedError::Conflict(e)) => {
+            log::info!("Refusing update to user {:?}: {}", u.uname(), &e);
+            true
+        }
+        Err(e) => {
+            log::error!("Error updating user {:?}: {}", &u, &e,);
+            return text_500(Some(e.to_string()));
+        }
+    };
+    if let Err(e) = glob.write().await.refresh_user(u.uname()).await {
+        log::error!("Error rereading {:?} from database: {}", u.uname(), &e);
+        return text_500(Some("Unable to reread user from database.".to_owned()));
+    }
+
+    if conflict {
+        return as_conflict(populate_users(glob).await);
+    }
+
+    //populate_role(glob, u.role()).await
+    populate_users(glob).await
+}
+
+/**
+Respond to a request to delete a User form the database.
+
+Req'ments:
+```text
+x-camp-action: delete-user
+```
+Body should be `uname` of user to be deleted.
+*/
+async fn delete_user(body: Option<String>, glob: Arc<GlobCell>) -> Response {
+    let uname = match body {
+        Some(uname) => uname,
+        None => {
+            return respond_bad_request(
+                "Request must include the uname to delete as a body.".to_owned(),
+            );
+        }
+    };
+
+    if let Err(e) = glob.load().delete_user(&uname).await {
+        log::error!("Error deleting user {:?}: {}", uname, &e);
+        return text_500(Some(e.to_string()));
+    }
     {
         let mut glob = glob.write().await;
-        if let Err(e) = glob.insert_user(&u).await {
-            log::error!("Error inserting new user ({:?})into database: {}", &u, &e,);
-            return text_500(Some(format!("Unable to insert User into database: {}", &e)));
+        // Not just `refresh_user()`: deleting a Student also takes it from
+        // its Parents' `children`.
+        if let Err(e) = glob.refresh_users().await {
+            log::error!("Error refreshing user hash from database: {}", &e);
+            return text_500(Some("Unable to reread users from database.".to_owned()));
+        }
+        // The deleted user's extra permissions are gone, too.
+        if let Err(e) = glob.refresh_grants().await {
+            log::error!("Error refreshing permissions from database: {}", &e);
+            return text_500(Some("Unable to reread permissions from database.".to_owned()));
+        }
+    }
+
+    populate_users(glob).await
+}
+
+/**
+Respond to a request to clear a User's failed login attempts, lifting any
+lockout on their account.
+
+Req'ments:
+```text
+x-camp-action: unlock-user
+```
+Body should be `uname` of the user to unlock.
+*/
+async fn unlock_user(body: Option<String>, glob: Arc<GlobCell>) -> Response {
+    let uname = match body {
+        Some(uname) => uname,
+        None => {
+            return respond_bad_request(
+                "Request must include the uname to unlock as a body.".to_owned(),
+            );
+        }
+    };
+
+    let res = glob
+        .load()
+        .auth()
+        .read()
+        .await
+        .unlock_user(&uname)
+        .await;
+    match res {
+        Ok(n) => {
+            log::info!("Unlocked {:?} ({} failed attempts cleared).", &uname, &n);
+        }
+        Err(e) => {
+            log::error!("Error unlocking user {:?}: {}", &uname, &e);
+            return text_500(Some(format!("Unable to unlock user: {}", &e)));
+        }
+    }
+
+    populate_users(glob).await
+}
+
+/**
+Respond to a request to remove a User's two-factor enrollment (for
+example, because they've lost their authenticator and their recovery
+codes). If 2FA is mandatory, they'll be made to enroll again at their next
+login.
+
+Req'ments:
+```text
+x-camp-action: reset-2fa
+```
+Body should be `uname` of the user whose enrollment should be removed.
+*/
+async fn reset_totp(body: Option<String>, glob: Arc<GlobCell>) -> Response {
+    let uname = match body {
+        Some(uname) => uname,
+        None => {
+            return respond_bad_request(
+                "Request must include the uname to reset as a body.".to_owned(),
+            );
+        }
+    };
+
+    let res = glob
+        .load()
+        .auth()
+        .read()
+        .await
+        .disable_totp(&uname)
+        .await;
+    match res {
+        Ok(true) => {
+            log::info!("Removed two-factor enrollment for {:?}.", &uname);
+        }
+        Ok(false) => {
+            return respond_bad_request(format!(
+                "{:?} isn't enrolled in two-factor authentication.",
+                &uname
+            ));
+        }
+        Err(e) => {
+            log::error!("Error removing two-factor enrollment for {:?}: {}", &uname, &e);
+            return text_500(Some(format!("Unable to reset two-factor authentication: {}", &e)));
+        }
+    }
+
+    populate_users(glob).await
+}
+
+/**
+Generate a response for the frontend with the status ("pending",
+"accepted", or "expired") of each user who has been sent an invitation.
+
+Request requirements:
+```text
+x-camp-action: populate-invites
+```
+*/
+async fn populate_invites(glob: Arc<GlobCell>) -> Response {
+    let res = glob
+        .load()
+        .auth()
+        .read()
+        .await
+        .invite_statuses()
+        .await;
+    let statuses = match res {
+        Ok(statuses) => statuses,
+        Err(e) => {
+            log::error!("Error retrieving invitation statuses: {}", &e);
+            return text_500(Some(format!("Unable to retrieve invitations: {}", &e)));
+        }
+    };
+
+    (
+        StatusCode::OK,
+        [(
+            HeaderName::from_static("x-camp-action"),
+            HeaderValue::from_static("populate-invites"),
+        )],
+        Json(statuses),
+    )
+        .into_response()
+}
+
+/**
+Respond to a request to send a user a new invitation (because their old
+one expired, say). Any previous invitation stops working.
+
+Req'ments:
+```text
+x-camp-action: resend-invite
+```
+Body should be `uname` of the user to invite.
+*/
+async fn resend_invite(body: Option<String>, glob: Arc<GlobCell>) -> Response {
+    let uname = match body {
+        Some(uname) => uname,
+        None => {
+            return respond_bad_request(
+                "Request must include the uname to invite as a body.".to_owned(),
+            );
+        }
+    };
+
+    {
+        let glob = glob.load();
+        let u = match glob.users.get(&uname) {
+            Some(u) => u,
+            None => {
+                return respond_bad_request(format!("There is no user {:?}.", &uname));
+            }
+        };
+        if let Err(e) = send_invite(u, &glob).await {
+            log::error!("Error sending invitation to {:?}: {}", &uname, &e);
+            return text_500(Some(format!("Unable to send invitation: {}", &e)));
+        }
+    }
+
+    populate_invites(glob).await
+}
+
+/**
+Generate a response listing the given user's active sessions.
+
+Multiple session-related handlers use this to generate their responses.
+The JSON body has the form
+
+```json
+{
+    "uname": "jsmith",
+    "sessions": [ /* auth::Session objects */ ]
+}
+```
+*/
+async fn populate_sessions(uname: &str, glob: Arc<GlobCell>) -> Response {
+    let res = glob
+        .load()
+        .auth()
+        .read()
+        .await
+        .list_sessions(uname)
+        .await;
+    let sessions = match res {
+        Ok(sessions) => sessions,
+        Err(e) => {
+            log::error!("Error listing sessions for {:?}: {}", uname, &e);
+            return text_500(Some(format!("Unable to list sessions: {}", &e)));
+        }
+    };
+
+    (
+        StatusCode::OK,
+        [(
+            HeaderName::from_static("x-camp-action"),
+            HeaderValue::from_static("populate-sessions"),
+        )],
+        Json(json!({
+            "uname": uname,
+            "sessions": sessions,
+        })),
+    )
+        .into_response()
+}
+
+/**
+Respond to a request to list a User's active sessions (that is, unexpired
+keys), with when and from where each was issued.
+
+Req'ments:
+```text
+x-camp-action: list-sessions
+```
+Body should be `uname` of the user in question.
+*/
+async fn list_sessions(body: Option<String>, glob: Arc<GlobCell>) -> Response {
+    let uname = match body {
+        Some(uname) => uname,
+        None => {
+            return respond_bad_request(
+                "Request must include the uname as a body.".to_owned(),
+            );
+        }
+    };
+
+    populate_sessions(&uname, glob).await
+}
+
+/**
+Respond to a request to revoke a single one of a User's sessions.
+
+Req'ments:
+```text
+x-camp-action: revoke-session
+```
+Body should be a JSON object `{ "uname": "jsmith", "id": 1234 }`, where
+`id` comes from the list returned by a `list-sessions` request.
+*/
+async fn revoke_session(body: Option<String>, glob: Arc<GlobCell>) -> Response {
+    #[derive(serde::Deserialize)]
+    struct Target {
+        uname: String,
+        id: i64,
+    }
+
+    let body = match body {
+        Some(body) => body,
+        None => {
+            return respond_bad_request("Request requires a JSON body.".to_owned());
+        }
+    };
+
+    let target: Target = match serde_json::from_str(&body) {
+        Ok(t) => t,
+        Err(e) => {
+            log::error!("Error deserializing JSON {:?} as session: {}", &body, &e);
+            return respond_bad_request(format!("Unable to parse session: {}", &e));
+        }
+    };
+
+    let res = glob
+        .load()
+        .auth()
+        .read()
+        .await
+        .revoke_session(&target.uname, target.id)
+        .await;
+    match res {
+        Ok(true) => {
+            log::info!("Revoked session {} of {:?}.", &target.id, &target.uname);
+        }
+        Ok(false) => {
+            log::warn!(
+                "Session {} of {:?} not revoked: no such session.",
+                &target.id,
+                &target.uname
+            );
+        }
+        Err(e) => {
+            log::error!(
+                "Error revoking session {} of {:?}: {}",
+                &target.id,
+                &target.uname,
+                &e
+            );
+            return text_500(Some(format!("Unable to revoke session: {}", &e)));
+        }
+    }
+
+    populate_sessions(&target.uname, glob).await
+}
+
+/**
+Respond to a request to revoke all of a User's sessions, logging them out
+everywhere.
+
+Req'ments:
+```text
+x-camp-action: revoke-sessions
+```
+Body should be `uname` of the user to log out.
+*/
+async fn revoke_all_sessions(body: Option<String>, glob: Arc<GlobCell>) -> Response {
+    let uname = match body {
+        Some(uname) => uname,
+        None => {
+            return respond_bad_request(
+                "Request must include the uname to log out as a body.".to_owned(),
+            );
+        }
+    };
+
+    let res = glob
+        .load()
+        .auth()
+        .read()
+        .await
+        .revoke_all_sessions(&uname)
+        .await;
+    match res {
+        Ok(n) => {
+            log::info!("Revoked all {} sessions of {:?}.", &n, &uname);
+        }
+        Err(e) => {
+            log::error!("Error revoking sessions of {:?}: {}", &uname, &e);
+            return text_500(Some(format!("Unable to revoke sessions: {}", &e)));
+        }
+    }
+
+    populate_sessions(&uname, glob).await
+}
+
+/**
+Generate a response listing all API tokens.
+
+Request requirements:
+```text
+x-camp-action: populate-tokens
+```
+
+When responding to an `issue-token` request, `new_token` holds the name
+and value of the token just issued; this is the only time the value of a
+token is ever sent back.
+*/
+async fn populate_api_tokens(
+    new_token: Option<(String, String)>,
+    glob: Arc<GlobCell>,
+) -> Response {
+    let res = glob
+        .load()
+        .auth()
+        .read()
+        .await
+        .list_api_tokens()
+        .await;
+    let tokens = match res {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            log::error!("Error listing API tokens: {}", &e);
+            return text_500(Some(format!("Unable to list API tokens: {}", &e)));
+        }
+    };
+
+    let new_token = new_token.map(|(name, token)| json!({ "name": name, "token": token }));
+
+    (
+        StatusCode::OK,
+        [(
+            HeaderName::from_static("x-camp-action"),
+            HeaderValue::from_static("populate-tokens"),
+        )],
+        Json(json!({
+            "tokens": tokens,
+            "new_token": new_token,
+        })),
+    )
+        .into_response()
+}
+
+/**
+Respond to a request to issue a new API token for scripted access.
+
+Req'ments:
+```text
+x-camp-action: issue-token
+```
+Body should be a JSON object like
+```json
+{
+    "uname": "jsmith",
+    "name": "roster sync",
+    "actions": ["populate-users", "add-user", "update-user"],
+    "expires": "2024-06-30"
+}
+```
+where `expires` is optional (or `null`), and the token will only be good
+for the listed `x-camp-action` values.
+*/
+async fn issue_api_token(body: Option<String>, glob: Arc<GlobCell>) -> Response {
+    #[derive(serde::Deserialize)]
+    struct Request {
+        uname: String,
+        name: String,
+        actions: Vec<String>,
+        expires: Option<String>,
+    }
+
+    let body = match body {
+        Some(body) => body,
+        None => {
+            return respond_bad_request("Request requires a JSON body.".to_owned());
+        }
+    };
+
+    let rq: Request = match serde_json::from_str(&body) {
+        Ok(rq) => rq,
+        Err(e) => {
+            log::error!("Error deserializing JSON {:?} as API token request: {}", &body, &e);
+            return respond_bad_request(format!("Unable to parse API token request: {}", &e));
+        }
+    };
+
+    if rq.name.trim().is_empty() {
+        return respond_bad_request("An API token must have a name.".to_owned());
+    }
+    let actions: Vec<String> = rq
+        .actions
+        .iter()
+        .map(|a| a.trim())
+        .filter(|a| !a.is_empty())
+        .map(String::from)
+        .collect();
+    if actions.is_empty() {
+        return respond_bad_request(
+            "An API token must be good for at least one action.".to_owned(),
+        );
+    }
+    let expires = match rq.expires.as_deref().map(str::trim) {
+        None | Some("") => None,
+        Some(s) => match Date::parse(s, DATE_FMT) {
+            Ok(d) => Some(d),
+            Err(e) => {
+                return respond_bad_request(format!(
+                    "Unable to parse {:?} as a date: {}",
+                    s, &e
+                ));
+            }
+        },
+    };
+
+    let res = {
+        let glob = glob.load();
+        if !glob.users.contains_key(&rq.uname) {
+            return respond_bad_request(format!("There is no user {:?}.", &rq.uname));
         }
-        if let Err(e) = glob.refresh_users().await {
-            log::error!("Error refreshing user hash from database: {}", &e);
-            return text_500(Some("Unable to reread users from database.".to_owned()));
+        let auth = glob.auth();
+        let auth = auth.read().await;
+        auth.issue_api_token(&rq.uname, rq.name.trim(), &actions, expires)
+            .await
+    };
+    let token = match res {
+        Ok(token) => token,
+        Err(e) => {
+            log::error!("Error issuing API token for {:?}: {}", &rq.uname, &e);
+            return text_500(Some(format!("Unable to issue API token: {}", &e)));
         }
-    }
+    };
+    log::info!(
+        "Issued API token {:?} for {:?}, good for {:?}.",
+        rq.name.trim(),
+        &rq.uname,
+        &actions
+    );
 
-    //populate_role(glob, u.role()).await
-    populate_users(glob).await
+    populate_api_tokens(Some((rq.name.trim().to_owned(), token)), glob).await
 }
 
 /**
-Respond to a request to add multiple Students from data in CSV format.
+Respond to a request to revoke an API token.
 
-Request requirements:
+Req'ments:
 ```text
-x-camp-action: upload-students
+x-camp-action: revoke-token
 ```
-The request body should be CSV data in the specified format
-(see [`Student::vec_from_csv_reader`]).
+Body should be the `id` of the token, from the list returned by a
+`populate-tokens` request.
 */
-async fn upload_students(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Response {
-    let body = match body {
-        Some(body) => body,
+async fn revoke_api_token(body: Option<String>, glob: Arc<GlobCell>) -> Response {
+    let id: i64 = match body.as_deref().map(|b| b.trim().parse()) {
+        Some(Ok(id)) => id,
+        Some(Err(e)) => {
+            return respond_bad_request(format!("Unable to parse API token id: {}", &e));
+        }
         None => {
-            return respond_bad_request("Request requires a CSV body.".to_owned());
+            return respond_bad_request(
+                "Request must include the id of the API token as a body.".to_owned(),
+            );
         }
     };
 
-    {
-        let glob = glob.read().await;
-        if let Err(e) = glob.upload_students(&body).await {
-            log::error!(
-                "Error uploading new students via CSV: {}\n\nCSV text:\n\n{}\n",
-                &e,
-                &body
-            );
-            return text_500(Some(e.to_string()));
+    let res = glob
+        .load()
+        .auth()
+        .read()
+        .await
+        .revoke_api_token(id)
+        .await;
+    match res {
+        Ok(true) => {
+            log::info!("Revoked API token {}.", &id);
         }
-    }
-    {
-        let mut glob = glob.write().await;
-        if let Err(e) = glob.refresh_users().await {
-            log::error!("Error refreshing user hash from database: {}", &e);
-            return text_500(Some("Unable to reread users from database.".to_owned()));
+        Ok(false) => {
+            log::warn!("API token {} not revoked: no such token.", &id);
+        }
+        Err(e) => {
+            log::error!("Error revoking API token {}: {}", &id, &e);
+            return text_500(Some(format!("Unable to revoke API token: {}", &e)));
         }
     }
 
-    populate_users(glob).await
+    populate_api_tokens(None, glob).await
 }
 
 /**
-Respond to a request to update a User's data.
+Respond to an Admin's request to see what another user sees by issuing
+them a read-only key for that user. The frontend then posts the key to
+`/view-as` (see [`super::view_as`]) to render that user's view.
 
-Request requirements:
+Req'ments:
 ```text
-x-camp-action: update-user
+x-camp-action: impersonate
 ```
-The request body should be a JSON-deserializable `User` struct with the
-`uname` of the user whose data should be updated with the rest of the
-data in the struct.
-
-This action can't change the [`Role`] of a user.
+Body should be the uname of the user to view as; Admins can't be viewed as.
 */
-async fn update_user(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Response {
-    let body = match body {
-        Some(body) => body,
-        None => {
-            return respond_bad_request("Request requires a JSON body.".to_owned());
+async fn impersonate(
+    admin: &str,
+    body: Option<String>,
+    client: &ClientInfo,
+    glob: Arc<GlobCell>,
+) -> Response {
+    let uname = match body.as_deref().map(str::trim) {
+        None | Some("") => {
+            return respond_bad_request(
+                "Request must include the uname of the user to view as.".to_owned(),
+            );
         }
+        Some(uname) => uname,
     };
 
-    let u: User = match serde_json::from_str(&body) {
-        Ok(u) => u,
-        Err(e) => {
-            log::error!("Error deserializing JSON {:?} as User: {}", &body, &e);
-            return text_500(Some("Unable to deserialize User struct.".to_owned()));
+    let glob = glob.load();
+    match glob.users.get(uname) {
+        None => {
+            return respond_bad_request(format!("There is no user {:?}.", uname));
         }
-    };
+        Some(User::Admin(_)) => {
+            return respond_bad_request("You can't view as another Admin.".to_owned());
+        }
+        Some(_) => { /* Anyone else is fair game. */ }
+    }
 
-    {
-        let mut glob = glob.write().await;
-        if let Err(e) = glob.update_user(&u).await {
-            log::error!("Error updating user {:?}: {}", &u, &e,);
-            return text_500(Some(e.to_string()));
+    let res = glob
+        .auth()
+        .read()
+        .await
+        .issue_impersonation_key(uname, admin, client)
+        .await;
+    let key = match res {
+        Ok(AuthResult::Key(k)) => k,
+        Ok(x) => {
+            log::warn!(
+                "auth::Db::issue_impersonation_key( {:?}, {:?} ) returned {:?}, which shouldn't happen.",
+                uname, admin, &x
+            );
+            return text_500(None);
         }
-        if let Err(e) = glob.refresh_users().await {
-            log::error!("Error refreshing user hash from database: {}", &e);
-            return text_500(Some("Unable to reread users from database.".to_owned()));
+        Err(e) => {
+            log::error!(
+                "auth::Db::issue_impersonation_key( {:?}, {:?} ) error: {}",
+                uname,
+                admin,
+                &e
+            );
+            return text_500(Some(format!("Unable to issue key: {}", &e)));
         }
-    }
+    };
+    log::info!("Admin {:?} is viewing as {:?}.", admin, uname);
 
-    //populate_role(glob, u.role()).await
-    populate_users(glob).await
+    (
+        StatusCode::OK,
+        [(
+            HeaderName::from_static("x-camp-action"),
+            HeaderValue::from_static("impersonate"),
+        )],
+        Json(json!({
+            "uname": uname,
+            "key": key,
+        })),
+    )
+        .into_response()
 }
 
 /**
-Respond to a request to delete a User form the database.
+Generate a response listing the extra permissions granted to each user,
+along with every action each endpoint serves (for offering choices).
+
+Request requirements:
+```text
+x-camp-action: populate-grants
+```
+*/
+async fn populate_grants(glob: Arc<GlobCell>) -> Response {
+    let glob = glob.load();
+
+    (
+        StatusCode::OK,
+        [(
+            HeaderName::from_static("x-camp-action"),
+            HeaderValue::from_static("populate-grants"),
+        )],
+        Json(json!({
+            "grants": glob.grants.all(),
+            "actions": {
+                "admin": perm::ADMIN_ACTIONS,
+                "boss": perm::BOSS_ACTIONS,
+                "teacher": perm::TEACHER_ACTIONS,
+            },
+        })),
+    )
+        .into_response()
+}
+
+/**
+Respond to a request to grant a user an extra permission (if `grant` is
+`true`), or to take one away.
 
 Req'ments:
 ```text
-x-camp-action: delete-user
+x-camp-action: grant-permission (or revoke-permission)
 ```
-Body should be `uname` of user to be deleted.
+Body should be a JSON object like
+`{ "uname": "jsmith", "permission": "admin:update-course" }`.
+(See [`perm::Permission`] for the format.)
 */
-async fn delete_user(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Response {
-    let uname = match body {
-        Some(uname) => uname,
+async fn change_grant(body: Option<String>, grant: bool, glob: Arc<GlobCell>) -> Response {
+    #[derive(serde::Deserialize)]
+    struct Target {
+        uname: String,
+        permission: String,
+    }
+
+    let body = match body {
+        Some(body) => body,
         None => {
-            return respond_bad_request(
-                "Request must include the uname to delete as a body.".to_owned(),
-            );
+            return respond_bad_request("Request requires a JSON body.".to_owned());
         }
     };
 
-    {
-        let glob = glob.read().await;
-        if let Err(e) = glob.delete_user(&uname).await {
-            log::error!("Error deleting user {:?}: {}", uname, &e);
-            return text_500(Some(e.to_string()));
+    let target: Target = match serde_json::from_str(&body) {
+        Ok(t) => t,
+        Err(e) => {
+            log::error!("Error deserializing JSON {:?} as permission: {}", &body, &e);
+            return respond_bad_request(format!("Unable to parse permission: {}", &e));
         }
-    }
+    };
+    let perm: perm::Permission = match target.permission.parse() {
+        Ok(perm) => perm,
+        Err(e) => {
+            return respond_bad_request(e);
+        }
+    };
+
     {
-        if let Err(e) = glob.write().await.refresh_users().await {
-            log::error!("Error refreshing user hash from database: {}", &e);
-            return text_500(Some("Unable to reread users from database.".to_owned()));
+        let glob = glob.load();
+        if !glob.users.contains_key(&target.uname) {
+            return respond_bad_request(format!("There is no user {:?}.", &target.uname));
+        }
+
+        let res = if grant {
+            glob.auth().read().await.add_grant(&target.uname, &perm).await
+        } else {
+            glob.auth().read().await.remove_grant(&target.uname, &perm).await
+        };
+        match res {
+            Ok(true) => {
+                log::info!(
+                    "{} permission {} for {:?}.",
+                    if grant { "Granted" } else { "Revoked" },
+                    &perm,
+                    &target.uname
+                );
+            }
+            Ok(false) => {
+                log::warn!(
+                    "Permission {} for {:?} unchanged; it was already {}.",
+                    &perm,
+                    &target.uname,
+                    if grant { "granted" } else { "absent" }
+                );
+            }
+            Err(e) => {
+                log::error!(
+                    "Error changing permission {} for {:?}: {}",
+                    &perm,
+                    &target.uname,
+                    &e
+                );
+                return text_500(Some(format!("Unable to change permission: {}", &e)));
+            }
         }
     }
 
-    populate_users(glob).await
+    if let Err(e) = glob.write().await.refresh_grants().await {
+        log::error!("Error refreshing permissions from database: {}", &e);
+        return text_500(Some("Unable to reread permissions from database.".to_owned()));
+    }
+
+    populate_grants(glob).await
 }
 
 //
@@ -439,8 +1291,8 @@ Generate a response to send data about all extant courses to the frontend.
 Multiple request handlers in this module (generally dealing with inserting
 or altering `Course`s) use this function to generate their responses.
 */
-async fn populate_courses(glob: Arc<RwLock<Glob>>) -> Response {
-    let glob = glob.read().await;
+async fn populate_courses(glob: Arc<GlobCell>) -> Response {
+    let glob = glob.load();
 
     let mut courses: Vec<&Course> = glob.courses.iter().map(|(_, c)| c).collect();
 
@@ -468,16 +1320,13 @@ Reload all the [`Glob`]'s local copies of all [`Course`] (and thus also
 This function should be called at the end of any handler that makes
 a change to course data in the database.
 */
-async fn refresh_and_repopulate_courses(glob: Arc<RwLock<Glob>>) -> Response {
-    {
-        let mut glob = glob.write().await;
-        if let Err(e) = glob.refresh_courses().await {
-            log::error!("Error refreshing course hash from database: {}", &e);
-            return text_500(Some(format!(
-                "Unable to refresh course data from database: {}",
-                &e
-            )));
-        }
+async fn refresh_and_repopulate_courses(glob: Arc<GlobCell>) -> Response {
+    if let Err(e) = glob.write().await.refresh_courses().await {
+        log::error!("Error refreshing course hash from database: {}", &e);
+        return text_500(Some(format!(
+            "Unable to refresh course data from database: {}",
+            &e
+        )));
     }
 
     populate_courses(glob).await
@@ -494,7 +1343,7 @@ x-camp-action: upload course
 Request body should be data describing the `Course` and its `Chapter`s
 as described in  the [`course`] submodule-level documentation.
 */
-async fn upload_course(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Response {
+async fn upload_course(body: Option<String>, glob: Arc<GlobCell>) -> Response {
     let body = match body {
         Some(body) => body,
         None => {
@@ -514,7 +1363,7 @@ async fn upload_course(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Respons
     }
 
     {
-        let glob = glob.read().await;
+        let glob = glob.load();
 
         let data = glob.data();
         match data.read().await.insert_courses(&[crs]).await {
@@ -547,7 +1396,7 @@ x-camp-action: add-course
 Request body should be a JSON-deserializable `Course` struct with metadata
 about the empty course to add.
 */
-async fn add_course(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Response {
+async fn add_course(body: Option<String>, glob: Arc<GlobCell>) -> Response {
     let body = match body {
         Some(body) => body,
         None => {
@@ -569,7 +1418,7 @@ async fn add_course(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Response {
     }
 
     {
-        let glob = glob.read().await;
+        let glob = glob.load();
         let data = glob.data();
         match data.read().await.insert_courses(&[crs]).await {
             Ok((n_crs, n_ch)) => {
@@ -598,7 +1447,7 @@ x-camp-action: update-course
 ```
 Body should JSON-deserialize to a `Course` with the new metadata.
 */
-async fn update_course(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Response {
+async fn update_course(body: Option<String>, glob: Arc<GlobCell>) -> Response {
     let body = match body {
         Some(body) => body,
         None => {
@@ -620,7 +1469,7 @@ async fn update_course(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Respons
     }
 
     {
-        let glob = glob.read().await;
+        let glob = glob.load();
         let data = glob.data();
         if let Err(e) = data.read().await.update_course(&crs).await {
             return text_500(Some(format!("Unable to update Course: {}", &e)));
@@ -641,7 +1490,7 @@ x-camp-action: delete-course
 ```
 Body should be the `sym` of the `Course` in question.
 */
-async fn delete_course(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Response {
+async fn delete_course(body: Option<String>, glob: Arc<GlobCell>) -> Response {
     let body = match body {
         Some(body) => body,
         None => {
@@ -650,7 +1499,7 @@ async fn delete_course(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Respons
     };
 
     {
-        match glob.read().await.delete_course(&body).await {
+        match glob.load().delete_course(&body).await {
             Ok((n_crs, n_ch)) => {
                 log::trace!("Deleted {} Course, {} Chapters from Data DB.", n_crs, n_ch);
             }
@@ -676,7 +1525,7 @@ x-camp-action: add-chapters
 ```
 The body should JSON-decode to a `Vec` of the relevant `Chapter` data.
 */
-async fn add_chapters(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Response {
+async fn add_chapters(body: Option<String>, glob: Arc<GlobCell>) -> Response {
     let body = match body {
         Some(body) => body,
         None => {
@@ -703,7 +1552,7 @@ async fn add_chapters(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Response
     }
 
     {
-        let glob = glob.read().await;
+        let glob = glob.load();
         let data = glob.data();
         if let Err(e) = data.read().await.insert_chapters(&chapters).await {
             return text_500(Some(format!("Unable to insert Chapter: {}", &e)));
@@ -724,7 +1573,7 @@ x-camp-action; delete-chapter
 ```
 Body should be `id` of the chapter in question.
 */
-async fn delete_chapter(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Response {
+async fn delete_chapter(body: Option<String>, glob: Arc<GlobCell>) -> Response {
     let body = match body {
         Some(body) => body,
         None => {
@@ -742,7 +1591,7 @@ async fn delete_chapter(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Respon
         }
     };
 
-    if let Err(e) = glob.read().await.delete_chapter(ch_id).await {
+    if let Err(e) = glob.load().delete_chapter(ch_id).await {
         return text_500(Some(format!("Unable to delete Chapter: {}", &e)));
     };
 
@@ -760,7 +1609,7 @@ Body should be JSON-deserializable `Chapter` struct with the `id` of the
 `Chapter` that should be updated, with the rest of the values being the
 new data about the `Chapter.
 */
-async fn update_chapter(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Response {
+async fn update_chapter(body: Option<String>, glob: Arc<GlobCell>) -> Response {
     let body = match body {
         Some(body) => body,
         None => {
@@ -783,7 +1632,7 @@ async fn update_chapter(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Respon
     }
 
     {
-        let glob = glob.read().await;
+        let glob = glob.load();
         let data = glob.data();
         if let Err(e) = data.read().await.update_chapter(&ch).await {
             return text_500(Some(format!("Unable to update Chapter: {}", &e)));
@@ -808,10 +1657,9 @@ Req'ment:
 x-camp-action: populate-cal
 ```
 */
-async fn populate_calendar(glob: Arc<RwLock<Glob>>) -> Response {
+async fn populate_calendar(glob: Arc<GlobCell>) -> Response {
     let date_strs: Vec<String> = glob
-        .read()
-        .await
+        .load()
         .calendar
         .iter()
         .map(|d| format!("{}", d))
@@ -834,16 +1682,13 @@ and send that data to the frontend.
 
 This should be called by any handler that makes changes to the calendar.
 */
-async fn refresh_and_repopulate_calendar(glob: Arc<RwLock<Glob>>) -> Response {
-    {
-        let mut glob = glob.write().await;
-        if let Err(e) = glob.refresh_calendar().await {
-            log::error!("Error refreshing calendar Vec from database: {}", &e);
-            return text_500(Some(format!(
-                "Unable to refresh calendar data from database: {}",
-                &e
-            )));
-        }
+async fn refresh_and_repopulate_calendar(glob: Arc<GlobCell>) -> Response {
+    if let Err(e) = glob.write().await.refresh_calendar().await {
+        log::error!("Error refreshing calendar Vec from database: {}", &e);
+        return text_500(Some(format!(
+            "Unable to refresh calendar data from database: {}",
+            &e
+        )));
     }
 
     populate_calendar(glob).await
@@ -860,7 +1705,7 @@ x-camp-action: update-cal
 Body should JSON-deserialize to a vector of `&str`s that should be parseable
 as dates ("2021-01-27" format).
 */
-async fn update_calendar(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Response {
+async fn update_calendar(body: Option<String>, glob: Arc<GlobCell>) -> Response {
     let body: String = match body {
         Some(body) => body,
         None => {
@@ -896,7 +1741,7 @@ async fn update_calendar(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Respo
     }
 
     {
-        let glob = glob.read().await;
+        let glob = glob.load();
         let data = glob.data();
         let reader = data.read().await;
         if let Err(e) = reader.set_calendar(&dates).await {
@@ -916,10 +1761,9 @@ invoked directly by:
 x-camp-action: populate-dates
 ```
 */
-async fn populate_dates(glob: Arc<RwLock<Glob>>) -> Response {
+async fn populate_dates(glob: Arc<GlobCell>) -> Response {
     let date_map: HashMap<String, String> = glob
-        .read()
-        .await
+        .load()
         .dates
         .iter()
         .map(|(name, date)| (name.clone(), format!("{}", date)))
@@ -950,7 +1794,7 @@ Ex:
 ("end-fall", "2023-01-12")
 ```
 */
-async fn set_date(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Response {
+async fn set_date(body: Option<String>, glob: Arc<GlobCell>) -> Response {
     let body = match body {
         Some(body) => body,
         None => {
@@ -968,19 +1812,11 @@ async fn set_date(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Response {
     };
 
     if date_str.trim() == "" {
-        let mut glob = glob.write().await;
-        {
-            let data = glob.data();
-            if let Err(e) = data.read().await.delete_date(name).await {
-                log::error!("Error deleting date {:?} from database: {}", name, &e);
-                return text_500(Some("Error deleting date from database.".to_owned()));
-            }
-
-            if let Err(e) = glob.refresh_dates().await {
-                log::error!("Error calling Glob::refresh_dates(): {}", &e);
-                return text_500(Some("Error retrieving new dates from database.".to_owned()));
-            }
-        }
+        let data = glob.load().data();
+        if let Err(e) = data.read().await.delete_date(name).await {
+            log::error!("Error deleting date {:?} from database: {}", name, &e);
+            return text_500(Some("Error deleting date from database.".to_owned()));
+        };
     } else {
         let date = match Date::parse(date_str, DATE_FMT) {
             Ok(d) => d,
@@ -989,63 +1825,28 @@ async fn set_date(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Response {
             }
         };
 
-        let mut glob = glob.write().await;
-        {
-            let data = glob.data();
-            if let Err(e) = data.read().await.set_date(name, &date).await {
-                log::error!(
-                    "Error inserting date {:?}: {} into database: {}",
-                    name,
-                    &date,
-                    &e
-                );
-                return text_500(Some("Error inserting date into database.".to_owned()));
-            };
-        }
-        if let Err(e) = glob.refresh_dates().await {
-            log::error!("Error calling Glob::refresh_dates(): {}", &e);
-            return text_500(Some("Error retrieving new dates from database.".to_owned()));
-        }
-    }
-
-    populate_dates(glob).await
-}
-
-/**
-Respond to a request to delete all student data (all data from the `students`
-table in the database, along with all associated entries in the `users` table,
-as well as all goals.)
-
-Use sparingly.
-
-```text
-x-camp-action: reset-students
-```
-*/
-async fn reset_students(glob: Arc<RwLock<Glob>>) -> Response {
-    {
-        let mut glob = glob.write().await;
-
-        let res = glob.yearly_data_nuke().await;
-
-        if let Err(e) = glob.refresh_users().await {
-            let mut estr = format!(
-                "There was an error refreshing User data from the database: {}",
+        let data = glob.load().data();
+        if let Err(e) = data.read().await.set_date(name, &date).await {
+            log::error!(
+                "Error inserting date {:?}: {} into database: {}",
+                name,
+                &date,
                 &e
             );
-            if let Err(e) = res {
-                estr = format!("{}\n{}", &estr, &e);
-            }
+            return text_500(Some("Error inserting date into database.".to_owned()));
+        };
+    }
 
-            return text_500(Some(estr));
-        }
+    if let Err(e) = glob.write().await.refresh_dates().await {
+        log::error!("Error calling Glob::refresh_dates(): {}", &e);
+        return text_500(Some("Error retrieving new dates from database.".to_owned()));
     }
 
-    populate_users(glob).await
+    populate_dates(glob).await
 }
 
-async fn populate_completion(glob: Arc<RwLock<Glob>>) -> Response {
-    let map = match glob.read().await.data().read().await
+async fn populate_completion(glob: Arc<GlobCell>) -> Response {
+    let map = match glob.load().data().read().await
         .get_all_completion_histories().await
     {
         Ok(map) => map,
@@ -1075,7 +1876,7 @@ async fn populate_completion(glob: Arc<RwLock<Glob>>) -> Response {
 async fn add_completion(
     body: Option<String>,
     headers: &HeaderMap,
-    glob: Arc<RwLock<Glob>>
+    glob: Arc<GlobCell>
 ) -> Response {
     let uname = match get_head("x-camp-student", headers) {
         Ok(uname) => uname,
@@ -1102,7 +1903,7 @@ async fn add_completion(
         },
     };
 
-    if let Err(e) = glob.read().await.add_completion(
+    if let Err(e) = glob.load().add_completion(
         uname, hist.year, hist.term, &hist.sym
     ).await {
         log::error!(
@@ -1115,7 +1916,7 @@ async fn add_completion(
     update_completion(uname, glob).await
 }
 
-async fn delete_completion(headers: &HeaderMap, glob: Arc<RwLock<Glob>>) -> Response {
+async fn delete_completion(headers: &HeaderMap, glob: Arc<GlobCell>) -> Response {
     let uname = match get_head("x-camp-student", headers) {
         Ok(uname) => uname,
         Err(e) => { return respond_bad_request(e); },
@@ -1125,7 +1926,7 @@ async fn delete_completion(headers: &HeaderMap, glob: Arc<RwLock<Glob>>) -> Resp
         Err(e) => { return respond_bad_request(e); },
     };
 
-    if let Err(e) = glob.read().await.delete_completion(uname, sym).await {
+    if let Err(e) = glob.load().delete_completion(uname, sym).await {
         log::error!(
             "Error attempting to remove course {:?} from the completion history for {:?}: {}",
             sym, uname, &e
@@ -1134,4 +1935,93 @@ async fn delete_completion(headers: &HeaderMap, glob: Arc<RwLock<Glob>>) -> Resp
     };
 
     update_completion(uname, glob).await
-}
\ No newline at end of file
+}
+
+/**
+Respond with an archive of everything in both databases (see
+[`crate::archive`]), as `camp_YYYY-MM-DD.zip`.
+
+This changes nothing, but it's recorded in the audit log anyway, because
+the archive holds every user's password hash.
+*/
+async fn export_archive(glob: Arc<GlobCell>) -> Response {
+    let bytes = {
+        let glob = glob.load();
+        let auth = glob.auth();
+        let data = glob.data();
+        let res = crate::archive::export(&*auth.read().await, &*data.read().await).await;
+        match res {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                log::error!("Error exporting database archive: {}", &e);
+                return text_500(Some(e.to_string()));
+            }
+        }
+    };
+
+    let today = time::OffsetDateTime::now_utc()
+        .date()
+        .format(DATE_FMT)
+        .unwrap_or_default();
+    let disposition = match HeaderValue::from_str(&format!(
+        "attachment; filename=\"camp_{}.zip\"",
+        &today
+    )) {
+        Ok(val) => val,
+        Err(e) => {
+            log::error!("Error generating archive Content-Disposition header: {}", &e);
+            return text_500(None);
+        }
+    };
+
+    (
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, HeaderValue::from_static("application/zip")),
+            (header::CONTENT_DISPOSITION, disposition),
+            (
+                HeaderName::from_static("x-camp-action"),
+                HeaderValue::from_static("export-archive"),
+            ),
+        ],
+        bytes,
+    )
+        .into_response()
+}
+
+/**
+Reread this school's configuration file and the templates, and start using
+them (see [`Tenants::reconfigure`]), without restarting the server.
+
+Request requirements:
+```text
+x-camp-action: reload-config
+```
+
+The response body says what (if anything) will still need a restart.
+*/
+async fn reload_config(tenants: &Tenants, tenant: &Tenant) -> Response {
+    let notes = match tenants.reconfigure(Some(&tenant.id)).await {
+        Ok(notes) => notes,
+        Err(e) => {
+            log::error!("Error reloading configuration of tenant {:?}: {}", &tenant.id, &e);
+            return text_500(Some(format!("Configuration not reloaded: {}", &e)));
+        }
+    };
+
+    let mut text = String::from("Configuration and templates reloaded.");
+    for note in notes.iter() {
+        text.push('\n');
+        text.push_str(note);
+    }
+
+    (
+        StatusCode::OK,
+        [(
+            HeaderName::from_static("x-camp-action"),
+            HeaderValue::from_static("reload-config"),
+        )],
+        text,
+    )
+        .into_response()
+}
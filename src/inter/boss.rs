@@ -21,15 +21,13 @@ use futures::stream::{FuturesUnordered, StreamExt};
 use serde::{Deserialize, Serialize};
 use smallstr::SmallString;
 use time::{format_description::FormatItem, macros::format_description, Date};
-use tokio::sync::RwLock;
 
 use super::*;
 use crate::{
     auth::AuthResult,
-    config::Glob,
+    config::{Glob, GlobCell},
     hist::HistEntry,
     pace::{GoalDisplay, GoalStatus, Pace, PaceDisplay, RowDisplay, Term},
-    store::Store,
     user::{BaseUser, User},
     MiniString, MEDSTORE, SMALLSTORE,
 };
@@ -39,24 +37,32 @@ const DATE_FMT: &[FormatItem] = format_description!("[month repr:short] [day]");
 /**
 Ensure a Boss's login credentials check out, generate 'em a key, and serve
 the Boss view.
+
+If the Boss has two-factor authentication enabled (or it's mandatory),
+serve the second-factor page instead; the login is then completed by
+[`totp_login`].
 */
-pub async fn login(base: BaseUser, form: LoginData, glob: Arc<RwLock<Glob>>) -> Response {
+pub async fn login(
+    base: BaseUser,
+    form: LoginData,
+    client: &ClientInfo,
+    glob: Arc<GlobCell>,
+) -> Response {
     log::trace!("boss::login( {:?}, {:?}, [ Glob ] ) called.", &base, &form);
 
     let auth_response = {
-        glob.read()
-            .await
+        glob.load()
             .auth()
             .read()
             .await
-            .check_password_and_issue_key(&base.uname, &form.password, &base.salt)
+            .check_login(&base.uname, &form.password, &base.salt, &client.addr)
             .await
     };
 
-    let auth_key = match auth_response {
+    match auth_response {
         Err(e) => {
             log::error!(
-                "auth:Db::check_password( {:?}, {:?}, {:?} ): {}",
+                "auth:Db::check_login( {:?}, {:?}, {:?} ): {}",
                 &base.uname,
                 &form.password,
                 &base.salt,
@@ -64,19 +70,80 @@ pub async fn login(base: BaseUser, form: LoginData, glob: Arc<RwLock<Glob>>) ->
             );
             return html_500();
         }
-        Ok(AuthResult::Key(k)) => k,
+        Ok(AuthResult::Ok) => { /* Password checks out; proceed. */ }
         Ok(AuthResult::BadPassword) => {
             return respond_bad_password(&base.uname);
         }
+        Ok(AuthResult::Locked) => {
+            return respond_locked(&base.uname);
+        }
         Ok(x) => {
             log::warn!(
-                "auth::Db::check_password( {:?}, {:?}, {:?} ) returned {:?}, which shouldn't happen.",
+                "auth::Db::check_login( {:?}, {:?}, {:?} ) returned {:?}, which shouldn't happen.",
                 &base.uname, &form.password, &base.salt, &x
             );
             return respond_bad_password(&base.uname);
         }
     };
 
+    if let Some(response) = second_factor_page(&base.uname, &glob.load()).await {
+        return response;
+    }
+
+    let key_response = {
+        glob.load()
+            .auth()
+            .read()
+            .await
+            .issue_key(&base.uname, client)
+            .await
+    };
+    let auth_key = match key_response {
+        Ok(AuthResult::Key(k)) => k,
+        Ok(x) => {
+            log::warn!(
+                "auth::Db::issue_key( {:?} ) returned {:?}, which shouldn't happen.",
+                &base.uname,
+                &x
+            );
+            return html_500();
+        }
+        Err(e) => {
+            log::error!("auth::Db::issue_key( {:?} ): {}", &base.uname, &e);
+            return html_500();
+        }
+    };
+
+    serve_view(&base.uname, &auth_key, None, glob).await
+}
+
+/**
+Complete a Boss login by checking the second-factor code, then serve the
+Boss view.
+*/
+pub async fn totp_login(
+    base: BaseUser,
+    form: TotpData,
+    client: &ClientInfo,
+    glob: Arc<GlobCell>,
+) -> Response {
+    log::trace!("boss::totp_login( {:?}, {:?}, [ Glob ] ) called.", &base, &form);
+
+    let res = check_second_factor(&form, client, &glob.load()).await;
+    match res {
+        Ok(auth_key) => serve_view(&base.uname, &auth_key, None, glob).await,
+        Err(response) => response,
+    }
+}
+
+/// Render the Boss view (for an Admin viewing as the Boss, if
+/// `impersonator` is given).
+pub async fn serve_view(
+    uname: &str,
+    auth_key: &str,
+    impersonator: Option<&str>,
+    glob: Arc<GlobCell>,
+) -> Response {
     let calendar_string = match make_boss_calendars(glob.clone()).await {
         Ok(s) => s,
         Err(e) => {
@@ -102,11 +169,12 @@ pub async fn login(base: BaseUser, form: LoginData, glob: Arc<RwLock<Glob>>) ->
     };
 
     let data = json!({
-        "uname": &base.uname,
-        "key": &auth_key,
+        "uname": uname,
+        "key": auth_key,
         "calendars": calendar_string,
         "archives": archive_buttons_string,
         "completion_rows": histories_string,
+        "impersonator": impersonator,
     });
 
     serve_raw_template(StatusCode::OK, "boss", &data, vec![])
@@ -121,8 +189,8 @@ struct TeacherData<'a> {
 
 /// Draw the section of the Boss view with the buttons for downloading
 /// archives of all of each teacher's reports at one time.
-async fn make_archive_buttons(glob: Arc<RwLock<Glob>>) -> Result<String, String> {
-    let glob = glob.read().await;
+async fn make_archive_buttons(glob: Arc<GlobCell>) -> Result<String, String> {
+    let glob = glob.load();
 
     let mut output: Vec<u8> = Vec::new();
     for (uname, u) in glob.users.iter() {
@@ -304,10 +372,10 @@ fn write_cal_table<W: Write>(p: &Pace, glob: &Glob, mut buff: W) -> Result<(), S
 }
 
 /// Generate a `String` of HTML data containing all student pace calendar data.
-pub async fn make_boss_calendars(glob: Arc<RwLock<Glob>>) -> Result<String, String> {
+pub async fn make_boss_calendars(glob: Arc<GlobCell>) -> Result<String, String> {
     log::trace!("make_boss_page( [ Glob ] ) called.");
 
-    let glob = glob.read().await;
+    let glob = glob.load();
     let tunames: Vec<&str> = glob
         .users
         .iter()
@@ -359,10 +427,10 @@ pub async fn make_boss_calendars(glob: Arc<RwLock<Glob>>) -> Result<String, Stri
     Ok(buff)
 }
 
-async fn generate_boss_histories(glob: Arc<RwLock<Glob>>) -> Result<String, String> {
+async fn generate_boss_histories(glob: Arc<GlobCell>) -> Result<String, String> {
     log::trace!("generate_boss_histories( [ Glob ] ) called.");
 
-    let glob = glob.read().await;
+    let glob = glob.load();
     let map = glob.data().read().await.get_all_completion_histories().await
         .map_err(|e| format!(
             "error retrieving course completion history from database: {}", &e
@@ -430,7 +498,7 @@ async fn generate_boss_histories(glob: Arc<RwLock<Glob>>) -> Result<String, Stri
 pub async fn api(
     headers: HeaderMap,
     body: Option<String>,
-    Extension(glob): Extension<Arc<RwLock<Glob>>>,
+    Extension(glob): Extension<Arc<GlobCell>>,
 ) -> Response {
     let uname: &str = match headers.get("x-camp-uname") {
         Some(uname) => match uname.to_str() {
@@ -444,25 +512,8 @@ pub async fn api(
         }
     };
 
-    let u = {
-        let glob = glob.read().await;
-        if let Some(u) = glob.users.get(uname) {
-            u.clone()
-        } else {
-            return text_500(None);
-        }
-    };
-
-    match u {
-        User::Boss(_) => { /* Okay, request may proceed. */ }
-        _ => {
-            return (
-                StatusCode::FORBIDDEN,
-                "Who is this? What's your operating number?".to_owned(),
-            )
-                .into_response();
-        }
-    };
+    // Whether this user may perform the requested action has already been
+    // checked by the `inter::authorize` layer.
 
     let action = match headers.get("x-camp-action") {
         Some(act) => match act.to_str() {
@@ -476,18 +527,48 @@ pub async fn api(
         }
     };
 
-    match action {
+    let audit = Audit::begin(
+        Endpoint::Boss,
+        action,
+        &headers,
+        body.as_deref(),
+        &glob.load(),
+    )
+    .await;
+
+    let resp = match action {
         "compose-email" => compose_email(body, glob.clone()).await,
         "send-email" => send_email(body, glob.clone()).await,
         "email-all" => email_all(glob.clone()).await,
         "download-report" => download_report(&headers, glob.clone()).await,
         "report-archive" => download_archive(&headers, glob.clone()).await,
+        "populate-years" => populate_years(glob.clone()).await,
+        "populate-year-goals" => {
+            let tunames: Vec<String> = glob
+                .load()
+                .users
+                .values()
+                .filter(|u| matches!(u, User::Teacher(_)))
+                .map(|u| u.uname().to_owned())
+                .collect();
+            let tunames: Vec<&str> = tunames.iter().map(|s| s.as_str()).collect();
+            populate_year_goals(&tunames, &headers, glob.clone()).await
+        }
         "populate-histories" => populate_histories(glob.clone()).await,
+        "begin-2fa" => begin_totp_enrollment(uname, glob.clone()).await,
+        "confirm-2fa" => confirm_totp_enrollment(uname, body, glob.clone()).await,
+        "query-audit" => query_audit(body, glob.clone()).await,
+        "export-audit" => export_audit(body, glob.clone()).await,
         x => respond_bad_request(format!(
             "{:?} is not a recognizable x-camp-action value.",
             x
         )),
+    };
+
+    if let Some(audit) = audit {
+        audit.finish(resp.status(), &glob.load()).await;
     }
+    resp
 }
 
 /// Data required to render the `"boss_email"` template, generating the text
@@ -613,7 +694,7 @@ x-camp-action: compose-email
 ```
 Body should contain `uname` of student about whom to generate an email.
 */
-async fn compose_email(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Response {
+async fn compose_email(body: Option<String>, glob: Arc<GlobCell>) -> Response {
     let uname = match body {
         Some(uname) => uname,
         None => {
@@ -624,7 +705,7 @@ async fn compose_email(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Respons
     };
 
     let (text, student_name) = {
-        let glob = glob.read().await;
+        let glob = glob.load();
         let p = match glob.get_pace_by_student(&uname).await {
             Ok(p) => p,
             Err(e) => {
@@ -710,7 +791,7 @@ x-camp-action: send-email
 Body should JSON-deserialize to an `EmailEnvelope` with the appropriate
 `text` body and `uname` user name.
 */
-async fn send_email(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Response {
+async fn send_email(body: Option<String>, glob: Arc<GlobCell>) -> Response {
     let body = match body {
         Some(body) => body,
         None => {
@@ -736,7 +817,7 @@ async fn send_email(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Response {
     };
 
     {
-        let glob = glob.read().await;
+        let glob = glob.load();
         let stud = match glob.users.get(&env.uname) {
             Some(User::Student(s)) => s,
             x => {
@@ -820,11 +901,11 @@ x-camp-action: email-all
 
 Use sparingly.
 */
-async fn email_all(glob: Arc<RwLock<Glob>>) -> Response {
+async fn email_all(glob: Arc<GlobCell>) -> Response {
     let mut failures: Vec<String> = Vec::new();
 
     {
-        let glob = glob.read().await;
+        let glob = glob.load();
         let tunames: Vec<&str> = glob
             .users
             .iter()
@@ -913,140 +994,7 @@ async fn email_all(glob: Arc<RwLock<Glob>>) -> Response {
     }
 }
 
-async fn download_report(headers: &HeaderMap, glob: Arc<RwLock<Glob>>) -> Response {
-    let suname = match get_head("x-camp-student", headers) {
-        Ok(uname) => uname,
-        Err(e) => { return respond_bad_request(e); },
-    };
-    let term = match get_head("x-camp-term", headers) {
-        Ok(term) => term,
-        Err(e) => { return respond_bad_request(e); },
-    };
-    let term = match Term::from_str(term) {
-        Ok(term) => term,
-        Err(e) => {
-            log::warn!(
-                "Invalid x-camp-term value ({:?}) in attempt to download report for {:?}: {}",
-                term, suname, &e
-            );
-            return respond_bad_request(format!(
-                "Invalid x-camp-term value {:?}: {}", term, &e
-            ));
-        },
-    };
-
-    let glob = glob.read().await;
-
-    let stud = match glob.users.get(suname) {
-        Some(User::Student(s)) => s,
-        _ => {
-            log::warn!(
-                "Report for non-student {:?} requested.", suname
-            );
-            return respond_bad_request(format!(
-                "{:?} is not the user name of a student in the system.", suname
-            ));
-        },
-    };
-
-    let pdf_data = {
-        let data_handle = glob.data();
-        let data = data_handle.read().await;
-        let mut client = match data.connect().await {
-            Ok(c) => c,
-            Err(e) => {
-                log::error!(
-                    "Error getting DB connection to retrieve report PDF for {:?}: {}",
-                    suname, &e
-                );
-                return text_500(Some(format!(
-                    "Error connecting to the database: {}", &e
-                )));
-            },
-        };
-        let t = match client.transaction().await {
-            Ok(t) => t,
-            Err(e) => {
-                log::error!(
-                    "Error opening Transaction to retrieve report PDF for {:?}: {}",
-                    suname, &e
-                );
-                return text_500(Some(format!(
-                    "Error initiating database transaction: {}", &e
-                )));
-            },
-        };
-
-        let pdf_data = match Store::get_final(&t, suname, term).await {
-            Ok(Some(v)) => v,
-            Ok(None) => {
-                return (
-                    StatusCode::NOT_FOUND,
-                    format!(
-                        "{} {} does not yet have a {} report in the system.",
-                        &stud.rest, &stud.last, &term
-                    ),
-                ).into_response();
-            },
-            Err(e) => {
-                log::error!(
-                    "Error querying database for {} report for {:?}: {}",
-                    &term, suname, &e
-                );
-                return text_500(Some(format!(
-                    "Error retrieving report from database: {}", &e
-                )));
-            },
-        };
-
-        if let Err(e) = t.commit().await {
-            log::error!(
-                "<WEIRD!> Error committing transaction to retrieve {} PDF report for {:?}: {}",
-                &term, suname, &e
-            );
-            return text_500(Some(format!(
-                "Error committing transaction (weird, I know): {}", &e
-            )));
-        }
-
-        pdf_data
-    };
-
-    // The first thing this function does is respond with an error if there's
-    // no "x-camp-student" or "x-camp-term" headers, so these are both
-    // guaranteed to be here.
-    let suname_header = headers.get("x-camp-student").unwrap().clone();
-    let term_header = headers.get("x-camp-term").unwrap().clone();
-
-    (
-        StatusCode::OK,
-        [
-            (
-                header::CONTENT_TYPE,
-                HeaderValue::from_static("application/pdf"),
-            ),
-            (
-                header::CONTENT_DISPOSITION,
-                HeaderValue::from_static("inline"),
-            ),
-            (
-                HeaderName::from_static("x-camp-action"),
-                HeaderValue::from_static("download-pdf"),
-            ),
-            (
-                HeaderName::from_static("x-camp-student"),
-                suname_header,
-            ),
-            (
-                HeaderName::from_static("x-camp-term"),
-                term_header,
-            ),
-        ],
-        pdf_data
-    ).into_response()
-}
-
-async fn download_archive(headers: &HeaderMap, glob: Arc<RwLock<Glob>>) -> Response {
+async fn download_archive(headers: &HeaderMap, glob: Arc<GlobCell>) -> Response {
     let tuname = match get_head("x-camp-teacher", headers) {
         Ok(uname) => uname,
         Err(e) => { return respond_bad_request(e); },
@@ -1068,7 +1016,11 @@ async fn download_archive(headers: &HeaderMap, glob: Arc<RwLock<Glob>>) -> Respo
         },
     };
 
-    let glob = glob.read().await;
+    let glob = glob.load();
+    let year = match get_year(headers, &glob) {
+        Ok(year) => year,
+        Err(e) => { return respond_bad_request(e); },
+    };
     let t = match glob.users.get(tuname) {
         Some(User::Teacher(ref t)) => t,
         _ => {
@@ -1077,7 +1029,7 @@ async fn download_archive(headers: &HeaderMap, glob: Arc<RwLock<Glob>>) -> Respo
             ));
         },
     };
-    let data = match glob.get_reports_archive_by_teacher(tuname, term).await {
+    let data = match glob.get_reports_archive_by_teacher(tuname, year, term).await {
         Ok(Some(bytes)) => bytes,
         Ok(None) => {
             return (
@@ -1135,9 +1087,9 @@ async fn download_archive(headers: &HeaderMap, glob: Arc<RwLock<Glob>>) -> Respo
     ).into_response()
 }
 
-async fn populate_histories(glob: Arc<RwLock<Glob>>) -> Response {
+async fn populate_histories(glob: Arc<GlobCell>) -> Response {
     let map = {
-        let glob = glob.read().await;
+        let glob = glob.load();
         match glob.data().read().await.get_all_completion_histories().await {
             Ok(map) => map,
             Err(e) => {
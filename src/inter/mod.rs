@@ -3,34 +3,81 @@ Interoperation between the client (user) and server.
 
 (Not the application and the database; that's covered by `auth` and `store`.)
 */
-use std::{fmt::Debug, io::Write, path::Path, sync::Arc};
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    future::Future,
+    io::Write,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use arc_swap::ArcSwap;
 
 use axum::{
-    http::header::{HeaderMap, HeaderName, HeaderValue},
+    extract::{ConnectInfo, Form, Query},
+    http::header::{self, HeaderMap, HeaderName, HeaderValue},
     http::{Request, StatusCode},
     middleware::Next,
     response::{Html, IntoResponse, Response},
-    Extension,
+    Extension, Json,
 };
 use handlebars::Handlebars;
 use once_cell::sync::OnceCell;
 use serde::Serialize;
 use serde_json::json;
-use tokio::sync::RwLock;
 
-use crate::{auth::AuthResult, config::Glob, user::User, MiniString, MEDSTORE};
+use crate::{
+    audit,
+    auth::{self, AuthResult, ClientInfo},
+    config::{Glob, GlobCell},
+    pace::{CustomCh, Pace, Source, Term, CUSTOM_COURSE},
+    perm::Endpoint,
+    store::Store,
+    user::{Parent, User},
+    MiniString, MEDSTORE,
+};
 
 pub mod admin;
 pub mod boss;
 pub mod student;
 pub mod teacher;
+pub mod tenant;
+
+/// The [`Handlebars`] structs used for rendering, all registered from the
+/// same directory.
+struct Templates {
+    /// For rendering HTML-escaped text.
+    html: Handlebars<'static>,
+    /// For rendering JSON-escaped text.
+    json: Handlebars<'static>,
+    /// For rendering unescaped text.
+    raw: Handlebars<'static>,
+}
+
+/// The [`Templates`] registered from each templates directory in use.
+struct TemplateSets {
+    /// The directory [`init()`] was first called with, whose templates are
+    /// used outside of any tenant's request.
+    default: Option<PathBuf>,
+    by_dir: HashMap<PathBuf, Arc<Templates>>,
+}
+
+/// The [`TemplateSets`] in use, which are replaced wholesale when any of
+/// them are reloaded (see [`reload_templates()`]).
+static TEMPLATES: OnceCell<ArcSwap<TemplateSets>> = OnceCell::new();
+
+tokio::task_local! {
+    /// The templates directory of the tenant whose request is being handled
+    /// (see [`with_templates()`]).
+    static TEMPLATES_DIR: PathBuf;
+}
 
-/// [`Handlebars`] struct for rendering HTML-escaped text.
-static TEMPLATES: OnceCell<Handlebars> = OnceCell::new();
-/// [`Handlebars`] struct for rendering JSON-escaped text.
-static JSON_TEMPLATES: OnceCell<Handlebars> = OnceCell::new();
-/// [`Handlebars`] struct for rendering unescaped text.
-static RAW_TEMPLATES: OnceCell<Handlebars> = OnceCell::new();
+/// How often [`watch_templates()`] looks for changes.
+const TEMPLATE_POLL: Duration = Duration::from_secs(1);
 
 /// Text to be sent on an INTERNAL SERVER ERROR when responding to a request
 /// that expects HTML.
@@ -94,6 +141,47 @@ fn get_head<'a>(name: &'static str, headers: &'a HeaderMap) -> Result<&'a str, S
     })
 }
 
+/**
+Determine the address of the client making a request, for the purposes of
+login throttling.
+
+This is the address of the connecting socket, unless `trust_proxy` (see
+[`ConfigFile::trust_proxy_headers`](crate::config::ConfigFile::trust_proxy_headers))
+says requests arrive through a proxy, which appends the address it received
+the request from to the `x-forwarded-for` header; then it's the last entry
+there, the only one the client can't forge. Without such a proxy, anyone
+could set the header to whatever they liked.
+*/
+pub fn client_addr(headers: &HeaderMap, sock: &SocketAddr, trust_proxy: bool) -> String {
+    if trust_proxy {
+        if let Some(val) = headers.get("x-forwarded-for") {
+            if let Ok(s) = val.to_str() {
+                if let Some(addr) = s.rsplit(',').map(|a| a.trim()).find(|a| !a.is_empty()) {
+                    return addr.to_owned();
+                }
+            }
+        }
+    }
+
+    sock.ip().to_string()
+}
+
+/// Gather what can be known about the client making a request, to be
+/// recorded alongside any key issued to it. (See [`client_addr`] for
+/// `trust_proxy`.)
+pub fn client_info(headers: &HeaderMap, sock: &SocketAddr, trust_proxy: bool) -> ClientInfo {
+    let user_agent = headers
+        .get("user-agent")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .to_owned();
+
+    ClientInfo {
+        addr: client_addr(headers, sock, trust_proxy),
+        user_agent,
+    }
+}
+
 /// Data type to read the form data from a front-page login request.
 #[derive(serde::Deserialize, Debug)]
 pub struct LoginData {
@@ -101,6 +189,40 @@ pub struct LoginData {
     pub password: String,
 }
 
+/// Data type to read the form data from the second (TOTP) step of an Admin
+/// or Boss login.
+#[derive(serde::Deserialize, Debug)]
+pub struct TotpData {
+    pub uname: String,
+    pub token: String,
+    pub code: String,
+}
+
+/// Query parameters of the link in an invitation email.
+#[derive(serde::Deserialize, Debug)]
+pub struct InviteQuery {
+    pub uname: String,
+    pub token: String,
+}
+
+/// Data type to read the form data from the page on which an invited user
+/// sets their password.
+#[derive(serde::Deserialize)]
+pub struct InviteData {
+    pub uname: String,
+    pub token: String,
+    pub password: String,
+    pub confirm: String,
+}
+
+/// Data type to read the form data the Admin view posts in order to open
+/// the view of another user (see [`view_as`]).
+#[derive(serde::Deserialize)]
+pub struct ViewAsData {
+    pub uname: String,
+    pub key: String,
+}
+
 /// Escape function to be used by [`handlebars`] for escaping JSON data.
 fn escape_json(s: &str) -> String {
     let mut output = String::with_capacity(s.len());
@@ -116,6 +238,81 @@ fn escape_json(s: &str) -> String {
     output
 }
 
+/**
+Register every template in `dir`: `.html` templates for rendering with
+HTML-escaping, `.json` templates with JSON-escaping, and `.html`, `.txt`,
+and `.md` templates with no escaping at all.
+*/
+fn register_templates(dir: &Path) -> Result<Templates, String> {
+    let register = |h: &mut Handlebars<'static>, ext: &str| {
+        h.register_templates_directory(ext, dir).map_err(|e| {
+            format!(
+                "Error registering templates directory {} for {} templates: {}",
+                dir.display(),
+                ext,
+                &e
+            )
+        })
+    };
+
+    let mut html = Handlebars::new();
+    #[cfg(debug_assertions)]
+    html.set_dev_mode(true);
+    register(&mut html, ".html")?;
+    for (t, _) in html.get_templates().iter() {
+        log::debug!("registered TEMPLATE: {}", t);
+    }
+
+    let mut json = Handlebars::new();
+    #[cfg(debug_assertions)]
+    json.set_dev_mode(true);
+    register(&mut json, ".json")?;
+    json.register_escape_fn(escape_json);
+    for (t, _) in json.get_templates().iter() {
+        log::debug!("registered JSON TEMPLATE: {}", t);
+    }
+
+    let mut raw = Handlebars::new();
+    #[cfg(debug_assertions)]
+    raw.set_dev_mode(true);
+    register(&mut raw, ".html")?;
+    register(&mut raw, ".txt")?;
+    register(&mut raw, ".md")?;
+    raw.register_escape_fn(handlebars::no_escape);
+    for (t, _) in raw.get_templates().iter() {
+        log::debug!("registered RAW TEMPLATE: {}", t);
+    }
+
+    Ok(Templates { html, json, raw })
+}
+
+/**
+The [`Templates`] currently in use: those from the templates directory of
+the tenant whose request is being handled, or the default ones.
+*/
+fn templates() -> Arc<Templates> {
+    let sets = TEMPLATES.get().unwrap().load();
+    let scoped = TEMPLATES_DIR
+        .try_with(|dir| sets.by_dir.get(dir).cloned())
+        .ok()
+        .flatten();
+    match scoped {
+        Some(templates) => templates,
+        None => sets.by_dir[sets.default.as_ref().unwrap()].clone(),
+    }
+}
+
+/**
+Run `f` (handling a request for a tenant) using the templates registered
+from `template_dir`.
+
+Every template rendered from inside `f` comes from there, which is how each
+tenant gets its own pages, emails, and reports.
+*/
+pub async fn with_templates<F: Future>(template_dir: PathBuf, f: F) -> F::Output {
+    TEMPLATES_DIR.scope(template_dir, f).await
+}
+
 /**
 Initializes the resources used in this module. This function should be called
 before any functionality of this module or any of its submodules is used.
@@ -124,107 +321,125 @@ Currently the only thing that happens here is loading the templates used by
 `serve_template()`, which will panic unless `init()` has been called first.
 
 The argument is the path to the directory where the templates used by
-`serve_template()` can be found.
+`serve_template()` can be found. It may be called once for each tenant; the
+first directory's templates are used outside of any tenant's request (see
+[`with_templates()`]).
 */
 pub fn init<P: AsRef<Path>>(template_dir: P) -> Result<(), String> {
-    if TEMPLATES.get().is_some() {
-        log::warn!("Templates directory already initialized; ignoring.");
-        return Ok(());
+    let template_dir = template_dir.as_ref();
+    if let Some(sets) = TEMPLATES.get() {
+        if sets.load().by_dir.contains_key(template_dir) {
+            log::debug!(
+                "Templates directory {} already initialized; ignoring.",
+                template_dir.display()
+            );
+            return Ok(());
+        }
     }
 
-    let template_dir = template_dir.as_ref();
+    reload_templates(&[template_dir])?;
+    TEMPLATES.get().unwrap().rcu(|sets| TemplateSets {
+        default: sets.default.clone().or_else(|| Some(template_dir.to_owned())),
+        by_dir: sets.by_dir.clone(),
+    });
+    Ok(())
+}
 
-    let mut h = Handlebars::new();
-    #[cfg(debug_assertions)]
-    h.set_dev_mode(true);
-    h.register_templates_directory(".html", template_dir)
-        .map_err(|e| {
-            format!(
-                "Error registering templates directory {}: {}",
-                template_dir.display(),
-                &e
-            )
-        })?;
-    for (t, _) in h.get_templates().iter() {
-        log::debug!("registered TEMPLATE: {}", t);
-    }
+/**
+Register the templates in each of `template_dirs` anew, and start using them
+in place of the ones from those directories in use (if any), returning how
+many were registered.
 
-    TEMPLATES.set(h).map_err(|old_h| {
-        let mut estr = String::from("Templates directory already registered w/templates:");
-        for template_name in old_h.get_templates().keys() {
-            estr.push('\n');
-            estr.push_str(template_name.as_str());
+If any of them fail to register (because of a syntax error, say), the ones
+in use stay in use, in every directory. Anything being rendered when they're
+replaced finishes with the templates it started with.
+*/
+pub fn reload_templates<P: AsRef<Path>>(template_dirs: &[P]) -> Result<usize, String> {
+    let mut fresh: HashMap<PathBuf, Arc<Templates>> = HashMap::new();
+    let mut n = 0;
+    for dir in template_dirs.iter().map(AsRef::as_ref) {
+        if fresh.contains_key(dir) {
+            continue;
         }
-        estr
-    })?;
-
-    let mut j = Handlebars::new();
-    #[cfg(debug_assertions)]
-    j.set_dev_mode(true);
-    j.register_templates_directory(".json", template_dir)
-        .map_err(|e| {
-            format!(
-                "Error registering templates directory {}: {}",
-                template_dir.display(),
-                &e
-            )
-        })?;
-    j.register_escape_fn(escape_json);
-    for (t, _) in j.get_templates().iter() {
-        log::debug!("registered JSON TEMPLATE: {}", t);
+        let templates = register_templates(dir)?;
+        n += templates.html.get_templates().len()
+            + templates.json.get_templates().len()
+            + templates.raw.get_templates().len();
+        fresh.insert(dir.to_owned(), Arc::new(templates));
     }
 
-    JSON_TEMPLATES.set(j).map_err(|old_j| {
-        let mut estr = String::from("Templates directory already registered w/templates:");
-        for template_name in old_j.get_templates().keys() {
-            estr.push('\n');
-            estr.push_str(template_name.as_str());
+    let sets = TEMPLATES.get_or_init(|| {
+        ArcSwap::from_pointee(TemplateSets {
+            default: None,
+            by_dir: HashMap::new(),
+        })
+    });
+    sets.rcu(|sets| {
+        let mut by_dir = sets.by_dir.clone();
+        by_dir.extend(fresh.iter().map(|(dir, t)| (dir.clone(), t.clone())));
+        TemplateSets {
+            default: sets.default.clone(),
+            by_dir,
         }
-        estr
-    })?;
+    });
 
-    let mut r = Handlebars::new();
-    #[cfg(debug_assertions)]
-    r.set_dev_mode(true);
-    r.register_templates_directory(".html", template_dir)
-        .map_err(|e| {
-            format!(
-                "Error registering templates directory {} for .html templates: {}",
-                template_dir.display(),
-                &e
-            )
-        })?;
-    r.register_templates_directory(".txt", template_dir)
-        .map_err(|e| {
-            format!(
-                "Error registering templates directory {} for .txt templates: {}",
-                template_dir.display(),
-                &e
-            )
-        })?;
-    r.register_templates_directory(".md", template_dir)
-        .map_err(|e| {
-            format!(
-                "Error registering templates directory {} for .txt templates: {}",
-                template_dir.display(),
-                &e
-            )
-        })?;
-    r.register_escape_fn(handlebars::no_escape);
-    for (t, _) in r.get_templates().iter() {
-        log::debug!("registered RAW TEMPLATE: {}", t);
+    Ok(n)
+}
+
+/// Add the path and modification time of every file under `dir` to `found`.
+fn scan_templates(dir: &Path, found: &mut Vec<(PathBuf, Option<SystemTime>)>) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            log::warn!("Unable to read templates directory {}: {}", dir.display(), &e);
+            return;
+        }
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        match entry.metadata() {
+            Ok(md) if md.is_dir() => scan_templates(&path, found),
+            Ok(md) => found.push((path, md.modified().ok())),
+            Err(_) => found.push((path, None)),
+        }
     }
+}
 
-    RAW_TEMPLATES.set(r).map_err(|old_h| {
-        let mut estr = String::from("Templates directory already registered w/templates:");
-        for template_name in old_h.get_templates().keys() {
-            estr.push('\n');
-            estr.push_str(template_name.as_str());
+/**
+Reload the templates in `template_dir` whenever a file there is added,
+removed, or changed. This runs until the process ends, so it should be
+`tokio::spawn`ed.
+
+This is for working on the templates (see
+[`ConfigFile::dev_mode`](crate::config::ConfigFile::dev_mode)); it just
+looks over the directory every [`TEMPLATE_POLL`].
+*/
+pub async fn watch_templates(template_dir: PathBuf) {
+    log::info!(
+        "Watching {} for changes to templates.",
+        template_dir.display()
+    );
+
+    let mut last: Vec<(PathBuf, Option<SystemTime>)> = Vec::new();
+    scan_templates(&template_dir, &mut last);
+    last.sort();
+
+    loop {
+        tokio::time::sleep(TEMPLATE_POLL).await;
+
+        let mut now: Vec<(PathBuf, Option<SystemTime>)> = Vec::new();
+        scan_templates(&template_dir, &mut now);
+        now.sort();
+        if now == last {
+            continue;
         }
-        estr
-    })?;
+        last = now;
 
-    Ok(())
+        match reload_templates(&[&template_dir]) {
+            Ok(n) => log::info!("Templates changed; reloaded {} templates.", n),
+            Err(e) => log::error!("Templates changed, but not reloaded: {}", &e),
+        }
+    }
 }
 
 /**
@@ -246,9 +461,8 @@ pub fn text_500(text: Option<String>) -> Response {
 
 /// Render a template with HTML-escaping.
 pub fn render_template<T: Serialize>(name: &str, data: &T) -> Result<String, String> {
-    TEMPLATES
-        .get()
-        .unwrap()
+    templates()
+        .html
         .render(name, data)
         .map_err(|e| format!("Error rendering template {:?}: {}", name, &e))
 }
@@ -259,18 +473,16 @@ pub fn write_template<T: Serialize, W: Write>(
     data: &T,
     writer: W,
 ) -> Result<(), String> {
-    TEMPLATES
-        .get()
-        .unwrap()
+    templates()
+        .html
         .render_to_write(name, data, writer)
         .map_err(|e| format!("Error rendering template {:?}: {}", name, &e))
 }
 
 /// Render a template with no escaping.
 pub fn render_raw_template<T: Serialize>(name: &str, data: &T) -> Result<String, String> {
-    RAW_TEMPLATES
-        .get()
-        .unwrap()
+    templates()
+        .raw
         .render(name, data)
         .map_err(|e| format!("Error rendering raw template {:?}: {}", name, &e))
 }
@@ -281,18 +493,16 @@ pub fn write_raw_template<T: Serialize, W: Write>(
     data: &T,
     writer: W,
 ) -> Result<(), String> {
-    RAW_TEMPLATES
-        .get()
-        .unwrap()
+    templates()
+        .raw
         .render_to_write(name, data, writer)
         .map_err(|e| format!("Error rendering template {:?}: {}", name, &e))
 }
 
 /// Render a template with JSON-escaping.
 pub fn render_json_template<T: Serialize>(name: &str, data: &T) -> Result<String, String> {
-    JSON_TEMPLATES
-        .get()
-        .unwrap()
+    templates()
+        .json
         .render(name, data)
         .map_err(|e| format!("Error rendering template: {:?}: {}", name, &e))
 }
@@ -303,9 +513,8 @@ pub fn write_json_template<T: Serialize, W: Write>(
     data: &T,
     writer: W,
 ) -> Result<(), String> {
-    JSON_TEMPLATES
-        .get()
-        .unwrap()
+    templates()
+        .json
         .render_to_write(name, data, writer)
         .map_err(|e| format!("Error rendering template {:?}: {}", name, &e))
 }
@@ -326,7 +535,7 @@ where
         template_name
     );
 
-    match TEMPLATES.get().unwrap().render(template_name, data) {
+    match templates().html.render(template_name, data) {
         Ok(response_body) => (code, Html(response_body)).add_headers(addl_headers),
         Err(e) => {
             log::error!(
@@ -356,7 +565,7 @@ where
         template_name
     );
 
-    match RAW_TEMPLATES.get().unwrap().render(template_name, data) {
+    match templates().raw.render(template_name, data) {
         Ok(response_body) => (code, Html(response_body)).add_headers(addl_headers),
         Err(e) => {
             log::error!(
@@ -415,6 +624,31 @@ pub fn respond_bad_password(uname: &str) -> Response {
     serve_template(StatusCode::UNAUTHORIZED, "bad_password", &data, vec![])
 }
 
+/// Convenience function for generating a response to a login attempt refused
+/// because of too many recent failures.
+pub fn respond_locked(uname: &str) -> Response {
+    log::trace!("respond_locked( {:?} ) called.", uname);
+
+    respond_login_error(
+        StatusCode::TOO_MANY_REQUESTS,
+        "Too many failed login attempts. Please wait a while before trying again, or ask an administrator to unlock your account.",
+    )
+}
+
+/// Convenience function for re-serving the second-factor form after a wrong
+/// code; the challenge `token` is still good until it expires.
+pub fn respond_bad_code(uname: &str, token: &str) -> Response {
+    log::trace!("respond_bad_code( {:?} ) called.", uname);
+
+    let data = json!({
+        "error_message": "Invalid authentication code.",
+        "uname": uname,
+        "token": token,
+    });
+
+    serve_template(StatusCode::UNAUTHORIZED, "totp", &data, vec![])
+}
+
 /// Convenience function for generating a response to a key authentication
 /// failure.
 pub fn respond_bad_key() -> Response {
@@ -434,6 +668,21 @@ pub fn respond_bad_request(msg: String) -> Response {
     (StatusCode::BAD_REQUEST, msg).into_response()
 }
 
+/**
+Turn a response carrying the current state of something into a 409, for
+when a change to it was refused because it was made from a stale version.
+
+The frontend shows the body (and acts on its `x-camp-action`) as usual, so
+the user ends up looking at what's actually there. Error responses are
+passed through unchanged.
+*/
+pub fn as_conflict(mut resp: Response) -> Response {
+    if resp.status() == StatusCode::OK {
+        *resp.status_mut() = StatusCode::CONFLICT;
+    }
+    resp
+}
+
 pub async fn log_request<B>(req: Request<B>, next: Next<B>) -> Response {
     use std::fmt::Write as FmtWrite;
 
@@ -478,10 +727,16 @@ Middleware function to ensure key authentications for request layers
 that require it.
 
 Username should be sent as `x-camp-uname` header; key should be in the
-`x-camp-key` header.
+`x-camp-key` header. An API token (see [`auth::Db::issue_api_token`]) may
+be sent in place of a key, in which case it's also checked against the
+`x-camp-action` header.
+
+Keys issued to an Admin viewing as another user (see
+[`auth::Db::issue_impersonation_key`]) are only good for actions explicitly
+listed as changing nothing (see [`Endpoint::is_read_only`]).
 */
 pub async fn key_authenticate<B>(req: Request<B>, next: Next<B>) -> Response {
-    let glob: &Arc<RwLock<Glob>> = req.extensions().get().unwrap();
+    let glob: &Arc<GlobCell> = req.extensions().get().unwrap();
 
     let key = match req.headers().get("x-camp-key") {
         Some(k_val) => match k_val.to_str() {
@@ -518,26 +773,52 @@ pub async fn key_authenticate<B>(req: Request<B>, next: Next<B>) -> Response {
     // But seriously, we return the result, then match on the returned value,
     // instead of just matching on the huge-ass chain expression so that
     // the locks will release.
-    let res = glob
-        .read()
-        .await
-        .auth()
-        .read()
-        .await
-        .check_key(uname, key)
-        .await;
+    let res = if key.starts_with(auth::API_TOKEN_PREFIX) {
+        let action = match req.headers().get("x-camp-action") {
+            Some(a_val) => match a_val.to_str() {
+                Ok(s) => s,
+                Err(_) => {
+                    return respond_bad_request("x-camp-action header unrecognizable.".to_owned());
+                }
+            },
+            None => {
+                return respond_bad_request(
+                    "Request must have an x-camp-action header.".to_owned(),
+                );
+            }
+        };
+        glob.load()
+            .auth()
+            .read()
+            .await
+            .check_api_token(uname, key, action)
+            .await
+    } else {
+        glob.load()
+            .auth()
+            .read()
+            .await
+            .check_key(uname, key)
+            .await
+    };
 
     match res {
         Err(e) => {
             log::error!(
-                "auth::Db::check_key( {:?}, {:?} ) returned error: {}",
+                "Error checking auth key (or API token) for {:?}: {}",
                 uname,
-                key,
                 &e
             );
 
             return text_500(None);
         }
+        Ok(AuthResult::OutOfScope) => {
+            return (
+                StatusCode::FORBIDDEN,
+                "This API token isn't authorized for that action.".to_owned(),
+            )
+                .into_response();
+        }
         Ok(AuthResult::InvalidKey) => {
             return (
                 StatusCode::UNAUTHORIZED,
@@ -549,6 +830,27 @@ pub async fn key_authenticate<B>(req: Request<B>, next: Next<B>) -> Response {
             // This is the good path. We will just fall through and call the
             // next layer after the match.
         }
+        Ok(AuthResult::ReadOnly(admin)) => {
+            let action = get_head("x-camp-action", req.headers()).unwrap_or_default();
+            let read_only = match Endpoint::from_path(req.uri().path()) {
+                Some(endpoint) => endpoint.is_read_only(action),
+                None => false,
+            };
+            if !read_only {
+                log::warn!(
+                    "Admin {:?}, viewing as {:?}, attempted {:?}.",
+                    &admin,
+                    uname,
+                    action
+                );
+                return (
+                    StatusCode::FORBIDDEN,
+                    "This is a read-only view; you can't change anything from here.".to_owned(),
+                )
+                    .into_response();
+            }
+            log::info!("Admin {:?}, viewing as {:?}: {:?}", &admin, uname, action);
+        }
         Ok(x) => {
             log::warn!(
                 "auth::Db::check_key() returned {:?}, which should never happen.",
@@ -562,103 +864,388 @@ pub async fn key_authenticate<B>(req: Request<B>, next: Next<B>) -> Response {
 }
 
 /**
-Make an HTTP request to the [Sendgrid](https://sendgrid.com/) service to send
-an email.
+Middleware function to ensure the user making an API request is permitted
+to perform the requested action (see [`crate::perm`]).
 
-`json_body` should be a valid Sendgrid
-[Mail Send v3 request body](https://docs.sendgrid.com/api-reference/mail-send/mail-send),
-and the [`Glob`] should have your appropriate Sendgrid credentials.
-
-The `student` parameter is only for generating nice(r) error messages.
+This should run after [`key_authenticate`], which establishes that the
+request's `x-camp-uname` is who it says it is.
 */
-pub async fn make_sendgrid_request(
-    json_body: String,
-    glob: &Glob,
-    student: MiniString<MEDSTORE>,
-) -> Result<(), String> {
-    use hyper::{Body, Client, Method, Uri};
+pub async fn authorize<B>(req: Request<B>, next: Next<B>) -> Response {
+    let endpoint = match Endpoint::from_path(req.uri().path()) {
+        Some(endpoint) => endpoint,
+        None => {
+            log::error!(
+                "authorize() layer applied to unrecognized path {:?}.",
+                req.uri().path()
+            );
+            return text_500(None);
+        }
+    };
+    let uname = match get_head("x-camp-uname", req.headers()) {
+        Ok(uname) => uname,
+        Err(e) => {
+            return respond_bad_request(e);
+        }
+    };
+    let action = match get_head("x-camp-action", req.headers()) {
+        Ok(action) => action,
+        Err(e) => {
+            return respond_bad_request(e);
+        }
+    };
 
-    log::trace!(
-        "make_sendgrid_request( [ {} bytes of body ] ) called.",
-        json_body.len()
-    );
-    log::debug!("Sendgrid request body:\n{}", &json_body);
+    if !endpoint.actions().contains(&action) {
+        return respond_bad_request(format!(
+            "{:?} is not a recognizable x-camp-action value.",
+            action
+        ));
+    }
 
-    let target_uri: Uri = "https://api.sendgrid.com/v3/mail/send"
-        .parse()
-        .map_err(|e| format!("Error parsing target URI: {}", &e))?;
-    let https = hyper_rustls::HttpsConnectorBuilder::new()
-        .with_native_roots()
-        .https_only()
-        .enable_http1()
-        .build();
-    let client: Client<_, hyper::Body> = Client::builder().build(https);
+    let allowed = {
+        let glob: &Arc<GlobCell> = req.extensions().get().unwrap();
+        let glob = glob.load();
+        match glob.users.get(uname) {
+            Some(u) => glob.grants.allows(u, endpoint, action),
+            None => false,
+        }
+    };
 
-    let req = Request::builder()
-        .method(Method::POST)
-        .uri(target_uri)
-        .header("Authorization", &glob.sendgrid_auth)
-        .header("Content-Type", "application/json")
-        .body(Body::from(json_body))
-        .map_err(|e| format!("Error building sendgrid request: {}", &e))?;
+    if !allowed {
+        log::warn!(
+            "{:?} attempted {}:{} without permission.",
+            uname,
+            &endpoint,
+            action
+        );
+        return (
+            StatusCode::FORBIDDEN,
+            "You aren't permitted to do that.".to_owned(),
+        )
+            .into_response();
+    }
 
-    let resp = client
-        .request(req)
-        .await
-        .map_err(|e| format!("Error from sendgrid request: {}", &e))
-        .map_err(|e| format!("Error sending sendgrid request: {}", &e))?;
+    next.run(req).await
+}
 
-    if resp.status() == 202 {
-        Ok(())
-    } else {
-        Err(format!(
-            "Sendgrid returned {} response (expected 202) while sending email about {}.",
-            &resp.status(),
-            &student
-        ))
+/**
+Called once an Admin or Boss has passed the password check, to determine
+whether they must also present a second factor.
+
+Returns `None` if they can be issued a key straight away. Otherwise returns
+the page to serve instead: the TOTP code form if they're enrolled, or (if
+2FA is mandatory and they aren't) an enrollment page that shows the new
+secret and recovery codes alongside the code form.
+*/
+pub async fn second_factor_page(uname: &str, glob: &Glob) -> Option<Response> {
+    let auth = glob.auth();
+    let auth = auth.read().await;
+
+    let enabled = match auth.totp_enabled(uname).await {
+        Ok(b) => b,
+        Err(e) => {
+            log::error!("auth::Db::totp_enabled( {:?} ) error: {}", uname, &e);
+            return Some(html_500());
+        }
+    };
+    if !enabled && !glob.require_2fa {
+        return None;
     }
-}
 
-/// Generate (and send) a password reset email for the supplied [`User`].
-///
-/// This includes generating and registering a key to use in the password
-/// reset process.
-pub async fn generate_email(u: &User, glob: &Glob) -> Response {
-    let key = match glob.auth().read().await.issue_key(u.uname()).await {
+    let token = match auth.issue_totp_challenge(uname).await {
+        Ok(t) => t,
         Err(e) => {
-            log::error!("auth::Db::issue_key( {:?} ) returned {:?}", u.uname(), &e);
-            return text_500(None);
+            log::error!("auth::Db::issue_totp_challenge( {:?} ) error: {}", uname, &e);
+            return Some(html_500());
         }
-        Ok(AuthResult::Key(k)) => k,
-        Ok(x) => {
-            log::warn!(
-                "auth::Db::issue_key( {:?} ) returned {:?}, which shouldn't happen.",
-                u.uname(),
-                &x
-            );
-            return text_500(None);
+    };
+
+    if enabled {
+        let data = json!({
+            "uname": uname,
+            "token": &token,
+        });
+        return Some(serve_template(StatusCode::OK, "totp", &data, vec![]));
+    }
+
+    let enrollment = match auth.begin_totp_enrollment(uname).await {
+        Ok(e) => e,
+        Err(e) => {
+            log::error!("auth::Db::begin_totp_enrollment( {:?} ) error: {}", uname, &e);
+            return Some(html_500());
         }
     };
+    let data = json!({
+        "uname": uname,
+        "token": &token,
+        "secret": &enrollment.secret,
+        "uri": &enrollment.uri,
+        "recovery_codes": &enrollment.recovery_codes,
+    });
+    Some(serve_template(StatusCode::OK, "totp_enroll", &data, vec![]))
+}
 
-    let data = match u {
-        User::Student(ref s) => json!({
+/**
+Check the second step of an Admin or Boss login, returning a freshly-issued
+key on success, or the appropriate response on failure.
+*/
+pub async fn check_second_factor(
+    form: &TotpData,
+    client: &ClientInfo,
+    glob: &Glob,
+) -> Result<String, Response> {
+    let res = glob
+        .auth()
+        .read()
+        .await
+        .check_totp_and_issue_key(&form.uname, &form.token, &form.code, client)
+        .await;
+
+    match res {
+        Err(e) => {
+            log::error!(
+                "auth::Db::check_totp_and_issue_key( {:?}, ... ) error: {}",
+                &form.uname,
+                &e
+            );
+            Err(html_500())
+        }
+        Ok(AuthResult::Key(k)) => Ok(k),
+        Ok(AuthResult::BadCode) => Err(respond_bad_code(&form.uname, &form.token)),
+        Ok(AuthResult::Locked) => Err(respond_locked(&form.uname)),
+        Ok(AuthResult::InvalidKey) => Err(respond_login_error(
+            StatusCode::UNAUTHORIZED,
+            "Your login attempt has expired; please log in again.",
+        )),
+        Ok(x) => {
+            log::warn!(
+                "auth::Db::check_totp_and_issue_key( {:?}, ... ) returned {:?}, which shouldn't happen.",
+                &form.uname,
+                &x
+            );
+            Err(respond_bad_password(&form.uname))
+        }
+    }
+}
+
+/**
+Respond to an API request from an Admin or Boss to begin enrolling in
+two-factor authentication.
+
+Responds with a JSON object with `secret`, `uri`, and `recovery_codes`
+fields. The enrollment isn't in force until confirmed with
+[`confirm_totp_enrollment`].
+*/
+pub async fn begin_totp_enrollment(uname: &str, glob: Arc<GlobCell>) -> Response {
+    let res = glob
+        .load()
+        .auth()
+        .read()
+        .await
+        .begin_totp_enrollment(uname)
+        .await;
+
+    match res {
+        Ok(enrollment) => (StatusCode::OK, axum::Json(enrollment)).into_response(),
+        Err(e) => {
+            log::error!("auth::Db::begin_totp_enrollment( {:?} ) error: {}", uname, &e);
+            text_500(Some(format!("Unable to begin enrollment: {}", &e)))
+        }
+    }
+}
+
+/**
+Respond to an API request confirming a pending two-factor enrollment.
+
+Body should be a code generated from the newly-enrolled secret.
+*/
+pub async fn confirm_totp_enrollment(
+    uname: &str,
+    body: Option<String>,
+    glob: Arc<GlobCell>,
+) -> Response {
+    let code = match body {
+        Some(code) => code,
+        None => {
+            return respond_bad_request(
+                "Request must include the authentication code as a body.".to_owned(),
+            );
+        }
+    };
+
+    let res = glob
+        .load()
+        .auth()
+        .read()
+        .await
+        .confirm_totp(uname, &code)
+        .await;
+
+    match res {
+        Ok(AuthResult::Ok) => {
+            log::info!("{:?} enabled two-factor authentication.", uname);
+            StatusCode::OK.into_response()
+        }
+        Ok(AuthResult::BadCode) => (
+            StatusCode::UNAUTHORIZED,
+            "Invalid authentication code.".to_owned(),
+        )
+            .into_response(),
+        Ok(x) => {
+            log::warn!(
+                "auth::Db::confirm_totp( {:?}, ... ) returned {:?}, which shouldn't happen.",
+                uname,
+                &x
+            );
+            text_500(None)
+        }
+        Err(e) => {
+            log::error!("auth::Db::confirm_totp( {:?}, ... ) error: {}", uname, &e);
+            text_500(None)
+        }
+    }
+}
+
+/**
+Make an HTTP request to the [Sendgrid](https://sendgrid.com/) service to send
+an email.
+
+`json_body` should be a valid Sendgrid
+[Mail Send v3 request body](https://docs.sendgrid.com/api-reference/mail-send/mail-send),
+and the [`Glob`] should have your appropriate Sendgrid credentials.
+
+The `student` parameter is only for generating nice(r) error messages.
+*/
+pub async fn make_sendgrid_request(
+    json_body: String,
+    glob: &Glob,
+    student: MiniString<MEDSTORE>,
+) -> Result<(), String> {
+    use hyper::{Body, Client, Method, Uri};
+
+    log::trace!(
+        "make_sendgrid_request( [ {} bytes of body ] ) called.",
+        json_body.len()
+    );
+    log::debug!("Sendgrid request body:\n{}", &json_body);
+
+    let target_uri: Uri = "https://api.sendgrid.com/v3/mail/send"
+        .parse()
+        .map_err(|e| format!("Error parsing target URI: {}", &e))?;
+    let https = hyper_rustls::HttpsConnectorBuilder::new()
+        .with_native_roots()
+        .https_only()
+        .enable_http1()
+        .build();
+    let client: Client<_, hyper::Body> = Client::builder().build(https);
+
+    let req = Request::builder()
+        .method(Method::POST)
+        .uri(target_uri)
+        .header("Authorization", &glob.sendgrid_auth)
+        .header("Content-Type", "application/json")
+        .body(Body::from(json_body))
+        .map_err(|e| format!("Error building sendgrid request: {}", &e))?;
+
+    let resp = client
+        .request(req)
+        .await
+        .map_err(|e| format!("Error from sendgrid request: {}", &e))
+        .map_err(|e| format!("Error sending sendgrid request: {}", &e))?;
+
+    if resp.status() == 202 {
+        Ok(())
+    } else {
+        Err(format!(
+            "Sendgrid returned {} response (expected 202) while sending email about {}.",
+            &resp.status(),
+            &student
+        ))
+    }
+}
+
+/// How to address Parent `p` in an email: "Parent of" their Students' names.
+fn parent_name(p: &Parent, glob: &Glob) -> String {
+    let names: Vec<String> = p
+        .children
+        .iter()
+        .map(|uname| match glob.users.get(uname) {
+            Some(User::Student(s)) => format!("{} {}", &s.rest, &s.last),
+            _ => uname.clone(),
+        })
+        .collect();
+    format!("Parent of {}", names.join(", "))
+}
+
+/// Generate (and send) a password reset email for the supplied [`User`].
+///
+/// This includes generating and registering a key to use in the password
+/// reset process; `client` is whoever requested it.
+pub async fn generate_email(u: &User, client: &ClientInfo, glob: &Glob) -> Response {
+    let auth = glob.auth();
+    let auth_handle = auth.read().await;
+    let key = match auth_handle
+        .issue_reset_token(u.uname(), &client.addr)
+        .await
+    {
+        Err(e) => {
+            log::error!(
+                "auth::Db::issue_reset_token( {:?}, {:?} ) returned {:?}",
+                u.uname(),
+                &client.addr,
+                &e
+            );
+            return text_500(None);
+        }
+        Ok(AuthResult::Key(k)) => k,
+        Ok(AuthResult::Locked) => {
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too many password reset requests. Please check your email for a key that has already been sent, or wait a while before trying again.".to_owned(),
+            )
+                .into_response();
+        }
+        Ok(x) => {
+            log::warn!(
+                "auth::Db::issue_reset_token( {:?}, {:?} ) returned {:?}, which shouldn't happen.",
+                u.uname(),
+                &client.addr,
+                &x
+            );
+            return text_500(None);
+        }
+    };
+    let minutes = auth_handle.reset_policy().token_life_seconds / 60;
+    drop(auth_handle);
+
+    let data = match u {
+        User::Student(ref s) => json!({
             "name": format!("{} {}", &s.rest, &s.last),
             "uname":  u.uname(),
             "email": u.email(),
             "parent": &s.parent,
             "key": &key,
+            "minutes": minutes,
         }),
         User::Teacher(ref t) => json!({
             "name": &t.name,
             "uname": u.uname(),
             "email": u.email(),
             "key": &key,
+            "minutes": minutes,
+        }),
+        User::Parent(ref p) => json!({
+            "name": parent_name(p, glob),
+            "uname": u.uname(),
+            "email": u.email(),
+            "key": &key,
+            "minutes": minutes,
         }),
         User::Admin(_) | User::Boss(_) => json!({
             "name": u.uname(),
             "uname": u.uname(),
             "email": u.email(),
             "key": &key,
+            "minutes": minutes,
         }),
     };
 
@@ -693,7 +1280,7 @@ This request should have the following headers:
 ```
 x-camp-action: reset-password (or it won't get here)
 x-camp-uname: [ user's user name ]
-x-camp-key: [ auth key sent in password reset email]
+x-camp-key: [ reset token sent in password reset email]
 x-camp-password: [ the new requested password ]
 ```
 */
@@ -735,38 +1322,24 @@ pub async fn update_password(u: &User, headers: &HeaderMap, glob: &Glob) -> Resp
     let auth = glob.auth();
     let auth_handle = auth.read().await;
 
-    match auth_handle.check_key(u.uname(), key).await {
-        Err(e) => {
-            log::error!(
-                "auth::Db::check_key( {:?}, {:?} ) error: {}",
-                u.uname(),
-                key,
-                &e
-            );
-            return text_500(None);
-        }
-        Ok(AuthResult::InvalidKey) => {
-            return respond_bad_key();
-        }
-        Ok(AuthResult::Ok) => { /* This is the happy path; proceed. */ }
+    match auth_handle
+        .reset_password(u.uname(), key, new_pwd, u.salt())
+        .await
+    {
+        Ok(AuthResult::Ok) => StatusCode::OK.into_response(),
+        Ok(AuthResult::InvalidKey) => respond_bad_key(),
         Ok(x) => {
             log::warn!(
-                "auth::Db::check_key( {:?}. {:?} ) returned {:?}, which shouldn't happen.",
+                "auth::Db::reset_password( {:?}, [ key ], ... ) returned {:?}, which shouldn't happen.",
                 u.uname(),
-                key,
                 &x
             );
-            return text_500(None);
+            text_500(None)
         }
-    }
-
-    match auth_handle.set_password(u.uname(), new_pwd, u.salt()).await {
-        Ok(()) => StatusCode::OK.into_response(),
         Err(e) => {
             log::error!(
-                "auth::Db::set_password( {:?}, {:?}, {:?} ) error: {}",
+                "auth::Db::reset_password( {:?}, [ key ], [ password ], {:?} ) error: {}",
                 u.uname(),
-                new_pwd,
                 u.salt(),
                 &e
             );
@@ -778,8 +1351,9 @@ pub async fn update_password(u: &User, headers: &HeaderMap, glob: &Glob) -> Resp
 /// API endpoint for HTTP requests sent to "/pwd", which have to do with
 /// requesting and executing password resets.
 pub async fn password_reset(
+    ConnectInfo(sock): ConnectInfo<SocketAddr>,
     headers: HeaderMap,
-    Extension(glob): Extension<Arc<RwLock<Glob>>>,
+    Extension(glob): Extension<Arc<GlobCell>>,
 ) -> Response {
     let uname = match headers.get("x-camp-uname") {
         Some(u_val) => match u_val.to_str() {
@@ -815,7 +1389,7 @@ pub async fn password_reset(
         }
     };
 
-    let glob = glob.read().await;
+    let glob = glob.load();
     let u = match glob.users.get(uname) {
         Some(u) => u,
         None => {
@@ -824,7 +1398,10 @@ pub async fn password_reset(
     };
 
     match action {
-        "request-email" => generate_email(u, &glob).await,
+        "request-email" => {
+            let client = client_info(&headers, &sock, glob.trust_proxy_headers);
+            generate_email(u, &client, &glob).await
+        }
         "reset-password" => update_password(u, &headers, &glob).await,
         x => respond_bad_request(format!(
             "Unrecognized or invalid x-camp-action value: {:?}",
@@ -832,3 +1409,905 @@ pub async fn password_reset(
         )),
     }
 }
+
+/**
+Issue `u` a new invitation and email them (and their parent, if `u` is a
+Student) a link with which to set their password.
+
+Any invitation previously sent to `u` stops working.
+*/
+pub async fn send_invite(u: &User, glob: &Glob) -> Result<(), String> {
+    log::trace!("send_invite( {:?}, [ Glob ] ) called.", u.uname());
+
+    let token = glob
+        .auth()
+        .read()
+        .await
+        .issue_invite(u.uname())
+        .await
+        .map_err(|e| format!("Unable to issue invitation for {:?}: {}", u.uname(), &e))?;
+
+    let link = format!("{}invite?uname={}&token={}", &glob.uri, u.uname(), &token);
+    let data = match u {
+        User::Student(ref s) => json!({
+            "name": format!("{} {}", &s.rest, &s.last),
+            "uname": u.uname(),
+            "email": u.email(),
+            "parent": &s.parent,
+            "link": &link,
+        }),
+        User::Teacher(ref t) => json!({
+            "name": &t.name,
+            "uname": u.uname(),
+            "email": u.email(),
+            "link": &link,
+        }),
+        User::Parent(ref p) => json!({
+            "name": parent_name(p, glob),
+            "uname": u.uname(),
+            "email": u.email(),
+            "link": &link,
+        }),
+        User::Admin(_) | User::Boss(_) => json!({
+            "name": u.uname(),
+            "uname": u.uname(),
+            "email": u.email(),
+            "link": &link,
+        }),
+    };
+
+    let body = render_json_template("invite_email", &data)?;
+    let name: MiniString<MEDSTORE> = MiniString::from(u.uname());
+    make_sendgrid_request(body, glob, name).await
+}
+
+/// Serve the page on which an invited user sets their password, in response
+/// to a visit to the link in their invitation email.
+pub async fn invite_page(
+    Query(query): Query<InviteQuery>,
+    Extension(glob): Extension<Arc<GlobCell>>,
+) -> Response {
+    log::trace!("invite_page( {:?}, [ Glob ] ) called.", &query.uname);
+
+    if !glob.load().users.contains_key(&query.uname) {
+        return respond_login_error(
+            StatusCode::NOT_FOUND,
+            "This invitation is no longer valid. Please ask an administrator to send you another.",
+        );
+    }
+
+    let data = json!({
+        "uname": &query.uname,
+        "token": &query.token,
+    });
+    serve_template(StatusCode::OK, "invite", &data, vec![])
+}
+
+/// Set an invited user's password from the form on the [`invite_page`].
+pub async fn accept_invite(
+    Form(form): Form<InviteData>,
+    Extension(glob): Extension<Arc<GlobCell>>,
+) -> Response {
+    log::trace!("accept_invite( {:?}, [ Glob ] ) called.", &form.uname);
+
+    if form.password.is_empty() || form.password != form.confirm {
+        let data = json!({
+            "error_message": "The passwords you entered don't match.",
+            "uname": &form.uname,
+            "token": &form.token,
+        });
+        return serve_template(StatusCode::BAD_REQUEST, "invite", &data, vec![]);
+    }
+
+    let glob = glob.load();
+    let salt = match glob.users.get(&form.uname) {
+        Some(u) => u.salt().to_owned(),
+        None => {
+            return respond_login_error(
+                StatusCode::UNAUTHORIZED,
+                "This invitation is no longer valid. Please ask an administrator to send you another.",
+            );
+        }
+    };
+
+    let res = glob
+        .auth()
+        .read()
+        .await
+        .accept_invite(&form.uname, &form.token, &form.password, &salt)
+        .await;
+
+    match res {
+        Ok(AuthResult::Ok) => respond_login_error(
+            StatusCode::OK,
+            "Your password has been set. You may now log in.",
+        ),
+        Ok(AuthResult::InvalidKey) => respond_login_error(
+            StatusCode::UNAUTHORIZED,
+            "This invitation has expired or has already been used. Please ask an administrator to send you another.",
+        ),
+        Ok(x) => {
+            log::warn!(
+                "auth::Db::accept_invite( {:?}, ... ) returned {:?}, which shouldn't happen.",
+                &form.uname,
+                &x
+            );
+            html_500()
+        }
+        Err(e) => {
+            log::error!(
+                "auth::Db::accept_invite( {:?}, ... ) error: {}",
+                &form.uname,
+                &e
+            );
+            html_500()
+        }
+    }
+}
+
+/**
+Render the view of user `form.uname` for the Admin to whom the read-only key
+`form.key` was issued (by an `impersonate` request), with a banner saying
+so. The Teacher and Boss views then work as usual, except that the key is
+refused for anything that would change something (see [`key_authenticate`]).
+*/
+pub async fn view_as(
+    Form(form): Form<ViewAsData>,
+    Extension(glob): Extension<Arc<GlobCell>>,
+) -> Response {
+    log::trace!("view_as( {:?}, [ key ], [ Glob ] ) called.", &form.uname);
+
+    let res = glob
+        .load()
+        .auth()
+        .read()
+        .await
+        .check_key(&form.uname, &form.key)
+        .await;
+    let admin = match res {
+        Ok(AuthResult::ReadOnly(admin)) => admin,
+        Ok(_) => {
+            return respond_login_error(
+                StatusCode::UNAUTHORIZED,
+                "This view has expired. Please open it again from the Admin view.",
+            );
+        }
+        Err(e) => {
+            log::error!(
+                "auth::Db::check_key( {:?}, [ key ] ) error: {}",
+                &form.uname,
+                &e
+            );
+            return html_500();
+        }
+    };
+
+    let user = glob.load().users.get(&form.uname).cloned();
+    match user {
+        Some(User::Boss(b)) => boss::serve_view(&b.uname, &form.key, Some(&admin), glob).await,
+        Some(User::Teacher(t)) => teacher::serve_view(&t, &form.key, Some(&admin)),
+        Some(User::Student(s)) => student::serve_view(&s, Some(&admin), &glob.load()).await,
+        Some(User::Parent(p)) => {
+            student::serve_parent_view(&p, Some(&admin), &glob.load()).await
+        }
+        Some(User::Admin(_)) | None => {
+            respond_login_error(StatusCode::NOT_FOUND, "There is no such user to view as.")
+        }
+    }
+}
+
+/**
+A write made through one of the `api()` dispatchers, to be recorded in the
+[audit log](crate::audit) once it's known to have succeeded.
+*/
+#[derive(Debug)]
+pub struct Audit {
+    actor: String,
+    role: String,
+    action: String,
+    target: Option<String>,
+    student: Option<String>,
+    before: Option<String>,
+    after: Option<String>,
+}
+
+/// What an action is aimed at, for the purposes of the audit log.
+enum AuditSubject {
+    User(String),
+    NewGoal(String),
+    Goal(i64),
+    Course(String),
+    Chapter(i64),
+    Custom(i64),
+    Date(String),
+    Other(&'static str),
+    Nothing,
+}
+
+/// Pull the uname out of the JSON representation of a [`User`] (which is
+/// nested differently depending on the role).
+fn user_json_uname(v: &serde_json::Value) -> Option<String> {
+    let inner = v.as_object()?.values().next()?;
+    inner
+        .get("uname")
+        .or_else(|| inner.get("base").and_then(|b| b.get("uname")))
+        .and_then(|u| u.as_str())
+        .map(String::from)
+}
+
+impl Audit {
+    /**
+    Gather what's required to record the `action` request to `endpoint`
+    (with the given `headers` and `body`) in the audit log; in particular, what its target
+    looks like _before_ the action is performed.
+
+    Returns `None` if `action` doesn't write anything.
+    */
+    pub async fn begin(
+        endpoint: Endpoint,
+        action: &str,
+        headers: &HeaderMap,
+        body: Option<&str>,
+        glob: &Glob,
+    ) -> Option<Audit> {
+        if !audit::is_write(endpoint, action) {
+            return None;
+        }
+
+        let actor = get_head("x-camp-uname", headers).unwrap_or_default();
+        let role = match glob.users.get(actor) {
+            Some(u) => u.role().to_string(),
+            None => String::new(),
+        };
+
+        let body_str = body.map(|b| b.trim().to_owned());
+        let json: Option<serde_json::Value> = body.and_then(|b| serde_json::from_str(b).ok());
+        let json_str = |key: &str| {
+            json.as_ref()
+                .and_then(|j| j.get(key))
+                .and_then(|v| v.as_str())
+                .map(String::from)
+        };
+        let json_id = json
+            .as_ref()
+            .and_then(|j| j.get("id"))
+            .and_then(|v| v.as_i64());
+        let body_id = body_str.as_ref().and_then(|b| b.parse::<i64>().ok());
+        let student_head = get_head("x-camp-student", headers).ok().map(String::from);
+
+        let subject = match action {
+            "add-user" | "update-user" => json
+                .as_ref()
+                .and_then(user_json_uname)
+                .map(AuditSubject::User),
+            "delete-user" | "unlock-user" | "reset-2fa" | "revoke-sessions" | "resend-invite"
+            | "impersonate" | "autopace" | "clear-goals" => {
+                body_str.clone().map(AuditSubject::User)
+            }
+            "revoke-session" | "issue-token" | "grant-permission" | "revoke-permission"
+            | "update-numbers" | "update-sidecar" | "send-email" => {
+                json_str("uname").map(AuditSubject::User)
+            }
+            "render-report" | "discard-pdf" | "add-completion" | "delete-completion" => {
+                student_head.map(AuditSubject::User)
+            }
+            "begin-2fa" | "confirm-2fa" => Some(AuditSubject::User(actor.to_owned())),
+            "add-goal" => json_str("uname").map(AuditSubject::NewGoal),
+            "update-goal" => json_id.map(AuditSubject::Goal),
+            "delete-goal" => body_id.map(AuditSubject::Goal),
+            "add-course" | "update-course" => json_str("sym").map(AuditSubject::Course),
+            "delete-course" => body_str.clone().map(AuditSubject::Course),
+            "update-chapter" => json_id.map(AuditSubject::Chapter),
+            "delete-chapter" => body_id.map(AuditSubject::Chapter),
+            "update-custom" => json_id.map(AuditSubject::Custom),
+            "delete-custom" => body_id.map(AuditSubject::Custom),
+            "add-custom" => Some(AuditSubject::Other("new custom chapter")),
+            "set-date" => json
+                .as_ref()
+                .and_then(|j| j.get(0))
+                .and_then(|v| v.as_str())
+                .map(|name| AuditSubject::Date(name.to_owned())),
+            "revoke-token" => Some(AuditSubject::Other("API token")),
+            "update-cal" => Some(AuditSubject::Other("calendar")),
+            _ => None,
+        }
+        .unwrap_or(AuditSubject::Nothing);
+
+        let data = glob.data();
+        let data = data.read().await;
+        let (target, student, before) = match subject {
+            AuditSubject::User(uname) => match glob.users.get(&uname) {
+                Some(u) => {
+                    let mut u = u.clone();
+                    // Salts are nobody's business.
+                    match u {
+                        User::Admin(ref mut b) | User::Boss(ref mut b) => b.salt.clear(),
+                        User::Teacher(ref mut t) => t.base.salt.clear(),
+                        User::Student(ref mut s) => s.base.salt.clear(),
+                        User::Parent(ref mut p) => p.base.salt.clear(),
+                    }
+                    let student = match u {
+                        User::Student(_) => Some(uname.clone()),
+                        _ => None,
+                    };
+                    (Some(uname), student, serde_json::to_string(&u).ok())
+                }
+                None => (Some(uname), None, None),
+            },
+            AuditSubject::NewGoal(uname) => (Some("new goal".to_owned()), Some(uname), None),
+            AuditSubject::Goal(id) => {
+                let before = data
+                    .audit_snapshot("goals", "id", &id)
+                    .await
+                    .unwrap_or_else(|e| {
+                        log::error!("Error taking audit snapshot of Goal {}: {}", &id, &e);
+                        None
+                    });
+                let student = before
+                    .as_deref()
+                    .and_then(|s| serde_json::from_str::<serde_json::Value>(s).ok())
+                    .and_then(|v| v.get("uname").and_then(|u| u.as_str()).map(String::from));
+                (Some(format!("goal {}", &id)), student, before)
+            }
+            AuditSubject::Course(sym) => {
+                let before = data
+                    .audit_snapshot("courses", "sym", &sym)
+                    .await
+                    .unwrap_or_else(|e| {
+                        log::error!("Error taking audit snapshot of Course {:?}: {}", &sym, &e);
+                        None
+                    });
+                (Some(format!("course {}", &sym)), None, before)
+            }
+            AuditSubject::Chapter(id) => {
+                let before = data
+                    .audit_snapshot("chapters", "id", &id)
+                    .await
+                    .unwrap_or_else(|e| {
+                        log::error!("Error taking audit snapshot of Chapter {}: {}", &id, &e);
+                        None
+                    });
+                (Some(format!("chapter {}", &id)), None, before)
+            }
+            AuditSubject::Custom(id) => {
+                let before = data
+                    .audit_snapshot("custom_chapters", "id", &id)
+                    .await
+                    .unwrap_or_else(|e| {
+                        log::error!("Error taking audit snapshot of custom chapter {}: {}", &id, &e);
+                        None
+                    });
+                (Some(format!("custom chapter {}", &id)), None, before)
+            }
+            AuditSubject::Date(name) => {
+                let before = data
+                    .audit_snapshot("dates", "name", &name)
+                    .await
+                    .unwrap_or_else(|e| {
+                        log::error!("Error taking audit snapshot of date {:?}: {}", &name, &e);
+                        None
+                    });
+                (Some(format!("date {}", &name)), None, before)
+            }
+            AuditSubject::Other(what) => (Some(what.to_owned()), None, None),
+            AuditSubject::Nothing => (None, None, None),
+        };
+
+        // The body of a "confirm-2fa" request is a TOTP code, which doesn't
+        // belong in the log.
+        let after = match action {
+            "confirm-2fa" => None,
+            _ => body_str,
+        };
+
+        Some(Audit {
+            actor: actor.to_owned(),
+            role,
+            action: action.to_owned(),
+            target,
+            student,
+            before,
+            after,
+        })
+    }
+
+    /**
+    What's required to record the super-admin `action` (see
+    [`tenant::api`]), aimed at `target`, in a tenant's audit log. There's
+    no user behind these, so the actor is recorded as `super-admin`.
+    */
+    pub fn super_admin(action: &str, target: Option<String>) -> Audit {
+        Audit {
+            actor: "super-admin".to_owned(),
+            role: "Super-admin".to_owned(),
+            action: action.to_owned(),
+            target,
+            student: None,
+            before: None,
+            after: None,
+        }
+    }
+
+    /// Record this action in the audit log, if the `status` of its response
+    /// indicates it succeeded.
+    pub async fn finish(self, status: StatusCode, glob: &Glob) {
+        if !status.is_success() {
+            return;
+        }
+
+        let rec = audit::Record {
+            actor: &self.actor,
+            role: &self.role,
+            action: &self.action,
+            target: self.target.as_deref(),
+            student: self.student.as_deref(),
+            before: self.before.as_deref(),
+            after: self.after.as_deref(),
+        };
+        if let Err(e) = glob.data().read().await.insert_audit_record(&rec).await {
+            log::error!("Error writing to audit log: {}\nrecord: {:?}", &e, &rec);
+        }
+    }
+}
+
+/// Body of "query-audit" and "export-audit" requests; blank fields match
+/// anything, dates are YYYY-MM-DD.
+#[derive(Debug, Default, serde::Deserialize)]
+struct AuditQueryData {
+    actor: Option<String>,
+    student: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+}
+
+impl AuditQueryData {
+    fn into_query(self) -> Result<audit::Query, String> {
+        let parse_date = |s: Option<String>| match crate::blank_string_means_none(s) {
+            None => Ok(None),
+            Some(s) => time::Date::parse(s.trim(), crate::DATE_FMT)
+                .map(Some)
+                .map_err(|e| format!("Unable to parse {:?} as a date: {}", &s, &e)),
+        };
+
+        Ok(audit::Query {
+            actor: crate::blank_string_means_none(self.actor),
+            student: crate::blank_string_means_none(self.student),
+            from: parse_date(self.from)?,
+            to: parse_date(self.to)?,
+        })
+    }
+}
+
+/// Read the audit log entries matching the query in `body`.
+async fn audit_entries(body: Option<String>, glob: &Glob) -> Result<Vec<audit::Entry>, Response> {
+    let qdata: AuditQueryData = match body.as_deref().map(str::trim) {
+        None | Some("") => AuditQueryData::default(),
+        Some(body) => serde_json::from_str(body)
+            .map_err(|e| respond_bad_request(format!("Unable to parse audit log query: {}", &e)))?,
+    };
+    let q = qdata.into_query().map_err(respond_bad_request)?;
+
+    glob.data()
+        .read()
+        .await
+        .get_audit_entries(&q)
+        .await
+        .map_err(|e| {
+            log::error!("Error reading audit log ({:?}): {}", &q, &e);
+            text_500(Some(format!("Unable to read audit log: {}", &e)))
+        })
+}
+
+/**
+Respond to a request for audit log entries.
+
+Request requirements:
+```text
+x-camp-action: query-audit
+```
+with a JSON body like
+`{ "actor": "jenny", "student": "", "from": "2026-01-01", "to": "" }`.
+*/
+pub async fn query_audit(body: Option<String>, glob: Arc<GlobCell>) -> Response {
+    let entries = match audit_entries(body, &glob.load()).await {
+        Ok(entries) => entries,
+        Err(resp) => {
+            return resp;
+        }
+    };
+
+    (
+        StatusCode::OK,
+        [(
+            HeaderName::from_static("x-camp-action"),
+            HeaderValue::from_static("query-audit"),
+        )],
+        Json(entries),
+    )
+        .into_response()
+}
+
+/**
+Respond to a request for audit log entries as a CSV file.
+
+Request requirements:
+```text
+x-camp-action: export-audit
+```
+with the same sort of body as a "query-audit" request.
+*/
+pub async fn export_audit(body: Option<String>, glob: Arc<GlobCell>) -> Response {
+    let entries = match audit_entries(body, &glob.load()).await {
+        Ok(entries) => entries,
+        Err(resp) => {
+            return resp;
+        }
+    };
+
+    let mut buff: Vec<u8> = Vec::new();
+    if let Err(e) = audit::write_csv(&entries, &mut buff) {
+        log::error!("Error exporting audit log: {}", &e);
+        return text_500(Some(e));
+    }
+
+    (
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, HeaderValue::from_static("text/csv")),
+            (
+                header::CONTENT_DISPOSITION,
+                HeaderValue::from_static("attachment; filename=\"audit.csv\""),
+            ),
+            (
+                HeaderName::from_static("x-camp-action"),
+                HeaderValue::from_static("export-audit"),
+            ),
+        ],
+        buff,
+    )
+        .into_response()
+}
+
+/**
+Respond to a request for a Student's finished report PDF.
+
+Request requirements:
+```text
+x-camp-action: download-report
+x-camp-student: [ uname of the Student ]
+x-camp-term: [ "fall", "spring", or "summer" ]
+x-camp-year: [ optional; starting year of the academic year in question ]
+```
+The current academic year is assumed if there's no `x-camp-year` header.
+*/
+pub async fn download_report(headers: &HeaderMap, glob: Arc<GlobCell>) -> Response {
+    let suname = match get_head("x-camp-student", headers) {
+        Ok(uname) => uname,
+        Err(e) => { return respond_bad_request(e); },
+    };
+    let term = match get_head("x-camp-term", headers) {
+        Ok(term) => term,
+        Err(e) => { return respond_bad_request(e); },
+    };
+    let term = match Term::from_str(term) {
+        Ok(term) => term,
+        Err(e) => {
+            log::warn!(
+                "Invalid x-camp-term value ({:?}) in attempt to download report for {:?}: {}",
+                term, suname, &e
+            );
+            return respond_bad_request(format!(
+                "Invalid x-camp-term value {:?}: {}", term, &e
+            ));
+        },
+    };
+
+    let glob = glob.load();
+    let year = match get_year(headers, &glob) {
+        Ok(year) => year,
+        Err(e) => { return respond_bad_request(e); },
+    };
+
+    let stud = match glob.users.get(suname) {
+        Some(User::Student(s)) => s,
+        _ => {
+            log::warn!(
+                "Report for non-student {:?} requested.", suname
+            );
+            return respond_bad_request(format!(
+                "{:?} is not the user name of a student in the system.", suname
+            ));
+        },
+    };
+
+    let pdf_data = {
+        let data_handle = glob.data();
+        let data = data_handle.read().await;
+        let mut client = match data.connect().await {
+            Ok(c) => c,
+            Err(e) => {
+                log::error!(
+                    "Error getting DB connection to retrieve report PDF for {:?}: {}",
+                    suname, &e
+                );
+                return text_500(Some(format!(
+                    "Error connecting to the database: {}", &e
+                )));
+            },
+        };
+        let t = match client.transaction().await {
+            Ok(t) => t,
+            Err(e) => {
+                log::error!(
+                    "Error opening Transaction to retrieve report PDF for {:?}: {}",
+                    suname, &e
+                );
+                return text_500(Some(format!(
+                    "Error initiating database transaction: {}", &e
+                )));
+            },
+        };
+
+        let pdf_data = match Store::get_final(&t, suname, year, term).await {
+            Ok(Some(v)) => v,
+            Ok(None) => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    format!(
+                        "{} {} does not have a {} {} report in the system.",
+                        &stud.rest, &stud.last, &term,
+                        crate::academic_year_from_start_year(year)
+                    ),
+                ).into_response();
+            },
+            Err(e) => {
+                log::error!(
+                    "Error querying database for {} report for {:?}: {}",
+                    &term, suname, &e
+                );
+                return text_500(Some(format!(
+                    "Error retrieving report from database: {}", &e
+                )));
+            },
+        };
+
+        if let Err(e) = t.commit().await {
+            log::error!(
+                "<WEIRD!> Error committing transaction to retrieve {} PDF report for {:?}: {}",
+                &term, suname, &e
+            );
+            return text_500(Some(format!(
+                "Error committing transaction (weird, I know): {}", &e
+            )));
+        }
+
+        pdf_data
+    };
+
+    // The first thing this function does is respond with an error if there's
+    // no "x-camp-student" or "x-camp-term" headers, so these are both
+    // guaranteed to be here.
+    let suname_header = headers.get("x-camp-student").unwrap().clone();
+    let term_header = headers.get("x-camp-term").unwrap().clone();
+
+    (
+        StatusCode::OK,
+        [
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/pdf"),
+            ),
+            (
+                header::CONTENT_DISPOSITION,
+                HeaderValue::from_static("inline"),
+            ),
+            (
+                HeaderName::from_static("x-camp-action"),
+                HeaderValue::from_static("download-pdf"),
+            ),
+            (
+                HeaderName::from_static("x-camp-student"),
+                suname_header,
+            ),
+            (
+                HeaderName::from_static("x-camp-term"),
+                term_header,
+            ),
+        ],
+        pdf_data
+    ).into_response()
+}
+
+/**
+Determine the academic year (by starting year) a request concerns: the one
+in its `x-camp-year` header, if it has one, or else the current one.
+*/
+fn get_year(headers: &HeaderMap, glob: &Glob) -> Result<i32, String> {
+    if headers.get("x-camp-year").is_none() {
+        return Ok(glob.academic_year());
+    }
+
+    let year_str = get_head("x-camp-year", headers)?;
+    year_str.parse::<i32>().map_err(|e| format!(
+        "Invalid x-camp-year value {:?}: {}", year_str, &e
+    ))
+}
+
+/**
+Respond to a request for the list of academic years with data in the
+system.
+
+Request requirements:
+```text
+x-camp-action: populate-years
+```
+The response body looks like
+`{ "current": 2023, "years": [ { "year": 2022, "name": "2022--2023" }, ... ] }`.
+*/
+pub async fn populate_years(glob: Arc<GlobCell>) -> Response {
+    let glob = glob.load();
+    let years = match glob.academic_years().await {
+        Ok(years) => years,
+        Err(e) => {
+            log::error!("Error retrieving list of academic years: {}", &e);
+            return text_500(Some(format!(
+                "Error retrieving academic years from the database: {}", &e
+            )));
+        }
+    };
+
+    let years: Vec<serde_json::Value> = years
+        .iter()
+        .map(|y| json!({
+            "year": y,
+            "name": crate::academic_year_from_start_year(*y).as_str(),
+        }))
+        .collect();
+
+    (
+        StatusCode::OK,
+        [(
+            HeaderName::from_static("x-camp-action"),
+            HeaderValue::from_static("populate-years"),
+        )],
+        Json(json!({
+            "current": glob.academic_year(),
+            "years": years,
+        })),
+    )
+        .into_response()
+}
+
+/// A single Goal as shown in the (read-only) view of a past academic year.
+#[derive(Debug, Serialize)]
+struct YearGoal<'a> {
+    id: i64,
+    course: &'a str,
+    chapter: &'a str,
+    rev: bool,
+    inc: bool,
+    due: Option<String>,
+    done: Option<String>,
+    score: Option<&'a str>,
+}
+
+/// One Student's Goals as shown in the (read-only) view of a past academic
+/// year.
+#[derive(Debug, Serialize)]
+struct YearPace<'a> {
+    uname: &'a str,
+    last: &'a str,
+    rest: &'a str,
+    teacher: &'a str,
+    goals: Vec<YearGoal<'a>>,
+}
+
+impl<'a> YearPace<'a> {
+    fn from_pace(p: &'a Pace, glob: &'a Glob) -> YearPace<'a> {
+        let goals = p
+            .goals
+            .iter()
+            .map(|g| {
+                let (course, chapter) = match &g.source {
+                    Source::Book(bch) => match glob.course_by_sym(&bch.sym) {
+                        Some(crs) => (
+                            crs.title.as_str(),
+                            crs.chapter(bch.seq).map(|c| c.title.as_str()).unwrap_or(""),
+                        ),
+                        None => (bch.sym.as_str(), ""),
+                    },
+                    Source::Custom(CustomCh(id)) => (
+                        CUSTOM_COURSE,
+                        glob.custom_chapter(*id).map(|c| c.title.as_str()).unwrap_or(""),
+                    ),
+                };
+
+                YearGoal {
+                    id: g.id,
+                    course,
+                    chapter,
+                    rev: g.review,
+                    inc: g.incomplete,
+                    due: g.due.map(|d| d.to_string()),
+                    done: g.done.map(|d| d.to_string()),
+                    score: g.score.as_deref(),
+                }
+            })
+            .collect();
+
+        YearPace {
+            uname: &p.student.base.uname,
+            last: &p.student.last,
+            rest: &p.student.rest,
+            teacher: &p.teacher.name,
+            goals,
+        }
+    }
+}
+
+/**
+Respond to a request for the Goals the Students of the given Teachers had in
+some academic year. The response can't be used to change anything; past
+years are only there to be looked at.
+
+Request requirements:
+```text
+x-camp-action: populate-year-goals
+x-camp-year: [ starting year of the academic year in question ]
+```
+*/
+pub async fn populate_year_goals(
+    tunames: &[&str],
+    headers: &HeaderMap,
+    glob: Arc<GlobCell>,
+) -> Response {
+    let glob = glob.load();
+    // Unlike most places, the year here isn't optional.
+    let year = match get_head("x-camp-year", headers).and_then(|_| get_year(headers, &glob)) {
+        Ok(year) => year,
+        Err(e) => { return respond_bad_request(e); },
+    };
+
+    let mut paces: Vec<Pace> = Vec::new();
+    for tuname in tunames.iter() {
+        match glob.get_paces_by_teacher_for_year(tuname, year).await {
+            Ok(mut v) => {
+                paces.append(&mut v);
+            },
+            Err(e) => {
+                log::error!(
+                    "Error retrieving {} Goals for Teacher {:?}: {}",
+                    year, tuname, &e
+                );
+                return text_500(Some(format!(
+                    "Error retrieving Goals from the database: {}", &e
+                )));
+            },
+        }
+    }
+    paces.retain(|p| !p.goals.is_empty());
+    paces.sort_by(|a, b| {
+        (&a.student.last, &a.student.rest).cmp(&(&b.student.last, &b.student.rest))
+    });
+
+    let data: Vec<YearPace> = paces
+        .iter()
+        .map(|p| YearPace::from_pace(p, &glob))
+        .collect();
+
+    (
+        StatusCode::OK,
+        [
+            (
+                HeaderName::from_static("x-camp-action"),
+                HeaderValue::from_static("populate-year-goals"),
+            ),
+            (
+                HeaderName::from_static("x-camp-year"),
+                HeaderValue::from(year),
+            ),
+        ],
+        Json(data),
+    )
+        .into_response()
+}
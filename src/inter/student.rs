@@ -1,11 +1,12 @@
 /*!
-Displaying individual student calendars.
+Displaying individual student calendars, to Students themselves and
+(read-only) to their Parents.
 */
 use time::{format_description::FormatItem, macros::format_description, Date};
 
 use crate::{
     pace::{GoalDisplay, GoalStatus, PaceDisplay, RowDisplay, SummaryDisplay},
-    user::Student,
+    user::{BaseUser, Parent, Student},
     MiniString, SMALLSTORE,
 };
 
@@ -144,65 +145,62 @@ fn write_summary(buff: &mut Vec<u8>, s: &SummaryDisplay) -> Result<(), String> {
 }
 
 /**
-Determine whether the student's login credentials check out, then render the
-view they are supposed to see.
+Check the login credentials of `base`, returning the appropriate error
+response if they don't check out.
 */
-pub async fn login(s: Student, form: LoginData, glob: Arc<RwLock<Glob>>) -> Response {
-    let glob = glob.read().await;
+async fn check_password(
+    base: &BaseUser,
+    form: &LoginData,
+    client: &ClientInfo,
+    glob: &Glob,
+) -> Result<(), Response> {
     match glob
         .auth()
         .read()
         .await
-        .check_password(&s.base.uname, &form.password, &s.base.salt)
+        .check_login(&base.uname, &form.password, &base.salt, &client.addr)
         .await
     {
         Err(e) => {
             log::error!(
-                "auth::Db::check_password( {:?}, {:?}, {:?} ) error: {}",
-                &s.base.uname,
+                "auth::Db::check_login( {:?}, {:?}, {:?} ) error: {}",
+                &base.uname,
                 &form.password,
-                &s.base.salt,
+                &base.salt,
                 &e
             );
-            return html_500();
-        }
-        Ok(AuthResult::Ok) => { /* This is the happy path; proceed. */ }
-        Ok(AuthResult::BadPassword) => {
-            return respond_bad_password(&s.base.uname);
+            Err(html_500())
         }
+        Ok(AuthResult::Ok) => Ok(()),
+        Ok(AuthResult::BadPassword) => Err(respond_bad_password(&base.uname)),
+        Ok(AuthResult::Locked) => Err(respond_locked(&base.uname)),
         Ok(x) => {
             log::warn!(
-                "auth::Db::check_password( {:?}, {:?}, {:?} ) returned {:?}, which shouldn't happen.",
-                &s.base.uname, &form.password, &s.base.salt, &x
+                "auth::Db::check_login( {:?}, {:?}, {:?} ) returned {:?}, which shouldn't happen.",
+                &base.uname, &form.password, &base.salt, &x
             );
-            return respond_bad_password(&s.base.uname);
+            Err(respond_bad_password(&base.uname))
         }
     }
+}
 
-    let p = match glob.get_pace_by_student(&s.base.uname).await {
-        Ok(p) => p,
-        Err(e) => {
-            log::error!(
-                "Glob::get_pace_by_student( {:?} ) error: {}",
-                &s.base.uname,
-                &e
-            );
-            return html_500();
-        }
-    };
+/**
+Gather the data required to render the view of Student `uname`'s pace
+(either in the `"student"` template, or as one of the `children` in the
+`"parent"` template).
+*/
+async fn pace_view_data(uname: &str, glob: &Glob) -> Result<serde_json::Value, String> {
+    let p = glob
+        .get_pace_by_student(uname)
+        .await
+        .map_err(|e| format!("Glob::get_pace_by_student( {:?} ) error: {}", uname, &e))?;
 
-    let pd = match PaceDisplay::from(&p, &glob) {
-        Ok(pd) => pd,
-        Err(e) => {
-            log::error!(
-                "PaceDisplay::from( [ Pace {:?} ] ) error: {}\npace data: {:#?} )",
-                &p.student.base.uname,
-                &e,
-                &p
-            );
-            return html_500();
-        }
-    };
+    let pd = PaceDisplay::from(&p, glob).map_err(|e| {
+        format!(
+            "PaceDisplay::from( [ Pace {:?} ] ) error: {}\npace data: {:#?} )",
+            &p.student.base.uname, &e, &p
+        )
+    })?;
 
     let today = crate::now();
 
@@ -211,27 +209,18 @@ pub async fn login(s: Student, form: LoginData, glob: Arc<RwLock<Glob>>) -> Resp
     for row_display in pd.rows.iter() {
         match row_display {
             RowDisplay::Goal(g) => {
-                if let Err(e) = write_goal(&mut goals_buff, g, &today) {
-                    log::error!("Error writing goal: {}\ndata: {:?}", &e, g);
-                    return html_500();
-                }
+                write_goal(&mut goals_buff, g, &today)
+                    .map_err(|e| format!("Error writing goal: {}\ndata: {:?}", &e, g))?;
             }
             RowDisplay::Summary(s) => {
-                if let Err(e) = write_summary(&mut goals_buff, s) {
-                    log::error!("Error writing summary line: {}\ndata: {:?}", &e, s);
-                    return html_500();
-                }
+                write_summary(&mut goals_buff, s)
+                    .map_err(|e| format!("Error writing summary line: {}\ndata: {:?}", &e, s))?;
             }
         }
     }
 
-    let rows = match String::from_utf8(goals_buff) {
-        Ok(s) => s,
-        Err(e) => {
-            log::error!("Buffer of Goal lines for some reaosn not UTF-8: {}", &e);
-            return html_500();
-        }
-    };
+    let rows = String::from_utf8(goals_buff)
+        .map_err(|e| format!("Buffer of Goal lines for some reaosn not UTF-8: {}", &e))?;
 
     let rev_foot = if pd.has_review_chapters {
         "*R after a chapter indicates previously-completed material that requires review."
@@ -244,7 +233,7 @@ pub async fn login(s: Student, form: LoginData, glob: Arc<RwLock<Glob>>) -> Resp
         ""
     };
 
-    let data = json!({
+    Ok(json!({
         "name": format!("{} {}", pd.rest, pd.last),
         "uname": pd.uname,
         "teacher": pd.teacher,
@@ -255,7 +244,81 @@ pub async fn login(s: Student, form: LoginData, glob: Arc<RwLock<Glob>>) -> Resp
         "rows": rows,
         "rev_foot": rev_foot,
         "inc_foot": inc_foot,
+    }))
+}
+
+/**
+Determine whether the student's login credentials check out, then render the
+view they are supposed to see.
+*/
+pub async fn login(
+    s: Student,
+    form: LoginData,
+    client: &ClientInfo,
+    glob: Arc<GlobCell>,
+) -> Response {
+    let glob = glob.load();
+    if let Err(resp) = check_password(&s.base, &form, client, &glob).await {
+        return resp;
+    }
+
+    serve_view(&s, None, &glob).await
+}
+
+/// Render the Student's view of their pace (for an Admin viewing as the
+/// Student, if `impersonator` is given).
+pub async fn serve_view(s: &Student, impersonator: Option<&str>, glob: &Glob) -> Response {
+    match pace_view_data(&s.base.uname, glob).await {
+        Ok(mut data) => {
+            data["impersonator"] = json!(impersonator);
+            data["school"] = json!(&glob.school_name);
+            serve_raw_template(StatusCode::OK, "student", &data, vec![])
+        }
+        Err(e) => {
+            log::error!("{}", &e);
+            html_500()
+        }
+    }
+}
+
+/**
+Determine whether the parent's login credentials check out, then render the
+(read-only) views of each of their Students.
+*/
+pub async fn parent_login(
+    p: Parent,
+    form: LoginData,
+    client: &ClientInfo,
+    glob: Arc<GlobCell>,
+) -> Response {
+    let glob = glob.load();
+    if let Err(resp) = check_password(&p.base, &form, client, &glob).await {
+        return resp;
+    }
+
+    serve_parent_view(&p, None, &glob).await
+}
+
+/// Render the Parent's view of their Students' paces (for an Admin viewing
+/// as the Parent, if `impersonator` is given).
+pub async fn serve_parent_view(p: &Parent, impersonator: Option<&str>, glob: &Glob) -> Response {
+    let mut children: Vec<serde_json::Value> = Vec::with_capacity(p.children.len());
+    for uname in p.children.iter() {
+        match pace_view_data(uname, glob).await {
+            Ok(data) => children.push(data),
+            Err(e) => {
+                log::error!("Error rendering view for Parent {:?}: {}", &p.base.uname, &e);
+                return html_500();
+            }
+        }
+    }
+
+    let data = json!({
+        "uname": &p.base.uname,
+        "children": children,
+        "impersonator": impersonator,
+        "school": &glob.school_name,
     });
 
-    serve_raw_template(StatusCode::OK, "student", &data, vec![])
+    serve_raw_template(StatusCode::OK, "parent", &data, vec![])
 }
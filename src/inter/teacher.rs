@@ -17,16 +17,15 @@ use axum::{
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use time::Date;
-use tokio::sync::RwLock;
 
 use super::*;
 use crate::{
     auth::AuthResult,
-    config::Glob,
-    course::Course,
-    pace::{maybe_parse_score_str, BookCh, Goal, Pace, Source, Term},
+    config::{Glob, GlobCell},
+    course::{Course, Custom},
+    pace::{maybe_parse_score_str, BookCh, CustomCh, Goal, Pace, Source, Term},
     report, report::ReportSidecar,
-    store::Store,
+    store::{Store, Versioned},
     user::*,
     DATE_FMT,
 };
@@ -45,19 +44,23 @@ fn maybe_parse_date(date_opt: Option<&str>) -> Result<Option<Date>, String> {
 
 /// Check that the teacher's login credentials check out, generate them a
 /// key, then serve them the page that contains their view.
-pub async fn login(t: Teacher, form: LoginData, glob: Arc<RwLock<Glob>>) -> Response {
+pub async fn login(
+    t: Teacher,
+    form: LoginData,
+    client: &ClientInfo,
+    glob: Arc<GlobCell>,
+) -> Response {
     log::trace!(
         "teacher::login( {:?}, ... , [ glob ]) called.",
         &t.base.uname
     );
 
     let auth_response = {
-        glob.read()
-            .await
+        glob.load()
             .auth()
             .read()
             .await
-            .check_password_and_issue_key(&t.base.uname, &form.password, &t.base.salt)
+            .check_password_and_issue_key(&t.base.uname, &form.password, &t.base.salt, client)
             .await
     };
 
@@ -77,6 +80,9 @@ pub async fn login(t: Teacher, form: LoginData, glob: Arc<RwLock<Glob>>) -> Resp
         Ok(AuthResult::BadPassword) => {
             return respond_bad_password(&t.base.uname);
         }
+        Ok(AuthResult::Locked) => {
+            return respond_locked(&t.base.uname);
+        }
         Ok(x) => {
             log::warn!(
                 "auth::Db::check_password_and_issue_key( {:?}, {:?}. {:?} returned {:?}, which shouldn't ever happen.",
@@ -87,10 +93,17 @@ pub async fn login(t: Teacher, form: LoginData, glob: Arc<RwLock<Glob>>) -> Resp
         }
     };
 
+    serve_view(&t, &auth_key, None)
+}
+
+/// Render the Teacher view (for an Admin viewing as the Teacher, if
+/// `impersonator` is given).
+pub fn serve_view(t: &Teacher, auth_key: &str, impersonator: Option<&str>) -> Response {
     let data = json!({
         "uname": &t.base.uname,
-        "key": &auth_key,
+        "key": auth_key,
         "name": &t.name,
+        "impersonator": impersonator,
     });
 
     serve_template(StatusCode::OK, "teacher", &data, vec![])
@@ -109,43 +122,10 @@ checks out.
 pub async fn api(
     headers: HeaderMap,
     body: Option<String>,
-    Extension(glob): Extension<Arc<RwLock<Glob>>>,
+    Extension(glob): Extension<Arc<GlobCell>>,
 ) -> Response {
-    let uname: &str = match headers.get("x-camp-uname") {
-        Some(uname) => match uname.to_str() {
-            Ok(s) => s,
-            Err(_) => {
-                return text_500(None);
-            }
-        },
-        None => {
-            return text_500(None);
-        }
-    };
-
-    let u = {
-        let glob = glob.read().await;
-        if let Some(u) = glob.users.get(uname) {
-            u.clone()
-        } else {
-            return text_500(None);
-        }
-    };
-
-    // While a service layer has already checked that the request's `uname`
-    // header and `key` header are a valid combination, we have not yet
-    // verified that the user in question is actually a _teacher_. Here's
-    // where that gets checked.
-    match u {
-        User::Teacher(_) => { /* Okay, approved, you can be here. */ }
-        _ => {
-            return (
-                StatusCode::FORBIDDEN,
-                "Who is this? What's you're operating number?".to_owned(),
-            )
-                .into_response();
-        }
-    }
+    // Whether this user may perform the requested action has already been
+    // checked by the `inter::authorize` layer.
 
     let action = match headers.get("x-camp-action") {
         Some(act) => match act.to_str() {
@@ -159,12 +139,26 @@ pub async fn api(
         }
     };
 
-    match action {
+    let audit = Audit::begin(
+        Endpoint::Teacher,
+        action,
+        &headers,
+        body.as_deref(),
+        &glob.load(),
+    )
+    .await;
+
+    let resp = match action {
         "populate-dates" => populate_dates(glob.clone()).await,
         "populate-courses" => populate_courses(glob.clone()).await,
         "populate-goals" => populate_goals(&headers, glob.clone()).await,
+        "populate-teacher-goals" => populate_teacher_goals(&headers, glob.clone()).await,
         "populate-traits" => populate_traits(glob.clone()).await,
-        "add-goal" => insert_goal(body, glob.clone()).await,
+        "populate-custom" => populate_custom(glob.clone()).await,
+        "add-custom" => insert_custom(&headers, body, glob.clone()).await,
+        "update-custom" => update_custom(&headers, body, glob.clone()).await,
+        "delete-custom" => delete_custom(&headers, body, glob.clone()).await,
+        "add-goal" => insert_goal(&headers, body, glob.clone()).await,
         "update-goal" => update_goal(body, glob.clone()).await,
         "delete-goal" => delete_goal(body, glob.clone()).await,
         "update-numbers" => update_numbers(body, glob.clone()).await,
@@ -176,8 +170,19 @@ pub async fn api(
         "render-report" => generate_report(&headers, body, glob.clone()).await,
         "discard-pdf" => discard_pdf(&headers, glob.clone()).await,
         "student-history" => student_history(&headers, glob.clone()).await,
+        "download-report" => download_student_report(&headers, glob.clone()).await,
+        "populate-years" => populate_years(glob.clone()).await,
+        "populate-year-goals" => match get_head("x-camp-uname", &headers) {
+            Ok(uname) => populate_year_goals(&[uname], &headers, glob.clone()).await,
+            Err(e) => text_500(Some(e)),
+        },
         x => respond_bad_request(format!("{:?} is not a recognized x-camp-action value.", &x)),
+    };
+
+    if let Some(audit) = audit {
+        audit.finish(resp.status(), &glob.load()).await;
     }
+    resp
 }
 
 /**
@@ -201,10 +206,9 @@ Header which gets us here:
 x-camp-action: populate-dates
 ```
 */
-async fn populate_dates(glob: Arc<RwLock<Glob>>) -> Response {
+async fn populate_dates(glob: Arc<GlobCell>) -> Response {
     let dates_bucket: HashMap<String, String> = glob
-        .read()
-        .await
+        .load()
         .dates
         .iter()
         .map(|(n, d)| (n.clone(), d.to_string()))
@@ -306,8 +310,8 @@ Header that gets us here:
 x-camp-action: populate-courses
 ```
 */
-async fn populate_courses(glob: Arc<RwLock<Glob>>) -> Response {
-    let glob = glob.read().await;
+async fn populate_courses(glob: Arc<GlobCell>) -> Response {
+    let glob = glob.load();
 
     let mut course_data: Vec<CourseData> = Vec::with_capacity(glob.courses.len());
     for (_, crs) in glob.courses.iter() {
@@ -332,12 +336,232 @@ async fn populate_courses(glob: Arc<RwLock<Glob>>) -> Response {
         .into_response()
 }
 
+/**
+Respond to a request for all the custom chapters (material for Goals that
+doesn't come from any Course) in the system.
+
+Header that gets us here:
+```
+x-camp-action: populate-custom
+```
+*/
+async fn populate_custom(glob: Arc<GlobCell>) -> Response {
+    let glob = glob.load();
+
+    let mut customs: Vec<&Custom> = glob.custom_chapters.values().collect();
+    customs.sort_by(|a, b| a.title.cmp(&b.title));
+
+    (
+        StatusCode::OK,
+        [(
+            HeaderName::from_static("x-camp-action"),
+            HeaderValue::from_static("populate-custom"),
+        )],
+        Json(customs),
+    )
+        .into_response()
+}
+
+/**
+Reload the [`Glob`]'s copy of the custom chapters from the database and
+resend them all to the frontend.
+
+This should be called at the end of any handler that changes custom chapters.
+*/
+async fn refresh_and_repopulate_custom(glob: Arc<GlobCell>) -> Response {
+    if let Err(e) = glob.write().await.refresh_custom_chapters().await {
+        log::error!("Error refreshing custom chapters from database: {}", &e);
+        return text_500(Some(format!(
+            "Unable to refresh custom chapters from database: {}",
+            &e
+        )));
+    }
+
+    populate_custom(glob).await
+}
+
+/// Read a [`Custom`] chapter from the body of a request, checking its title
+/// and weight. The error is suitable for a bad request response.
+fn custom_from_body(body: Option<String>) -> Result<Custom, String> {
+    let body = body.ok_or_else(|| {
+        "Request needs application/json body with custom chapter details.".to_owned()
+    })?;
+
+    let cust: Custom = serde_json::from_str(&body).map_err(|e| {
+        log::error!("Error deserializing {:?} as Custom: {}", &body, &e);
+        "Unable to deserialize as custom chapter.".to_owned()
+    })?;
+
+    if cust.title.trim().is_empty() {
+        return Err("Custom chapters must have titles.".to_owned());
+    }
+    Glob::check_custom_for_bad_chars(&cust)?;
+    if !(cust.weight.is_finite() && cust.weight > 0.0) {
+        return Err("Custom chapter weights must be positive numbers.".to_owned());
+    }
+
+    Ok(cust)
+}
+
+/// Ensure the custom chapter with the given `id` exists and belongs to the
+/// teacher `tuname`; teachers may only alter (or assign) their own custom
+/// chapters.
+fn check_custom_owner(id: i64, tuname: &str, glob: &Glob) -> Result<(), (StatusCode, String)> {
+    match glob.custom_chapter(id) {
+        Some(cust) if cust.uname == tuname => Ok(()),
+        Some(_) => Err((
+            StatusCode::FORBIDDEN,
+            "You may only use custom chapters you created.".to_owned(),
+        )),
+        None => Err((
+            StatusCode::BAD_REQUEST,
+            format!("There is no custom chapter with id {}.", &id),
+        )),
+    }
+}
+
+/// Ensure every custom chapter among the sources of `goals` belongs to the
+/// teacher `tuname` (see [`check_custom_owner`]).
+fn check_custom_sources(
+    goals: &[Goal],
+    tuname: &str,
+    glob: &Glob,
+) -> Result<(), (StatusCode, String)> {
+    for g in goals.iter() {
+        if let Source::Custom(CustomCh(id)) = g.source {
+            check_custom_owner(id, tuname, glob)?;
+        }
+    }
+    Ok(())
+}
+
+/**
+Insert a new custom chapter, belonging to the requesting teacher.
+
+```
+x-camp-action: add-custom
+```
+The body should JSON-deserialize into a [`Custom`]; its `id` and `uname`
+are ignored.
+*/
+async fn insert_custom(
+    headers: &HeaderMap,
+    body: Option<String>,
+    glob: Arc<GlobCell>,
+) -> Response {
+    let tuname = match get_head("x-camp-uname", headers) {
+        Ok(uname) => uname,
+        Err(e) => { return text_500(Some(e)); }
+    };
+    let mut cust = match custom_from_body(body) {
+        Ok(cust) => cust,
+        Err(e) => { return respond_bad_request(e); }
+    };
+    cust.uname = tuname.to_owned();
+
+    {
+        let glob = glob.load();
+        if let Err(e) = glob.data().read().await.insert_custom_chapter(&cust).await {
+            log::error!("Error inserting custom chapter {:?}: {}", &cust, &e);
+            return text_500(Some(format!("Error inserting custom chapter: {}", &e)));
+        }
+    }
+
+    refresh_and_repopulate_custom(glob).await
+}
+
+/**
+Change the title and weight of one of the requesting teacher's custom
+chapters.
+
+```
+x-camp-action: update-custom
+```
+The body should JSON-deserialize into a [`Custom`] with the `id` of the
+custom chapter to change.
+*/
+async fn update_custom(
+    headers: &HeaderMap,
+    body: Option<String>,
+    glob: Arc<GlobCell>,
+) -> Response {
+    let tuname = match get_head("x-camp-uname", headers) {
+        Ok(uname) => uname,
+        Err(e) => { return text_500(Some(e)); }
+    };
+    let cust = match custom_from_body(body) {
+        Ok(cust) => cust,
+        Err(e) => { return respond_bad_request(e); }
+    };
+
+    {
+        let glob = glob.load();
+        if let Err(e) = check_custom_owner(cust.id, tuname, &glob) {
+            return e.into_response();
+        }
+        if let Err(e) = glob.data().read().await.update_custom_chapter(&cust).await {
+            log::error!("Error updating custom chapter {:?}: {}", &cust, &e);
+            return text_500(Some(format!("Error updating custom chapter: {}", &e)));
+        }
+    }
+
+    refresh_and_repopulate_custom(glob).await
+}
+
+/**
+Delete one of the requesting teacher's custom chapters. Custom chapters
+still used by any Goals can't be deleted.
+
+```
+x-camp-action: delete-custom
+```
+The body should be the `id` of the custom chapter to delete.
+*/
+async fn delete_custom(
+    headers: &HeaderMap,
+    body: Option<String>,
+    glob: Arc<GlobCell>,
+) -> Response {
+    let tuname = match get_head("x-camp-uname", headers) {
+        Ok(uname) => uname,
+        Err(e) => { return text_500(Some(e)); }
+    };
+    let id: i64 = match body.as_deref().map(|b| b.trim().parse()) {
+        Some(Ok(n)) => n,
+        _ => {
+            return respond_bad_request(
+                "Request body must be the id of a custom chapter.".to_owned(),
+            );
+        }
+    };
+
+    {
+        let glob = glob.load();
+        if let Err(e) = check_custom_owner(id, tuname, &glob) {
+            return e.into_response();
+        }
+        if let Err(e) = glob.data().read().await.delete_custom_chapter(id).await {
+            log::error!("Error deleting custom chapter {}: {}", &id, &e);
+            return text_500(Some(format!("Error deleting custom chapter: {}", &e)));
+        }
+    }
+
+    refresh_and_repopulate_custom(glob).await
+}
+
 /*
 The GoalData and CourseData structs serialize data about student pace
 and goals in a way that the frontend expects them.
 
 The GaolData will also _deserialize_ information about a goal as sent
 from the fontend.
+
+Goals on Course material have a `sym` and `seq`; custom Goals instead have
+the `id` of their custom chapter as `custom`.
+
+Both carry the `version` of what they describe (the Goal, or the Student
+whose pace it is), which the frontend sends back with any change so an edit
+made from stale data can be refused.
 */
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -345,8 +569,9 @@ struct GoalData<'a> {
     id: i64,
     #[serde(skip_serializing)]
     uname: &'a str,
-    sym: &'a str,
-    seq: i16,
+    sym: Option<&'a str>,
+    seq: Option<i16>,
+    custom: Option<i64>,
     rev: bool,
     inc: bool,
     due: Option<String>,
@@ -354,17 +579,25 @@ struct GoalData<'a> {
     tries: Option<i16>,
     weight: f32,
     score: Option<&'a str>,
+    #[serde(default)]
+    version: i64,
 }
 
 impl<'a> GoalData<'a> {
     /// Called upon receipt of new or updated information about a `Goal` from
     /// the frontend to convert that data into the appropriate internal format.
     fn into_goal(self) -> Result<Goal, String> {
-        let source = BookCh {
-            sym: self.sym.to_owned(),
-            seq: self.seq,
-            // doesn't matter on insertion
-            level: 0.0,
+        let source = match (self.custom, self.sym, self.seq) {
+            (Some(id), _, _) => Source::Custom(CustomCh(id)),
+            (None, Some(sym), Some(seq)) => Source::Book(BookCh {
+                sym: sym.to_owned(),
+                seq,
+                // doesn't matter on insertion
+                level: 0.0,
+            }),
+            _ => {
+                return Err("Goal needs either a course and chapter or a custom chapter.".into());
+            }
         };
 
         let _ = maybe_parse_score_str(self.score)?;
@@ -372,7 +605,7 @@ impl<'a> GoalData<'a> {
         let g = Goal {
             id: self.id,
             uname: self.uname.to_owned(),
-            source: Source::Book(source),
+            source,
             review: self.rev,
             incomplete: self.inc,
             due: maybe_parse_date(self.due.as_deref())
@@ -382,6 +615,7 @@ impl<'a> GoalData<'a> {
             tries: self.tries,
             weight: self.weight,
             score: self.score.map(|s| s.to_owned()),
+            version: self.version,
         };
 
         Ok(g)
@@ -406,6 +640,8 @@ struct PaceData<'a> {
     /// Fall/Spring notices
     fnot: i16,
     snot: i16,
+    #[serde(default)]
+    version: i64,
 }
 
 impl<'a> PaceData<'a> {
@@ -414,21 +650,17 @@ impl<'a> PaceData<'a> {
     pub fn from_pace(pcal: &'a Pace) -> Result<PaceData, String> {
         let mut goals: Vec<GoalData> = Vec::with_capacity(pcal.goals.len());
         for g in pcal.goals.iter() {
-            let src = match &g.source {
-                Source::Book(bch) => bch,
-                _ => {
-                    return Err(format!(
-                        "Student {:?} ({}, {}) has Goal w/ (unsupported) custom Source.",
-                        &pcal.student.base.uname, &pcal.student.last, &pcal.student.rest
-                    ));
-                }
+            let (sym, seq, custom) = match &g.source {
+                Source::Book(bch) => (Some(bch.sym.as_str()), Some(bch.seq), None),
+                Source::Custom(CustomCh(id)) => (None, None, Some(*id)),
             };
 
             let gdat = GoalData {
                 id: g.id,
                 uname: "",
-                sym: &src.sym,
-                seq: src.seq,
+                sym,
+                seq,
+                custom,
                 rev: g.review,
                 inc: g.incomplete,
                 due: g.due.map(|d| d.to_string()),
@@ -436,6 +668,7 @@ impl<'a> PaceData<'a> {
                 tries: g.tries,
                 weight: g.weight,
                 score: g.score.as_deref(),
+                version: g.version,
             };
 
             goals.push(gdat);
@@ -456,6 +689,7 @@ impl<'a> PaceData<'a> {
             sex_frac: pcal.student.spring_exam_fraction,
             fnot: pcal.student.fall_notices,
             snot: pcal.student.spring_notices,
+            version: pcal.student.version,
         };
 
         Ok(pdat)
@@ -471,13 +705,47 @@ The header to get this:
 x-camp-action: populate-goals
 ```
 */
-async fn populate_goals(headers: &HeaderMap, glob: Arc<RwLock<Glob>>) -> Response {
+async fn populate_goals(headers: &HeaderMap, glob: Arc<GlobCell>) -> Response {
     let uname = match get_head("x-camp-uname", headers) {
         Ok(uname) => uname,
         Err(e) => { return text_500(Some(e)); }
     };
 
-    let pace_cals = match glob.read().await.get_paces_by_teacher(uname).await {
+    goals_response(uname, glob).await
+}
+
+/**
+Send the Goal data for all of another teacher's students. This isn't
+something Teachers can do by default; it must be granted (to a department
+head, say) as the `teacher:populate-teacher-goals` permission.
+
+```
+x-camp-action: populate-teacher-goals
+x-camp-teacher: [ uname of the teacher whose students should be sent ]
+```
+
+The response looks just like the response to `populate-goals`.
+*/
+async fn populate_teacher_goals(headers: &HeaderMap, glob: Arc<GlobCell>) -> Response {
+    let tuname = match get_head("x-camp-teacher", headers) {
+        Ok(uname) => uname,
+        Err(e) => { return respond_bad_request(e); }
+    };
+
+    match glob.load().users.get(tuname) {
+        Some(User::Teacher(_)) => { /* This is the happy path. */ },
+        _ => {
+            return respond_bad_request(format!("{:?} is not a Teacher.", tuname));
+        },
+    }
+
+    goals_response(tuname, glob).await
+}
+
+/// Generate the response to a `populate-goals` (or `populate-teacher-goals`)
+/// request: the Goals of all the students of the Teacher `uname`.
+async fn goals_response(uname: &str, glob: Arc<GlobCell>) -> Response {
+    let pace_cals = match glob.load().get_paces_by_teacher(uname).await {
         Ok(goals) => goals,
         Err(e) => {
             return text_500(Some(format!("{}", &e)));
@@ -507,8 +775,8 @@ async fn populate_goals(headers: &HeaderMap, glob: Arc<RwLock<Glob>>) -> Respons
         .into_response()
 }
 
-async fn populate_traits(glob: Arc<RwLock<Glob>>) -> Response {
-    let glob = glob.read().await;
+async fn populate_traits(glob: Arc<GlobCell>) -> Response {
+    let glob = glob.load();
 
     (
         StatusCode::OK,
@@ -528,8 +796,8 @@ the frontend.
 This is generally called by a handler function to generate a response when
 the teacher makes a request that alter's a student's pace calendar in some way.
 */
-async fn update_pace(uname: &str, glob: Arc<RwLock<Glob>>) -> Response {
-    let p = match glob.read().await.get_pace_by_student(uname).await {
+async fn update_pace(uname: &str, glob: Arc<GlobCell>) -> Response {
+    let p = match glob.load().get_pace_by_student(uname).await {
         Ok(p) => p,
         Err(e) => {
             log::error!("Error getting Pace for student {:?}: {}", uname, &e);
@@ -565,9 +833,19 @@ What' gets us here:
 ```
 x-camp-action: add-goal
 ```
-And the request body should be JSON-deserializable into a `GoalData`.
+And the request body should be JSON-deserializable into a `GoalData`. A
+custom chapter it names must be one of the requesting teacher's own.
 */
-async fn insert_goal(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Response {
+async fn insert_goal(
+    headers: &HeaderMap,
+    body: Option<String>,
+    glob: Arc<GlobCell>,
+) -> Response {
+    let tuname = match get_head("x-camp-uname", headers) {
+        Ok(uname) => uname,
+        Err(e) => { return text_500(Some(e)); }
+    };
+
     let body = match body {
         Some(body) => body,
         None => {
@@ -592,18 +870,22 @@ async fn insert_goal(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Response
         }
     };
 
-    if let Err(e) = glob
-        .read()
-        .await
+    let glob_guard = glob.load();
+    if let Err(e) = check_custom_sources(std::slice::from_ref(&g), tuname, &glob_guard) {
+        return e.into_response();
+    }
+    let year = glob_guard.academic_year();
+    if let Err(e) = glob_guard
         .data()
         .read()
         .await
-        .insert_one_goal(&g)
+        .insert_one_goal(&g, year)
         .await
     {
         log::error!("Error inserting Goal {:?} into database: {}", &g, &e);
         return text_500(Some(format!("Error inserting Goal into database: {}", &e)));
     }
+    drop(glob_guard);
 
     update_pace(&g.uname, glob).await
 }
@@ -616,9 +898,11 @@ Header that gets us here:
 x-camp-action: update-goal
 ```
 The body of the request should be JSON-deserializable into a `GoalData` with
-the `id` of the [`Goal`] to change and the updated data.
+the `id` of the [`Goal`] to change, the updated data, and the `version` of
+the Goal it was read at. If the Goal has changed since, nothing is written,
+and the student's current pace comes back with a 409.
 */
-async fn update_goal(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Response {
+async fn update_goal(body: Option<String>, glob: Arc<GlobCell>) -> Response {
     let body = match body {
         Some(body) => body,
         None => {
@@ -643,9 +927,24 @@ async fn update_goal(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Response
         }
     };
 
-    if let Err(e) = glob.read().await.data().read().await.update_goal(&g).await {
-        log::error!("Error inserting Goal {:?} into database: {}", &g, &e);
-        return text_500(Some(format!("Error inserting Goal into database: {}", &e)));
+    let written = {
+        let glob = glob.load();
+        let year = glob.academic_year();
+        match glob.data().read().await.update_goal(&g, year).await {
+            Ok(written) => written,
+            Err(e) => {
+                log::error!("Error inserting Goal {:?} into database: {}", &g, &e);
+                return text_500(Some(format!("Error inserting Goal into database: {}", &e)));
+            }
+        }
+    };
+
+    if written == Versioned::Conflict {
+        log::info!(
+            "Refusing update to Goal {} from stale version {}.",
+            &g.id, &g.version
+        );
+        return as_conflict(update_pace(&g.uname, glob).await);
     }
 
     update_pace(&g.uname, glob).await
@@ -660,7 +959,7 @@ x-camp-action: delete-goal
 ```
 With a body parseable into the `id` of the [`Goal`] to delete.
 */
-async fn delete_goal(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Response {
+async fn delete_goal(body: Option<String>, glob: Arc<GlobCell>) -> Response {
     let body = match body {
         Some(body) => body,
         None => {
@@ -678,11 +977,15 @@ async fn delete_goal(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Response
         }
     };
 
-    let uname = match glob.read().await.data().read().await.delete_goal(id).await {
-        Ok(uname) => uname,
-        Err(e) => {
-            log::error!("Error deleting Goal w/id {} from database: {}", &id, &e);
-            return text_500(Some(format!("Error deleting from database: {}", &e)));
+    let uname = {
+        let glob = glob.load();
+        let year = glob.academic_year();
+        match glob.data().read().await.delete_goal(id, year).await {
+            Ok(uname) => uname,
+            Err(e) => {
+                log::error!("Error deleting Goal w/id {} from database: {}", &id, &e);
+                return text_500(Some(format!("Error deleting from database: {}", &e)));
+            }
         }
     };
 
@@ -699,9 +1002,11 @@ Header:
 x-camp-action: update-numbers
 ```
 The body should JSON-deserialize into a `PaceData` struct with the six values
-in question set appropriately.
+in question set appropriately, and the `version` of the Student they were
+read from; if the Student has changed since, nothing is written, and the
+current pace comes back with a 409.
 */
-async fn update_numbers(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Response {
+async fn update_numbers(body: Option<String>, glob: Arc<GlobCell>) -> Response {
     let body = match body {
         Some(body) => body,
         None => {
@@ -721,7 +1026,7 @@ async fn update_numbers(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Respon
 
     log::debug!("update_numbers() rec'd body:\n{:#?}\n", &pdata);
 
-    let mut s = match glob.read().await.users.get(pdata.uname) {
+    let mut s = match glob.load().users.get(pdata.uname) {
         Some(User::Student(s)) => s.clone(),
         _ => {
             log::error!("Data uname {:?} not a Student.", &pdata.uname);
@@ -755,10 +1060,10 @@ async fn update_numbers(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Respon
     };
     s.fall_exam_fraction = pdata.fex_frac;
     s.spring_exam_fraction = pdata.sex_frac;
+    s.version = pdata.version;
 
-    {
-        let mut glob = glob.write().await;
-        let data = glob.data();
+    let written = {
+        let data = glob.load().data();
         let data_reader = data.read().await;
         let mut client = match data_reader.connect().await {
             Ok(c) => c,
@@ -778,23 +1083,44 @@ async fn update_numbers(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Respon
             }
         };
 
-        if let Err(e) = data_reader.update_student(&t, &s).await {
-            log::error!("Error updating student w/ data {:?}: {}", &s, &e);
-            return text_500(Some(format!("Error updating student: {}", &e)));
-        }
+        let written = match data_reader.update_student(&t, &s).await {
+            Ok(written) => written,
+            Err(e) => {
+                log::error!("Error updating student w/ data {:?}: {}", &s, &e);
+                return text_500(Some(format!("Error updating student: {}", &e)));
+            }
+        };
 
-        if let Err(e) = t.commit().await {
-            log::error!("Error committing transaction: {}", &e);
+        // On a conflict, nothing gets written, but the Student still gets
+        // reread, because our copy is evidently out of date.
+        let res = match written {
+            Versioned::Written(_) => t.commit().await,
+            Versioned::Conflict => t.rollback().await,
+        };
+        if let Err(e) = res {
+            log::error!("Error finishing transaction: {}", &e);
             return text_500(Some(format!(
-                "Error committing database transaction: {}",
+                "Error finishing database transaction: {}",
                 &e
             )));
         }
+        drop(client);
+        drop(data_reader);
 
-        if let Err(e) = glob.refresh_users().await {
-            log::error!("Error refreshing user hash from database: {}", &e);
-            return text_500(Some("Unable to reread users from database.".to_owned()));
+        if let Err(e) = glob.write().await.refresh_user(&s.base.uname).await {
+            log::error!("Error rereading {:?} from database: {}", &s.base.uname, &e);
+            return text_500(Some("Unable to reread user from database.".to_owned()));
         }
+
+        written
+    };
+
+    if written == Versioned::Conflict {
+        log::info!(
+            "Refusing update to Student {:?} from stale version {}.",
+            &s.base.uname, &s.version
+        );
+        return as_conflict(update_pace(pdata.uname, glob).await);
     }
 
     update_pace(pdata.uname, glob).await
@@ -809,7 +1135,7 @@ x-camp-action: autopace
 ```
 With a body containing the `uname` of the student to autopace.
 */
-async fn autopace(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Response {
+async fn autopace(body: Option<String>, glob: Arc<GlobCell>) -> Response {
     let body = match body {
         Some(body) => body,
         None => {
@@ -820,7 +1146,7 @@ async fn autopace(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Response {
     let uname: &str = &body;
 
     {
-        let glob = glob.read().await;
+        let glob = glob.load();
         let mut p = match glob.get_pace_by_student(uname).await {
             Ok(p) => p,
             Err(e) => {
@@ -864,7 +1190,7 @@ x-camp-action: clear-goals
 ```
 With a body containing the `uname` of the student in question.
 */
-async fn clear_goals(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Response {
+async fn clear_goals(body: Option<String>, glob: Arc<GlobCell>) -> Response {
     let body = match body {
         Some(body) => body,
         None => {
@@ -875,7 +1201,7 @@ async fn clear_goals(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Response
     let uname: &str = &body;
 
     {
-        let glob = glob.read().await;
+        let glob = glob.load();
         let data = glob.data();
         let data_reader = data.read().await;
         let mut client = match data_reader.connect().await {
@@ -895,7 +1221,10 @@ async fn clear_goals(body: Option<String>, glob: Arc<RwLock<Glob>>) -> Response
             }
         };
 
-        if let Err(e) = data_reader.delete_goals_by_student(&t, uname).await {
+        if let Err(e) = data_reader
+            .delete_goals_by_student(&t, uname, glob.academic_year())
+            .await
+        {
             log::error!("Error deleting goals for {:?}: {}", uname, &e);
             return text_500(Some(format!("Error deleting goals: {}", &e)));
         }
@@ -918,12 +1247,13 @@ Header:
 x-camp-action: upload-goals
 ```
 With the body being the CSV data in question. For the CSV format, see
-[`Pace::from_csv`].
+[`Pace::from_csv`]. Every Student must be the requesting teacher's, and every
+custom chapter (`*`) the teacher's own.
 */
 async fn upload_goals(
     headers: &HeaderMap,
     body: Option<String>,
-    glob: Arc<RwLock<Glob>>,
+    glob: Arc<GlobCell>,
 ) -> Response {
     let body = match body {
         Some(body) => body,
@@ -940,7 +1270,7 @@ async fn upload_goals(
     let mut others_students = String::new();
     let mut goals: Vec<Goal> = Vec::new();
     {
-        let glob = glob.read().await;
+        let glob = glob.load();
 
         let reader = Cursor::new(body);
         let mut pcals = match Pace::from_csv(reader, &glob) {
@@ -968,6 +1298,10 @@ async fn upload_goals(
             return (StatusCode::FORBIDDEN, estr).into_response();
         }
 
+        if let Err(e) = check_custom_sources(&goals, tuname, &glob) {
+            return e.into_response();
+        }
+
         match glob.insert_goals(&goals).await {
             Ok(n) => {
                 log::trace!("{} inserted {} goals.", tuname, &n);
@@ -985,7 +1319,7 @@ async fn upload_goals(
 async fn show_sidecar(
     headers: &HeaderMap,
     body: Option<String>,
-    glob: Arc<RwLock<Glob>>,
+    glob: Arc<GlobCell>,
 ) -> Response {
     let body = match body {
         Some(body) => body,
@@ -1001,7 +1335,7 @@ async fn show_sidecar(
         Err(e) => { return text_500(Some(e)); },
     };
 
-    let glob = glob.read().await;
+    let glob = glob.load();
 
     match glob.users.get(uname) {
         Some(User::Student(s)) => {
@@ -1019,6 +1353,11 @@ async fn show_sidecar(
         }
     }
 
+    sidecar_response(uname, &glob).await
+}
+
+/// Respond with the current year's [`ReportSidecar`] for the student `uname`.
+async fn sidecar_response(uname: &str, glob: &Glob) -> Response {
     let this_year = glob.academic_year();
 
     let data_guard = glob.data();
@@ -1049,7 +1388,7 @@ async fn show_sidecar(
 async fn update_sidecar(
     headers: &HeaderMap,
     body: Option<String>,
-    glob: Arc<RwLock<Glob>>,
+    glob: Arc<GlobCell>,
 ) -> Response {
     let body = match body {
         Some(body) => body,
@@ -1098,7 +1437,7 @@ async fn update_sidecar(
         },
     };
 
-    let glob = glob.read().await;
+    let glob = glob.load();
 
     match glob.users.get(&sidecar.uname) {
         Some(User::Student(s)) => {
@@ -1121,10 +1460,21 @@ async fn update_sidecar(
     let data_guard = glob.data();
     let data = data_guard.read().await;
 
-    if let Err(e) = data.set_report_sidecar(&sidecar, this_year).await {
-        log::error!("Error setting report sidecar: {}\ndata: {:?}", &e, &sidecar);
-        let estr = format!("Error saving report sidecar info: {}", &e);
-        return text_500(Some(estr));
+    match data.set_report_sidecar(&sidecar, this_year).await {
+        Ok(Versioned::Written(_)) => {}
+        Ok(Versioned::Conflict) => {
+            log::info!(
+                "Refusing update to {:?}'s sidecar from stale version {}.",
+                &sidecar.uname, &sidecar.version
+            );
+            drop(data);
+            return as_conflict(sidecar_response(&sidecar.uname, &glob).await);
+        }
+        Err(e) => {
+            log::error!("Error setting report sidecar: {}\ndata: {:?}", &e, &sidecar);
+            let estr = format!("Error saving report sidecar info: {}", &e);
+            return text_500(Some(estr));
+        }
     }
 
     let text = match report::generate_report_markup(&sidecar.uname, term, &glob).await {
@@ -1186,7 +1536,7 @@ async fn update_sidecar(
 async fn generate_report(
     headers: &HeaderMap,
     body: Option<String>,
-    glob: Arc<RwLock<Glob>>
+    glob: Arc<GlobCell>
 ) -> Response {
     let suname = match get_head("x-camp-student", headers) {
         Ok(uname) => uname,
@@ -1221,7 +1571,7 @@ async fn generate_report(
         }
     };
 
-    let glob = glob.read().await;
+    let glob = glob.load();
 
     match glob.users.get(suname) {
         Some(User::Student(s)) => {
@@ -1250,7 +1600,7 @@ async fn generate_report(
             Ok(trans) => trans,
             Err(e) => { return text_500(Some(e.to_string())); },
         };
-        if let Err(e) = Store::set_draft(&t, suname, term, &body).await {
+        if let Err(e) = Store::set_draft(&t, suname, glob.academic_year(), term, &body).await {
             log::error!(
                 "Error attempting to store {} report draft for {:?}: {}",
                 &term, &suname, &e
@@ -1294,7 +1644,7 @@ async fn generate_report(
             Ok(trans) => trans,
             Err(e) => { return text_500(Some(e.to_string())); },
         };
-        if let Err(e) = Store::set_final(&t, suname, term, &pdf_data).await {
+        if let Err(e) = Store::set_final(&t, suname, glob.academic_year(), term, &pdf_data).await {
             log::error!(
                 "Error attempting to store final {} report PDF for {:?}: {}",
                 &term, &suname, &e
@@ -1350,7 +1700,7 @@ async fn generate_report(
     ).into_response()
 }
 
-async fn discard_pdf(headers: &HeaderMap, glob: Arc<RwLock<Glob>>) -> Response {
+async fn discard_pdf(headers: &HeaderMap, glob: Arc<GlobCell>) -> Response {
     let suname = match get_head("x-camp-student", headers) {
         Ok(uname) => uname,
         Err(e) => { return respond_bad_request(e); },
@@ -1376,7 +1726,7 @@ async fn discard_pdf(headers: &HeaderMap, glob: Arc<RwLock<Glob>>) -> Response {
         },
     };
 
-    let glob = glob.read().await;
+    let glob = glob.load();
     match glob.users.get(suname) {
         Some(User::Student(s)) => {
             if s.teacher != tuname {
@@ -1393,7 +1743,7 @@ async fn discard_pdf(headers: &HeaderMap, glob: Arc<RwLock<Glob>>) -> Response {
         },
     }
 
-    match glob.data().read().await.clear_final(suname, term).await {
+    match glob.data().read().await.clear_final(suname, glob.academic_year(), term).await {
         Ok(()) => { return respond_ok(); },
         Err(e) => {
             log::error!(
@@ -1407,13 +1757,53 @@ async fn discard_pdf(headers: &HeaderMap, glob: Arc<RwLock<Glob>>) -> Response {
     }
 }
 
-async fn student_history(headers: &HeaderMap, glob: Arc<RwLock<Glob>>) -> Response {
+/**
+Respond to a request for one of the Teacher's own Students' finished
+report PDFs, from this or any earlier academic year.
+
+```text
+x-camp-action: download-report
+x-camp-student: [ uname of the Student ]
+x-camp-term: [ "fall", "spring", or "summer" ]
+x-camp-year: [ optional; starting year of the academic year in question ]
+```
+*/
+async fn download_student_report(headers: &HeaderMap, glob: Arc<GlobCell>) -> Response {
     let suname = match get_head("x-camp-student", headers) {
         Ok(uname) => uname,
         Err(e) => { return respond_bad_request(e); },
     };
+    let tuname = match get_head("x-camp-uname", headers) {
+        Ok(uname) => uname,
+        Err(e) => { return text_500(Some(e)); },
+    };
 
-    let hist = match glob.read().await.get_student_completion_history(suname).await {
+    match glob.load().users.get(suname) {
+        Some(User::Student(s)) => {
+            if s.teacher != tuname {
+                let estr = format!("The student {:?} is not yours.", &suname);
+                return (StatusCode::FORBIDDEN, estr).into_response();
+            }
+        },
+        _ => {
+            let estr = format!(
+                "The uname {:?} does not belong to a student in the system.",
+                &suname
+            );
+            return respond_bad_request(estr);
+        },
+    }
+
+    download_report(headers, glob).await
+}
+
+async fn student_history(headers: &HeaderMap, glob: Arc<GlobCell>) -> Response {
+    let suname = match get_head("x-camp-student", headers) {
+        Ok(uname) => uname,
+        Err(e) => { return respond_bad_request(e); },
+    };
+
+    let hist = match glob.load().get_student_completion_history(suname).await {
         Ok(hist) => hist,
         Err(e) => {
             log::error!(
@@ -1440,4 +1830,77 @@ async fn student_history(headers: &HeaderMap, glob: Arc<RwLock<Glob>>) -> Respon
         ],
         Json(&hist)
     ).into_response()
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::init_env;
+
+    fn custom_goal(uname: &str, custom: i64) -> String {
+        json!({
+            "id": 0,
+            "uname": uname,
+            "sym": null,
+            "seq": null,
+            "custom": custom,
+            "rev": false,
+            "inc": false,
+            "due": null,
+            "done": null,
+            "tries": null,
+            "weight": 0.0,
+            "score": null,
+        })
+        .to_string()
+    }
+
+    #[tokio::test]
+    async fn others_custom_chapters() {
+        let mut g = init_env().await.unwrap();
+        let mut customs: Vec<Custom> = Vec::new();
+        for tuname in ["bob", "sal"] {
+            let cust = g
+                .data()
+                .read()
+                .await
+                .insert_custom_chapter(&Custom {
+                    id: 0,
+                    uname: tuname.to_owned(),
+                    title: format!("{}'s Project", tuname),
+                    weight: 0.25,
+                })
+                .await
+                .unwrap();
+            customs.push(cust);
+        }
+        g.refresh_custom_chapters().await.unwrap();
+        let (bobs, sals) = (customs[0].id, customs[1].id);
+
+        let glob = Arc::new(GlobCell::new(g));
+        let mut headers = HeaderMap::new();
+        headers.insert("x-camp-uname", HeaderValue::from_static("bob"));
+
+        // Bob can't give his Students Goals with Sally's custom chapter...
+        let resp = insert_goal(&headers, Some(custom_goal("dval", sals)), glob.clone()).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let csv = format!("dval,msa2h,1,2022,9,1,,\n,*,{},,,2,,\n", sals);
+        let resp = upload_goals(&headers, Some(csv), glob.clone()).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let p = glob.load().get_pace_by_student("dval").await.unwrap();
+        assert!(p.goals.is_empty());
+
+        // ...only with his own.
+        let resp = insert_goal(&headers, Some(custom_goal("dval", bobs)), glob.clone()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let csv = format!("dval,msa2h,1,2022,9,1,,\n,*,{},,,2,,\n", bobs);
+        let resp = upload_goals(&headers, Some(csv), glob.clone()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let p = glob.load().get_pace_by_student("dval").await.unwrap();
+        assert_eq!(p.goals.len(), 3);
+
+        // So Sally can still delete hers.
+        let data = glob.load().data();
+        assert!(data.read().await.delete_custom_chapter(sals).await.is_ok());
+    }
+}
@@ -0,0 +1,276 @@
+/*!
+Routing requests to tenants (see [`crate::tenant`]), and the super-admin API
+for looking after them.
+*/
+use std::{net::SocketAddr, sync::Arc};
+
+use axum::{
+    extract::{ConnectInfo, Extension},
+    http::header::{self, HeaderMap, HeaderName, HeaderValue},
+    http::{Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+
+use super::*;
+use crate::{
+    archive,
+    auth::AuthResult,
+    migrate::State,
+    tenant::{Tenant, Tenants},
+};
+
+/**
+Middleware function to route each request to the tenant that serves its
+`Host`, by inserting that tenant's `Arc<GlobCell>` (and its
+`Arc<Tenant>`) into the request's extensions, where the handlers and other
+middleware expect to find them. The request is handled with that tenant's
+templates (see [`with_templates()`]).
+
+The [`Tenants`] themselves should be in an `Extension` layered outside this
+one.
+*/
+pub async fn route<B>(mut req: Request<B>, next: Next<B>) -> Response {
+    let tenants: &Arc<Tenants> = req.extensions().get().unwrap();
+
+    let host = match req.headers().get(header::HOST) {
+        Some(h_val) => h_val.to_str().unwrap_or_default(),
+        None => req.uri().host().unwrap_or_default(),
+    };
+
+    let tenant = match tenants.for_host(host) {
+        Some(t) => t,
+        None => {
+            log::warn!("Request for unrecognized host {:?}.", host);
+            return (
+                StatusCode::NOT_FOUND,
+                "No school is served at this address.".to_owned(),
+            )
+                .into_response();
+        }
+    };
+
+    let template_dir = tenant.glob.load().cfg().templates_dir.clone();
+    req.extensions_mut().insert(tenant.glob.clone());
+    req.extensions_mut().insert(tenant);
+    with_templates(template_dir, next.run(req)).await
+}
+
+/// Serve the requesting tenant's login page.
+pub async fn serve_index(Extension(tenant): Extension<Arc<Tenant>>) -> Response {
+    serve_static(StatusCode::OK, &tenant.index, vec![])
+}
+
+/// What the super-admin API's `list-tenants` reports about each tenant.
+#[derive(Debug, Serialize)]
+struct TenantInfo<'a> {
+    id: &'a str,
+    school: String,
+    hosts: &'a [String],
+    year: String,
+    users: usize,
+    courses: usize,
+    /// Migrations not yet applied to the auth and data databases.
+    pending_migrations: usize,
+}
+
+async fn list_tenants(tenants: &Tenants) -> Response {
+    let mut infos: Vec<TenantInfo> = Vec::new();
+    for t in tenants.iter() {
+        let glob = t.glob.load();
+        let mut pending = 0;
+        let auth_status = glob.auth().read().await.migration_status().await;
+        let data_status = glob.data().read().await.migration_status().await;
+        match (auth_status, data_status) {
+            (Ok(auth), Ok(data)) => {
+                pending += auth
+                    .iter()
+                    .chain(data.iter())
+                    .filter(|s| s.state == State::Pending)
+                    .count();
+            }
+            (Err(e), _) => {
+                log::error!("Error checking tenant {:?} auth DB: {}", &t.id, &e);
+                return text_500(Some(format!("Error checking tenant {:?}: {}", &t.id, &e)));
+            }
+            (_, Err(e)) => {
+                log::error!("Error checking tenant {:?} data DB: {}", &t.id, &e);
+                return text_500(Some(format!("Error checking tenant {:?}: {}", &t.id, &e)));
+            }
+        }
+
+        infos.push(TenantInfo {
+            id: &t.id,
+            school: glob.school_name.clone(),
+            hosts: &t.hosts,
+            year: glob.academic_year_string().to_string(),
+            users: glob.users.len(),
+            courses: glob.courses.len(),
+            pending_migrations: pending,
+        });
+    }
+
+    (
+        StatusCode::OK,
+        [(
+            HeaderName::from_static("x-camp-action"),
+            HeaderValue::from_static("list-tenants"),
+        )],
+        Json(infos),
+    )
+        .into_response()
+}
+
+async fn reload_tenant(tenant: &Tenant) -> Response {
+    if let Err(e) = tenant.reload().await {
+        log::error!("Error reloading tenant {:?}: {}", &tenant.id, &e);
+        return text_500(Some(format!("Error reloading tenant {:?}: {}", &tenant.id, &e)));
+    }
+
+    (
+        StatusCode::OK,
+        [(
+            HeaderName::from_static("x-camp-action"),
+            HeaderValue::from_static("reload-tenant"),
+        )],
+        format!("Tenant {:?} reloaded.", &tenant.id),
+    )
+        .into_response()
+}
+
+async fn export_tenant(tenant: &Tenant) -> Response {
+    let bytes = {
+        let glob = tenant.glob.load();
+        let auth = glob.auth();
+        let data = glob.data();
+        let auth = auth.read().await;
+        let data = data.read().await;
+        archive::export(&auth, &data).await
+    };
+    let bytes = match bytes {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            log::error!("Error exporting tenant {:?}: {}", &tenant.id, &e);
+            return text_500(Some(format!("Error exporting tenant {:?}: {}", &tenant.id, &e)));
+        }
+    };
+
+    let disposition = format!("attachment; filename=\"{}.zip\"", &tenant.id);
+    (
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, HeaderValue::from_static("application/zip")),
+            (
+                header::CONTENT_DISPOSITION,
+                HeaderValue::from_str(&disposition).unwrap(),
+            ),
+            (
+                HeaderName::from_static("x-camp-action"),
+                HeaderValue::from_static("export-tenant"),
+            ),
+        ],
+        bytes,
+    )
+        .into_response()
+}
+
+/**
+The super-admin API, for managing the tenants a deployment serves.
+
+Every request needs the `super_admin_key` from the tenants file in its
+`x-camp-key` header (wrong keys lock out their address like failed logins; see
+[`Tenants::check_super_admin_key`]), and one of these actions:
+
+```text
+x-camp-action: list-tenants
+x-camp-action: reload-tenant
+x-camp-action: export-tenant
+```
+
+`reload-tenant` and `export-tenant` also need the tenant's id in an
+`x-camp-tenant` header. `reload-tenant` rereads everything the tenant's
+`Glob` caches from its databases; `export-tenant` responds with an archive
+of both of them (see [`crate::archive`]).
+
+Each successful action is recorded in the audit log of the tenant it
+concerns (every tenant's, for `list-tenants`).
+*/
+pub async fn api(
+    ConnectInfo(sock): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Extension(tenants): Extension<Arc<Tenants>>,
+) -> Response {
+    let key = match get_head("x-camp-key", &headers) {
+        Ok(key) => key,
+        Err(e) => {
+            return respond_bad_request(e);
+        }
+    };
+    let addr = client_addr(&headers, &sock, tenants.trust_proxy_headers());
+    match tenants.check_super_admin_key(key, &addr).await {
+        AuthResult::Ok => {}
+        AuthResult::Locked => {
+            log::warn!(
+                "Super-admin request from {:?} refused: too many bad keys from there.",
+                &addr
+            );
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too many bad keys. Please wait a while before trying again.".to_owned(),
+            )
+                .into_response();
+        }
+        _ => {
+            log::warn!("Super-admin request with bad key from {:?}.", &addr);
+            return respond_bad_key();
+        }
+    }
+
+    let action = match get_head("x-camp-action", &headers) {
+        Ok(action) => action,
+        Err(e) => {
+            return respond_bad_request(e);
+        }
+    };
+    log::info!("Super-admin action {:?} from {:?}.", action, &addr);
+
+    if action == "list-tenants" {
+        let resp = list_tenants(&tenants).await;
+        for t in tenants.iter() {
+            Audit::super_admin(action, None)
+                .finish(resp.status(), &t.glob.load())
+                .await;
+        }
+        return resp;
+    }
+
+    let tenant = match get_head("x-camp-tenant", &headers) {
+        Ok(id) => match tenants.get(id) {
+            Some(t) => t,
+            None => {
+                return respond_bad_request(format!("There is no tenant {:?}.", id));
+            }
+        },
+        Err(e) => {
+            return respond_bad_request(e);
+        }
+    };
+
+    let resp = match action {
+        "reload-tenant" => reload_tenant(&tenant).await,
+        "export-tenant" => export_tenant(&tenant).await,
+        x => {
+            return respond_bad_request(format!(
+                "{:?} is not a recognizable x-camp-action value.",
+                x
+            ));
+        }
+    };
+    Audit::super_admin(action, Some(format!("tenant {}", &tenant.id)))
+        .finish(resp.status(), &tenant.glob.load())
+        .await;
+
+    resp
+}
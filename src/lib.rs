@@ -8,14 +8,23 @@ use serde::Serialize;
 use smallstr::SmallString;
 use time::{format_description::FormatItem, macros::format_description, Date};
 
+pub mod archive;
+pub mod audit;
 pub mod auth;
 pub mod config;
 pub mod course;
+pub mod db;
 pub mod hist;
 pub mod inter;
+pub mod migrate;
+pub mod notify;
 pub mod pace;
+pub mod perm;
+pub mod pool;
 pub mod report;
 pub mod store;
+pub mod tenant;
+pub mod totp;
 pub mod user;
 
 #[allow(clippy::upper_case_acronyms)]
@@ -46,15 +55,18 @@ static EPOCH: Lazy<Date> =
 /// thus simplifing error propagation with `?`.
 #[derive(Debug)]
 pub enum UnifiedError {
-    Postgres(tokio_postgres::error::Error),
+    Database(crate::db::Error),
     Auth(crate::auth::DbError),
     Data(crate::store::DbError),
     String(String),
+    /// The thing being changed has been changed by someone else since it
+    /// was read (see [`store::Versioned`]).
+    Conflict(String),
 }
 
-impl From<tokio_postgres::error::Error> for UnifiedError {
-    fn from(e: tokio_postgres::error::Error) -> Self {
-        Self::Postgres(e)
+impl From<crate::db::Error> for UnifiedError {
+    fn from(e: crate::db::Error) -> Self {
+        Self::Database(e)
     }
 }
 impl From<crate::auth::DbError> for UnifiedError {
@@ -76,10 +88,11 @@ impl From<String> for UnifiedError {
 impl Display for UnifiedError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
-            Self::Postgres(e) => write!(f, "Underlying database error: {}", e),
+            Self::Database(e) => write!(f, "Underlying database error: {}", e),
             Self::Auth(e) => write!(f, "Auth DB error: {}", e),
             Self::Data(e) => write!(f, "Data DB error: {}", e),
             Self::String(e) => write!(f, "Error: {}", e),
+            Self::Conflict(e) => write!(f, "Conflict: {}", e),
         }
     }
 }
@@ -274,6 +287,227 @@ pub fn academic_year_from_start_date(d: &Date) -> MiniString<SMALLSTORE> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::Glob;
+    use crate::course::Course;
+    use crate::user::{BaseUser, Role, User};
+
+    use std::fs::{read_to_string, File};
+
+    /**
+    Connection string for the auth DB used in tests: the value of the
+    `CAMP_TEST_AUTH_DB` environment variable if it's set (for example, to
+    `sqlite:/tmp/camp_auth_test.db`; see [`crate::db`]), otherwise the local
+    Postgres test database.
+    */
+    pub fn test_auth_db() -> String {
+        std::env::var("CAMP_TEST_AUTH_DB").unwrap_or_else(|_| {
+            "host=localhost user=camp_test password='camp_test' dbname=camp_auth_test".to_owned()
+        })
+    }
+
+    /// Connection string for the data DB used in tests; like
+    /// [`test_auth_db`], but read from `CAMP_TEST_DATA_DB`.
+    pub fn test_data_db() -> String {
+        std::env::var("CAMP_TEST_DATA_DB").unwrap_or_else(|_| {
+            "host=localhost user=camp_test password='camp_test' dbname=camp_store_test".to_owned()
+        })
+    }
+
+    /// Which pair of databases a test from [`backend_tests`] runs against.
+    #[derive(Clone, Copy, Debug)]
+    pub enum TestDbs {
+        /// Fresh in-memory SQLite databases ([`crate::db::MEMORY`]).
+        Memory,
+        /// [`test_auth_db`] and [`test_data_db`].
+        Configured,
+    }
+
+    impl TestDbs {
+        pub fn auth(self) -> String {
+            match self {
+                TestDbs::Memory => crate::db::MEMORY.to_owned(),
+                TestDbs::Configured => test_auth_db(),
+            }
+        }
+
+        pub fn data(self) -> String {
+            match self {
+                TestDbs::Memory => crate::db::MEMORY.to_owned(),
+                TestDbs::Configured => test_data_db(),
+            }
+        }
+    }
+
+    /**
+    Run each of the named tests, which take a [`TestDbs`] and return a
+    `Result<(), UnifiedError>`, against both backends: in a `memory` module
+    with fresh in-memory databases (in parallel with everything else), and
+    in a `configured` module with the configured test databases (serially,
+    as they're shared).
+
+    This is how the `Store` and `auth::Db` operations that are written
+    differently for each backend (locking, `RETURNING`, and so on) get
+    checked against both; see also [`crate::db`]'s conformance checks.
+    */
+    macro_rules! backend_tests {
+        ($($test:ident),* $(,)?) => {
+            mod memory {
+                $(
+                    #[tokio::test]
+                    async fn $test() -> Result<(), crate::UnifiedError> {
+                        super::$test(crate::tests::TestDbs::Memory).await
+                    }
+                )*
+            }
+
+            mod configured {
+                use serial_test::serial;
+
+                $(
+                    #[tokio::test]
+                    #[serial]
+                    async fn $test() -> Result<(), crate::UnifiedError> {
+                        super::$test(crate::tests::TestDbs::Configured).await
+                    }
+                )*
+            }
+        };
+    }
+    pub(crate) use backend_tests;
+
+    static COURSE_FILES: &[&str] = &[
+        "test/env/course_0.mix",
+        "test/env/course_1.mix",
+        "test/env/course_2.mix",
+        "test/env/course_3.mix",
+    ];
+
+    static BOSS: (&str, &str) = ("boss", "boss@camelthingy.com");
+    static TEACHERS: &[(&str, &str, &str)] = &[
+        ("bob", "Mr Bob", "bob@school.com"),
+        ("sal", "Ms Sally, not Sal Khan", "sally@school.com"),
+        ("yak", "Yakov Smirnoff", "yakov@school.com"),
+    ];
+    const STUDENT_FILE: &str = "test/env/students.csv";
+    pub const GOALS_FILE: &str = "test/env/goals.csv";
+    const DATES: &[(&str, &str)] = &[("end-fall", "2023-01-10"), ("end-spring", "2023-05-26")];
+
+    const CONFIG_FILE: &str = "test/env/config.toml";
+
+    /**
+    Load the test configuration (whose databases are in memory, so each
+    call gets a fresh pair) and fill it with the courses, staff, students,
+    and dates in `test/env/`. The Goals in [`GOALS_FILE`] are left for each
+    test to insert (or not) as it needs.
+    */
+    pub async fn init_env() -> Result<Glob, String> {
+        init_env_on(TestDbs::Memory).await
+    }
+
+    /// Like [`init_env`], but with the given databases.
+    pub async fn init_env_on(dbs: TestDbs) -> Result<Glob, String> {
+        ensure_logging();
+
+        let mut cfg = config::Cfg::from_file(CONFIG_FILE).unwrap();
+        cfg.auth_db_connect_string = dbs.auth();
+        cfg.data_db_connect_string = dbs.data();
+        let mut g = config::load_cfg(cfg).await.unwrap();
+
+        let courses: Vec<Course> = COURSE_FILES
+            .iter()
+            .map(|fname| File::open(fname).unwrap())
+            .map(|f| Course::from_reader(f).unwrap())
+            .collect();
+
+        let boss = BaseUser {
+            uname: BOSS.0.to_owned(),
+            role: Role::Boss,
+            salt: String::new(),
+            email: BOSS.1.to_owned(),
+        }
+        .into_boss();
+
+        let student_csv = read_to_string(STUDENT_FILE).unwrap();
+
+        let teachers: Vec<User> = TEACHERS
+            .iter()
+            .map(|(uname, name, email)| {
+                BaseUser {
+                    uname: uname.to_string(),
+                    role: Role::Teacher,
+                    salt: String::new(),
+                    email: email.to_string(),
+                }
+                .into_teacher(name.to_string())
+            })
+            .collect();
+
+        {
+            let data = g.data();
+            data.read().await.insert_courses(&courses).await?;
+        }
+
+        g.insert_user(&boss).await.unwrap();
+        for u in teachers.iter() {
+            g.insert_user(u).await.unwrap();
+        }
+        g.refresh_users().await.unwrap();
+        g.upload_students(&student_csv).await.unwrap();
+
+        {
+            let data_handle = g.data();
+            let data = data_handle.read().await;
+            for (date_name, date_val) in DATES.iter() {
+                data.set_date(date_name, &Date::parse(date_val, DATE_FMT).unwrap())
+                    .await
+                    .unwrap();
+            }
+        }
+        g.refresh_dates().await.unwrap();
+
+        g.refresh_courses().await.unwrap();
+        g.refresh_users().await.unwrap();
+
+        Ok(g)
+    }
+
+    /// Insert the Goals in [`GOALS_FILE`] into a `Glob` from [`init_env`].
+    pub async fn insert_test_goals(g: &Glob) -> Result<(), UnifiedError> {
+        let paces = pace::Pace::from_csv(File::open(GOALS_FILE).unwrap(), g)?;
+        for p in paces.iter() {
+            g.insert_goals(&p.goals).await?;
+        }
+        Ok(())
+    }
+
+    /// Clear out the databases of a `Glob` from [`init_env`].
+    pub async fn teardown_env(g: Glob) -> Result<(), String> {
+        use std::fmt::Write;
+
+        let mut err_msgs = String::new();
+
+        {
+            let data = g.data();
+            let dread = data.read().await;
+            if let Err(e) = dread.nuke_database().await {
+                log::error!("Error tearing down data DB: {}", &e);
+                writeln!(&mut err_msgs, "Data DB: {}", &e).unwrap();
+            }
+
+            let auth = g.auth();
+            let aread = auth.read().await;
+            if let Err(e) = aread.nuke_database().await {
+                log::error!("Error tearing down auth DB: {}", &e);
+                writeln!(&mut err_msgs, "Auth DB: {}", &e).unwrap();
+            }
+        }
+
+        if err_msgs.is_empty() {
+            Ok(())
+        } else {
+            Err(err_msgs)
+        }
+    }
 
     pub fn ensure_logging() {
         use simplelog::{ColorChoice, TermLogger, TerminalMode};
@@ -1,10 +1,11 @@
 /*!
 Here we go!
 */
-use std::sync::Arc;
+use std::{net::SocketAddr, path::PathBuf, sync::Arc};
 
 use axum::{
-    http::StatusCode,
+    extract::ConnectInfo,
+    http::{HeaderMap, StatusCode},
     middleware,
     response::{IntoResponse, Response},
     routing::{get, get_service, post},
@@ -12,13 +13,12 @@ use axum::{
 };
 use hyper::header::{HeaderName, HeaderValue};
 use simplelog::{ColorChoice, TermLogger, TerminalMode};
-use tokio::sync::RwLock;
 use tower_http::{
-    services::fs::{ServeDir, ServeFile},
+    services::fs::ServeDir,
     set_header::response::SetResponseHeaderLayer,
 };
 
-use camp::{config, config::Glob, inter, user::User};
+use camp::{archive, config, config::GlobCell, inter, migrate, notify, tenant, user::User};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
@@ -30,26 +30,216 @@ async fn catchall_error_handler(e: std::io::Error) -> impl IntoResponse {
 }
 
 async fn handle_login(
+    ConnectInfo(sock): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     Form(form): Form<inter::LoginData>,
-    Extension(glob): Extension<Arc<RwLock<Glob>>>,
+    Extension(glob): Extension<Arc<GlobCell>>,
 ) -> Response {
     log::trace!("handle_login( {:?}, [ global state ]) called.", &form);
 
+    let client = inter::client_info(&headers, &sock, glob.load().trust_proxy_headers);
+
     let user = {
-        let glob = glob.read().await;
+        let glob = glob.load();
         match glob.users.get(&form.uname) {
             Some(u) => u.clone(),
             None => {
+                // Guessing at unames still counts against the client address.
+                let res = glob
+                    .auth()
+                    .read()
+                    .await
+                    .record_failed_login(&form.uname, &client.addr)
+                    .await;
+                if let Err(e) = res {
+                    log::error!(
+                        "Error recording failed login for {:?} from {:?}: {}",
+                        &form.uname,
+                        &client.addr,
+                        &e
+                    );
+                }
                 return inter::respond_bad_password(&form.uname);
             }
         }
     };
 
     match user {
-        User::Admin(a) => inter::admin::login(a, form, glob.clone()).await,
-        User::Boss(b) => inter::boss::login(b, form, glob.clone()).await,
-        User::Teacher(t) => inter::teacher::login(t, form, glob.clone()).await,
-        User::Student(s) => inter::student::login(s, form, glob.clone()).await,
+        User::Admin(a) => inter::admin::login(a, form, &client, glob.clone()).await,
+        User::Boss(b) => inter::boss::login(b, form, &client, glob.clone()).await,
+        User::Teacher(t) => inter::teacher::login(t, form, &client, glob.clone()).await,
+        User::Student(s) => inter::student::login(s, form, &client, glob.clone()).await,
+        User::Parent(p) => inter::student::parent_login(p, form, &client, glob.clone()).await,
+    }
+}
+
+async fn handle_totp(
+    ConnectInfo(sock): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Form(form): Form<inter::TotpData>,
+    Extension(glob): Extension<Arc<GlobCell>>,
+) -> Response {
+    log::trace!("handle_totp( {:?}, [ global state ]) called.", &form);
+
+    let client = inter::client_info(&headers, &sock, glob.load().trust_proxy_headers);
+
+    let user = glob.load().users.get(&form.uname).cloned();
+
+    // Only Admins and Bosses ever get sent to the second step.
+    match user {
+        Some(User::Admin(a)) => inter::admin::totp_login(a, form, &client, glob.clone()).await,
+        Some(User::Boss(b)) => inter::boss::totp_login(b, form, &client, glob.clone()).await,
+        _ => inter::respond_bad_password(&form.uname),
+    }
+}
+
+/// Print the state of each migration in `statuses` (of the database `which`).
+fn print_migration_status(which: &str, statuses: &[migrate::Status]) {
+    println!("{} DB:", which);
+    for s in statuses.iter() {
+        let line = format!(
+            "    {:04} {:<24} {:<8} {}",
+            &s.version,
+            &s.name,
+            &s.state,
+            s.applied.as_deref().unwrap_or("")
+        );
+        println!("{}", line.trim_end());
+    }
+}
+
+/**
+Handle the `migrate` subcommand:
+
+```text
+camp migrate status [ config.toml ]
+camp migrate dry-run [ config.toml ]
+```
+
+`status` shows the state of each migration of each database; `dry-run`
+applies any pending migrations and then rolls them back, to show that
+they'll work. Given a tenants file (see [`camp::tenant`]) instead of a
+configuration file, it does this for each tenant's databases in turn.
+*/
+async fn migrate_command(args: &[String]) -> Result<(), String> {
+    let usage = "usage: camp migrate ( status | dry-run ) [ config.toml ]";
+    let dry_run = match args.first().map(String::as_str) {
+        Some("status") => false,
+        Some("dry-run") => true,
+        _ => {
+            return Err(usage.to_owned());
+        }
+    };
+    let config_path = args.get(1).map(String::as_str).unwrap_or("config.toml");
+
+    for (tenant_id, path) in tenant::config_files(config_path)?.iter() {
+        if let Some(id) = tenant_id {
+            println!("tenant {}:", id);
+        }
+        let cfg = match tenant_id {
+            Some(id) => config::Cfg::for_tenant(path, id)?,
+            None => config::Cfg::from_file(path)?,
+        };
+        let auth_db = cfg.auth_db();
+        let data_db = cfg.data_db();
+
+        if !dry_run {
+            print_migration_status("auth", &auth_db.migration_status().await?);
+            print_migration_status("data", &data_db.migration_status().await?);
+            continue;
+        }
+
+        for (which, applied) in [
+            ("auth", auth_db.migrate(true).await?),
+            ("data", data_db.migrate(true).await?),
+        ] {
+            if applied.is_empty() {
+                println!("{} DB: no pending migrations", which);
+            } else {
+                println!(
+                    "{} DB: migrations {:?} would apply successfully",
+                    which, &applied
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/**
+Handle the `export` and `import` subcommands:
+
+```text
+camp export archive.zip [ config.toml ]
+camp import archive.zip [ config.toml ]
+```
+
+`export` writes the contents of both databases to a new archive; `import`
+restores them from one, into empty databases. (See [`camp::archive`].)
+*/
+async fn archive_command(cmd: &str, args: &[String]) -> Result<(), String> {
+    let usage = format!("usage: camp {} archive.zip [ config.toml ]", cmd);
+    let archive_path = args.first().ok_or(usage)?;
+    let config_path = args.get(1).map(String::as_str).unwrap_or("config.toml");
+    if tenant::is_tenants_file(config_path)? {
+        return Err(format!(
+            "{} is a tenants file; give the configuration file of the tenant to {}.",
+            config_path, cmd
+        ));
+    }
+    let cfg = config::Cfg::from_file(config_path)?;
+    let auth_db = cfg.auth_db();
+    let data_db = cfg.data_db();
+
+    if cmd == "export" {
+        let bytes = archive::export(&auth_db, &data_db)
+            .await
+            .map_err(|e| e.to_string())?;
+        std::fs::write(archive_path, &bytes)
+            .map_err(|e| format!("Error writing {:?}: {}", archive_path, &e))?;
+        println!("wrote {} ({} bytes)", archive_path, bytes.len());
+    } else {
+        let bytes = std::fs::read(archive_path)
+            .map_err(|e| format!("Error reading {:?}: {}", archive_path, &e))?;
+        let manifest = archive::import(&auth_db, &data_db, &bytes)
+            .await
+            .map_err(|e| e.to_string())?;
+        for (which, entry) in [("auth", &manifest.auth), ("data", &manifest.data)] {
+            let rows: usize = entry.tables.iter().map(|t| t.rows).sum();
+            println!(
+                "{} DB: restored {} rows in {} tables",
+                which,
+                rows,
+                entry.tables.len()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/**
+Reread the configuration files and templates (see
+[`Tenants::reconfigure`](tenant::Tenants::reconfigure)) every time the
+process gets a `SIGHUP`.
+*/
+#[cfg(unix)]
+async fn reconfigure_on_hangup(tenants: Arc<tenant::Tenants>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            log::error!("Unable to listen for SIGHUP: {}", &e);
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        log::info!("SIGHUP received; reloading configuration and templates.");
+        if let Err(e) = tenants.reconfigure(None).await {
+            log::error!("Configuration not reloaded: {}", &e);
+        }
     }
 }
 
@@ -70,39 +260,71 @@ async fn main() {
 
     let args: Vec<String> = std::env::args().collect();
 
+    if args.get(1).map(String::as_str) == Some("migrate") {
+        if let Err(e) = migrate_command(&args[2..]).await {
+            eprintln!("{}", &e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(cmd @ ("export" | "import")) = args.get(1).map(String::as_str) {
+        if let Err(e) = archive_command(cmd, &args[2..]).await {
+            eprintln!("{}", &e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let config_path = match args.get(1) {
         Some(path) => path.as_str(),
         None => "config.toml",
     };
-    let glob = config::load_configuration(config_path).await.unwrap();
-
-    let glob = Arc::new(RwLock::new(glob));
-
-    let serve_root =
-        get_service(ServeFile::new("data/index.html")).handle_error(catchall_error_handler);
+    let tenants = Arc::new(tenant::load(config_path).await.unwrap());
+    for t in tenants.iter() {
+        tokio::spawn(notify::keep_in_step(t.glob.clone(), format!("tenant {:?}", &t.id)));
+    }
+    #[cfg(unix)]
+    tokio::spawn(reconfigure_on_hangup(tenants.clone()));
+    let mut dev_templates: Vec<PathBuf> = tenants
+        .iter()
+        .map(|t| t.glob.load())
+        .filter(|glob| glob.cfg().dev_mode)
+        .map(|glob| glob.cfg().templates_dir.clone())
+        .collect();
+    dev_templates.sort();
+    dev_templates.dedup();
+    for dir in dev_templates {
+        tokio::spawn(inter::watch_templates(dir));
+    }
 
     let serve_static = get_service(ServeDir::new("static")).handle_error(catchall_error_handler);
 
-    let addr = glob.read().await.addr;
+    let addr = tenants.addr;
     let app = Router::new()
         .route("/boss", post(inter::boss::api))
         .route("/admin", post(inter::admin::api))
         .route("/teacher", post(inter::teacher::api))
+        .layer(middleware::from_fn(inter::authorize))
         .layer(middleware::from_fn(inter::key_authenticate))
         .layer(middleware::from_fn(inter::request_identity))
         .route("/pwd", get(inter::password_reset))
+        .route("/invite", get(inter::invite_page).post(inter::accept_invite))
         .route("/login", post(handle_login))
-        .layer(Extension(glob.clone()))
+        .route("/login/totp", post(handle_totp))
+        .route("/view-as", post(inter::view_as))
+        .route("/", get(inter::tenant::serve_index))
+        .layer(middleware::from_fn(inter::tenant::route))
+        .route("/super", post(inter::tenant::api))
+        .layer(Extension(tenants))
         .nest("/static", serve_static)
         //.layer(middleware::from_fn(inter::log_request))
-        .route("/", serve_root)
         .layer(SetResponseHeaderLayer::appending(
             HeaderName::from_static("x-camp-version"),
             HeaderValue::from_static(VERSION)
         ));
 
     axum::Server::bind(&addr)
-        .serve(app.into_make_service())
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .unwrap();
 }
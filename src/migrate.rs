@@ -0,0 +1,373 @@
+/*!
+Versioned schema migrations for the auth and data databases.
+
+Each database's schema is defined by an ordered list of SQL files (under
+`migrations/auth/` and `migrations/data/`, compiled into the binary), each
+numbered in its file name. The SQLite backend (see [`crate::db`]) has its
+own equivalent list under `migrations/sqlite/`; the two should always have
+the same versions. A `schema_version` table in each database
+records which migrations have been applied, along with a checksum of each
+file as it was when applied.
+
+Applying migrations (which happens at startup; see
+[`auth::Db::ensure_db_schema`](crate::auth::Db::ensure_db_schema) and
+[`Store::ensure_db_schema`](crate::store::Store::ensure_db_schema)) runs
+every pending migration, in order, in a single transaction. It refuses to
+run at all if an applied migration's file has since been changed, or if the
+database has had migrations applied that this version doesn't know about.
+
+Migrations should never be edited once they've been deployed; to change the
+schema, add a new file (and a new entry to the appropriate list below).
+
+`camp migrate status` shows the state of each migration, and
+`camp migrate dry-run` applies any pending ones and then rolls them back.
+*/
+use std::fmt::{Display, Formatter};
+
+use serde::Serialize;
+
+use crate::db::{Dialect, Transaction};
+
+/// A single schema change.
+#[derive(Debug)]
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+impl Migration {
+    /// Hex-encoded hash of the migration's SQL.
+    pub fn checksum(&self) -> String {
+        blake3::hash(self.sql.as_bytes()).to_hex().to_string()
+    }
+}
+
+/// Migrations of the auth database, in order.
+pub static AUTH_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial",
+        sql: include_str!("../migrations/auth/0001_initial.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "grant notifications",
+        sql: include_str!("../migrations/auth/0002_grant_notifications.sql"),
+    },
+];
+
+/// Migrations of the data database, in order.
+pub static DATA_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial",
+        sql: include_str!("../migrations/data/0001_initial.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "academic years",
+        sql: include_str!("../migrations/data/0002_academic_years.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "row versions",
+        sql: include_str!("../migrations/data/0003_row_versions.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "cache notifications",
+        sql: include_str!("../migrations/data/0004_cache_notifications.sql"),
+    },
+];
+
+/// Migrations of an SQLite auth database, in order.
+pub static SQLITE_AUTH_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial",
+        sql: include_str!("../migrations/sqlite/auth/0001_initial.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "grant notifications",
+        sql: include_str!("../migrations/sqlite/auth/0002_grant_notifications.sql"),
+    },
+];
+
+/// Migrations of an SQLite data database, in order.
+pub static SQLITE_DATA_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial",
+        sql: include_str!("../migrations/sqlite/data/0001_initial.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "academic years",
+        sql: include_str!("../migrations/sqlite/data/0002_academic_years.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "row versions",
+        sql: include_str!("../migrations/sqlite/data/0003_row_versions.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "cache notifications",
+        sql: include_str!("../migrations/sqlite/data/0004_cache_notifications.sql"),
+    },
+];
+
+/// The migrations of an auth database that speaks `dialect`.
+pub fn auth_migrations(dialect: Dialect) -> &'static [Migration] {
+    match dialect {
+        Dialect::Postgres => AUTH_MIGRATIONS,
+        Dialect::Sqlite => SQLITE_AUTH_MIGRATIONS,
+    }
+}
+
+/// The migrations of a data database that speaks `dialect`.
+pub fn data_migrations(dialect: Dialect) -> &'static [Migration] {
+    match dialect {
+        Dialect::Postgres => DATA_MIGRATIONS,
+        Dialect::Sqlite => SQLITE_DATA_MIGRATIONS,
+    }
+}
+
+const SCHEMA_VERSION_TABLE: &str = "CREATE TABLE IF NOT EXISTS schema_version (
+    version  INT PRIMARY KEY,
+    name     TEXT NOT NULL,
+    checksum TEXT NOT NULL,
+    applied  TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+)";
+
+/// Held for the duration of a migration transaction, so that two server
+/// processes starting at once don't both try to apply the same migrations.
+const LOCK_ID: i64 = 0x63616d70; // "camp"
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum State {
+    Applied,
+    Pending,
+    /// Applied, but the migration's SQL has changed since.
+    Modified,
+    /// Recorded as applied, but not a migration this version knows about.
+    Unknown,
+}
+
+impl Display for State {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let s = match self {
+            State::Applied => "applied",
+            State::Pending => "pending",
+            State::Modified => "MODIFIED",
+            State::Unknown => "UNKNOWN",
+        };
+        f.pad(s)
+    }
+}
+
+/// The state of a single migration in a particular database.
+#[derive(Debug, Serialize)]
+pub struct Status {
+    pub version: i32,
+    pub name: String,
+    pub state: State,
+    /// When it was applied ("YYYY-MM-DD HH:MM:SS"), if it has been.
+    pub applied: Option<String>,
+}
+
+/// Ensure the `migrations` are listed in strictly increasing order of
+/// version.
+fn check_order(migrations: &[Migration]) -> Result<(), String> {
+    for pair in migrations.windows(2) {
+        if pair[0].version >= pair[1].version {
+            return Err(format!(
+                "Migration {} ({:?}) is listed before migration {} ({:?}).",
+                pair[0].version, pair[0].name, pair[1].version, pair[1].name
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Report the state of each of the `migrations` (and of any unknown
+/// migrations recorded as applied) in the database of transaction `t`.
+pub async fn status(t: &Transaction<'_>, migrations: &[Migration]) -> Result<Vec<Status>, String> {
+    check_order(migrations)?;
+
+    let exists = t
+        .table_exists("schema_version")
+        .await
+        .map_err(|e| format!("Error checking for schema_version table: {}", &e))?;
+
+    let mut applied: Vec<(i32, String, String, String)> = Vec::new();
+    if exists {
+        let rows = t
+            .query(
+                &format!(
+                    "SELECT version, name, checksum, {} AS applied
+                    FROM schema_version ORDER BY version",
+                    t.dialect().timestamp_text("applied")
+                ),
+                &[],
+            )
+            .await
+            .map_err(|e| format!("Error reading schema_version table: {}", &e))?;
+        for row in rows.iter() {
+            applied.push((
+                row.try_get("version").map_err(|e| e.to_string())?,
+                row.try_get("name").map_err(|e| e.to_string())?,
+                row.try_get("checksum").map_err(|e| e.to_string())?,
+                row.try_get("applied").map_err(|e| e.to_string())?,
+            ));
+        }
+    }
+
+    let mut statuses: Vec<Status> = Vec::with_capacity(migrations.len());
+    for m in migrations.iter() {
+        let stat = match applied.iter().find(|a| a.0 == m.version) {
+            None => Status {
+                version: m.version,
+                name: m.name.to_owned(),
+                state: State::Pending,
+                applied: None,
+            },
+            Some((_, _, checksum, at)) => Status {
+                version: m.version,
+                name: m.name.to_owned(),
+                state: if *checksum == m.checksum() {
+                    State::Applied
+                } else {
+                    State::Modified
+                },
+                applied: Some(at.clone()),
+            },
+        };
+        statuses.push(stat);
+    }
+    for (version, name, _, at) in applied.into_iter() {
+        if !migrations.iter().any(|m| m.version == version) {
+            statuses.push(Status {
+                version,
+                name,
+                state: State::Unknown,
+                applied: Some(at),
+            });
+        }
+    }
+    statuses.sort_by_key(|s| s.version);
+
+    Ok(statuses)
+}
+
+/**
+Apply any pending `migrations`, in order, in transaction `t`. Returns the
+versions applied.
+
+Committing (or not) `t` is up to the caller.
+*/
+pub async fn run(t: &Transaction<'_>, migrations: &[Migration]) -> Result<Vec<i32>, String> {
+    t.lock_id(LOCK_ID)
+        .await
+        .map_err(|e| format!("Unable to acquire migration lock: {}", &e))?;
+    t.execute(SCHEMA_VERSION_TABLE, &[])
+        .await
+        .map_err(|e| format!("Unable to create schema_version table: {}", &e))?;
+
+    let statuses = status(t, migrations).await?;
+    if let Some(s) = statuses.iter().find(|s| s.state == State::Modified) {
+        return Err(format!(
+            "Migration {} ({:?}) has been changed since it was applied.",
+            s.version, &s.name
+        ));
+    }
+    if let Some(s) = statuses.iter().find(|s| s.state == State::Unknown) {
+        return Err(format!(
+            "Database has migration {} ({:?}) applied, which this version doesn't know about.",
+            s.version, &s.name
+        ));
+    }
+
+    let mut applied_versions: Vec<i32> = Vec::new();
+    for m in migrations.iter() {
+        let pending = statuses
+            .iter()
+            .any(|s| s.version == m.version && s.state == State::Pending);
+        if !pending {
+            continue;
+        }
+
+        log::info!("Applying migration {} ({:?}).", m.version, m.name);
+        t.batch_execute(m.sql).await.map_err(|e| {
+            format!(
+                "Error applying migration {} ({:?}): {}",
+                m.version, m.name, &e
+            )
+        })?;
+        t.execute(
+            "INSERT INTO schema_version (version, name, checksum) VALUES ($1, $2, $3)",
+            &[&m.version, &m.name, &m.checksum()],
+        )
+        .await
+        .map_err(|e| format!("Error recording migration {}: {}", m.version, &e))?;
+        applied_versions.push(m.version);
+    }
+
+    Ok(applied_versions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migration_lists() {
+        for migrations in [
+            AUTH_MIGRATIONS,
+            DATA_MIGRATIONS,
+            SQLITE_AUTH_MIGRATIONS,
+            SQLITE_DATA_MIGRATIONS,
+        ] {
+            check_order(migrations).unwrap();
+            assert_eq!(migrations.first().map(|m| m.version), Some(1));
+        }
+
+        // Each dialect has the same versions.
+        let versions = |ms: &[Migration]| ms.iter().map(|m| m.version).collect::<Vec<_>>();
+        assert_eq!(versions(AUTH_MIGRATIONS), versions(SQLITE_AUTH_MIGRATIONS));
+        assert_eq!(versions(DATA_MIGRATIONS), versions(SQLITE_DATA_MIGRATIONS));
+
+        let out_of_order = [
+            Migration {
+                version: 2,
+                name: "second",
+                sql: "",
+            },
+            Migration {
+                version: 1,
+                name: "first",
+                sql: "",
+            },
+        ];
+        assert!(check_order(&out_of_order).is_err());
+    }
+
+    #[test]
+    fn checksums() {
+        let m = Migration {
+            version: 1,
+            name: "first",
+            sql: "CREATE TABLE foo ( bar TEXT );",
+        };
+        let n = Migration {
+            version: 1,
+            name: "first",
+            sql: "CREATE TABLE foo ( bar INT );",
+        };
+        assert_eq!(m.checksum(), m.checksum());
+        assert_ne!(m.checksum(), n.checksum());
+    }
+}
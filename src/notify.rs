@@ -0,0 +1,324 @@
+/*!
+Keeping the data each [`Glob`] caches in step with the data and auth DBs
+when more than one server process is using them.
+
+A `Glob` keeps the users, courses, custom chapters, calendar, and special
+dates (from the data DB) and the permissions granted to individual users
+(from the auth DB) in memory, and the process that changes any of them
+rereads them itself. Other processes sharing the same database (several instances
+behind a load balancer, say) would otherwise keep serving their old copies
+until restarted.
+
+So in Postgres, every change to the tables these come from sends a
+notification on the [`CHANNEL`] channel (this is done by triggers; see
+`migrations/data/0004_cache_notifications.sql` and
+`migrations/auth/0002_grant_notifications.sql`), and each process runs
+[`keep_in_step`] for each of its `Glob`s, which listens on that channel in
+both databases and rereads whatever has changed. In case a notification goes missing (they
+aren't delivered while the listening connection is down, for example),
+everything is also reread every so often (see
+[`ConfigFile::cache_resync_seconds`](crate::config::ConfigFile::cache_resync_seconds)),
+and whenever the listening connection has to be reestablished.
+
+SQLite doesn't do notifications, but an SQLite database is only ever used by
+one process, so there's nothing to keep in step.
+*/
+use std::{collections::BTreeSet, sync::Arc, time::Duration};
+
+use serde::Deserialize;
+use tokio::time::{sleep, sleep_until, timeout, Instant};
+
+use crate::{
+    config::{Glob, GlobCell},
+    db::Listener,
+};
+
+/// The channel on which changes to cached tables are announced.
+pub const CHANNEL: &str = "camp_cache";
+
+/// Changes are often made several statements at a time; after a
+/// notification, wait this long for more before rereading anything, so
+/// it only has to be done once.
+const COALESCE: Duration = Duration::from_millis(200);
+/// How long to wait before trying to listen again after failing to, at
+/// first...
+const MIN_RETRY: Duration = Duration::from_secs(1);
+/// ...doubling each time up to this.
+const MAX_RETRY: Duration = Duration::from_secs(60);
+
+/// The parts of a [`Glob`] that can be reread separately.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Cached {
+    Users,
+    Courses,
+    Custom,
+    Calendar,
+    Dates,
+    Grants,
+}
+
+impl Cached {
+    /// Which part of the `Glob` comes from `table`, if any.
+    pub fn from_table(table: &str) -> Option<Cached> {
+        match table {
+            "users" | "teachers" | "students" | "parents" => Some(Cached::Users),
+            "courses" | "chapters" => Some(Cached::Courses),
+            "custom_chapters" => Some(Cached::Custom),
+            "calendar" => Some(Cached::Calendar),
+            "dates" => Some(Cached::Dates),
+            "grants" => Some(Cached::Grants),
+            _ => None,
+        }
+    }
+
+    async fn refresh(self, glob: &mut Glob) -> Result<(), String> {
+        match self {
+            Cached::Users => glob.refresh_users().await,
+            Cached::Courses => glob.refresh_courses().await,
+            Cached::Custom => glob.refresh_custom_chapters().await,
+            Cached::Calendar => glob.refresh_calendar().await,
+            Cached::Dates => glob.refresh_dates().await,
+            Cached::Grants => glob.refresh_grants().await,
+        }
+    }
+}
+
+/// The databases whose changes a [`Glob`] follows.
+#[derive(Clone, Copy, Debug)]
+enum Source {
+    Data,
+    Auth,
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::Data => write!(f, "data DB"),
+            Source::Auth => write!(f, "auth DB"),
+        }
+    }
+}
+
+/// The payload of a notification sent on [`CHANNEL`].
+#[derive(Debug, Deserialize)]
+struct Notice {
+    schema: String,
+    table: String,
+    /// `application_name` of the connection that made the change.
+    from: String,
+}
+
+/**
+What, if anything, the notification with the given `payload` received by
+`listener` means needs rereading.
+
+Changes to another schema's tables (another tenant's, say; see
+[`crate::tenant`]) and changes made by this process (which will have
+already reread whatever it changed) don't need anything.
+*/
+fn needs_refresh(payload: &str, listener: &Listener) -> Option<Cached> {
+    let notice: Notice = match serde_json::from_str(payload) {
+        Ok(notice) => notice,
+        Err(e) => {
+            log::warn!("Unrecognizable notification {:?}: {}", payload, &e);
+            return None;
+        }
+    };
+
+    if notice.schema != listener.schema() || notice.from == listener.application_name() {
+        return None;
+    }
+    Cached::from_table(&notice.table)
+}
+
+/// Reread everything `glob` caches, logging any error.
+async fn resync(glob: &GlobCell, label: &str) {
+    log::debug!("Resyncing {} cache.", label);
+    if let Err(e) = glob.write().await.refresh_all().await {
+        log::error!("Error resyncing {} cache: {}", label, &e);
+    }
+}
+
+/// Reread the `stale` parts of `glob`, logging any errors.
+async fn reread(glob: &GlobCell, label: &str, source: Source, stale: BTreeSet<Cached>) {
+    log::debug!("Rereading {:?} after changes to {} {}.", &stale, label, source);
+    let mut glob = glob.write().await;
+    for c in stale.into_iter() {
+        if let Err(e) = c.refresh(&mut glob).await {
+            log::error!("Error rereading {} {:?}: {}", label, c, &e);
+        }
+    }
+}
+
+/**
+Listen for changes to the `source` DB of `glob` made by other processes,
+and reread the parts of it they affect, until the listening connection is
+lost (when it returns `Ok(true)`).
+
+Returns `Ok(false)` right away if the DB doesn't do notifications.
+
+Only the data DB's follower does the periodic resync, since that rereads
+everything (grants included) anyway.
+*/
+async fn follow(
+    glob: &GlobCell,
+    label: &str,
+    source: Source,
+    reconnecting: bool,
+) -> Result<bool, String> {
+    let (listener, resync_every) = {
+        let glob = glob.load();
+        match source {
+            Source::Data => (
+                glob.data().read().await.listen(CHANNEL).await?,
+                glob.cache_resync,
+            ),
+            Source::Auth => (glob.auth().read().await.listen(CHANNEL).await?, None),
+        }
+    };
+    let mut listener = match listener {
+        Some(listener) => listener,
+        None => {
+            log::info!(
+                "{} {} doesn't do notifications; not listening for changes.",
+                label,
+                source
+            );
+            return Ok(false);
+        }
+    };
+    log::info!("Listening for changes to {} {}.", label, source);
+
+    // Anything at all might have changed while we weren't listening.
+    if reconnecting {
+        match source {
+            Source::Data => resync(glob, label).await,
+            Source::Auth => reread(glob, label, source, BTreeSet::from([Cached::Grants])).await,
+        }
+    }
+
+    let mut next_resync = resync_every.map(|d| Instant::now() + d);
+    loop {
+        tokio::select! {
+            payload = listener.recv() => {
+                let payload = match payload {
+                    Some(payload) => payload,
+                    None => { return Ok(true); }
+                };
+
+                let mut stale: BTreeSet<Cached> = BTreeSet::new();
+                stale.extend(needs_refresh(&payload, &listener));
+                while let Ok(Some(payload)) = timeout(COALESCE, listener.recv()).await {
+                    stale.extend(needs_refresh(&payload, &listener));
+                }
+                if stale.is_empty() {
+                    continue;
+                }
+
+                reread(glob, label, source, stale).await;
+            }
+            _ = sleep_until(next_resync.unwrap_or_else(Instant::now)), if next_resync.is_some() => {
+                resync(glob, label).await;
+                next_resync = resync_every.map(|d| Instant::now() + d);
+            }
+        }
+    }
+}
+
+/// Run [`follow`] for the `source` DB of `glob`, listening again whenever
+/// the connection is lost, until it turns out not to do notifications.
+async fn keep_following(glob: &GlobCell, label: &str, source: Source) {
+    let mut retry = MIN_RETRY;
+    let mut reconnecting = false;
+    loop {
+        match follow(glob, label, source, reconnecting).await {
+            Ok(false) => {
+                return;
+            }
+            Ok(true) => {
+                log::warn!("Lost connection listening for changes to {} {}.", label, source);
+                retry = MIN_RETRY;
+            }
+            Err(e) => {
+                log::error!("Unable to listen for changes to {} {}: {}", label, source, &e);
+            }
+        }
+        reconnecting = true;
+        sleep(retry).await;
+        retry = (retry * 2).min(MAX_RETRY);
+    }
+}
+
+/**
+Keep what `glob` caches up to date with changes made to its data and auth
+DBs by other processes (see the [module-level documentation](self)).
+
+This runs until the process ends (unless neither DB is one that does
+notifications, in which case it returns right away), so it should be
+`tokio::spawn`ed. `label` identifies `glob` in log messages.
+*/
+pub async fn keep_in_step(glob: Arc<GlobCell>, label: String) {
+    tokio::join!(
+        keep_following(&glob, &label, Source::Data),
+        keep_following(&glob, &label, Source::Auth),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        store::Store,
+        tests::{ensure_logging, test_data_db},
+    };
+
+    use serial_test::serial;
+
+    #[test]
+    fn tables() {
+        assert_eq!(Cached::from_table("students"), Some(Cached::Users));
+        assert_eq!(Cached::from_table("chapters"), Some(Cached::Courses));
+        assert_eq!(Cached::from_table("custom_chapters"), Some(Cached::Custom));
+        assert_eq!(Cached::from_table("grants"), Some(Cached::Grants));
+        assert_eq!(Cached::from_table("goals"), None);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn changes_are_announced() {
+        ensure_logging();
+
+        let db = Store::new(test_data_db());
+        db.ensure_db_schema().await.unwrap();
+        let mut listener = match db.listen(CHANNEL).await.unwrap() {
+            Some(listener) => listener,
+            // No notifications to test.
+            None => {
+                db.nuke_database().await.unwrap();
+                return;
+            }
+        };
+
+        db.set_date("end-fall", &time::macros::date!(2023 - 01 - 10))
+            .await
+            .unwrap();
+        let payload = timeout(Duration::from_secs(5), listener.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let notice: Notice = serde_json::from_str(&payload).unwrap();
+        assert_eq!(Cached::from_table(&notice.table), Some(Cached::Dates));
+        assert_eq!(notice.schema, listener.schema());
+
+        // It was this process that made the change, so nothing needs
+        // rereading.
+        assert_eq!(notice.from, listener.application_name());
+        assert_eq!(needs_refresh(&payload, &listener), None);
+
+        // But the same change from anywhere else would.
+        let elsewhere = payload.replace(listener.application_name(), "camp elsewhere");
+        assert_eq!(needs_refresh(&elsewhere, &listener), Some(Cached::Dates));
+
+        db.nuke_database().await.unwrap();
+    }
+}
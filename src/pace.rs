@@ -18,6 +18,9 @@ use crate::{
     MiniString, MEDSTORE,
 };
 
+/// What [`GoalDisplay`]s show in place of a course title for custom `Goal`s.
+pub const CUSTOM_COURSE: &str = "Custom";
+
 #[derive(Clone, Copy, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
 pub enum Term {
     Fall,
@@ -150,15 +153,16 @@ impl PartialEq for BookCh {
 impl Eq for BookCh {}
 
 /// Represents material for a "custom" goal (not from an extant Course in
-/// the database.) This is currently not supported.
+/// the database).
 ///
-/// `id` would be the value of the database's primary key from the table of
-/// custom goals (if it existed).
+/// The `i64` is the `id` of the [`Custom`](crate::course::Custom) chapter
+/// in the database.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub struct CustomCh(i64);
+pub struct CustomCh(pub i64);
 
-/// Currently, only `Source::Book` values are supported, and trying to do
-/// anything wtih a `Source::Custom` will yield you an error.
+/// Where the material for a [`Goal`] comes from: either a chapter of a
+/// [`Course`](crate::course::Course), or a custom chapter that belongs to no
+/// course.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Source {
     Book(BookCh),
@@ -192,13 +196,18 @@ pub struct Goal {
     /// Weight of the `Goal` relative to the entire weight of the course of
     /// which it's a part. (If a student's assigned pace for the year consists
     /// of exactly all the `Chapter`s of a single course, their weights should
-    /// sum to exactly 1.0).
+    /// sum to exactly 1.0). Custom `Goal`s take their weights directly from
+    /// their [`Custom`](crate::course::Custom) chapters.
     ///
     /// Should get set in the constructor of the `Pace` calendar.
     pub weight: f32,
     /// Score string of a completed Goal (see [`parse_score_str`]).
     /// As-of-yet unfinished `Goal`s will have scores of `None`.
     pub score: Option<String>,
+    /// Bumped every time the `Goal` changes in the database (see
+    /// [`Store::update_goal`](crate::store::Store::update_goal)). `Goal`s
+    /// that haven't been inserted yet have version 0.
+    pub version: i64,
 }
 
 impl PartialEq for Goal {
@@ -242,6 +251,9 @@ impl Goal {
     goal, so to save work, you don't need to include them if they're the same
     as the previous line.
 
+    A `sym` of `*` marks a custom Goal; its `seq` is then the `id` of the
+    custom chapter, rather than a chapter number.
+
     Columns `rev` and `inc` are considered `true` if they have any text
     whatsoever.
      */
@@ -258,7 +270,9 @@ impl Goal {
             },
         };
 
-        let seq: i16 = match blank_means_none(row.get(2)) {
+        // This is a chapter number, unless this is a custom Goal, in which
+        // case it's the id of a custom chapter.
+        let n: i64 = match blank_means_none(row.get(2)) {
             Some(s) => match s.parse() {
                 Ok(n) => n,
                 Err(_) => {
@@ -269,23 +283,22 @@ impl Goal {
                 return Err("No chapter number.".into());
             }
         };
-
-        let book_ch = match blank_means_none(row.get(1)) {
-            Some(s) => BookCh {
-                sym: s.to_owned(),
+        let book = |sym: String| -> Result<Source, String> {
+            let seq = i16::try_from(n).map_err(|_| format!("{} is not a chapter number.", n))?;
+            Ok(Source::Book(BookCh {
+                sym,
                 seq,
                 level: 0.0,
-            },
+            }))
+        };
+
+        let source = match blank_means_none(row.get(1)) {
+            Some("*") => Source::Custom(CustomCh(n)),
+            Some(s) => book(s.to_owned())?,
             None => match prev {
                 Some(g) => match &g.source {
-                    Source::Book(bch) => BookCh {
-                        sym: bch.sym.clone(),
-                        seq,
-                        level: 0.0,
-                    },
-                    Source::Custom(_) => {
-                        return Err("No course symbol.".into());
-                    }
+                    Source::Book(bch) => book(bch.sym.clone())?,
+                    Source::Custom(_) => Source::Custom(CustomCh(n)),
                 },
                 None => {
                     return Err("No course symbol".into());
@@ -372,7 +385,7 @@ impl Goal {
             // This doesn't matter; it will be set upon database insertion.
             id: 0,
             uname,
-            source: Source::Book(book_ch),
+            source,
             review,
             incomplete,
             due: Some(due),
@@ -384,6 +397,7 @@ impl Goal {
             weight: 0.0,
             // Goals read from .csv files should have no score yet.
             score: None,
+            version: 0,
         };
 
         Ok(g)
@@ -514,8 +528,14 @@ fn affirm_goal(mut g: Goal, glob: &Glob) -> Result<Goal, String> {
             };
             g.weight = chp.weight / crs_wgt;
         }
-        Source::Custom(_) => {
-            return Err("Custom Goals not yet supported.".to_owned());
+        Source::Custom(CustomCh(id)) => {
+            let cust = match glob.custom_chapter(id) {
+                Some(c) => c,
+                None => {
+                    return Err(format!("There is no custom chapter with id {}.", &id));
+                }
+            };
+            g.weight = cust.weight;
         }
     }
 
@@ -539,39 +559,43 @@ impl Pace {
         let mut due_weight: f32 = 0.0;
         let mut done_weight: f32 = 0.0;
         for g in goals.iter_mut() {
-            let source = match &mut g.source {
-                Source::Book(bch) => bch,
-                _ => {
-                    return Err("Custom chapters not supported.".into());
-                }
-            };
-            let crs = match glob.course_by_sym(&source.sym) {
-                Some(crs) => crs,
-                None => {
-                    return Err(format!("Unknown course symbol {:?}", &source.sym));
-                }
-            };
-            let chp = match crs.chapter(source.seq) {
-                Some(chp) => chp,
-                None => {
-                    return Err(format!(
-                        "Course {:?} ({}) doesn't have a chapter {}.",
-                        &source.sym, &crs.title, &source.seq
-                    ));
-                }
-            };
+            let weight = match &mut g.source {
+                Source::Book(source) => {
+                    let crs = match glob.course_by_sym(&source.sym) {
+                        Some(crs) => crs,
+                        None => {
+                            return Err(format!("Unknown course symbol {:?}", &source.sym));
+                        }
+                    };
+                    let chp = match crs.chapter(source.seq) {
+                        Some(chp) => chp,
+                        None => {
+                            return Err(format!(
+                                "Course {:?} ({}) doesn't have a chapter {}.",
+                                &source.sym, &crs.title, &source.seq
+                            ));
+                        }
+                    };
 
-            let weight = match crs.weight {
-                Some(w) => chp.weight / w,
-                None => {
-                    return Err(format!(
-                        "Course {:?} ({}) has not had its weights set.",
-                        &source.sym, &crs.title
-                    ));
+                    source.level = crs.level;
+                    match crs.weight {
+                        Some(w) => chp.weight / w,
+                        None => {
+                            return Err(format!(
+                                "Course {:?} ({}) has not had its weights set.",
+                                &source.sym, &crs.title
+                            ));
+                        }
+                    }
                 }
+                Source::Custom(CustomCh(id)) => match glob.custom_chapter(*id) {
+                    Some(cust) => cust.weight,
+                    None => {
+                        return Err(format!("Unknown custom chapter id {}", id));
+                    }
+                },
             };
 
-            source.level = crs.level;
             g.weight = weight;
             if let Some(due_date) = &g.due {
                 total_weight += weight;
@@ -614,6 +638,9 @@ impl Pace {
     previous goal, so to save work, you don't need to include them if they're
     the same as the previous line.
 
+    A `sym` of `*` marks a custom Goal, whose `seq` is the `id` of a custom
+    chapter.
+
     Columns `rev` and `inc` are considered `true` if they have any text
     whatsoever.
      */
@@ -787,7 +814,8 @@ each display the `Goal`'s data slightly differently.
 pub struct GoalDisplay<'a> {
     /// The ID of the goal.
     pub id: i64,
-    /// Title of the [`Course`](crate::course::Course) to which this `Goal` belongs.
+    /// Title of the [`Course`](crate::course::Course) to which this `Goal`
+    /// belongs (or [`CUSTOM_COURSE`] for custom `Goal`s).
     pub course: &'a str,
     /// Title of the textbook (or other source) form which this material
     /// is drawn.
@@ -819,23 +847,32 @@ pub struct GoalDisplay<'a> {
 impl<'a> GoalDisplay<'a> {
     /// Generate all the information necessary to display the given [`Goal`].
     fn from_goal(g: &'a Goal, glob: &'a Glob, today: &Date) -> Result<GoalDisplay<'a>, String> {
-        let bch = match &g.source {
-            Source::Book(bch) => bch,
-            _ => {
-                return Err(format!("Goal {}: custom sources unsupported.", &g.id));
+        let (course, book, title, subject) = match &g.source {
+            Source::Book(bch) => {
+                let crs = glob.course_by_sym(&bch.sym).ok_or_else(|| {
+                    format!("Goal {}: no course with symbol {:?}.", &g.id, &bch.sym)
+                })?;
+                let chp = crs.chapter(bch.seq).ok_or_else(|| {
+                    format!(
+                        "Goal {}: Course {:?} has no Chapter {}",
+                        &g.id, &bch.sym, &bch.seq
+                    )
+                })?;
+                (
+                    crs.title.as_str(),
+                    crs.book.as_str(),
+                    chp.title.as_str(),
+                    chp.subject.as_deref(),
+                )
+            }
+            Source::Custom(CustomCh(id)) => {
+                let cust = glob
+                    .custom_chapter(*id)
+                    .ok_or_else(|| format!("Goal {}: no custom chapter with id {}.", &g.id, id))?;
+                (CUSTOM_COURSE, "", cust.title.as_str(), None)
             }
         };
 
-        let crs = glob
-            .course_by_sym(&bch.sym)
-            .ok_or_else(|| format!("Goal {}: no course with symbol {:?}.", &g.id, &bch.sym))?;
-        let chp = crs.chapter(bch.seq).ok_or_else(|| {
-            format!(
-                "Goal {}: Course {:?} has no Chapter {}",
-                &g.id, &bch.sym, &bch.seq
-            )
-        })?;
-
         let mut mark: MiniString<MEDSTORE> = MiniString::new();
         if let Some(s) = g.score.as_deref() {
             write!(&mut mark, "{}", s)
@@ -864,10 +901,10 @@ impl<'a> GoalDisplay<'a> {
 
         let gd = GoalDisplay {
             id: g.id,
-            course: crs.title.as_str(),
-            book: crs.book.as_str(),
-            title: chp.title.as_str(),
-            subject: chp.subject.as_deref(),
+            course,
+            book,
+            title,
+            subject,
             rev: g.review,
             inc: g.incomplete,
             due: g.due,
@@ -1287,133 +1324,14 @@ impl<'a> PaceDisplay<'a> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::Glob;
-    use crate::course::Course;
-    use crate::tests::ensure_logging;
-    use crate::user::{BaseUser, Role, User};
+    use crate::course::Custom;
+    use crate::tests::{backend_tests, init_env, init_env_on, teardown_env, TestDbs, GOALS_FILE};
+    use crate::user::User;
     use crate::*;
 
-    use std::fs::{read_to_string, File};
-
-    use serial_test::serial;
-
-    static AUTH_CONN: &str =
-        "host=localhost user=camp_test password='camp_test' dbname=camp_auth_test";
-    static DATA_CONN: &str =
-        "host=localhost user=camp_test password='camp_test' dbname=camp_store_test";
-
-    static COURSE_FILES: &[&str] = &[
-        "test/env/course_0.mix",
-        "test/env/course_1.mix",
-        "test/env/course_2.mix",
-        "test/env/course_3.mix",
-    ];
-
-    static BOSS: (&str, &str) = ("boss", "boss@camelthingy.com");
-    static TEACHERS: &[(&str, &str, &str)] = &[
-        ("bob", "Mr Bob", "bob@school.com"),
-        ("sal", "Ms Sally, not Sal Khan", "sally@school.com"),
-        ("yak", "Yakov Smirnoff", "yakov@school.com"),
-    ];
-    const STUDENT_FILE: &str = "test/env/students.csv";
-    const GOALS_FILE: &str = "test/env/goals.csv";
-    const DATES: &[(&str, &str)] = &[("end-fall", "2023-01-10")];
-
-    const CONFIG_FILE: &str = "test/env/config.toml";
-
-    async fn init_env() -> Result<Glob, String> {
-        ensure_logging();
-
-        let mut g = config::load_configuration(CONFIG_FILE).await.unwrap();
-
-        let courses: Vec<Course> = COURSE_FILES
-            .iter()
-            .map(|fname| File::open(fname).unwrap())
-            .map(|f| Course::from_reader(f).unwrap())
-            .collect();
-
-        let boss = BaseUser {
-            uname: BOSS.0.to_owned(),
-            role: Role::Boss,
-            salt: String::new(),
-            email: BOSS.1.to_owned(),
-        }
-        .into_boss();
-
-        let student_csv = read_to_string(STUDENT_FILE).unwrap();
-
-        let teachers: Vec<User> = TEACHERS
-            .iter()
-            .map(|(uname, name, email)| {
-                BaseUser {
-                    uname: uname.to_string(),
-                    role: Role::Teacher,
-                    salt: String::new(),
-                    email: email.to_string(),
-                }
-                .into_teacher(name.to_string())
-            })
-            .collect();
-
-        {
-            let data = g.data();
-            data.read().await.insert_courses(&courses).await?;
-        }
-
-        g.insert_user(&boss).await.unwrap();
-        for u in teachers.iter() {
-            g.insert_user(u).await.unwrap();
-        }
-        g.refresh_users().await.unwrap();
-        g.upload_students(&student_csv).await.unwrap();
-
-        {
-            let data_handle = g.data();
-            let data = data_handle.read().await;
-            for (date_name, date_val) in DATES.iter() {
-                data.set_date(date_name, &Date::parse(date_val, DATE_FMT).unwrap())
-                    .await
-                    .unwrap();
-            }
-        }
-        g.refresh_dates().await.unwrap();
-
-        g.refresh_courses().await.unwrap();
-        g.refresh_users().await.unwrap();
-
-        Ok(g)
-    }
-
-    async fn teardown_env(g: Glob) -> Result<(), String> {
-        use std::fmt::Write;
-
-        let mut err_msgs = String::new();
-
-        {
-            let data = g.data();
-            let dread = data.read().await;
-            if let Err(e) = dread.nuke_database().await {
-                log::error!("Error tearing down data DB: {}", &e);
-                writeln!(&mut err_msgs, "Data DB: {}", &e).unwrap();
-            }
-
-            let auth = g.auth();
-            let aread = auth.read().await;
-            if let Err(e) = aread.nuke_database().await {
-                log::error!("Error tearing down auth DB: {}", &e);
-                writeln!(&mut err_msgs, "Auth DB: {}", &e).unwrap();
-            }
-        }
-
-        if err_msgs.is_empty() {
-            Ok(())
-        } else {
-            Err(err_msgs)
-        }
-    }
+    use std::fs::File;
 
     #[tokio::test]
-    #[serial]
     async fn test_env() {
         let g = init_env().await.unwrap();
         log::info!(
@@ -1426,7 +1344,6 @@ mod tests {
     }
 
     #[tokio::test]
-    #[serial]
     async fn goals_from_csv() {
         let g = init_env().await.unwrap();
         let goals = Pace::from_csv(File::open(GOALS_FILE).unwrap(), &g).unwrap();
@@ -1444,7 +1361,6 @@ mod tests {
     }
 
     #[tokio::test]
-    #[serial]
     async fn show_pace_display() {
         let g = init_env().await.unwrap();
         let paces = Pace::from_csv(File::open(GOALS_FILE).unwrap(), &g).unwrap();
@@ -1464,4 +1380,200 @@ mod tests {
 
         teardown_env(g).await.unwrap();
     }
+
+    async fn goals_by_year(dbs: TestDbs) -> Result<(), UnifiedError> {
+        const PAST: i32 = 2020;
+
+        let g = init_env_on(dbs).await.unwrap();
+        let this_year = g.academic_year();
+        let paces = Pace::from_csv(File::open(GOALS_FILE).unwrap(), &g).unwrap();
+        let p = paces.iter().find(|p| p.student.base.uname == "dval").unwrap();
+        let n_goals = p.goals.len();
+
+        {
+            let data = g.data();
+            let data = data.read().await;
+            data.insert_goals(&p.goals, PAST).await.unwrap();
+        }
+        g.insert_goals(&p.goals[..1]).await.unwrap();
+
+        let past = g.get_pace_by_student_for_year("dval", PAST).await.unwrap();
+        assert_eq!(past.goals.len(), n_goals);
+        let current = g.get_pace_by_student("dval").await.unwrap();
+        assert_eq!(current.goals.len(), 1);
+        let teachers_past = g
+            .get_paces_by_teacher_for_year(&p.teacher.base.uname, PAST)
+            .await
+            .unwrap();
+        let dval = teachers_past
+            .iter()
+            .find(|p| p.student.base.uname == "dval")
+            .unwrap();
+        assert_eq!(dval.goals.len(), n_goals);
+
+        assert_eq!(
+            g.academic_years().await.unwrap(),
+            vec![this_year.min(PAST), this_year.max(PAST)]
+        );
+
+        // Past years' Goals can't be changed as if they were this year's.
+        {
+            let data = g.data();
+            let data = data.read().await;
+            let mut old_goal = past.goals[0].clone();
+            old_goal.done = Some(time::macros::date!(2021 - 03 - 01));
+            assert!(data.update_goal(&old_goal, this_year).await.is_err());
+            assert!(data.delete_goal(old_goal.id, this_year).await.is_err());
+            data.update_goal(&old_goal, PAST).await.unwrap();
+        }
+        let past = g.get_pace_by_student_for_year("dval", PAST).await.unwrap();
+        assert_eq!(past.goals.len(), n_goals);
+        assert!(past.goals.iter().any(|g| g.done.is_some()));
+
+        teardown_env(g).await.unwrap();
+        Ok(())
+    }
+
+    async fn stale_edits(dbs: TestDbs) -> Result<(), UnifiedError> {
+        use crate::store::Versioned;
+
+        let mut g = init_env_on(dbs).await.unwrap();
+        let this_year = g.academic_year();
+        let paces = Pace::from_csv(File::open(GOALS_FILE).unwrap(), &g).unwrap();
+        let p = paces.iter().find(|p| p.student.base.uname == "dval").unwrap();
+        g.insert_goals(&p.goals).await.unwrap();
+        let p = g.get_pace_by_student("dval").await.unwrap();
+
+        // Two people with the same Goal open; the second one to save loses.
+        {
+            let data = g.data();
+            let data = data.read().await;
+            let mut first = p.goals[0].clone();
+            let mut second = p.goals[0].clone();
+            first.review = !first.review;
+            second.incomplete = !second.incomplete;
+            assert_eq!(
+                data.update_goal(&first, this_year).await.unwrap(),
+                Versioned::Written(first.version + 1)
+            );
+            assert_eq!(
+                data.update_goal(&second, this_year).await.unwrap(),
+                Versioned::Conflict
+            );
+        }
+        let goal = g.get_pace_by_student("dval").await.unwrap().goals[0].clone();
+        assert_eq!(goal.review, !p.goals[0].review);
+        assert_eq!(goal.incomplete, p.goals[0].incomplete);
+        assert_eq!(goal.version, p.goals[0].version + 1);
+
+        // Same with Students (and the cached copy can be brought up to date).
+        let mut s = p.student.clone();
+        s.fall_notices += 1;
+        g.update_user(&User::Student(s.clone())).await.unwrap();
+        assert!(matches!(
+            g.update_user(&User::Student(s.clone())).await,
+            Err(UnifiedError::Conflict(_))
+        ));
+        g.refresh_user("dval").await.unwrap();
+        match g.users.get("dval") {
+            Some(User::Student(fresh)) => assert_eq!(fresh.version, s.version + 1),
+            x => panic!("dval should be a Student, not {:?}", x),
+        }
+
+        // And report sidecars, which start out at version 0 (no row).
+        {
+            let data = g.data();
+            let data = data.read().await;
+            let car = data.get_report_sidecar("dval", this_year).await.unwrap();
+            assert_eq!(car.version, 0);
+            assert_eq!(
+                data.set_report_sidecar(&car, this_year).await.unwrap(),
+                Versioned::Written(1)
+            );
+            assert_eq!(
+                data.set_report_sidecar(&car, this_year).await.unwrap(),
+                Versioned::Conflict
+            );
+            let car = data.get_report_sidecar("dval", this_year).await.unwrap();
+            assert_eq!(car.version, 1);
+        }
+
+        teardown_env(g).await.unwrap();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn custom_goals() {
+        let mut g = init_env().await.unwrap();
+        let today = crate::now();
+
+        let project = g
+            .data()
+            .read()
+            .await
+            .insert_custom_chapter(&Custom {
+                id: 0,
+                uname: "bob".to_owned(),
+                title: "Science Fair Project".to_owned(),
+                weight: 0.25,
+            })
+            .await
+            .unwrap();
+        g.refresh_custom_chapters().await.unwrap();
+
+        // A custom Goal between two book chapters, then a second one that
+        // gets its `*` from the line before.
+        let csv = format!(
+            "dval,msa2h,1,2022,9,1,,\n,*,{0},,,2,,\n,msa2h,2,,,3,,\n,*,{0},,,4,,x\n",
+            &project.id
+        );
+        let paces = Pace::from_csv(csv.as_bytes(), &g).unwrap();
+        assert_eq!(paces.len(), 1);
+        let p = &paces[0];
+        let customs: Vec<&Goal> = p
+            .goals
+            .iter()
+            .filter(|g| g.source == Source::Custom(CustomCh(project.id)))
+            .collect();
+        assert_eq!(customs.len(), 2);
+        assert!(customs.iter().all(|g| g.weight == 0.25));
+        assert!(customs[1].incomplete);
+        assert!(Pace::from_csv("dval,*,987654,2022,9,1,,".as_bytes(), &g).is_err());
+
+        g.insert_goals(&p.goals).await.unwrap();
+        let mut p = g.get_pace_by_student("dval").await.unwrap();
+        let chapter_weight = p
+            .goals
+            .iter()
+            .find(|g| matches!(g.source, Source::Book(_)))
+            .unwrap()
+            .weight;
+        assert!((p.total_weight - (2.0 * chapter_weight + 0.5)).abs() < 0.0001);
+
+        let cust = p
+            .goals
+            .iter()
+            .find(|g| matches!(g.source, Source::Custom(_)))
+            .unwrap();
+        let gd = GoalDisplay::from_goal(cust, &g, &today).unwrap();
+        assert_eq!(gd.course, CUSTOM_COURSE);
+        assert_eq!(gd.title, "Science Fair Project");
+
+        // Autopacing spreads custom Goals out by weight along with the rest.
+        let start = time::macros::date!(2022 - 09 - 01);
+        let dates: Vec<Date> = (0..20).map(|n| start + time::Duration::days(n)).collect();
+        p.autopace(&dates).unwrap();
+        let dues: Vec<Date> = p.goals.iter().filter_map(|g| g.due).collect();
+        assert_eq!(dues.len(), 4);
+        assert!(dues.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(dues.last(), dates.last());
+
+        // Custom chapters can't be deleted out from under their Goals.
+        let data = g.data();
+        assert!(data.read().await.delete_custom_chapter(project.id).await.is_err());
+
+        teardown_env(g).await.unwrap();
+    }
+
+    backend_tests!(goals_by_year, stale_edits);
 }
@@ -0,0 +1,539 @@
+/*!
+Who may do what.
+
+Every API request is directed at one of the [`Endpoint`]s, and names an
+action in its `x-camp-action` header. Whether a user may perform that action
+is determined by their [`Role`] (according to the default matrix below) and
+by any extra [`Permission`]s they've been granted individually.
+
+Permissions are written `endpoint:action` (for example, `admin:update-course`
+would let a Boss fix a typo in a Course), or `endpoint:*` for every action at
+that endpoint.
+*/
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use serde::Serialize;
+
+use crate::user::{Role, User};
+
+/// Actions served by the `/admin` endpoint.
+pub const ADMIN_ACTIONS: &[&str] = &[
+    "populate-users",
+    "populate-admins",
+    "populate-bosses",
+    "add-user",
+    "update-user",
+    "delete-user",
+    "unlock-user",
+    "begin-2fa",
+    "confirm-2fa",
+    "reset-2fa",
+    "list-sessions",
+    "revoke-session",
+    "revoke-sessions",
+    "impersonate",
+    "upload-students",
+    "populate-invites",
+    "resend-invite",
+    "populate-tokens",
+    "issue-token",
+    "revoke-token",
+    "populate-grants",
+    "grant-permission",
+    "revoke-permission",
+    "populate-courses",
+    "upload-course",
+    "add-course",
+    "delete-course",
+    "update-course",
+    "add-chapters",
+    "update-chapter",
+    "delete-chapter",
+    "populate-cal",
+    "update-cal",
+    "populate-dates",
+    "set-date",
+    "populate-completion",
+    "add-completion",
+    "delete-completion",
+    "query-audit",
+    "export-audit",
+    "export-archive",
+    "reload-config",
+];
+
+/// Actions served by the `/boss` endpoint.
+pub const BOSS_ACTIONS: &[&str] = &[
+    "compose-email",
+    "send-email",
+    "email-all",
+    "download-report",
+    "report-archive",
+    "populate-histories",
+    "populate-years",
+    "populate-year-goals",
+    "begin-2fa",
+    "confirm-2fa",
+    "query-audit",
+    "export-audit",
+];
+
+/// Actions served by the `/teacher` endpoint.
+pub const TEACHER_ACTIONS: &[&str] = &[
+    "populate-dates",
+    "populate-courses",
+    "populate-goals",
+    "populate-traits",
+    "populate-custom",
+    "add-custom",
+    "update-custom",
+    "delete-custom",
+    "add-goal",
+    "update-goal",
+    "delete-goal",
+    "update-numbers",
+    "autopace",
+    "clear-goals",
+    "upload-goals",
+    "show-sidecar",
+    "update-sidecar",
+    "render-report",
+    "discard-pdf",
+    "student-history",
+    "download-report",
+    "populate-years",
+    "populate-year-goals",
+    "populate-teacher-goals",
+];
+
+/// Actions served by the `/admin` endpoint that change nothing.
+pub const ADMIN_READ_ONLY: &[&str] = &[
+    "populate-users",
+    "populate-admins",
+    "populate-bosses",
+    "list-sessions",
+    "populate-invites",
+    "populate-tokens",
+    "populate-grants",
+    "populate-courses",
+    "populate-cal",
+    "populate-dates",
+    "populate-completion",
+    "query-audit",
+    "export-audit",
+];
+
+/// Actions served by the `/boss` endpoint that change nothing.
+pub const BOSS_READ_ONLY: &[&str] = &[
+    "compose-email",
+    "download-report",
+    "report-archive",
+    "populate-histories",
+    "populate-years",
+    "populate-year-goals",
+    "query-audit",
+    "export-audit",
+];
+
+/// Actions served by the `/teacher` endpoint that change nothing.
+pub const TEACHER_READ_ONLY: &[&str] = &[
+    "populate-dates",
+    "populate-courses",
+    "populate-goals",
+    "populate-traits",
+    "populate-custom",
+    "show-sidecar",
+    "student-history",
+    "download-report",
+    "populate-years",
+    "populate-year-goals",
+    "populate-teacher-goals",
+];
+
+/**
+Actions no role may perform by default, even at its own endpoint; they must
+be granted individually.
+
+For now that's just `teacher:populate-teacher-goals` (viewing another
+Teacher's Students).
+*/
+pub const GRANTED_ONLY: &[(Endpoint, &str)] = &[(Endpoint::Teacher, "populate-teacher-goals")];
+
+/**
+The default permission matrix: each role may perform the listed actions at
+the listed endpoint (except those in [`GRANTED_ONLY`]), and nothing else.
+*/
+const DEFAULT_MATRIX: &[(Role, Endpoint, &[&str])] = &[
+    (Role::Admin, Endpoint::Admin, ADMIN_ACTIONS),
+    (Role::Boss, Endpoint::Boss, BOSS_ACTIONS),
+    (Role::Teacher, Endpoint::Teacher, TEACHER_ACTIONS),
+];
+
+/// The API endpoints subject to permission checks.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Endpoint {
+    Admin,
+    Boss,
+    Teacher,
+}
+
+impl Endpoint {
+    /// The endpoint served at the given request path, if any.
+    pub fn from_path(path: &str) -> Option<Endpoint> {
+        match path.trim_end_matches('/') {
+            "/admin" => Some(Endpoint::Admin),
+            "/boss" => Some(Endpoint::Boss),
+            "/teacher" => Some(Endpoint::Teacher),
+            _ => None,
+        }
+    }
+
+    /// All the actions this endpoint serves.
+    pub fn actions(&self) -> &'static [&'static str] {
+        match self {
+            Endpoint::Admin => ADMIN_ACTIONS,
+            Endpoint::Boss => BOSS_ACTIONS,
+            Endpoint::Teacher => TEACHER_ACTIONS,
+        }
+    }
+
+    /**
+    Whether `action` at this endpoint is known to change nothing. Anything
+    not explicitly listed as read-only (even an action this endpoint doesn't
+    serve) is assumed to write something.
+    */
+    pub fn is_read_only(&self, action: &str) -> bool {
+        let read_only = match self {
+            Endpoint::Admin => ADMIN_READ_ONLY,
+            Endpoint::Boss => BOSS_READ_ONLY,
+            Endpoint::Teacher => TEACHER_READ_ONLY,
+        };
+        read_only.contains(&action)
+    }
+}
+
+impl Display for Endpoint {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let token = match self {
+            Endpoint::Admin => "admin",
+            Endpoint::Boss => "boss",
+            Endpoint::Teacher => "teacher",
+        };
+
+        write!(f, "{}", token)
+    }
+}
+
+impl FromStr for Endpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "admin" => Ok(Endpoint::Admin),
+            "boss" => Ok(Endpoint::Boss),
+            "teacher" => Ok(Endpoint::Teacher),
+            _ => Err(format!("{:?} is not a valid endpoint.", s)),
+        }
+    }
+}
+
+/// Leave to perform an `action` (or, if `action` is `None`, every action) at
+/// an [`Endpoint`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Permission {
+    pub endpoint: Endpoint,
+    pub action: Option<String>,
+}
+
+impl Permission {
+    pub fn allows(&self, endpoint: Endpoint, action: &str) -> bool {
+        self.endpoint == endpoint
+            && match &self.action {
+                None => true,
+                Some(a) => a == action,
+            }
+    }
+}
+
+impl Display for Permission {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match &self.action {
+            Some(action) => write!(f, "{}:{}", &self.endpoint, action),
+            None => write!(f, "{}:*", &self.endpoint),
+        }
+    }
+}
+
+/// Only recognizes actions actually served by the given endpoint.
+impl FromStr for Permission {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (endpoint, action) = s
+            .trim()
+            .split_once(':')
+            .ok_or_else(|| format!("{:?} is not of the form \"endpoint:action\".", s))?;
+        let endpoint: Endpoint = endpoint.parse()?;
+        let action = match action {
+            "*" => None,
+            a if endpoint.actions().contains(&a) => Some(a.to_owned()),
+            a => {
+                return Err(format!(
+                    "{:?} is not an action of the {} endpoint.",
+                    a, &endpoint
+                ));
+            }
+        };
+
+        Ok(Permission { endpoint, action })
+    }
+}
+
+impl Serialize for Permission {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Whether the default matrix allows `role` to perform `action` at `endpoint`.
+pub fn role_allows(role: Role, endpoint: Endpoint, action: &str) -> bool {
+    if GRANTED_ONLY.contains(&(endpoint, action)) {
+        return false;
+    }
+    DEFAULT_MATRIX
+        .iter()
+        .any(|(r, e, actions)| *r == role && *e == endpoint && actions.contains(&action))
+}
+
+/**
+The extra permissions granted to individual users, keyed by uname.
+
+The [`Glob`](crate::config::Glob) keeps a copy of these, so checks don't
+require a trip to the database.
+*/
+#[derive(Clone, Debug, Default)]
+pub struct Grants(HashMap<String, Vec<Permission>>);
+
+impl Grants {
+    pub fn new(grants: HashMap<String, Vec<Permission>>) -> Self {
+        Self(grants)
+    }
+
+    /// Extra permissions granted to `uname`.
+    pub fn of(&self, uname: &str) -> &[Permission] {
+        self.0.get(uname).map(|v| v.as_slice()).unwrap_or(&[])
+    }
+
+    /// All extra permissions, keyed by uname.
+    pub fn all(&self) -> &HashMap<String, Vec<Permission>> {
+        &self.0
+    }
+
+    /// Whether user `u` may perform `action` at `endpoint`, either by
+    /// virtue of their role or because they've been granted leave to.
+    pub fn allows(&self, u: &User, endpoint: Endpoint, action: &str) -> bool {
+        role_allows(u.role(), endpoint, action)
+            || self.of(u.uname()).iter().any(|p| p.allows(endpoint, action))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENDPOINTS: &[Endpoint] = &[Endpoint::Admin, Endpoint::Boss, Endpoint::Teacher];
+    const ROLES: &[Role] = &[
+        Role::Admin,
+        Role::Boss,
+        Role::Teacher,
+        Role::Student,
+        Role::Parent,
+    ];
+
+    #[test]
+    fn default_matrix() {
+        for &endpoint in ENDPOINTS {
+            for &action in endpoint.actions() {
+                for &role in ROLES {
+                    let expected = matches!(
+                        (role, endpoint),
+                        (Role::Admin, Endpoint::Admin)
+                            | (Role::Boss, Endpoint::Boss)
+                            | (Role::Teacher, Endpoint::Teacher)
+                    ) && action != "populate-teacher-goals";
+                    assert_eq!(
+                        role_allows(role, endpoint, action),
+                        expected,
+                        "{} {}:{}",
+                        &role,
+                        &endpoint,
+                        action
+                    );
+                }
+            }
+        }
+
+        // Every action in the matrix is actually served by its endpoint.
+        for (_, endpoint, actions) in DEFAULT_MATRIX.iter() {
+            for action in actions.iter() {
+                assert!(endpoint.actions().contains(action), "{}:{}", endpoint, action);
+            }
+        }
+
+        for (endpoint, action) in GRANTED_ONLY.iter() {
+            assert!(endpoint.actions().contains(action), "{}:{}", endpoint, action);
+        }
+
+        for &role in ROLES {
+            assert!(!role_allows(role, Endpoint::Admin, "not-an-action"));
+        }
+    }
+
+    #[test]
+    fn parse_permissions() {
+        let p: Permission = "admin:update-course".parse().unwrap();
+        assert_eq!(p.endpoint, Endpoint::Admin);
+        assert_eq!(p.action.as_deref(), Some("update-course"));
+        assert_eq!(&p.to_string(), "admin:update-course");
+
+        let p: Permission = " teacher:* ".parse().unwrap();
+        assert_eq!(p.action, None);
+        assert_eq!(&p.to_string(), "teacher:*");
+
+        assert!("admin".parse::<Permission>().is_err());
+        assert!("student:*".parse::<Permission>().is_err());
+        assert!("boss:update-course".parse::<Permission>().is_err());
+    }
+
+    #[test]
+    fn individual_grants() {
+        use crate::user::BaseUser;
+
+        let boss = User::Boss(BaseUser {
+            uname: "boss".to_owned(),
+            role: Role::Boss,
+            salt: String::new(),
+            email: String::new(),
+        });
+        let other_boss = User::Boss(BaseUser {
+            uname: "other".to_owned(),
+            role: Role::Boss,
+            salt: String::new(),
+            email: String::new(),
+        });
+
+        let mut map = HashMap::new();
+        map.insert(
+            "boss".to_owned(),
+            vec![
+                "admin:update-course".parse().unwrap(),
+                "teacher:*".parse().unwrap(),
+            ],
+        );
+        let grants = Grants::new(map);
+
+        assert!(grants.allows(&boss, Endpoint::Boss, "email-all"));
+        assert!(grants.allows(&boss, Endpoint::Admin, "update-course"));
+        assert!(!grants.allows(&boss, Endpoint::Admin, "delete-course"));
+        assert!(grants.allows(&boss, Endpoint::Teacher, "populate-teacher-goals"));
+        assert!(!grants.allows(&other_boss, Endpoint::Admin, "update-course"));
+        assert!(grants.of("other").is_empty());
+    }
+
+    /**
+    Every action must be deliberately classified as read-only or not, since
+    read-only ones are allowed from a view-as (impersonation) key and go
+    unrecorded. Adding an action means adding it to one of these, or to the
+    endpoint's read-only list.
+    */
+    #[test]
+    fn read_only_classified() {
+        const WRITES: &[(Endpoint, &[&str])] = &[
+            (
+                Endpoint::Admin,
+                &[
+                    "add-user",
+                    "update-user",
+                    "delete-user",
+                    "unlock-user",
+                    "begin-2fa",
+                    "confirm-2fa",
+                    "reset-2fa",
+                    "revoke-session",
+                    "revoke-sessions",
+                    "impersonate",
+                    "upload-students",
+                    "resend-invite",
+                    "issue-token",
+                    "revoke-token",
+                    "grant-permission",
+                    "revoke-permission",
+                    "upload-course",
+                    "add-course",
+                    "delete-course",
+                    "update-course",
+                    "add-chapters",
+                    "update-chapter",
+                    "delete-chapter",
+                    "update-cal",
+                    "set-date",
+                    "add-completion",
+                    "delete-completion",
+                    "export-archive",
+                    "reload-config",
+                ],
+            ),
+            (
+                Endpoint::Boss,
+                &["send-email", "email-all", "begin-2fa", "confirm-2fa"],
+            ),
+            (
+                Endpoint::Teacher,
+                &[
+                    "add-custom",
+                    "update-custom",
+                    "delete-custom",
+                    "add-goal",
+                    "update-goal",
+                    "delete-goal",
+                    "update-numbers",
+                    "autopace",
+                    "clear-goals",
+                    "upload-goals",
+                    "update-sidecar",
+                    "render-report",
+                    "discard-pdf",
+                ],
+            ),
+        ];
+
+        for &(endpoint, writes) in WRITES {
+            for &action in endpoint.actions() {
+                assert!(
+                    endpoint.is_read_only(action) != writes.contains(&action),
+                    "{}:{} must be either read-only or a write",
+                    &endpoint,
+                    action
+                );
+            }
+            for &action in writes {
+                assert!(endpoint.actions().contains(&action), "{}:{}", &endpoint, action);
+            }
+        }
+        for (endpoint, read_only) in [
+            (Endpoint::Admin, ADMIN_READ_ONLY),
+            (Endpoint::Boss, BOSS_READ_ONLY),
+            (Endpoint::Teacher, TEACHER_READ_ONLY),
+        ] {
+            for &action in read_only {
+                assert!(endpoint.actions().contains(&action), "{}:{}", &endpoint, action);
+            }
+        }
+
+        // Anything unknown is assumed to write.
+        assert!(!Endpoint::Teacher.is_read_only("populate-anything"));
+        assert!(!Endpoint::Boss.is_read_only("update-goal"));
+    }
+}
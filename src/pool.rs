@@ -0,0 +1,123 @@
+/*!
+Pooled Postgres connections, shared by [`auth::Db`](crate::auth::Db) and
+[`Store`](crate::store::Store).
+
+Each of those holds a pool of open connections, created the first time it's
+asked to `connect()`; a connection goes back into the pool when the client
+handed out is dropped. Before a pooled connection is handed out again, it's
+checked to make sure it's still alive (and, if `health_check` is set, that
+it can still run a query).
+
+Every connection identifies itself to Postgres with an `application_name`
+unique to this process (see [`application_name`]), so that changes this
+process makes can be told from those made by other instances sharing the
+same database (see [`crate::notify`]).
+
+(SQLite databases aren't pooled; see [`db`](crate::db).)
+*/
+use std::time::Duration;
+
+use deadpool_postgres::{Manager, ManagerConfig, Pool, RecyclingMethod, Runtime};
+use once_cell::sync::Lazy;
+use rand::{distributions::Alphanumeric, Rng};
+use tokio_postgres::NoTls;
+
+const DEFAULT_MAX_SIZE: usize = 16;
+const DEFAULT_TIMEOUT_SECONDS: u64 = 10;
+const INSTANCE_ID_LENGTH: usize = 8;
+
+/// Random string identifying this process among any others using the same
+/// databases.
+static INSTANCE_ID: Lazy<String> = Lazy::new(|| {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(INSTANCE_ID_LENGTH)
+        .map(char::from)
+        .collect()
+});
+
+/**
+The `application_name` this process's connections to the database described
+by `pg_config` go by: the one in its connection string (or "camp", if there
+isn't one), followed by this process's instance id.
+*/
+pub fn application_name(pg_config: &tokio_postgres::Config) -> String {
+    format!(
+        "{} {}",
+        pg_config.get_application_name().unwrap_or("camp"),
+        INSTANCE_ID.as_str()
+    )
+}
+
+/// Parameters governing a pool of database connections.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PoolConfig {
+    /// Maximum number of connections to hold open at once.
+    pub max_size: usize,
+    /// Number of seconds to wait for a new connection to be established, or
+    /// for a connection to become free when all `max_size` are in use.
+    pub timeout_seconds: u64,
+    /// Whether to run a test query on a pooled connection before reusing it
+    /// (as opposed to just checking that it hasn't been closed).
+    pub health_check: bool,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            max_size: DEFAULT_MAX_SIZE,
+            timeout_seconds: DEFAULT_TIMEOUT_SECONDS,
+            health_check: true,
+        }
+    }
+}
+
+impl PoolConfig {
+    /// Build a pool of connections to the database described by
+    /// `connection_string`. No connections are made until one is requested.
+    pub fn build(&self, connection_string: &str) -> Result<Pool, String> {
+        let timeout = Duration::from_secs(self.timeout_seconds);
+
+        let mut pg_config: tokio_postgres::Config = connection_string
+            .parse()
+            .map_err(|e| format!("Unable to parse connection string: {}", &e))?;
+        pg_config.connect_timeout(timeout);
+        pg_config.application_name(&application_name(&pg_config));
+
+        let recycling_method = if self.health_check {
+            RecyclingMethod::Verified
+        } else {
+            RecyclingMethod::Fast
+        };
+        let manager = Manager::from_config(pg_config, NoTls, ManagerConfig { recycling_method });
+
+        Pool::builder(manager)
+            .max_size(self.max_size)
+            .wait_timeout(Some(timeout))
+            .create_timeout(Some(timeout))
+            .recycle_timeout(Some(timeout))
+            .runtime(Runtime::Tokio1)
+            .build()
+            .map_err(|e| format!("Unable to build connection pool: {}", &e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_pool() {
+        let cfg = PoolConfig {
+            max_size: 3,
+            ..Default::default()
+        };
+        let pool = cfg
+            .build("host=localhost user=camp_test password='camp_test' dbname=camp_store_test")
+            .unwrap();
+        assert_eq!(pool.status().max_size, 3);
+        assert_eq!(pool.status().size, 0);
+
+        assert!(cfg.build("host=localhost port=notaport").is_err());
+    }
+}
@@ -215,6 +215,11 @@ pub struct ReportSidecar {
     pub spring_complete: Vec<String>,
     pub summer_complete: Vec<String>,
     pub mastery: Vec<Mastery>,
+    /// Bumped every time any of this data changes (see
+    /// [`Store::set_report_sidecar`](crate::store::Store::set_report_sidecar));
+    /// 0 if it's never been written.
+    #[serde(default)]
+    pub version: i64,
 }
 
 fn fact_status_display(factstatus: FactStatus) -> &'static str {
@@ -753,53 +758,53 @@ pub async fn render_markdown(text: String, glob: &Glob) -> Result<Vec<u8>, Unifi
 
 #[cfg(test)]
 mod tests {
-    use serial_test::serial;
-
-    use crate::{config, tests::ensure_logging};
+    use crate::tests::{init_env, insert_test_goals, teardown_env};
 
     use super::*;
 
-    static CONFIG: &str = "fakeprod_data/config.toml";
-    static UNAME: &str = "zmilk";
+    static UNAME: &str = "dval";
     static OUTDIR: &str = "scratch/";
 
     #[tokio::test]
-    #[serial]
     async fn fall_markdown() -> Result<(), Box<dyn std::error::Error>> {
-        ensure_logging();
-        let glob = config::load_configuration(CONFIG).await?;
+        let glob = init_env().await?;
+        insert_test_goals(&glob).await?;
         let text = generate_report_markup(UNAME, Term::Fall, &glob).await?;
         let mut fname = String::from(OUTDIR);
         fname.push_str(UNAME);
         fname.push_str("_fall.md");
         std::fs::write(&fname, &text.as_bytes())?;
+        teardown_env(glob).await?;
         Ok(())
     }
 
     #[tokio::test]
-    #[serial]
     async fn spring_markdown() -> Result<(), Box<dyn std::error::Error>> {
-        ensure_logging();
-        let glob = config::load_configuration(CONFIG).await?;
+        let glob = init_env().await?;
+        insert_test_goals(&glob).await?;
         let text = generate_report_markup(UNAME, Term::Spring, &glob).await?;
         let mut fname = String::from(OUTDIR);
         fname.push_str(UNAME);
         fname.push_str("_spring.md");
         std::fs::write(&fname, &text.as_bytes())?;
+        teardown_env(glob).await?;
         Ok(())
     }
 
+    /// This one also needs a Pandoc rendering service (`pandoc_uri`), which
+    /// the test configuration doesn't have.
     #[tokio::test]
-    #[serial]
+    #[ignore]
     async fn render_report() -> Result<(), Box<dyn std::error::Error>> {
-        ensure_logging();
-        let glob = config::load_configuration(CONFIG).await?;
+        let glob = init_env().await?;
+        insert_test_goals(&glob).await?;
         let text = generate_report_markup(UNAME, Term::Spring, &glob).await?;
         let pdf_bytes = render_markdown(text, &glob).await?;
         let mut fname = String::from(OUTDIR);
         fname.push_str(UNAME);
         fname.push_str("_spring.pdf");
         std::fs::write(&fname, &pdf_bytes)?;
+        teardown_env(glob).await?;
         Ok(())
     }
 }
@@ -0,0 +1,199 @@
+/*!
+`Store` methods for writing to and reading from the audit log.
+
+```sql
+CREATE TABLE audit (
+    id      BIGSERIAL PRIMARY KEY,
+    at      TIMESTAMP NOT NULL DEFAULT now(),
+    actor   TEXT NOT NULL,
+    role    TEXT NOT NULL,
+    action  TEXT NOT NULL,
+    target  TEXT,
+    student TEXT,
+    before  TEXT,   /* JSON */
+    after   TEXT    /* request body */
+);
+```
+
+The log deliberately doesn't reference the `users` table: entries must
+outlive the users (and Students) they mention. There are no methods here to
+change or delete entries.
+*/
+use super::{DbError, Store};
+use crate::{
+    audit::{Entry, Query, Record},
+    db::{Row, ToValue},
+};
+
+fn entry_from_row(row: &Row) -> Result<Entry, DbError> {
+    Ok(Entry {
+        id: row.try_get("id")?,
+        at: row.try_get("at")?,
+        actor: row.try_get("actor")?,
+        role: row.try_get("role")?,
+        action: row.try_get("action")?,
+        target: row.try_get("target")?,
+        student: row.try_get("student")?,
+        before: row.try_get("before")?,
+        after: row.try_get("after")?,
+    })
+}
+
+impl Store {
+    /// Append `r` to the audit log.
+    pub async fn insert_audit_record(&self, r: &Record<'_>) -> Result<(), DbError> {
+        log::trace!("Store::insert_audit_record( {:?} ) called.", r);
+
+        let client = self.connect().await?;
+        client
+            .execute(
+                "INSERT INTO audit (actor, role, action, target, student, before, after)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7)",
+                &[
+                    &r.actor, &r.role, &r.action, &r.target, &r.student, &r.before, &r.after,
+                ],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Return the audit log entries matching `q`, oldest first.
+    pub async fn get_audit_entries(&self, q: &Query) -> Result<Vec<Entry>, DbError> {
+        log::trace!("Store::get_audit_entries( {:?} ) called.", q);
+
+        let client = self.connect().await?;
+        let d = client.dialect();
+        let conditions = format!(
+            "({} IS NULL OR actor = $1)
+                AND ({} IS NULL OR student = $2)
+                AND ({} IS NULL OR at >= $3)
+                AND ({} IS NULL OR at < {})",
+            d.typed("$1", "TEXT"),
+            d.typed("$2", "TEXT"),
+            d.typed("$3", "DATE"),
+            d.typed("$4", "DATE"),
+            d.next_day("$4"),
+        );
+        let rows = client
+            .query(
+                &format!(
+                    "SELECT id, {} AS at,
+                            actor, role, action, target, student, before, after
+                        FROM audit
+                        WHERE {}
+                        ORDER BY id",
+                    client.dialect().timestamp_text("at"),
+                    conditions
+                ),
+                &[&q.actor, &q.student, &q.from, &q.to],
+            )
+            .await?;
+
+        let mut entries: Vec<Entry> = Vec::with_capacity(rows.len());
+        for row in rows.iter() {
+            entries.push(entry_from_row(row)?);
+        }
+
+        log::trace!(
+            "    ...Store::get_audit_entries() returns {} entries.",
+            entries.len()
+        );
+        Ok(entries)
+    }
+
+    /**
+    Return a JSON representation of the row of `table` whose `key_col` is
+    `key`, if there is one, for recording the "before" state of an entity
+    in the audit log.
+    */
+    pub async fn audit_snapshot(
+        &self,
+        table: &'static str,
+        key_col: &'static str,
+        key: &(dyn ToValue + Sync),
+    ) -> Result<Option<String>, DbError> {
+        log::trace!(
+            "Store::audit_snapshot( {:?}, {:?}, {:?} ) called.",
+            table,
+            key_col,
+            key
+        );
+
+        let client = self.connect().await?;
+        Ok(client.row_json(table, key_col, key).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use time::macros::date;
+
+    use crate::tests::{backend_tests, ensure_logging, TestDbs};
+    use crate::UnifiedError;
+
+    async fn audit_log(dbs: TestDbs) -> Result<(), UnifiedError> {
+        ensure_logging();
+
+        let db = Store::new(dbs.data());
+        db.ensure_db_schema().await?;
+
+        let rec = Record {
+            actor: "jenny",
+            role: "Teacher",
+            action: "update-goal",
+            target: Some("goal 12"),
+            student: Some("frog"),
+            before: Some("{}"),
+            after: Some("{}"),
+        };
+        db.insert_audit_record(&rec).await?;
+        db.insert_audit_record(&Record {
+            actor: "admin",
+            role: "Admin",
+            action: "delete-user",
+            target: Some("ghill"),
+            student: Some("ghill"),
+            before: None,
+            after: Some("ghill"),
+        })
+        .await?;
+
+        let all = db.get_audit_entries(&Query::default()).await?;
+        assert_eq!(all.len(), 2);
+        assert_eq!(&all[0].action, "update-goal");
+        assert_eq!(all[1].before, None);
+
+        let q = Query {
+            student: Some("frog".to_owned()),
+            ..Default::default()
+        };
+        let frog = db.get_audit_entries(&q).await?;
+        assert_eq!(frog.len(), 1);
+        assert_eq!(&frog[0].actor, "jenny");
+
+        let q = Query {
+            actor: Some("admin".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(db.get_audit_entries(&q).await?.len(), 1);
+
+        let q = Query {
+            to: Some(date!(2000 - 01 - 01)),
+            ..Default::default()
+        };
+        assert!(db.get_audit_entries(&q).await?.is_empty());
+
+        db.set_date("end-fall", &date!(2026 - 01 - 16)).await?;
+        let snap = db.audit_snapshot("dates", "name", &"end-fall").await?;
+        assert!(snap.unwrap().contains("end-fall"));
+        assert_eq!(db.audit_snapshot("dates", "name", &"nope").await?, None);
+
+        db.nuke_database().await?;
+        Ok(())
+    }
+
+    backend_tests!(audit_log);
+}
@@ -20,9 +20,9 @@ use std::collections::HashMap;
 
 use futures::stream::{FuturesUnordered, StreamExt};
 use time::Date;
-use tokio_postgres::types::{ToSql, Type};
 
 use super::{DbError, Store};
+use crate::db::ToValue;
 
 impl Store {
     /// Store this collection of dates as making up the "working days" of the
@@ -33,9 +33,7 @@ impl Store {
         let mut client = self.connect().await?;
         let t = client.transaction().await?;
 
-        let insert_statement = t
-            .prepare_typed("INSERT INTO calendar (day) VALUES ($1)", &[Type::DATE])
-            .await?;
+        let insert_statement = "INSERT INTO calendar (day) VALUES ($1)";
 
         let n_deleted = t
             .execute("DELETE FROM calendar", &[])
@@ -44,17 +42,17 @@ impl Store {
 
         let mut n_inserted: u64 = 0;
         {
-            let date_refs: Vec<[&(dyn ToSql + Sync); 1]> = dates
+            let date_refs: Vec<[&(dyn ToValue + Sync); 1]> = dates
                 .iter()
                 .map(|d| {
-                    let p: [&(dyn ToSql + Sync); 1] = [d];
+                    let p: [&(dyn ToValue + Sync); 1] = [d];
                     p
                 })
                 .collect();
 
             let mut inserts = FuturesUnordered::new();
             for params in date_refs.iter() {
-                inserts.push(t.execute(&insert_statement, &params[..]));
+                inserts.push(t.execute(insert_statement, &params[..]));
             }
 
             while let Some(res) = inserts.next().await {
@@ -106,7 +104,7 @@ impl Store {
             .execute(
                 "INSERT INTO dates (name, day)
                 VALUES ($1, $2)
-                ON CONFLICT ON CONSTRAINT dates_pkey
+                ON CONFLICT (name)
                 DO UPDATE set day = $2",
                 &[&name, &day],
             )
@@ -28,14 +28,16 @@ CREATE TABLE custom_chapters (
     weight REAL     /* NULL should give default value of 1.0 */
 );
 ```
+
+Custom chapters (see [`Custom`]) belong to no Course; a Goal refers to one
+through its `custom` column instead of its `sym` and `seq`.
 */
 use std::collections::HashMap;
 use std::fmt::Write;
 
-use tokio_postgres::{types::Type, Row, Transaction};
-
 use super::{DbError, Store};
-use crate::course::{Chapter, Course};
+use crate::course::{Chapter, Course, Custom};
+use crate::db::{Row, Transaction};
 
 fn chapter_from_row(row: &Row) -> Result<Chapter, DbError> {
     Ok(Chapter {
@@ -51,6 +53,17 @@ fn chapter_from_row(row: &Row) -> Result<Chapter, DbError> {
     })
 }
 
+fn custom_from_row(row: &Row) -> Result<Custom, DbError> {
+    Ok(Custom {
+        id: row.try_get("id")?,
+        uname: row.try_get("uname")?,
+        title: row.try_get("title")?,
+        weight: row
+            .try_get::<_, Option<f32>>("weight")?
+            .unwrap_or(1.0),
+    })
+}
+
 fn course_from_row(row: &Row) -> Result<Course, DbError> {
     Ok(Course::new(
         row.try_get("id")?,
@@ -73,12 +86,10 @@ impl Store {
 
         let mut client = self.connect().await?;
         let t = client.transaction().await?;
-        let preexisting_sym_query = t
-            .prepare_typed(
-                "SELECT sym, title FROM courses WHERE sym = ANY($1)",
-                &[Type::TEXT_ARRAY],
-            )
-            .await?;
+        let preexisting_sym_query = format!(
+            "SELECT sym, title FROM courses WHERE {}",
+            t.dialect().in_array("sym", "$1")
+        );
 
         // Check to see if any of our new courses are duplicating `sym`bols
         // already in use and return with an informative error if so.
@@ -101,22 +112,12 @@ impl Store {
             return Err(DbError(estr));
         }
 
-        let insert_course_query = t
-            .prepare_typed(
-                "INSERT INTO courses (sym, book, title, level)
+        let insert_course_query = "INSERT INTO courses (sym, book, title, level)
                 VALUES ($1, $2, $3, $4)
-                RETURNING id",
-                &[Type::TEXT, Type::TEXT, Type::TEXT, Type::FLOAT4],
-            )
-            .await?;
-        let insert_chapter_query = t
-            .prepare_typed(
-                "INSERT INTO chapters
+                RETURNING id";
+        let insert_chapter_query = "INSERT INTO chapters
                 (course, sequence, title, subject, weight)
-                VALUES ($1, $2, $3, $4, $5)",
-                &[Type::INT8, Type::INT2, Type::TEXT, Type::TEXT, Type::FLOAT4],
-            )
-            .await?;
+                VALUES ($1, $2, $3, $4, $5)";
 
         let mut n_courses: usize = 0;
         let mut n_chapters: u64 = 0;
@@ -126,7 +127,7 @@ impl Store {
         for crs in courses.iter() {
             let row = t
                 .query_one(
-                    &insert_course_query,
+                    insert_course_query,
                     &[&crs.sym, &crs.book, &crs.title, &crs.level],
                 )
                 .await?;
@@ -136,7 +137,7 @@ impl Store {
             for ch in crs.all_chapters() {
                 let n = t
                     .execute(
-                        &insert_chapter_query,
+                        insert_chapter_query,
                         &[&id, &ch.seq, &ch.title, &ch.subject, &ch.weight],
                     )
                     .await?;
@@ -178,14 +179,9 @@ impl Store {
         let mut client = self.connect().await?;
         let t = client.transaction().await?;
 
-        let insert_chapter_query = t
-            .prepare_typed(
-                "INSERT INTO chapters
+        let insert_chapter_query = "INSERT INTO chapters
                 (course, sequence, title, subject, weight)
-                VALUES ($1, $2, $3, $4, $5)",
-                &[Type::INT8, Type::INT2, Type::TEXT, Type::TEXT, Type::FLOAT4],
-            )
-            .await?;
+                VALUES ($1, $2, $3, $4, $5)";
 
         let mut n_chapters: u64 = 0;
 
@@ -194,7 +190,7 @@ impl Store {
         for ch in chapters.iter() {
             let n = t
                 .execute(
-                    &insert_chapter_query,
+                    insert_chapter_query,
                     &[&ch.course_id, &ch.seq, &ch.title, &ch.subject, &ch.weight],
                 )
                 .await?;
@@ -378,6 +374,99 @@ impl Store {
 
         Ok(course_map)
     }
+
+    /// Insert a new [`Custom`] chapter, returning it with its `id` set.
+    pub async fn insert_custom_chapter(&self, cust: &Custom) -> Result<Custom, DbError> {
+        log::trace!("Store::insert_custom_chapter( {:?} ) called.", cust);
+
+        let client = self.connect().await?;
+
+        let row = client
+            .query_one(
+                "INSERT INTO custom_chapters (uname, title, weight)
+                VALUES ($1, $2, $3)
+                RETURNING id",
+                &[&cust.uname, &cust.title, &cust.weight],
+            )
+            .await?;
+
+        let mut new = cust.clone();
+        new.id = row.try_get("id")?;
+        Ok(new)
+    }
+
+    /// Update the title and weight of the [`Custom`] chapter with the id
+    /// `cust.id`.
+    pub async fn update_custom_chapter(&self, cust: &Custom) -> Result<(), DbError> {
+        log::trace!("Store::update_custom_chapter( {:?} ) called.", cust);
+
+        let client = self.connect().await?;
+
+        let n = client
+            .execute(
+                "UPDATE custom_chapters SET title = $1, weight = $2
+                WHERE id = $3",
+                &[&cust.title, &cust.weight, &cust.id],
+            )
+            .await?;
+
+        if n == 0 {
+            return Err(DbError(format!("No custom chapter with id {}.", &cust.id)));
+        }
+
+        Ok(())
+    }
+
+    /// Delete the [`Custom`] chapter with the given `id`.
+    ///
+    /// This will fail if any [`Goal`](crate::pace::Goal)s (from any year)
+    /// use it.
+    pub async fn delete_custom_chapter(&self, id: i64) -> Result<(), DbError> {
+        log::trace!("Store::delete_custom_chapter( {} ) called.", &id);
+
+        let mut client = self.connect().await?;
+        let t = client.transaction().await?;
+
+        let row = t
+            .query_one(
+                "SELECT COUNT(*) AS n FROM goals WHERE custom = $1",
+                &[&id],
+            )
+            .await?;
+        let n_goals: i64 = row.try_get("n")?;
+        if n_goals > 0 {
+            return Err(DbError(format!(
+                "This custom chapter is used by {} Goal(s), and can't be deleted.",
+                &n_goals
+            )));
+        }
+
+        let n = t
+            .execute("DELETE FROM custom_chapters WHERE id = $1", &[&id])
+            .await?;
+        if n == 0 {
+            return Err(DbError(format!("No custom chapter with id {}.", &id)));
+        }
+
+        t.commit().await?;
+        Ok(())
+    }
+
+    /// Return a HashMap of all [`Custom`] chapters in the database.
+    pub async fn get_custom_chapters(&self) -> Result<HashMap<i64, Custom>, DbError> {
+        log::trace!("Store::get_custom_chapters() called.");
+
+        let client = self.connect().await?;
+
+        let rows = client.query("SELECT * FROM custom_chapters", &[]).await?;
+        let mut customs: HashMap<i64, Custom> = HashMap::with_capacity(rows.len());
+        for row in rows.iter() {
+            let cust = custom_from_row(row)?;
+            customs.insert(cust.id, cust);
+        }
+
+        Ok(customs)
+    }
 }
 
 #[cfg(test)]
@@ -389,8 +478,7 @@ mod tests {
     use float_cmp::approx_eq;
     use serial_test::serial;
 
-    use crate::store::tests::TEST_CONNECTION;
-    use crate::tests::ensure_logging;
+    use crate::tests::{ensure_logging, test_data_db};
 
     fn same_chapters(a: &Chapter, b: &Chapter) -> bool {
         if a.seq != b.seq {
@@ -442,7 +530,7 @@ mod tests {
 
         let course_vec = vec![cpc, hdg];
 
-        let db = Store::new(TEST_CONNECTION.to_owned());
+        let db = Store::new(test_data_db());
         db.ensure_db_schema().await.unwrap();
 
         let (n_crs, n_chp) = db.insert_courses(&course_vec).await.unwrap();
@@ -474,7 +562,7 @@ mod tests {
             .map(|fname| Course::from_reader(File::open(fname).unwrap()).unwrap())
             .collect();
 
-        let db = Store::new(TEST_CONNECTION.to_owned());
+        let db = Store::new(test_data_db());
         db.ensure_db_schema().await.unwrap();
 
         let (n_crs, n_chap) = db.insert_courses(&loaded_courses).await.unwrap();
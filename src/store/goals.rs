@@ -14,28 +14,38 @@ CREATE TABLE goals (
     due         DATE,
     done        DATE,
     tries       SMALLINT,
-    score   TEXT
+    score       TEXT,
+    year        INT NOT NULL,
+    version     BIGINT NOT NULL DEFAULT 1
 );
 ```
+
+Every Goal belongs to an academic `year` (identified by the year in which it
+starts). The methods here all work with a single year's Goals; the current
+year is [`Glob::academic_year`](crate::config::Glob::academic_year), and
+earlier years are kept around to be looked at.
 */
 use futures::stream::{FuturesUnordered, StreamExt};
-use tokio_postgres::{types::ToSql, types::Type, Row, Transaction};
 
-use super::{DbError, Store};
-use crate::pace::{BookCh, Goal, Source};
+use super::{DbError, Store, Versioned};
+use crate::db::{Row, ToValue, Transaction};
+use crate::pace::{BookCh, CustomCh, Goal, Source};
 
 fn goal_from_row(row: &Row) -> Result<Goal, DbError> {
-    let bkch = BookCh {
-        sym: row.try_get("sym")?,
-        seq: row.try_get("seq")?,
-        // Gets set in the `Pace` constructor.
-        level: 0.0,
+    let source = match row.try_get::<_, Option<i64>>("custom")? {
+        Some(id) => Source::Custom(CustomCh(id)),
+        None => Source::Book(BookCh {
+            sym: row.try_get("sym")?,
+            seq: row.try_get("seq")?,
+            // Gets set in the `Pace` constructor.
+            level: 0.0,
+        }),
     };
 
     Ok(Goal {
         id: row.try_get("id")?,
         uname: row.try_get("uname")?,
-        source: Source::Book(bkch),
+        source,
         review: row.try_get("review")?,
         incomplete: row.try_get("incomplete")?,
         due: row.try_get("due")?,
@@ -44,9 +54,19 @@ fn goal_from_row(row: &Row) -> Result<Goal, DbError> {
         // Gets set in the `Pace` constructor.
         weight: 0.0,
         score: row.try_get("score")?,
+        version: row.try_get("version")?,
     })
 }
 
+/// The values of the `sym`, `seq`, and `custom` columns for a Goal with
+/// the given [`Source`]; the columns that don't apply are NULL.
+fn source_columns(src: &Source) -> (Option<&str>, Option<i16>, Option<i64>) {
+    match src {
+        Source::Book(bch) => (Some(bch.sym.as_str()), Some(bch.seq), None),
+        Source::Custom(CustomCh(id)) => (None, None, Some(*id)),
+    }
+}
+
 impl Store {
     /**
     Insert the supplied [`Goal`]s into the database.
@@ -54,61 +74,42 @@ impl Store {
     [`Glob::insert_goals`](crate::config::Glob::insert_goals) calls this method
     and supplies better error messages; it should be used instead.
     */
-    pub async fn insert_goals(&self, goals: &[Goal]) -> Result<usize, DbError> {
-        log::trace!("Store::insert_goals( [ {} goals ] ) called.", &goals.len());
-
-        // Make copies of all the book `Source`s, and throw an error on custom
-        // ones because we don't support those yet.
-        for g in goals.iter() {
-            if let Source::Custom(_) = &g.source {
-                return Err(DbError("Custom Sources are unsupported.".to_owned()));
-            }
-        }
-        let sources: Vec<BookCh> = goals
-            .iter()
-            .map(|g| match g.source {
-                Source::Book(ref bch) => bch.clone(),
-                _ => panic!("We just checked, and there shouldn't be any Custom Sources."),
-            })
-            .collect();
+    pub async fn insert_goals(&self, goals: &[Goal], year: i32) -> Result<usize, DbError> {
+        log::trace!(
+            "Store::insert_goals( [ {} goals ], {} ) called.",
+            &goals.len(),
+            year
+        );
+
+        let sources: Vec<(Option<&str>, Option<i16>, Option<i64>)> =
+            goals.iter().map(|g| source_columns(&g.source)).collect();
 
         let mut client = self.connect().await?;
         let t = client.transaction().await?;
 
-        let insert_stmt = t
-            .prepare_typed(
-                "INSERT INTO goals (
-                uname, sym, seq, review, incomplete,
-                due, done
+        let insert_stmt = "INSERT INTO goals (
+                uname, sym, seq, custom, review, incomplete,
+                due, done, year
             )
             VALUES (
-                $1, $2, $3, $4, $5,
-                $6, $7
-            )",
-                &[
-                    Type::TEXT,
-                    Type::TEXT,
-                    Type::INT2,
-                    Type::BOOL,
-                    Type::BOOL,
-                    Type::DATE,
-                    Type::DATE,
-                ],
-            )
-            .await?;
+                $1, $2, $3, $4, $5, $6,
+                $7, $8, $9
+            )";
 
-        let pvec: Vec<[&(dyn ToSql + Sync); 7]> = goals
+        let pvec: Vec<[&(dyn ToValue + Sync); 9]> = goals
             .iter()
             .zip(sources.iter())
-            .map(|(g, src)| {
-                let p: [&(dyn ToSql + Sync); 7] = [
+            .map(|(g, (sym, seq, custom))| {
+                let p: [&(dyn ToValue + Sync); 9] = [
                     &g.uname,
-                    &src.sym,
-                    &src.seq,
+                    sym,
+                    seq,
+                    custom,
                     &g.review,
                     &g.incomplete,
                     &g.due,
                     &g.done,
+                    &year,
                 ];
                 p
             })
@@ -118,7 +119,7 @@ impl Store {
         {
             let mut inserts = FuturesUnordered::new();
             for params in pvec.iter() {
-                inserts.push(t.execute(&insert_stmt, params));
+                inserts.push(t.execute(insert_stmt, params));
             }
 
             while let Some(res) = inserts.next().await {
@@ -140,36 +141,33 @@ impl Store {
     }
 
     /// Insert a single [`Goal`].
-    pub async fn insert_one_goal(&self, g: &Goal) -> Result<(), DbError> {
-        log::trace!("Store::insert_one_goal( {:?} ) called.", g);
+    pub async fn insert_one_goal(&self, g: &Goal, year: i32) -> Result<(), DbError> {
+        log::trace!("Store::insert_one_goal( {:?}, {} ) called.", g, year);
 
-        let src = match &g.source {
-            Source::Book(bch) => bch,
-            _ => {
-                return Err(DbError("Custom sources not yet supported.".to_owned()));
-            }
-        };
+        let (sym, seq, custom) = source_columns(&g.source);
 
         let client = self.connect().await?;
 
         client
             .execute(
                 "INSERT INTO goals (
-                uname, sym, seq, review, incomplete,
-                due, done
+                uname, sym, seq, custom, review, incomplete,
+                due, done, year
             )
             VALUES (
-                $1, $2, $3, $4, $5,
-                $6, $7
+                $1, $2, $3, $4, $5, $6,
+                $7, $8, $9
             )",
                 &[
                     &g.uname,
-                    &src.sym,
-                    &src.seq,
+                    &sym,
+                    &seq,
+                    &custom,
                     &g.review,
                     &g.incomplete,
                     &g.due,
                     &g.done,
+                    &year,
                 ],
             )
             .await?;
@@ -177,29 +175,33 @@ impl Store {
         Ok(())
     }
 
-    /// Update the goal in the database with the `id` of  `g.id` with the
-    /// rest of the information in `g`.
-    pub async fn update_goal(&self, g: &Goal) -> Result<(), DbError> {
-        log::trace!("Store_update_goal( {:?} ) called.", g);
+    /**
+    Update the goal in the database with the `id` of  `g.id` with the
+    rest of the information in `g`. Only Goals from the given `year` can
+    be changed.
 
-        let src = match &g.source {
-            Source::Book(bch) => bch,
-            _ => {
-                return Err(DbError("Custom sources not yet supported.".to_owned()));
-            }
-        };
+    Nothing changes (and this returns [`Versioned::Conflict`]) unless the
+    Goal in the database is still at `g.version`.
+    */
+    pub async fn update_goal(&self, g: &Goal, year: i32) -> Result<Versioned, DbError> {
+        log::trace!("Store_update_goal( {:?}, {} ) called.", g, year);
+
+        let (sym, seq, custom) = source_columns(&g.source);
 
         let client = self.connect().await?;
 
-        client
-            .execute(
+        let row = client
+            .query_opt(
                 "UPDATE goals SET
-                sym = $1, seq = $2, review = $3, incomplete = $4,
-                due = $5, done = $6, tries = $7, score = $8
-            WHERE id = $9",
+                sym = $1, seq = $2, custom = $3, review = $4, incomplete = $5,
+                due = $6, done = $7, tries = $8, score = $9,
+                version = version + 1
+            WHERE id = $10 AND year = $11 AND version = $12
+            RETURNING version",
                 &[
-                    &src.sym,
-                    &src.seq,
+                    &sym,
+                    &seq,
+                    &custom,
                     &g.review,
                     &g.incomplete,
                     &g.due,
@@ -207,19 +209,37 @@ impl Store {
                     &g.tries,
                     &g.score,
                     &g.id,
+                    &year,
+                    &g.version,
                 ],
             )
             .await?;
 
-        Ok(())
+        if let Some(row) = row {
+            return Ok(Versioned::Written(row.try_get("version")?));
+        }
+
+        let exists = client
+            .query_opt(
+                "SELECT id FROM goals WHERE id = $1 AND year = $2",
+                &[&g.id, &year],
+            )
+            .await?;
+        match exists {
+            Some(_) => Ok(Versioned::Conflict),
+            None => Err(DbError(format!(
+                "There is no Goal with id {} in the {} academic year.",
+                &g.id, year
+            ))),
+        }
     }
 
     /**
     Update the due dates of the goals in the databases with `id`s that match
     those in `goals` with the due dates from the `Goal`s in `goals.
 
-    This function only affects the due dates of the goals in question; it is
-    used when autopacing a student's calendar.
+    This function only affects the due dates (and versions) of the goals in
+    question; it is used when autopacing a student's calendar.
     */
     pub async fn update_due_dates(&self, goals: &[Goal]) -> Result<usize, DbError> {
         log::trace!("Store::update_goals( [ {} goals] ) called.", &goals.len());
@@ -227,17 +247,12 @@ impl Store {
         let mut client = self.connect().await?;
         let t = client.transaction().await?;
 
-        let update_stmt = t
-            .prepare_typed(
-                "UPDATE goals SET due = $1 WHERE id = $2",
-                &[Type::DATE, Type::INT8],
-            )
-            .await?;
+        let update_stmt = "UPDATE goals SET due = $1, version = version + 1 WHERE id = $2";
 
-        let pvec: Vec<[&(dyn ToSql + Sync); 2]> = goals
+        let pvec: Vec<[&(dyn ToValue + Sync); 2]> = goals
             .iter()
             .map(|g| {
-                let p: [&(dyn ToSql + Sync); 2] = [&g.due, &g.id];
+                let p: [&(dyn ToValue + Sync); 2] = [&g.due, &g.id];
                 p
             })
             .collect();
@@ -246,7 +261,7 @@ impl Store {
         {
             let mut inserts = FuturesUnordered::new();
             for params in pvec.iter() {
-                inserts.push(t.execute(&update_stmt, params));
+                inserts.push(t.execute(update_stmt, params));
             }
 
             while let Some(res) = inserts.next().await {
@@ -266,30 +281,47 @@ impl Store {
         Ok(n_changed as usize)
     }
 
-    /// Delete the goal with the given `id` from the database.
-    pub async fn delete_goal(&self, id: i64) -> Result<String, DbError> {
-        log::trace!("Store::delete_goal( {} ) called.", &id);
+    /// Delete the goal with the given `id` (which must be from the given
+    /// `year`) from the database.
+    pub async fn delete_goal(&self, id: i64, year: i32) -> Result<String, DbError> {
+        log::trace!("Store::delete_goal( {}, {} ) called.", &id, year);
 
         let client = self.connect().await?;
 
         let row = client
-            .query_one("DELETE FROM goals WHERE id = $1 RETURNING uname", &[&id])
-            .await?;
+            .query_opt(
+                "DELETE FROM goals WHERE id = $1 AND year = $2 RETURNING uname",
+                &[&id, &year],
+            )
+            .await?
+            .ok_or_else(|| {
+                DbError(format!(
+                    "There is no Goal with id {} in the {} academic year.",
+                    &id, year
+                ))
+            })?;
 
         let uname: String = row.try_get("uname")?;
 
         Ok(uname)
     }
 
-    /// Fetch all of a student's pace goals and wrap them in a vector of
-    /// [`Goal`]s.
-    pub async fn get_goals_by_student(&self, uname: &str) -> Result<Vec<Goal>, DbError> {
-        log::trace!("Store::get_goals_by_student( {:?} ) called.", uname);
+    /// Fetch all of a student's pace goals for the given `year` and wrap
+    /// them in a vector of [`Goal`]s.
+    pub async fn get_goals_by_student(
+        &self,
+        uname: &str,
+        year: i32,
+    ) -> Result<Vec<Goal>, DbError> {
+        log::trace!("Store::get_goals_by_student( {:?}, {} ) called.", uname, year);
 
         let client = self.connect().await?;
 
         let rows = client
-            .query("SELECT * FROM goals WHERE uname = $1", &[&uname])
+            .query(
+                "SELECT * FROM goals WHERE uname = $1 AND year = $2",
+                &[&uname, &year],
+            )
             .await?;
 
         let mut goals: Vec<Goal> = Vec::with_capacity(rows.len());
@@ -310,27 +342,40 @@ impl Store {
         Ok(goals)
     }
 
-    /// Delete all of a student's pace goals.
+    /// Delete all of a student's pace goals for the given `year`.
     pub async fn delete_goals_by_student(
         &self,
         t: &Transaction<'_>,
         uname: &str,
+        year: i32,
     ) -> Result<usize, DbError> {
-        log::trace!("Store::delete_goals_by_student( {:?} ) called.", uname);
+        log::trace!(
+            "Store::delete_goals_by_student( {:?}, {} ) called.",
+            uname,
+            year
+        );
 
         let n_goals = t
-            .execute("DELETE FROM goals WHERE uname = $1", &[&uname])
+            .execute(
+                "DELETE FROM goals WHERE uname = $1 AND year = $2",
+                &[&uname, &year],
+            )
             .await?;
 
         Ok(n_goals as usize)
     }
 
-    /// Retrieve all of the goals of students who have the given teacher.
+    /// Retrieve all of the goals for the given `year` of students who
+    /// (currently) have the given teacher.
     ///
     /// This is used, among other things, to fetch data for the teacher's
     /// view.
-    pub async fn get_goals_by_teacher(&self, tuname: &str) -> Result<Vec<Goal>, DbError> {
-        log::trace!("Store::get_goals_by_teacher( {:?} ) called.", tuname);
+    pub async fn get_goals_by_teacher(
+        &self,
+        tuname: &str,
+        year: i32,
+    ) -> Result<Vec<Goal>, DbError> {
+        log::trace!("Store::get_goals_by_teacher( {:?}, {} ) called.", tuname, year);
 
         let client = self.connect().await?;
 
@@ -338,12 +383,12 @@ impl Store {
             .query(
                 "SELECT
                 id, goals.uname, sym, seq, custom, review, incomplete,
-                due, done, tries, score
+                due, done, tries, score, goals.version
             FROM
                 goals INNER JOIN students ON goals.uname = students.uname
             WHERE
-                students.teacher = $1",
-                &[&tuname],
+                students.teacher = $1 AND goals.year = $2",
+                &[&tuname, &year],
             )
             .await?;
 
@@ -362,12 +407,32 @@ impl Store {
         Ok(goals)
     }
 
-    /// Clear all student Goal data for the year.
-    pub async fn yearly_clear_goals(t: &Transaction<'_>) -> Result<(), DbError> {
-        log::trace!("Store::yearly_clear_goals( [ T ] ) called.");
+    /**
+    The academic years (by starting year) for which there are any Goals or
+    report data, in order.
+    */
+    pub async fn get_academic_years(&self) -> Result<Vec<i32>, DbError> {
+        log::trace!("Store::get_academic_years() called.");
 
-        let _ = t.execute("DELETE FROM goals", &[]).await?;
+        let client = self.connect().await?;
 
-        Ok(())
+        let rows = client
+            .query(
+                "SELECT year FROM goals
+                UNION SELECT year FROM facts
+                UNION SELECT year FROM social
+                UNION SELECT year FROM drafts
+                UNION SELECT year FROM reports
+                ORDER BY year",
+                &[],
+            )
+            .await?;
+
+        let mut years: Vec<i32> = Vec::with_capacity(rows.len());
+        for row in rows.iter() {
+            years.push(row.try_get("year")?);
+        }
+
+        Ok(years)
     }
 }
@@ -5,11 +5,16 @@ TODO:
   * Better `.map_err()` annotations.
 
 */
-use std::fmt::Write;
-
+use once_cell::sync::OnceCell;
 use rand::{distributions, Rng};
-use tokio_postgres::{Client, NoTls};
 
+use crate::{
+    db::{self, Backend, Client},
+    migrate,
+    pool::PoolConfig,
+};
+
+mod audit;
 mod cal;
 mod courses;
 mod goals;
@@ -19,183 +24,35 @@ mod users;
 const DEFAULT_SALT_LENGTH: usize = 4;
 const DEFAULT_SALT_CHARS: &str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
 
-static SCHEMA: &[(&str, &str, &str)] = &[
-    // Three tables of course info: courses, chapters, and custom "chapters".
-    (
-        "SELECT FROM information_schema.tables WHERE table_name = 'courses'",
-        "CREATE TABLE courses (
-            id    BIGSERIAL PRIMARY KEY,
-            sym   TEXT UNIQUE NOT NULL,
-            title TEXT NOT NULL,
-            book  TEXT,
-            level REAL
-        )",
-        "DROP TABLE courses",
-    ),
-    (
-        "SELECT FROM information_schema.tables WHERE table_name = 'chapters'",
-        "CREATE TABLE chapters (
-            id          BIGSERIAL PRIMARY KEY,
-            course      BIGINT REFERENCES courses(id),
-            sequence    SMALLINT,
-            title       TEXT,   /* default is generated 'Chapter N' title */
-            subject     TEXT,   /* default is blank */
-            weight      REAL    /* default is 1.0 */
-        )",
-        "DROP TABLE chapters",
-    ),
-    (
-        "SELECT FROM information_schema.tables WHERE table_name = 'custom_chapters'",
-        "CREATE TABLE custom_chapters (
-            id      BIGSERIAL PRIMARY KEY,
-            uname   TEXT,   /* REFERENCES user(uname), when 'users' table available */
-            title   TEXT NOT NULL,
-            weight  REAL    /* default should be 1.0 */
-        )",
-        "DROP TABLE custom_chapters",
-    ),
-    /*
-    Three tables for users: users, teachers, students.
-
-    Admin and Boss roles require nothing beyond data in the users table.
-    Extra info for Teachers and Students in their respective tables.
-    */
-    (
-        "SELECT FROM information_schema.tables WHERE table_name = 'users'",
-        "CREATE TABLE users (
-            uname TEXT PRIMARY KEY,
-            role  TEXT NOT NULL,
-            salt  TEXT,
-            email TEXT
-        )",
-        "DROP TABLE users",
-    ),
-    (
-        "SELECT FROM information_schema.tables WHERE table_name = 'teachers'",
-        "CREATE TABLE teachers (
-            uname TEXT UNIQUE REFERENCES users(uname),
-            name  TEXT
-        )",
-        "DROP TABLE teachers",
-    ),
-    (
-        "SELECT FROM information_schema.tables WHERE table_name = 'students'",
-        "CREATE TABLE students (
-            uname   TEXT UNIQUE REFERENCES users(uname),
-            last    TEXT,
-            rest    TEXT,
-            teacher TEXT REFERENCES teachers(uname),
-            parent  TEXT,     /* parent email address */
-            fall_exam TEXT,
-            spring_exam TEXT,
-            fall_exam_fraction REAL,
-            spring_exam_fraction REAL,
-            fall_notices SMALLINT,
-            spring_notices SMALLINT
-        )",
-        "DROP TABLE students",
-    ),
-    /*
-    Two tables of calendar info.
-
-    calendar holds just a list of "working days" as used by the autopacer.
-    dates holds special dates (so far this is just the ends of the Spring
-    and Fall Semesters).
-    */
-    (
-        "SELECT FROM information_schema.tables WHERE table_name = 'calendar'",
-        "CREATE TABLE calendar ( day DATE UNIQUE NOT NULL )",
-        "DROP TABLE calendar",
-    ),
-    (
-        "SELECT FROM information_schema.tables WHERE table_name = 'dates'",
-        "CREATE TABLE dates (
-            name TEXT PRIMARY KEY,
-            day DATE NOT NULL
-        )",
-        "DROP TABLE dates",
-    ),
-    // Student pace goals.
-    (
-        "SELECT FROM information_schema.tables WHERE table_name = 'goals'",
-        "CREATE TABLE goals (
-            id          BIGSERIAL PRIMARY KEY,
-            uname       TEXT REFERENCES students(uname),
-            sym         TEXT REFERENCES courses(sym),
-            seq         SMALLINT,
-            custom      BIGINT REFERENCES custom_chapters(id),
-            review      BOOL,
-            incomplete  BOOL,
-            due         DATE,
-            done        DATE,
-            tries       SMALLINT,
-            score       TEXT
-        )",
-        "DROP TABLE goals",
-    ),
-    // Report writing extraness.
-    (
-        "SELECT FROM information_schema.tables WHERE table_name = 'nmr'",
-        "CREATE TABLE nmr (
-            id      BIGINT PRIMARY KEY REFERENCES goals(id),
-            status  TEXT    /* one of { NULL, 'M', 'R' } */
-        )",
-        "DROP TABLE nmr",
-    ),
-    (
-        "SELECT FROM information_schema.tables WHERE table_name = 'facts'",
-        "CREATE TABLE facts (
-            uname   TEXT REFERENCES students(uname),
-            add     TEXT,
-            sub     TEXT,
-            mul     TEXT,
-            div     TEXT
-        )",
-        "DROP TABLE facts",
-    ),
-    (
-        "SELECT FROM information_schema.tables WHERE table_name = 'social'",
-        "CREATE TABLE social (
-            uname   TEXT REFERENCES students(uname),
-            term    TEXT,
-            trait   TEXT,
-            score   TEXT    /* 1- (worst) to 3+ (best) */
-        )",
-        "DROP TABLE social",
-    ),
-    (
-        "SELECT FROM information_schema.tables WHERE table_name = 'completion'",
-        "CREATE TABLE completion (
-            uname   TEXT REFERENCES students(uname),
-            term    TEXT,
-            courses TEXT REFERENCES courses(sym),
-            year    INT
-        )",
-        "DROP TABLE completion",
-    ),
-    (
-        "SELECT FROM information_schema.tables WHERE table_name = 'drafts'",
-        "CREATE TABLE drafts (
-            uname   TEXT REFERENCES students(uname),
-            term    TEXT,
-            draft   TEXT
-        )",
-        "DROP TABLE drafts",
-    ),
-    (
-        "SELECT FROM information_schema.tables WHERE table_name = 'reports'",
-        "CREATE TABLE reports (
-            uname   TEXT REFERENCES students(uname),
-            term    TEXT,
-            doc     bytea
-        )",
-        "DROP TABLE reports",
-    ),
+/// Every table in the data DB, in the order they were created, so they can be
+/// dropped in reverse. (The schema itself is defined by the migrations in
+/// `migrations/data/`; see [`crate::migrate`].)
+#[cfg(any(test, feature = "fake"))]
+static TABLES: &[&str] = &[
+    "courses",
+    "chapters",
+    "custom_chapters",
+    "users",
+    "teachers",
+    "students",
+    "parents",
+    "calendar",
+    "dates",
+    "goals",
+    "nmr",
+    "facts",
+    "social",
+    "completion",
+    "drafts",
+    "reports",
+    "audit",
+    "sidecars",
+    "schema_version",
 ];
 
 /**
 Errors returned by [`Store`] methods. Usually these are just wrapped
-[`tokio_postgres`] errors (with possibly some additional context).
+[`db::Error`]s (with possibly some additional context).
 */
 #[derive(Debug, PartialEq)]
 pub struct DbError(String);
@@ -212,13 +69,9 @@ impl DbError {
     }
 }
 
-impl From<tokio_postgres::error::Error> for DbError {
-    fn from(e: tokio_postgres::error::Error) -> DbError {
-        let mut s = format!("Data DB: {}", &e);
-        if let Some(dbe) = e.as_db_error() {
-            write!(&mut s, "; {}", dbe).unwrap();
-        }
-        DbError(s)
+impl From<db::Error> for DbError {
+    fn from(e: db::Error) -> DbError {
+        DbError(format!("Data DB: {}", &e))
     }
 }
 
@@ -247,14 +100,27 @@ impl From<DbError> for String {
 }
 
 /**
-Endpoint for interacting with the underlying Postgres store.
+What became of a write that only goes through if the row(s) it changes are
+still at the version the writer read them at.
+
+Goals, Students, and report sidecars carry a `version` that's bumped with
+every change; the methods that update them take the version the caller
+last saw, and change nothing if it's been changed since.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Versioned {
+    /// The write went through; this is the new version.
+    Written(i64),
+    /// Someone else got there first; nothing was written.
+    Conflict,
+}
+
+/**
+Endpoint for interacting with the underlying data store (Postgres or SQLite;
+see [`crate::db`]).
 
-As it stands, each "interaction" with the database will open (and when
-finished close) a new connection. This is generally regarded as less
-performant and involves more out-of-band data exchange than reusing
-connections or using connection pools, but it is _way simpler_ to implement,
-so unless and until there's a burning need to change it, it stays
-inefficient but simple.
+Each "interaction" with the database borrows a connection (from a pool, in
+the case of Postgres; see [`crate::pool`]), which it returns when finished.
 
 Some methods take as one of their arguments an [`&Transaction`](Transaction). These are
 meant to be used in operations that may require multiple queries or
@@ -271,6 +137,8 @@ for an example of this.
 */
 pub struct Store {
     connection_string: String,
+    pool_config: PoolConfig,
+    backend: OnceCell<Box<dyn Backend>>,
     salt_chars: Vec<char>,
     salt_length: usize,
 }
@@ -284,11 +152,20 @@ impl Store {
 
         Self {
             connection_string,
+            pool_config: PoolConfig::default(),
+            backend: OnceCell::new(),
             salt_chars,
             salt_length,
         }
     }
 
+    /// Set the parameters of the connection pool.
+    ///
+    /// Has no effect once a connection has been made.
+    pub fn set_pool_config(&mut self, cfg: PoolConfig) {
+        self.pool_config = cfg;
+    }
+
     /// Set characters to use when generating user salt strings.
     ///
     /// Will quietly do nothing if `new_chars` has zero length.
@@ -318,7 +195,8 @@ impl Store {
     }
 
     /**
-    Return a connection to the underlying Postgres store.
+    Return a connection to the underlying store; with Postgres, it's pooled,
+    and goes back into the pool when dropped.
 
     This connection should only ever be used to instantiate a
     [`Transaction`] for use in one of the `Store` methods that requires one:
@@ -336,16 +214,14 @@ impl Store {
             &self.connection_string
         );
 
-        match tokio_postgres::connect(&self.connection_string, NoTls).await {
-            Ok((client, connection)) => {
+        let backend = self
+            .backend
+            .get_or_try_init(|| db::open(&self.connection_string, &self.pool_config))
+            .map_err(|e| DbError::from(e).annotate("Unable to connect"))?;
+
+        match backend.connect().await {
+            Ok(client) => {
                 log::trace!("    ...connection successful.");
-                tokio::spawn(async move {
-                    if let Err(e) = connection.await {
-                        log::error!("Data DB connection error: {}", &e);
-                    } else {
-                        log::trace!("tokio connection runtime drops.");
-                    }
-                });
                 Ok(client)
             }
             Err(e) => {
@@ -357,8 +233,23 @@ impl Store {
     }
 
     /**
-    Ensure that the underlying Postgres store contains all the necessary
-    tables.
+    Start listening for notifications on `channel` (see [`db::Listener`]),
+    on a connection of its own.
+
+    Returns `Ok(None)` if the underlying store isn't Postgres, which is the
+    only backend with notifications.
+    */
+    pub async fn listen(&self, channel: &str) -> Result<Option<db::Listener>, DbError> {
+        log::trace!("Store::listen( {:?} ) called.", channel);
+
+        db::listen(&self.connection_string, &self.pool_config, channel)
+            .await
+            .map_err(|e| DbError::from(e).annotate("Unable to listen"))
+    }
+
+    /**
+    Ensure that the underlying store contains all the necessary
+    tables, by applying any pending [migrations](crate::migrate).
 
     This should be called when the container starts up, but is also useful
     in setting up testing environments.
@@ -366,25 +257,55 @@ impl Store {
     pub async fn ensure_db_schema(&self) -> Result<(), DbError> {
         log::trace!("Store::ensure_db_schema() called.");
 
+        self.migrate(false).await.map(|_| ())
+    }
+
+    /**
+    Apply any pending [migrations](crate::migrate) to the data DB, in a
+    single transaction, returning the versions applied.
+
+    If `dry_run` is `true`, the transaction is rolled back instead of
+    committed.
+    */
+    pub async fn migrate(&self, dry_run: bool) -> Result<Vec<i32>, DbError> {
+        log::trace!("Store::migrate( {:?} ) called.", &dry_run);
+
         let mut client = self.connect().await?;
         let t = client
             .transaction()
             .await
             .map_err(|e| DbError::from(e).annotate("Data DB unable to begin transaction"))?;
 
-        for (test_stmt, create_stmt, _) in SCHEMA.iter() {
-            if t.query_opt(test_stmt.to_owned(), &[]).await?.is_none() {
-                log::info!(
-                    "{:?} returned no results; attempting to insert table.",
-                    test_stmt
-                );
-                t.execute(create_stmt.to_owned(), &[]).await?;
-            }
+        let applied = migrate::run(&t, migrate::data_migrations(t.dialect()))
+            .await
+            .map_err(|e| DbError(e).annotate("Data DB"))?;
+
+        if dry_run {
+            t.rollback()
+                .await
+                .map_err(|e| DbError::from(e).annotate("Error rolling back transaction"))?;
+        } else {
+            t.commit()
+                .await
+                .map_err(|e| DbError::from(e).annotate("Error committing transaction"))?;
         }
 
-        t.commit()
+        Ok(applied)
+    }
+
+    /// Report the state of each of the data DB's [migrations](crate::migrate).
+    pub async fn migration_status(&self) -> Result<Vec<migrate::Status>, DbError> {
+        log::trace!("Store::migration_status() called.");
+
+        let mut client = self.connect().await?;
+        let t = client
+            .transaction()
+            .await
+            .map_err(|e| DbError::from(e).annotate("Data DB unable to begin transaction"))?;
+
+        migrate::status(&t, migrate::data_migrations(t.dialect()))
             .await
-            .map_err(|e| DbError::from(e).annotate("Error committing transaction"))
+            .map_err(|e| DbError(e).annotate("Data DB"))
     }
 
     /**
@@ -399,8 +320,9 @@ impl Store {
 
         let client = self.connect().await?;
 
-        for (_, _, drop_stmt) in SCHEMA.iter().rev() {
-            if let Err(e) = client.execute(drop_stmt.to_owned(), &[]).await {
+        for table in TABLES.iter().rev() {
+            let drop_stmt = format!("DROP TABLE {}", table);
+            if let Err(e) = client.execute(drop_stmt.as_str(), &[]).await {
                 let err = DbError::from(e);
                 log::error!("Error dropping: {:?}: {}", &drop_stmt, &err.display());
             }
@@ -415,8 +337,8 @@ impl Store {
 mod tests {
     /*!
     These tests assume you have a Postgres instance running on your local
-    machine with resources named according to what you see in the
-    `static TEST_CONNECTION &str`:
+    machine with resources named according to what you see in
+    [`crate::tests::test_data_db`]:
 
     ```text
     user: camp_test
@@ -426,15 +348,15 @@ mod tests {
 
     database: camp_store_test
     ```
+
+    or that the `CAMP_TEST_DATA_DB` environment variable points at some
+    other database (like an SQLite file).
     */
     use super::*;
-    use crate::tests::ensure_logging;
+    use crate::tests::{ensure_logging, test_data_db};
 
     use serial_test::serial;
 
-    pub static TEST_CONNECTION: &str =
-        "host=localhost user=camp_test password='camp_test' dbname=camp_store_test";
-
     /**
     This function is for getting the database back in a blank slate state if
     a test panics partway through and leaves it munged.
@@ -448,7 +370,7 @@ mod tests {
     #[serial]
     async fn reset_store() {
         ensure_logging();
-        let db = Store::new(TEST_CONNECTION.to_owned());
+        let db = Store::new(test_data_db());
         db.nuke_database().await.unwrap();
     }
 
@@ -457,8 +379,62 @@ mod tests {
     async fn create_store() {
         ensure_logging();
 
-        let db = Store::new(TEST_CONNECTION.to_owned());
+        let db = Store::new(test_data_db());
         db.ensure_db_schema().await.unwrap();
         db.nuke_database().await.unwrap();
     }
+
+    #[tokio::test]
+    #[serial]
+    async fn migrations() {
+        ensure_logging();
+
+        let db = Store::new(test_data_db());
+        db.ensure_db_schema().await.unwrap();
+        let client = db.connect().await.unwrap();
+        let migrations = migrate::data_migrations(client.dialect());
+        let statuses = db.migration_status().await.unwrap();
+        assert_eq!(statuses.len(), migrations.len());
+        assert!(statuses
+            .iter()
+            .all(|s| s.state == migrate::State::Applied && s.applied.is_some()));
+
+        // Nothing left to do the second time around.
+        assert!(db.migrate(true).await.unwrap().is_empty());
+        db.ensure_db_schema().await.unwrap();
+
+        // A migration that has changed since it was applied stops everything.
+        client
+            .execute(
+                "UPDATE schema_version SET checksum = 'nope' WHERE version = 1",
+                &[],
+            )
+            .await
+            .unwrap();
+        let statuses = db.migration_status().await.unwrap();
+        assert_eq!(statuses[0].state, migrate::State::Modified);
+        assert!(db.ensure_db_schema().await.is_err());
+
+        // As does one this version doesn't know about.
+        client
+            .execute(
+                "UPDATE schema_version SET checksum = $1 WHERE version = 1",
+                &[&migrations[0].checksum()],
+            )
+            .await
+            .unwrap();
+        client
+            .execute(
+                "INSERT INTO schema_version (version, name, checksum)
+                VALUES (9999, 'future', 'whatever')",
+                &[],
+            )
+            .await
+            .unwrap();
+        let statuses = db.migration_status().await.unwrap();
+        assert_eq!(statuses.last().unwrap().state, migrate::State::Unknown);
+        assert!(db.ensure_db_schema().await.is_err());
+
+        db.nuke_database().await.unwrap();
+    }
 }
@@ -11,14 +11,16 @@ CREATE TABLE facts (
     add     TEXT,
     sub     TEXT,
     mul     TEXT,
-    div     TEXT
+    div     TEXT,
+    year    INT NOT NULL
 );
 
 CREATE TABLE social (
     uname   TEXT REFERENCES students(uname),
     term    TEXT,   /* one of { 'Fall', 'Spring', 'Summer' } */
     trait   TEXT,
-    score   TEXT    /* 1- (worst) to 3+ (best) */
+    score   TEXT,   /* 1- (worst) to 3+ (best) */
+    year    INT NOT NULL
 );
 
 CREATE TABLE completion (
@@ -31,14 +33,29 @@ CREATE TABLE completion (
 CREATE TABLE drafts (
     uname   TEXT REFERENCES students(uname),
     term    TEXT,
-    draft   TEXT
+    draft   TEXT,
+    year    INT NOT NULL
 );
 
 CREATE TABLE reports (
     uname   TEXT REFERENCES students(uname),
     term    TEXT,
-    doc     bytea
+    doc     bytea,
+    year    INT NOT NULL
 );
+
+CREATE TABLE sidecars (
+    uname   TEXT REFERENCES students(uname),
+    year    INT NOT NULL,
+    version BIGINT NOT NULL,
+    PRIMARY KEY (uname, year)
+);
+
+Everything is kept by academic year (the year in which it starts); `nmr`
+rows belong to the year of their Goal.
+
+A [`ReportSidecar`] is assembled from `facts`, `social`, `completion`, and
+`nmr`; its version (see [`Versioned`]) lives in `sidecars`.
 */
 use std::{
     collections::HashMap,
@@ -50,14 +67,10 @@ use futures::{
     stream::{FuturesUnordered, StreamExt},
     try_join,
 };
-use tokio_postgres::{
-    types::{ToSql, Type},
-    Row, Transaction,
-};
-
-use super::{DbError, Store};
+use super::{DbError, Store, Versioned};
 use crate::{
     blank_string_means_none,
+    db::{Row, ToValue, Transaction},
     hist::HistEntry,
     pace::Term, report::*,
 };
@@ -74,41 +87,41 @@ fn row2mastery(row: &Row) -> Result<Mastery, DbError> {
 }
 
 impl Store {
-    pub async fn set_mastery(t: &Transaction<'_>, stati: &[Mastery]) -> Result<usize, DbError> {
-        log::trace!("Store::set_mastery( [ &T ], {:?} ) called.", &stati);
-
-        let update_statement = t
-            .prepare_typed(
-                "INSERT INTO nmr (id, status)
-                VALUES ($1, $2)
-                ON CONFLICT ON CONSTRAINT nmr_pkey
-                DO UPDATE SET status = $2",
-                &[Type::INT8, Type::TEXT],
-            )
-            .await?;
+    pub async fn set_mastery(
+        t: &Transaction<'_>,
+        year: i32,
+        stati: &[Mastery],
+    ) -> Result<usize, DbError> {
+        log::trace!("Store::set_mastery( [ &T ], {}, {:?} ) called.", year, &stati);
+
+        // Only the statuses of the given year's Goals get set.
+        let update_statement = "INSERT INTO nmr (id, status)
+                SELECT id, $2 FROM goals WHERE id = $1 AND year = $3
+                ON CONFLICT (id)
+                DO UPDATE SET status = $2";
 
         let mastery_strs: Vec<Option<&str>> = stati.iter().map(|m| m.status.as_sql()).collect();
 
         let mut n_set: usize = 0;
         {
-            let data_refs: Vec<[&(dyn ToSql + Sync); 2]> = stati
+            let data_refs: Vec<[&(dyn ToValue + Sync); 3]> = stati
                 .iter()
                 .enumerate()
                 .map(|(n, m)| {
-                    let p: [&(dyn ToSql + Sync); 2] = [&m.id, &mastery_strs[n]];
+                    let p: [&(dyn ToValue + Sync); 3] = [&m.id, &mastery_strs[n], &year];
                     p
                 })
                 .collect();
 
             let mut inserts = FuturesUnordered::new();
             for params in data_refs.iter() {
-                inserts.push(t.execute(&update_statement, &params[..]));
+                inserts.push(t.execute(update_statement, &params[..]));
             }
 
             while let Some(res) = inserts.next().await {
                 match res {
-                    Ok(_) => {
-                        n_set += 1;
+                    Ok(n) => {
+                        n_set += n as usize;
                     }
                     Err(e) => {
                         let estr = format!("Error updating Goal mastery status: {}", &e);
@@ -121,15 +134,19 @@ impl Store {
         Ok(n_set)
     }
 
-    pub async fn get_mastery(t: &Transaction<'_>, uname: &str) -> Result<Vec<Mastery>, DbError> {
-        log::trace!("Store::get_mastery( [ &T ], {:?} ) called.", uname);
+    pub async fn get_mastery(
+        t: &Transaction<'_>,
+        uname: &str,
+        year: i32,
+    ) -> Result<Vec<Mastery>, DbError> {
+        log::trace!("Store::get_mastery( [ &T ], {:?}, {} ) called.", uname, year);
 
         let rows = t
             .query(
                 "SELECT goals.id, status FROM nmr
                 INNER JOIN goals ON nmr.id = goals.id
-            WHERE goals.uname = $1",
-                &[&uname],
+            WHERE goals.uname = $1 AND goals.year = $2",
+                &[&uname, &year],
             )
             .await?;
 
@@ -143,14 +160,18 @@ impl Store {
         Ok(masteries)
     }
 
-    pub async fn get_facts(t: &Transaction<'_>, uname: &str) -> Result<FactSet, DbError> {
-        log::trace!("Store::get_facts( [ &T ], {:?} ) called.", uname);
+    pub async fn get_facts(
+        t: &Transaction<'_>,
+        uname: &str,
+        year: i32,
+    ) -> Result<FactSet, DbError> {
+        log::trace!("Store::get_facts( [ &T ], {:?}, {} ) called.", uname, year);
 
         let opt = t
             .query_opt(
-                "SELECT add, sub, mul, div FROM facts
-                WHERE uname = $1",
-                &[&uname],
+                "SELECT \"add\", sub, mul, div FROM facts
+                WHERE uname = $1 AND year = $2",
+                &[&uname, &year],
             )
             .await?;
 
@@ -175,40 +196,46 @@ impl Store {
     pub async fn set_facts(
         t: &Transaction<'_>,
         uname: &str,
+        year: i32,
         facts: &FactSet,
     ) -> Result<(), DbError> {
         log::trace!(
-            "Store::set_facts( [ &T ], {:?}, {:?} ) called.",
+            "Store::set_facts( [ &T ], {:?}, {}, {:?} ) called.",
             uname,
+            year,
             facts
         );
 
         let opt = t
-            .query_opt("SELECT FROM facts WHERE uname = $1", &[&uname])
+            .query_opt(
+                "SELECT 1 FROM facts WHERE uname = $1 AND year = $2",
+                &[&uname, &year],
+            )
             .await?;
 
-        let params: [&(dyn ToSql + Sync); 5] = [
+        let params: [&(dyn ToValue + Sync); 6] = [
             &facts.add.as_str(),
             &facts.sub.as_str(),
             &facts.mul.as_str(),
             &facts.div.as_str(),
             &uname,
+            &year,
         ];
 
         match opt {
             Some(_row) => {
                 t.execute(
                     "UPDATE facts SET
-                        add = $1, sub = $2, mul = $3, div = $4
-                        WHERE uname = $5",
+                        \"add\" = $1, sub = $2, mul = $3, div = $4
+                        WHERE uname = $5 AND year = $6",
                     &params,
                 )
                 .await?;
             }
             None => {
                 t.execute(
-                    "INSERT INTO facts (add, sub, mul, div, uname)
-                    VALUES ($1, $2, $3, $4, $5)",
+                    "INSERT INTO facts (\"add\", sub, mul, div, uname, year)
+                    VALUES ($1, $2, $3, $4, $5, $6)",
                     &params,
                 )
                 .await?;
@@ -221,45 +248,42 @@ impl Store {
     pub async fn set_social(
         t: &Transaction<'_>,
         uname: &str,
+        year: i32,
         term: Term,
         traits: &HashMap<String, String>,
     ) -> Result<(), DbError> {
         log::trace!(
-            "Store::set_social( [ &T ], {:?}, {:?}, [ &HashMap ] called. data:\n{:?}",
+            "Store::set_social( [ &T ], {:?}, {}, {:?}, [ &HashMap ] called. data:\n{:?}",
             uname,
+            year,
             &term,
             traits
         );
 
         t.execute(
             "DELETE FROM social
-                WHERE uname = $1 AND term = $2",
-            &[&uname, &term.as_str()],
+                WHERE uname = $1 AND term = $2 AND year = $3",
+            &[&uname, &term.as_str(), &year],
         )
         .await?;
 
-        let insert_stmt = t
-            .prepare_typed(
-                "INSERT INTO social (uname, term, trait, score)
-                VALUES ($1, $2, $3, $4)",
-                &[Type::TEXT, Type::TEXT, Type::TEXT, Type::TEXT],
-            )
-            .await?;
+        let insert_stmt = "INSERT INTO social (uname, term, trait, score, year)
+                VALUES ($1, $2, $3, $4, $5)";
 
         let term = term.as_str();
 
         {
-            let params: Vec<[&(dyn ToSql + Sync); 4]> = traits
+            let params: Vec<[&(dyn ToValue + Sync); 5]> = traits
                 .iter()
                 .map(|(k, v)| {
-                    let p: [&(dyn ToSql + Sync); 4] = [&uname, &term, k, v];
+                    let p: [&(dyn ToValue + Sync); 5] = [&uname, &term, k, v, &year];
                     p
                 })
                 .collect();
 
             let mut inserts = FuturesUnordered::new();
             for param in params.iter() {
-                inserts.push(t.execute(&insert_stmt, param));
+                inserts.push(t.execute(insert_stmt, param));
             }
 
             while let Some(res) = inserts.next().await {
@@ -279,19 +303,21 @@ impl Store {
     pub async fn get_social(
         t: &Transaction<'_>,
         uname: &str,
+        year: i32,
         term: Term,
     ) -> Result<HashMap<String, String>, DbError> {
         log::trace!(
-            "Store::get_social( [ &T ], {:?}, {:?} ) called.",
+            "Store::get_social( [ &T ], {:?}, {}, {:?} ) called.",
             uname,
+            year,
             &term
         );
 
         let rows = t
             .query(
                 "SELECT trait, score FROM social
-                WHERE uname = $1 AND term = $2",
-                &[&uname, &term.as_str()],
+                WHERE uname = $1 AND term = $2 AND year = $3",
+                &[&uname, &term.as_str(), &year],
             )
             .await?;
 
@@ -312,7 +338,7 @@ impl Store {
         term: Term,
         courses: &[S],
     ) -> Result<(), DbError>
-    where S: AsRef<str> + ToSql + Debug + Sync
+    where S: AsRef<str> + ToValue + Debug + Sync
     {
         log::trace!(
             "Store::set_completion( [ &T ], {:?}, {:?}, {} {:?} ) called.",
@@ -330,17 +356,12 @@ impl Store {
             "error clearing old completion values: {}", &e
         ))?;
 
-        let insert_statement = t.prepare_typed(
-            "INSERT INTO completion (uname, term, courses, year)
-            VALUES ($1, $2, $3, $4)",
-            &[Type::TEXT, Type::TEXT, Type::TEXT, Type::INT4]
-        ).await.map_err(|e| format!(
-            "error preparing completion insertion statement: {}", &e
-        ))?;
+        let insert_statement = "INSERT INTO completion (uname, term, courses, year)
+            VALUES ($1, $2, $3, $4)";
 
         for crs in courses.iter() {
             t.execute(
-                &insert_statement,
+                insert_statement,
                 &[&uname, &term.as_str(), &crs, &year]
             ).await.map_err(|e| format!(
                 "error inserting completed course {:?}: {}", crs, &e
@@ -371,6 +392,11 @@ impl Store {
             "error inserting course {:?} for uname for term {:?} {}-{}: {}",
             course, &term, year, year+1, &e
         ))?;
+        // The completion lists are part of that year's sidecar.
+        t.execute(
+            "UPDATE sidecars SET version = version + 1 WHERE uname = $1 AND year = $2",
+            &[&uname, &year]
+        ).await?;
 
         Ok(())
     }
@@ -392,6 +418,11 @@ impl Store {
             "error deleting course {:?} for {:?}: {}",
             course, uname, &e
         ))?;
+        // This could have been from any year's sidecar.
+        t.execute(
+            "UPDATE sidecars SET version = version + 1 WHERE uname = $1",
+            &[&uname]
+        ).await?;
 
         Ok(())
     }
@@ -526,11 +557,70 @@ impl Store {
         Ok(map)
     }
 
+    /// The version of `uname`'s report sidecar for `year` (0 if it's never
+    /// been written).
+    async fn get_sidecar_version(
+        t: &Transaction<'_>,
+        uname: &str,
+        year: i32
+    ) -> Result<i64, DbError> {
+        let row = t.query_opt(
+            "SELECT version FROM sidecars WHERE uname = $1 AND year = $2",
+            &[&uname, &year]
+        ).await?;
+
+        match row {
+            Some(row) => Ok(row.try_get("version")?),
+            None => Ok(0),
+        }
+    }
+
+    /**
+    Move `uname`'s `year` sidecar on from `version` to the next version, if
+    it's still at `version`.
+
+    This should happen first thing in the transaction that writes the
+    sidecar, so that a concurrent writer waits on it (and then finds the
+    version has moved on).
+    */
+    async fn claim_sidecar_version(
+        t: &Transaction<'_>,
+        uname: &str,
+        year: i32,
+        version: i64
+    ) -> Result<Versioned, DbError> {
+        let n = if version == 0 {
+            t.execute(
+                "INSERT INTO sidecars (uname, year, version) VALUES ($1, $2, 1)
+                ON CONFLICT DO NOTHING",
+                &[&uname, &year]
+            ).await?
+        } else {
+            t.execute(
+                "UPDATE sidecars SET version = version + 1
+                WHERE uname = $1 AND year = $2 AND version = $3",
+                &[&uname, &year, &version]
+            ).await?
+        };
+
+        if n == 0 {
+            Ok(Versioned::Conflict)
+        } else {
+            Ok(Versioned::Written(version + 1))
+        }
+    }
+
+    /**
+    Write all of `sidecar`'s data for the given `year`.
+
+    Nothing changes (and this returns [`Versioned::Conflict`]) unless the
+    sidecar in the database is still at `sidecar.version`.
+    */
     pub async fn set_report_sidecar(
             &self,
             sidecar: &ReportSidecar,
             year: i32
-        ) -> Result<(), DbError> {
+        ) -> Result<Versioned, DbError> {
         log::trace!("Store::set_report_sidecar( {:?} ) called.", &sidecar.uname);
 
         let uname = &sidecar.uname;
@@ -538,24 +628,30 @@ impl Store {
         let mut client = self.connect().await?;
         let t = client.transaction().await?;
 
+        let version = Store::claim_sidecar_version(&t, uname, year, sidecar.version).await?;
+        if version == Versioned::Conflict {
+            return Ok(version);
+        }
+
         let fact_set = match &sidecar.facts {
             Some(fs) => *fs,
             None => FactSet::default(),
         };
 
         if let Err(e) = tokio::try_join!(
-            Store::set_facts(&t, uname, &fact_set),
-            Store::set_social(&t, uname, Term::Fall, &sidecar.fall_social),
-            Store::set_social(&t, uname, Term::Spring, &sidecar.spring_social),
+            Store::set_facts(&t, uname, year, &fact_set),
+            Store::set_social(&t, uname, year, Term::Fall, &sidecar.fall_social),
+            Store::set_social(&t, uname, year, Term::Spring, &sidecar.spring_social),
             Store::set_completion(&t, uname, year, Term::Fall, &sidecar.fall_complete),
             Store::set_completion(&t, uname, year, Term::Spring, &sidecar.spring_complete),
             Store::set_completion(&t, uname, year, Term::Summer, &sidecar.summer_complete),
-            Store::set_mastery(&t, &sidecar.mastery),
+            Store::set_mastery(&t, year, &sidecar.mastery),
         ) {
             return Err(format!("Unable to write sidecar data to database: {}", &e).into());
         }
 
-        t.commit().await.map_err(|e| e.into())
+        t.commit().await?;
+        Ok(version)
     }
 
     pub async fn get_report_sidecar(
@@ -576,14 +672,16 @@ impl Store {
             spring_complete,
             summer_complete,
             mastery,
+            version,
         ) = tokio::try_join!(
-            Store::get_facts(&t, uname),
-            Store::get_social(&t, uname, Term::Fall),
-            Store::get_social(&t, uname, Term::Spring),
+            Store::get_facts(&t, uname, year),
+            Store::get_social(&t, uname, year, Term::Fall),
+            Store::get_social(&t, uname, year, Term::Spring),
             Store::get_completion(&t, uname, year, Term::Fall),
             Store::get_completion(&t, uname, year, Term::Spring),
             Store::get_completion(&t, uname, year, Term::Summer),
-            Store::get_mastery(&t, uname),
+            Store::get_mastery(&t, uname, year),
+            Store::get_sidecar_version(&t, uname, year),
         )?;
 
         t.commit().await?;
@@ -597,6 +695,7 @@ impl Store {
             fall_complete,
             spring_complete,
             summer_complete,
+            version,
         };
 
         Ok(car)
@@ -605,26 +704,28 @@ impl Store {
     pub async fn set_draft(
         t: &Transaction<'_>,
         uname: &str,
+        year: i32,
         term: Term,
         text: &str,
     ) -> Result<(), DbError> {
         log::trace!(
-            "Store::set_draft( [ &T ], {:?}, {:?}, [ {} bytes of text ] ) called.",
+            "Store::set_draft( [ &T ], {:?}, {}, {:?}, [ {} bytes of text ] ) called.",
             uname,
+            year,
             &term,
             text.len()
         );
 
-        let params: [&(dyn ToSql + Sync); 3] = [&uname, &term.as_str(), &text];
+        let params: [&(dyn ToValue + Sync); 4] = [&uname, &term.as_str(), &year, &text];
 
         try_join!(
             t.execute(
-                "DELETE FROM drafts WHERE uname = $1 AND term = $2",
-                &params[..2]
+                "DELETE FROM drafts WHERE uname = $1 AND term = $2 AND year = $3",
+                &params[..3]
             ),
             t.execute(
-                "INSERT INTO drafts (uname, term, draft)
-                    VALUES ($1, $2, $3)",
+                "INSERT INTO drafts (uname, term, year, draft)
+                    VALUES ($1, $2, $3, $4)",
                 &params[..]
             ),
         )
@@ -636,19 +737,21 @@ impl Store {
     pub async fn get_draft(
         t: &Transaction<'_>,
         uname: &str,
+        year: i32,
         term: Term,
     ) -> Result<Option<String>, DbError> {
         log::trace!(
-            "Store::get_draft( [ &T ], {:?}, {:?} ) called.",
+            "Store::get_draft( [ &T ], {:?}, {}, {:?} ) called.",
             uname,
+            year,
             &term
         );
 
         let opt = match t
             .query_opt(
                 "SELECT draft FROM drafts
-                WHERE uname = $1 AND term = $2",
-                &[&uname, &term.as_str()],
+                WHERE uname = $1 AND term = $2 AND year = $3",
+                &[&uname, &term.as_str(), &year],
             )
             .await?
         {
@@ -665,30 +768,28 @@ impl Store {
     pub async fn set_final(
         t: &Transaction<'_>,
         uname: &str,
+        year: i32,
         term: Term,
         pdf_bytes: &[u8],
     ) -> Result<(), DbError> {
         log::trace!(
-            "Store::set_final( [ &T ], {:?}, {:?}, [ {} bytes of pdf ] ) called.",
+            "Store::set_final( [ &T ], {:?}, {}, {:?}, [ {} bytes of pdf ] ) called.",
             uname,
+            year,
             &term,
             pdf_bytes.len()
         );
 
-        let insert_stmt = t
-            .prepare_typed(
-                "INSERT INTO reports (uname, term, doc)
-                    VALUES ($1, $2, $3)",
-                &[Type::TEXT, Type::TEXT, Type::BYTEA],
-            )
-            .await?;
-        let params: [&(dyn ToSql + Sync); 3] = [&uname, &term.as_str(), &pdf_bytes];
+        let insert_stmt = "INSERT INTO reports (uname, term, year, doc)
+                    VALUES ($1, $2, $3, $4)";
+        let params: [&(dyn ToValue + Sync); 4] =
+            [&uname, &term.as_str(), &year, &pdf_bytes];
 
         t.execute(
-            "DELETE FROM reports WHERE uname = $1 AND term = $2",
-            &params[..2]
+            "DELETE FROM reports WHERE uname = $1 AND term = $2 AND year = $3",
+            &params[..3]
         ).await?;
-        t.execute(&insert_stmt, &params[..]).await?;
+        t.execute(insert_stmt, &params[..]).await?;
 
         Ok(())
     }
@@ -696,18 +797,20 @@ impl Store {
     pub async fn get_final(
         t: &Transaction<'_>,
         uname: &str,
+        year: i32,
         term: Term,
     ) -> Result<Option<Vec<u8>>, DbError> {
         log::trace!(
-            "Store::get_final( [ &T ], {:?}, {:?} ) called.",
+            "Store::get_final( [ &T ], {:?}, {}, {:?} ) called.",
             uname,
+            year,
             &term.as_str()
         );
 
         let opt = match t
             .query_opt(
-                "SELECT doc FROM reports WHERE uname = $1 AND term = $2",
-                &[&uname, &term.as_str()],
+                "SELECT doc FROM reports WHERE uname = $1 AND term = $2 AND year = $3",
+                &[&uname, &term.as_str(), &year],
             )
             .await?
         {
@@ -733,40 +836,22 @@ impl Store {
     pub async fn clear_final(
         &self,
         uname: &str,
+        year: i32,
         term: Term,
     ) -> Result<(), DbError> {
         log::trace!(
-            "Store::clear_final( {:?}, {:?} ) called.",
-            uname, &term.as_str()
+            "Store::clear_final( {:?}, {}, {:?} ) called.",
+            uname, year, &term.as_str()
         );
 
         let client = self.connect().await?;
         client.execute(
-            "DELETE FROM reports WHERE uname = $1 AND term = $2",
-            &[&uname, &term.as_str()],
+            "DELETE FROM reports WHERE uname = $1 AND term = $2 AND year = $3",
+            &[&uname, &term.as_str(), &year],
         ).await?;
 
         Ok(())
     }
-
-    /**
-    Clear all sidecar student data for the year.
-
-    Leaves completion data intact, because that's important to keep.
-    */
-    pub async fn yearly_clear_sidecars(t: &Transaction<'_>) -> Result<(), DbError> {
-        log::trace!("Store::yearly_clear_sidecars( [ T ] ) called.");
-
-        let _ = tokio::try_join!(
-            t.execute("DELETE FROM nmr", &[]),
-            t.execute("DELETE FROM facts", &[]),
-            t.execute("DELETE FROM social", &[]),
-            t.execute("DELETE FROM drafts", &[]),
-            t.execute("DELETE FROM reports", &[]),
-        )?;
-
-        Ok(())
-    }
 }
 
 #[cfg(test)]
@@ -862,6 +947,7 @@ mod tests {
             spring_complete: vec![],
             summer_complete: vec![],
             mastery,
+            version: 0,
         };
 
         db.set_report_sidecar(&sc, YEAR).await?;
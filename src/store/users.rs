@@ -4,7 +4,7 @@
 ```sql
 CREATE TABLE users (
     uname TEXT PRIMARY KEY,
-    role  TEXT,      /* one of { 'admin', 'boss', 'teacher', 'student' } */
+    role  TEXT,      /* one of { 'admin', 'boss', 'teacher', 'student', 'parent' } */
     salt  TEXT,
     email TEXT
 );
@@ -25,7 +25,14 @@ CREATE TABLE students (
     fall_exam_fraction  REAL,
     spring_exam_fraction REAL,
     fall_notices   SMALLINT,
-    spring_notices SMALLINT
+    spring_notices SMALLINT,
+    version BIGINT NOT NULL DEFAULT 1
+);
+
+CREATE TABLE parents (
+    uname   TEXT REFERENCES users(uname),
+    student TEXT REFERENCES students(uname),
+    UNIQUE (uname, student)
 );
 
 ```
@@ -34,13 +41,10 @@ use std::collections::HashMap;
 use std::fmt::Write;
 
 use futures::stream::{FuturesUnordered, StreamExt};
-use tokio_postgres::{
-    types::{ToSql, Type},
-    Row, Transaction,
-};
 
-use super::{DbError, Store};
+use super::{DbError, Store, Versioned};
 use crate::blank_string_means_none;
+use crate::db::{Row, ToValue, Transaction};
 use crate::user::*;
 
 /**
@@ -72,6 +76,7 @@ struct StudentSidecar {
     spring_exam_fraction: f32,
     fall_notices: i16,
     spring_notices: i16,
+    version: i64,
 }
 
 /// Turn a row queried from the 'users' table in to a `BaseUser.
@@ -135,6 +140,7 @@ fn student_from_row(row: &Row) -> Result<StudentSidecar, DbError> {
         spring_exam_fraction: row.try_get("spring_exam_fraction")?,
         fall_notices: row.try_get("fall_notices")?,
         spring_notices: row.try_get("spring_notices")?,
+        version: row.try_get("version")?,
         fall_exam: match row.try_get("fall_exam") {
             Ok(x) => blank_string_means_none(x),
             Err(_) => None,
@@ -199,7 +205,7 @@ impl Store {
         DELETE statements is necessary to satisfy the borrow checker. Sorry.
         I absolutely invite you to make this suck less if you can.
         */
-        let params: [&(dyn ToSql + Sync); 1] = [&uname];
+        let params: [&(dyn ToValue + Sync); 1] = [&uname];
 
         tokio::try_join!(
             t.execute("DELETE FROM completion WHERE uname = $1", &params[..]),
@@ -213,9 +219,17 @@ impl Store {
             ),
             t.execute("DELETE FROM reports WHERE uname = $1", &params[..]),
             t.execute("DELETE FROM social WHERE uname = $1", &params[..]),
+            t.execute("DELETE FROM sidecars WHERE uname = $1", &params[..]),
+            t.execute(
+                "DELETE FROM parents WHERE uname = $1 OR student = $1",
+                &params[..]
+            ),
         )?;
 
-        let n_goals = self.delete_goals_by_student(t, uname).await?;
+        // Every year's Goals go, not just this year's.
+        let n_goals = t
+            .execute("DELETE FROM goals WHERE uname = $1", &params[..])
+            .await?;
         log::trace!("Deleted {} Goals.", &n_goals);
 
         let (s_del_res, t_del_res) = tokio::join!(
@@ -453,6 +467,83 @@ impl Store {
         Ok(())
     }
 
+    /// Insert a new Parent of the Students with the given `children` unames
+    /// and return the newly-generated salt.
+    pub async fn insert_parent(
+        &self,
+        t: &Transaction<'_>,
+        uname: &str,
+        email: &str,
+        children: &[String],
+    ) -> Result<String, DbError> {
+        log::trace!(
+            "Store::insert_parent( {:?}, {:?}, {:?} ) called.",
+            uname,
+            email,
+            children
+        );
+
+        let salt = self.insert_base_user(t, uname, email, Role::Parent).await?;
+        self.link_parent(t, uname, children).await?;
+
+        log::trace!("Inserted Parent {:?} ({}) of {:?}", uname, email, children);
+        Ok(salt)
+    }
+
+    /// Add the Students with the given `children` unames to those of Parent
+    /// `uname`. Students who are already linked to this Parent are ignored.
+    ///
+    /// Returns the number of new links made.
+    pub async fn link_parent(
+        &self,
+        t: &Transaction<'_>,
+        uname: &str,
+        children: &[String],
+    ) -> Result<usize, DbError> {
+        log::trace!(
+            "Store::link_parent( {:?}, {:?} ) called.",
+            uname,
+            children
+        );
+
+        let mut n: u64 = 0;
+        for child in children.iter() {
+            n += t
+                .execute(
+                    "INSERT INTO parents (uname, student)
+                        VALUES ($1, $2)
+                        ON CONFLICT DO NOTHING",
+                    &[&uname, child],
+                )
+                .await?;
+        }
+
+        Ok(n as usize)
+    }
+
+    /// Update Parent `uname`'s email address and replace their list of
+    /// Students with `children`.
+    pub async fn update_parent(
+        &self,
+        t: &Transaction<'_>,
+        uname: &str,
+        email: &str,
+        children: &[String],
+    ) -> Result<(), DbError> {
+        log::trace!(
+            "Store::update_parent( {:?}, {:?}, {:?} ) called.",
+            uname,
+            email,
+            children
+        );
+
+        self.update_base_user(t, uname, email).await?;
+        t.execute("DELETE FROM parents WHERE uname = $1", &[&uname])
+            .await?;
+        self.link_parent(t, uname, children).await?;
+        Ok(())
+    }
+
     /// Insert the slice of supplied students into the database. On success,
     /// the Student objects salts are set.
     pub async fn insert_students(
@@ -467,12 +558,10 @@ impl Store {
 
         let new_unames: Vec<&str> = students.iter().map(|s| s.base.uname.as_str()).collect();
 
-        let preexisting_uname_query = t
-            .prepare_typed(
-                "SELECT uname, role FROM users WHERE uname = ANY($1)",
-                &[Type::TEXT_ARRAY],
-            )
-            .await?;
+        let preexisting_uname_query = format!(
+            "SELECT uname, role FROM users WHERE {}",
+            t.dialect().in_array("uname", "$1")
+        );
 
         // Check to see if any of the new students have unames already in use
         // and return an informative error if so.
@@ -497,14 +586,9 @@ impl Store {
             return Err(DbError(estr));
         }
 
-        let (buiq, stiq) = tokio::join!(
-            t.prepare_typed(
-                "INSERT INTO users (uname, role, salt, email)
-                    VALUES ($1, $2, $3, $4)",
-                &[Type::TEXT, Type::TEXT, Type::TEXT, Type::TEXT]
-            ),
-            t.prepare_typed(
-                "INSERT INTO students (
+        let base_user_insert_query = "INSERT INTO users (uname, role, salt, email)
+                    VALUES ($1, $2, $3, $4)";
+        let student_table_insert_query = "INSERT INTO students (
                     uname, last, rest, teacher, parent,
                     fall_exam, spring_exam,
                     fall_exam_fraction, spring_exam_fraction,
@@ -513,23 +597,7 @@ impl Store {
                     VALUES (
                         $1, $2, $3, $4, $5,
                         $6, $7, $8, $9, $10, $11
-                    )",
-                &[
-                    Type::TEXT,
-                    Type::TEXT,
-                    Type::TEXT,
-                    Type::TEXT,
-                    Type::TEXT,
-                    Type::TEXT,
-                    Type::TEXT,
-                    Type::FLOAT4,
-                    Type::FLOAT4,
-                    Type::INT2,
-                    Type::INT2
-                ]
-            ),
-        );
-        let (base_user_insert_query, student_table_insert_query) = (buiq?, stiq?);
+                    )";
 
         /*
         This next block is terrible and confusing.
@@ -572,11 +640,11 @@ impl Store {
         {
             let student_role = Role::Student.to_string();
 
-            let pvec: Vec<[&(dyn ToSql + Sync); 4]> = students
+            let pvec: Vec<[&(dyn ToValue + Sync); 4]> = students
                 .iter()
                 .enumerate()
                 .map(|(n, s)| {
-                    let p: [&(dyn ToSql + Sync); 4] =
+                    let p: [&(dyn ToValue + Sync); 4] =
                         [&s.base.uname, &student_role, &salts[n], &s.base.email];
                     p
                 })
@@ -584,7 +652,7 @@ impl Store {
 
             let mut inserts = FuturesUnordered::new();
             for params in pvec.iter() {
-                inserts.push(t.execute(&base_user_insert_query, params));
+                inserts.push(t.execute(base_user_insert_query, params));
             }
 
             while let Some(res) = inserts.next().await {
@@ -606,10 +674,10 @@ impl Store {
         */
         let mut n_stud_inserted: u64 = 0;
         {
-            let pvec: Vec<[&(dyn ToSql + Sync); 11]> = students
+            let pvec: Vec<[&(dyn ToValue + Sync); 11]> = students
                 .iter()
                 .map(|s| {
-                    let p: [&(dyn ToSql + Sync); 11] = [
+                    let p: [&(dyn ToValue + Sync); 11] = [
                         &s.base.uname,
                         &s.last,
                         &s.rest,
@@ -628,7 +696,7 @@ impl Store {
 
             let mut inserts = FuturesUnordered::new();
             for params in pvec.iter() {
-                inserts.push(t.execute(&student_table_insert_query, params));
+                inserts.push(t.execute(student_table_insert_query, params));
             }
 
             while let Some(res) = inserts.next().await {
@@ -657,7 +725,20 @@ impl Store {
         Ok(n_stud_inserted as usize)
     }
 
-    pub async fn update_student(&self, t: &Transaction<'_>, u: &Student) -> Result<(), DbError> {
+    /**
+    Update the data of Student `u.base.uname` with the rest of the data
+    in `u`.
+
+    Nothing changes (and this returns [`Versioned::Conflict`]) unless the
+    Student in the database is still at `u.version`. In that case the
+    caller should roll back `t`, as the `users` table may already have been
+    touched.
+    */
+    pub async fn update_student(
+        &self,
+        t: &Transaction<'_>,
+        u: &Student,
+    ) -> Result<Versioned, DbError> {
         log::trace!("Store::update_student( [ {:?} ] ) called.", &u.base.uname);
 
         self.update_base_user(t, &u.base.uname, &u.base.email)
@@ -668,14 +749,16 @@ impl Store {
             x => Some(String::from(x)),
         };
 
-        let n_updated = t
-            .execute(
+        let row = t
+            .query_opt(
                 "UPDATE students SET
                 last = $1, rest = $2, teacher = $3, parent = $4,
                 fall_exam = $5, spring_exam = $6,
                 fall_exam_fraction = $7, spring_exam_fraction = $8,
-                fall_notices = $9, spring_notices = $10
-            WHERE uname = $11",
+                fall_notices = $9, spring_notices = $10,
+                version = version + 1
+            WHERE uname = $11 AND version = $12
+            RETURNING version",
                 &[
                     &u.last,
                     &u.rest,
@@ -688,24 +771,25 @@ impl Store {
                     &u.fall_notices,
                     &u.spring_notices,
                     &u.base.uname,
+                    &u.version,
                 ],
             )
             .await?;
 
-        if n_updated == 0 {
-            return Err(DbError(format!(
+        if let Some(row) = row {
+            return Ok(Versioned::Written(row.try_get("version")?));
+        }
+
+        let exists = t
+            .query_opt("SELECT uname FROM students WHERE uname = $1", &[&u.base.uname])
+            .await?;
+        match exists {
+            Some(_) => Ok(Versioned::Conflict),
+            None => Err(DbError(format!(
                 "{:?} has no entry in the 'students' table.",
                 &u.base.uname
-            )));
-        } else if n_updated > 1 {
-            log::warn!(
-                "User {:?} has {} entries in the 'students' table.",
-                &u.base.uname,
-                &n_updated
-            );
+            ))),
         }
-
-        Ok(())
     }
 
     async fn get_base_users(t: &Transaction<'_>) -> Result<HashMap<String, BaseUser>, DbError> {
@@ -754,20 +838,42 @@ impl Store {
         Ok(students)
     }
 
+    /// Return the unames of each Parent's Students, keyed by Parent uname.
+    async fn get_parent_links(
+        t: &Transaction<'_>,
+    ) -> Result<HashMap<String, Vec<String>>, DbError> {
+        log::trace!("Store::get_parent_links( &T ) called.");
+
+        let rows = t
+            .query("SELECT uname, student FROM parents ORDER BY student", &[])
+            .await?;
+        let mut map: HashMap<String, Vec<String>> = HashMap::new();
+        for row in rows.iter() {
+            let uname: String = row.try_get("uname")?;
+            map.entry(uname)
+                .or_default()
+                .push(row.try_get("student")?);
+        }
+
+        Ok(map)
+    }
+
     pub async fn get_users(&self) -> Result<HashMap<String, User>, DbError> {
         log::trace!("Store::get_users() called.");
 
         let mut client = self.connect().await?;
         let t = client.transaction().await?;
 
-        let (base_res, teach_res, stud_res) = tokio::join!(
+        let (base_res, teach_res, stud_res, parent_res) = tokio::join!(
             Store::get_base_users(&t),
             Store::get_teacher_sidecars(&t),
             Store::get_student_sidecars(&t),
+            Store::get_parent_links(&t),
         );
         t.commit().await?;
 
         let (mut base_map, mut teach_vec, mut stud_vec) = (base_res?, teach_res?, stud_res?);
+        let mut parent_map = parent_res?;
         let mut user_map: HashMap<String, User> = HashMap::with_capacity(base_map.len());
 
         for t in teach_vec.drain(..) {
@@ -812,6 +918,7 @@ This absolutely shouldn't be able to happen, but here we are.",
                     s.spring_exam_fraction,
                     s.fall_notices,
                     s.spring_notices,
+                    s.version,
                 ),
             );
         }
@@ -820,6 +927,10 @@ This absolutely shouldn't be able to happen, but here we are.",
             let u: User = match base.role {
                 Role::Admin => base.into_admin(),
                 Role::Boss => base.into_boss(),
+                Role::Parent => {
+                    let children = parent_map.remove(&base.uname).unwrap_or_default();
+                    base.into_parent(children)
+                }
                 x => {
                     log::error!(
                         "BaseUser {:?} has role of {}, but no corresponding sidecar in the appropriate table.",
@@ -900,6 +1011,19 @@ This absolutely shouldn't be able to happen, but here we are.",
         let u = match base.role {
             Role::Admin => base.into_admin(),
             Role::Boss => base.into_boss(),
+            Role::Parent => {
+                let rows = t
+                    .query(
+                        "SELECT student FROM parents WHERE uname = $1 ORDER BY student",
+                        &[&uname],
+                    )
+                    .await?;
+                let mut children: Vec<String> = Vec::with_capacity(rows.len());
+                for row in rows.iter() {
+                    children.push(row.try_get("student")?);
+                }
+                base.into_parent(children)
+            }
             Role::Teacher => match Store::try_get_teacher_sidecar(&t, uname).await? {
                 None => {
                     log::error!(
@@ -941,6 +1065,7 @@ This absolutely shouldn't be able to happen, but here we are.",
                     s.spring_exam_fraction,
                     s.fall_notices,
                     s.spring_notices,
+                    s.version,
                 ),
             },
         };
@@ -951,7 +1076,8 @@ This absolutely shouldn't be able to happen, but here we are.",
 
     /**
     Delete all Student-oriented data: everything from the `goals` table, all
-    the `students` sidecar data, all the `users` with role `student`.
+    the `students` sidecar data, all the `users` with role `student`, and
+    all the Parents of those Students.
 
     This is the inter-academic-year housecleaning function. It should return
     a Vec of usernames that have been deleted, so they can be removed from the
@@ -967,12 +1093,14 @@ This absolutely shouldn't be able to happen, but here we are.",
             t.execute("DELETE FROM nmr", &[]),
             t.execute("DELETE FROM reports", &[]),
             t.execute("DELETE FROM social", &[]),
+            t.execute("DELETE FROM sidecars", &[]),
+            t.execute("DELETE FROM parents", &[]),
         )?;
             t.execute("DELETE FROM goals", &[]).await?;
             t.execute("DELETE FROM students", &[]).await?;
         let uname_rows = t
             .query(
-                "DELETE FROM users WHERE role = 'Student'
+                "DELETE FROM users WHERE role = 'Student' OR role = 'Parent'
             RETURNING uname",
                 &[],
             )
@@ -991,10 +1119,7 @@ This absolutely shouldn't be able to happen, but here we are.",
 mod tests {
     use super::*;
 
-    use serial_test::serial;
-
-    use crate::store::tests::TEST_CONNECTION;
-    use crate::tests::ensure_logging;
+    use crate::tests::{backend_tests, ensure_logging, TestDbs};
     use crate::UnifiedError;
 
     fn same_students(a: &Student, b: &Student) -> bool {
@@ -1062,12 +1187,10 @@ mod tests {
     ghill, Hill, Griffin, g.wilder.hill@gmail.com, dan@camelotacademy.org, berro
     edriver, Driver, Elaine E., ee.driver@gmail.com, arol.parker@gmail.com, irfan";
 
-    #[tokio::test]
-    #[serial]
-    async fn insert_users() -> Result<(), UnifiedError> {
+    async fn insert_users(dbs: TestDbs) -> Result<(), UnifiedError> {
         ensure_logging();
 
-        let db = Store::new(TEST_CONNECTION.to_owned());
+        let db = Store::new(dbs.data());
         db.ensure_db_schema().await?;
 
         let mut client = db.connect().await?;
@@ -1140,15 +1263,13 @@ mod tests {
         Ok(())
     }
 
-    #[tokio::test]
-    #[serial]
-    async fn alter_users() -> Result<(), UnifiedError> {
+    async fn alter_users(dbs: TestDbs) -> Result<(), UnifiedError> {
         ensure_logging();
 
         const NEW_EMAIL: &str = "new@nowhere.org";
         const NEW_NAME: &str = "Teachy McTeacherson";
 
-        let db = Store::new(TEST_CONNECTION.to_owned());
+        let db = Store::new(dbs.data());
         db.ensure_db_schema().await.unwrap();
         let mut client = db.connect().await?;
         let t = client.transaction().await?;
@@ -1204,4 +1325,57 @@ mod tests {
         db.nuke_database().await?;
         Ok(())
     }
+
+    async fn parents(dbs: TestDbs) -> Result<(), UnifiedError> {
+        ensure_logging();
+
+        let db = Store::new(dbs.data());
+        db.ensure_db_schema().await?;
+        let mut client = db.connect().await?;
+
+        let t = client.transaction().await?;
+        for (uname, email, name) in TEACHERS.iter() {
+            db.insert_teacher(&t, uname, email, name).await?;
+        }
+        let mut studs =
+            Student::vec_from_csv_reader(std::io::Cursor::new(STUDENTS_CSV.as_bytes())).unwrap();
+        db.insert_students(&t, &mut studs).await?;
+
+        let kids = vec!["frog".to_owned(), "ghill".to_owned()];
+        db.insert_parent(&t, "ferdfrog", "ferd.frog@gmail.com", &kids[..1])
+            .await?;
+        // Linking an already-linked Student is a no-op.
+        assert_eq!(db.link_parent(&t, "ferdfrog", &kids).await?, 1);
+        t.commit().await?;
+
+        match db.get_users().await?.remove("ferdfrog") {
+            Some(User::Parent(p)) => assert_eq!(&p.children, &kids),
+            x => panic!("Expected User::Parent, got {:?}", &x),
+        }
+        match db.get_user_by_uname("ferdfrog").await? {
+            Some(User::Parent(p)) => assert_eq!(&p.children, &kids),
+            x => panic!("Expected User::Parent, got {:?}", &x),
+        }
+
+        // Deleting a Student removes them from their Parent's account.
+        let t = client.transaction().await?;
+        db.delete_user(&t, "ghill").await?;
+        t.commit().await?;
+        match db.get_users().await?.remove("ferdfrog") {
+            Some(User::Parent(p)) => assert_eq!(&p.children, &kids[..1]),
+            x => panic!("Expected User::Parent, got {:?}", &x),
+        }
+
+        // Clearing out the Students clears out their Parents, too.
+        let t = client.transaction().await?;
+        let deleted = db.delete_students(&t).await?;
+        t.commit().await?;
+        assert!(deleted.iter().any(|uname| uname == "ferdfrog"));
+        assert!(db.get_user_by_uname("ferdfrog").await?.is_none());
+
+        db.nuke_database().await?;
+        Ok(())
+    }
+
+    backend_tests!(insert_users, alter_users, parents);
 }
@@ -0,0 +1,874 @@
+/*!
+Serving several schools ("tenants") from one deployment.
+
+Each tenant is a complete, ordinary `camp` configuration: its own
+`config.toml`, and so its own auth and data databases, Sendgrid credentials,
+calendar, courses, and users. Isolation between tenants comes from keeping
+their data in separate databases (or separate Postgres schemas, by way of
+the `options=-csearch_path=...` connection parameter); nothing in the
+[`Store`](crate::store::Store) or [`auth::Db`](crate::auth::Db) has to know
+that other schools exist. Each tenant gets its own [`Glob`], and requests are
+routed to the right one by the `Host` they're addressed to (see
+[`inter::tenant`](crate::inter::tenant)).
+
+A tenants file looks something like this:
+
+```toml
+# Optional; these default to 0.0.0.0:8001 ($PORT still overrides the port).
+host = "0.0.0.0"
+port = 8001
+
+# Key for the /super API (see `inter::tenant::api`). If this is absent,
+# the super-admin API is disabled. Wrong guesses lock out the address they
+# come from, like failed logins, and the API's actions are recorded in the
+# tenants' audit logs.
+super_admin_key = "some long random string"
+
+# Optional; requests with a Host that matches no tenant go here.
+# Otherwise they get a 404.
+default_tenant = "north"
+
+[[tenant]]
+id = "north"
+hosts = ["north.camp.example.org"]
+config = "tenants/north/config.toml"
+# Optional; this tenant's login page. Defaults to "data/index.html".
+index = "tenants/north/index.html"
+
+[[tenant]]
+id = "south"
+hosts = ["south.camp.example.org", "camp.southacademy.org"]
+config = "tenants/south/config.toml"
+```
+
+Handing the server a regular `config.toml` instead of a tenants file
+still works; that's just one tenant that answers to every `Host`.
+
+Adding or removing a tenant means editing the tenants file and restarting
+the server. Changes to the tenants' configuration files, on the other hand,
+can be picked up without restarting (see [`Tenants::reconfigure`]).
+Each tenant's pages, emails, and reports (letterheads included) are rendered
+from the Handlebars templates in its own `templates_dir`; tenants that don't
+need their own can just point at the same one.
+*/
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    auth::{AuthResult, LoginThrottle},
+    config::{self, Cfg, Glob, GlobCell},
+    db::MEMORY,
+    UnifiedError,
+};
+
+/// The login page served to tenants that don't specify one.
+pub const DEFAULT_INDEX: &str = "data/index.html";
+
+/// The longest wrong guesses at the super-admin key (from anywhere) can
+/// hold up a check of it.
+const SUPER_ADMIN_MAX_DELAY: Duration = Duration::from_secs(5);
+
+/// One `[[tenant]]` entry in a [`TenantsFile`].
+#[derive(Clone, Debug, Deserialize)]
+pub struct TenantEntry {
+    /// Short identifier, used in logs and by the super-admin API. Letters,
+    /// digits, `-` and `_` only.
+    pub id: String,
+    /// Values of the `Host` header (without any port) this tenant answers to.
+    pub hosts: Vec<String>,
+    /// Path to this tenant's `camp` configuration file.
+    pub config: String,
+    /// Path to this tenant's login page.
+    pub index: Option<String>,
+}
+
+/**
+Deserialization of a tenants file, describing the schools served by a single
+deployment.
+
+Like [`config::ConfigFile`], this is only `pub` so its documentation shows
+up with `cargo doc`.
+*/
+#[derive(Debug, Deserialize)]
+pub struct TenantsFile {
+    /// Host to bind the TCP listening socket to.
+    pub host: Option<String>,
+    /// Port to bind the TCP listening socket to.
+    ///
+    /// This value may be overridden by the `PORT` environment variable.
+    pub port: Option<u16>,
+    /// Key required (in the `x-camp-key` header) by the super-admin API.
+    pub super_admin_key: Option<String>,
+    /// Tenant to serve requests whose `Host` matches no tenant's `hosts`.
+    pub default_tenant: Option<String>,
+    pub tenant: Vec<TenantEntry>,
+}
+
+/// Lowercase `host` and strip any port from it.
+fn normalize_host(host: &str) -> String {
+    let host = host.trim();
+    let bare = if host.starts_with('[') {
+        // IPv6 literal, possibly followed by a port.
+        match host.find(']') {
+            Some(n) => &host[..=n],
+            None => host,
+        }
+    } else {
+        host.split(':').next().unwrap_or(host)
+    };
+    bare.to_ascii_lowercase()
+}
+
+impl TenantsFile {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let file_contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Unable to read tenants file {}: {}", path.display(), &e))?;
+        let tf: TenantsFile = toml::from_str(&file_contents)
+            .map_err(|e| format!("Unable to deserialize tenants file: {}", &e))?;
+        tf.validate()?;
+        Ok(tf)
+    }
+
+    /// Ensure tenant ids and hosts are sensible and unambiguous.
+    fn validate(&self) -> Result<(), String> {
+        if self.tenant.is_empty() {
+            return Err("Tenants file must describe at least one [[tenant]].".to_owned());
+        }
+
+        let mut ids: HashSet<&str> = HashSet::new();
+        let mut hosts: HashMap<String, &str> = HashMap::new();
+        for t in self.tenant.iter() {
+            if t.id.is_empty()
+                || !t
+                    .id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                return Err(format!(
+                    "Tenant id {:?} must be nonempty and contain only letters, digits, '-', and '_'.",
+                    &t.id
+                ));
+            }
+            if !ids.insert(t.id.as_str()) {
+                return Err(format!("Tenant id {:?} is used more than once.", &t.id));
+            }
+            if t.hosts.is_empty() {
+                return Err(format!("Tenant {:?} has no hosts.", &t.id));
+            }
+            for h in t.hosts.iter() {
+                let h = normalize_host(h);
+                if let Some(other) = hosts.insert(h.clone(), &t.id) {
+                    return Err(format!(
+                        "Host {:?} belongs to both tenant {:?} and tenant {:?}.",
+                        &h, other, &t.id
+                    ));
+                }
+            }
+        }
+
+        if let Some(id) = &self.default_tenant {
+            if !ids.contains(id.as_str()) {
+                return Err(format!("Default tenant {:?} isn't a tenant.", id));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The address the server should listen on.
+    fn addr(&self) -> Result<SocketAddr, String> {
+        let mut addr = SocketAddr::new("0.0.0.0".parse().unwrap(), 8001);
+        if let Some(s) = &self.host {
+            addr.set_ip(
+                s.parse()
+                    .map_err(|e| format!("Error parsing {:?} as IP address: {}", s, &e))?,
+            );
+        }
+        if let Some(n) = self.port {
+            addr.set_port(n);
+        }
+        config::port_from_env(&mut addr);
+        Ok(addr)
+    }
+}
+
+/**
+Ensure no two tenants would share a database.
+
+In-memory SQLite databases are always separate, so they don't count.
+*/
+fn check_isolation<'a, I>(cfgs: I) -> Result<(), String>
+where
+    I: IntoIterator<Item = (&'a str, &'a Cfg)>,
+{
+    let mut dbs: HashMap<&str, &str> = HashMap::new();
+
+    for (id, cfg) in cfgs {
+        for connect_str in [
+            cfg.auth_db_connect_string.as_str(),
+            cfg.data_db_connect_string.as_str(),
+        ] {
+            if connect_str == MEMORY {
+                continue;
+            }
+            if let Some(other) = dbs.insert(connect_str, id) {
+                if other == id {
+                    return Err(format!(
+                        "Tenant {:?} uses the same database for auth and data.",
+                        id
+                    ));
+                }
+                return Err(format!(
+                    "Tenants {:?} and {:?} share a database; each tenant needs its own.",
+                    other, id
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether the file at `path` is a tenants file (as opposed to an ordinary
+/// `camp` configuration file).
+pub fn is_tenants_file<P: AsRef<Path>>(path: P) -> Result<bool, String> {
+    let path = path.as_ref();
+    let file_contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("Unable to read config file {}: {}", path.display(), &e))?;
+    let val: toml::Value = toml::from_str(&file_contents)
+        .map_err(|e| format!("Unable to deserialize config file: {}", &e))?;
+    Ok(val.get("tenant").is_some())
+}
+
+/**
+The configuration file(s) named by the file at `path`: each tenant's id
+and configuration file if it's a tenants file, or just `path` itself
+(with no id) if it isn't.
+
+This is for the command-line subcommands that operate on each tenant's
+databases in turn.
+*/
+pub fn config_files<P: AsRef<Path>>(path: P) -> Result<Vec<(Option<String>, PathBuf)>, String> {
+    let path = path.as_ref();
+    if !is_tenants_file(path)? {
+        return Ok(vec![(None, path.to_owned())]);
+    }
+
+    let tf = TenantsFile::from_file(path)?;
+    Ok(tf
+        .tenant
+        .into_iter()
+        .map(|t| (Some(t.id), PathBuf::from(t.config)))
+        .collect())
+}
+
+/// A school served by this deployment.
+pub struct Tenant {
+    pub id: String,
+    pub hosts: Vec<String>,
+    /// The configuration file this tenant's [`Glob`] was loaded from.
+    pub config: PathBuf,
+    /// This tenant's login page.
+    pub index: PathBuf,
+    pub glob: Arc<GlobCell>,
+    /// Whether this tenant was named in a tenants file (as opposed to being
+    /// the only one), and so can have its own `CAMP_<ID>_*` environment
+    /// variables (see [`config::ConfigFile`]).
+    named: bool,
+}
+
+impl Tenant {
+    /// Read this tenant's configuration file (along with any environment
+    /// variables that override it).
+    fn read_cfg(&self) -> Result<Cfg, String> {
+        let cfg = if self.named {
+            Cfg::for_tenant(&self.config, &self.id)
+        } else {
+            Cfg::from_file(&self.config)
+        };
+        cfg.map_err(|e| format!("Tenant {:?}: {}", &self.id, &e))
+    }
+
+    /**
+    Reread everything the [`Glob`] caches from this tenant's databases.
+
+    This is for when those databases have been changed by something other
+    than this server process (like restoring from an archive).
+    */
+    pub async fn reload(&self) -> Result<(), String> {
+        self.glob.write().await.refresh_all().await?;
+        log::info!("Reloaded tenant {:?}.", &self.id);
+        Ok(())
+    }
+}
+
+/// All the tenants served by this deployment.
+pub struct Tenants {
+    tenants: Vec<Arc<Tenant>>,
+    /// Maps each (normalized) host to its tenant's index in `tenants`.
+    hosts: HashMap<String, usize>,
+    default: Option<usize>,
+    super_admin_key: Option<blake3::Hash>,
+    /// When each recent wrong guess at the super-admin key was made, and
+    /// from what address. (There's no deployment-wide database to keep
+    /// these in, so each process counts its own.)
+    super_admin_failures: Mutex<Vec<(Instant, String)>>,
+    super_admin_throttle: LoginThrottle,
+    pub addr: SocketAddr,
+}
+
+impl Tenants {
+    /// A single tenant, loaded from an ordinary configuration file, that
+    /// serves requests for every host.
+    pub fn single<P: AsRef<Path>>(glob: Glob, config: P) -> Tenants {
+        let addr = glob.addr;
+        let tenant = Tenant {
+            id: "default".to_owned(),
+            hosts: Vec::new(),
+            config: config.as_ref().to_owned(),
+            index: PathBuf::from(DEFAULT_INDEX),
+            glob: Arc::new(GlobCell::new(glob)),
+            named: false,
+        };
+        Tenants {
+            tenants: vec![Arc::new(tenant)],
+            hosts: HashMap::new(),
+            default: Some(0),
+            super_admin_key: None,
+            super_admin_failures: Mutex::new(Vec::new()),
+            super_admin_throttle: LoginThrottle::default(),
+            addr,
+        }
+    }
+
+    /// The tenant that should serve requests addressed to `host`.
+    pub fn for_host(&self, host: &str) -> Option<Arc<Tenant>> {
+        let n = match self.hosts.get(&normalize_host(host)) {
+            Some(n) => *n,
+            None => self.default?,
+        };
+        Some(self.tenants[n].clone())
+    }
+
+    /// The tenant with the given `id`.
+    pub fn get(&self, id: &str) -> Option<Arc<Tenant>> {
+        self.tenants.iter().find(|t| t.id == id).cloned()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<Tenant>> {
+        self.tenants.iter()
+    }
+
+    /**
+    Reread the configuration file of the tenant with id `only` (or of every
+    tenant, if `None`), and every tenant's templates, and start using them.
+
+    Everything is read and checked before anything is changed: if any
+    configuration file can't be read, names different databases than it
+    did (see [`Cfg::restart_only_changes`]), or would leave tenants sharing
+    a database, or if the templates don't all register, nothing changes and an error is returned.
+
+    Otherwise this returns a note for each tenant with changed settings
+    that won't take effect until the server is restarted.
+    */
+    pub async fn reconfigure(&self, only: Option<&str>) -> Result<Vec<String>, String> {
+        let current: Vec<Arc<Glob>> = self.tenants.iter().map(|t| t.glob.load()).collect();
+        let mut fresh: Vec<Option<Cfg>> = Vec::with_capacity(self.tenants.len());
+        for t in self.tenants.iter() {
+            if only.is_none() || only == Some(t.id.as_str()) {
+                let cfg = t.read_cfg()?;
+                fresh.push(Some(cfg));
+            } else {
+                fresh.push(None);
+            }
+        }
+
+        let mut notes: Vec<String> = Vec::new();
+        for ((t, glob), cfg) in self.tenants.iter().zip(current.iter()).zip(fresh.iter()) {
+            if let Some(cfg) = cfg {
+                let changed = glob
+                    .cfg()
+                    .restart_only_changes(cfg)
+                    .map_err(|e| format!("Tenant {:?}: {}", &t.id, &e))?;
+                if !changed.is_empty() {
+                    notes.push(format!(
+                        "Tenant {:?}: changes to {} won't take effect until the server restarts.",
+                        &t.id,
+                        changed.join(", ")
+                    ));
+                }
+            }
+        }
+
+        let cfgs: Vec<&Cfg> = current
+            .iter()
+            .zip(fresh.iter())
+            .map(|(glob, cfg)| cfg.as_ref().unwrap_or_else(|| glob.cfg()))
+            .collect();
+        check_isolation(
+            self.tenants
+                .iter()
+                .map(|t| t.id.as_str())
+                .zip(cfgs.iter().copied()),
+        )?;
+        let template_dirs: Vec<&Path> = cfgs.iter().map(|cfg| cfg.templates_dir.as_path()).collect();
+        let n_templates = crate::inter::reload_templates(&template_dirs)?;
+        log::info!("Reloaded {} templates.", n_templates);
+
+        for (t, cfg) in self.tenants.iter().zip(fresh) {
+            if let Some(cfg) = cfg {
+                cfg.log_settings(&format!("tenant {:?}", &t.id));
+                t.glob.write().await.reconfigure(cfg).await;
+                log::info!("Reconfigured tenant {:?}.", &t.id);
+            }
+        }
+        for note in notes.iter() {
+            log::warn!("{}", note);
+        }
+
+        Ok(notes)
+    }
+
+    /// Whether to believe the `X-Forwarded-For` header of super-admin
+    /// requests: only if every tenant does (see
+    /// [`ConfigFile::trust_proxy_headers`](config::ConfigFile::trust_proxy_headers)).
+    pub fn trust_proxy_headers(&self) -> bool {
+        self.tenants
+            .iter()
+            .all(|t| t.glob.load().trust_proxy_headers)
+    }
+
+    /**
+    Check `key` (from a client at `addr`) against the super-admin key.
+
+    Wrong guesses from each address are throttled as if they were failed
+    logins by a single user, with the default [`LoginThrottle`]: this
+    returns `AuthResult::Locked`, without checking `key` at all, after too
+    many recent ones from `addr`. Wrong guesses from everywhere only slow
+    the check down (by up to [`SUPER_ADMIN_MAX_DELAY`]), so nobody can lock
+    the operator out. Otherwise it's `AuthResult::Ok` or
+    `AuthResult::BadPassword` (which it always is if there's no key
+    configured).
+    */
+    pub async fn check_super_admin_key(&self, key: &str, addr: &str) -> AuthResult {
+        let hash = match &self.super_admin_key {
+            Some(hash) => hash,
+            None => {
+                return AuthResult::BadPassword;
+            }
+        };
+
+        let throttle = &self.super_admin_throttle;
+        let lockout = Duration::from_secs(throttle.lockout_seconds);
+        let delay = {
+            let mut failures = self.super_admin_failures.lock().unwrap();
+            failures.retain(|(at, _)| at.elapsed() < lockout);
+            match (failures.len() as u32).checked_sub(throttle.free_attempts) {
+                None | Some(0) => Duration::ZERO,
+                Some(n_extra) => Duration::from_secs(
+                    throttle
+                        .backoff_seconds
+                        .saturating_mul(2u64.saturating_pow(n_extra - 1)),
+                )
+                .min(SUPER_ADMIN_MAX_DELAY),
+            }
+        };
+        tokio::time::sleep(delay).await;
+
+        // Held from the check until this attempt is counted, so simultaneous
+        // guesses from one address can't all get in first.
+        let mut failures = self.super_admin_failures.lock().unwrap();
+        let from_addr: Vec<&Instant> = failures
+            .iter()
+            .filter(|(_, a)| a == addr)
+            .map(|(at, _)| at)
+            .collect();
+        let n_addr = from_addr.len() as i64;
+        let since_last = from_addr.last().map(|at| at.elapsed().as_secs_f64());
+        if throttle.is_locked(n_addr, since_last, n_addr) {
+            return AuthResult::Locked;
+        }
+
+        // `blake3::Hash` comparison is constant-time.
+        if blake3::hash(key.as_bytes()) == *hash {
+            failures.retain(|(_, a)| a != addr);
+            AuthResult::Ok
+        } else {
+            failures.push((Instant::now(), addr.to_owned()));
+            AuthResult::BadPassword
+        }
+    }
+}
+
+/**
+Load the tenants described by the file at `path`, loading (and migrating the
+databases of) each one's configuration in turn with
+[`config::load_configuration`].
+
+If `path` is an ordinary configuration file, it's loaded as the single
+tenant (see [`Tenants::single`]).
+*/
+pub async fn load<P: AsRef<Path>>(path: P) -> Result<Tenants, UnifiedError> {
+    let path = path.as_ref();
+    if !is_tenants_file(path)? {
+        let glob = config::load_configuration(path).await?;
+        return Ok(Tenants::single(glob, path));
+    }
+
+    let tf = TenantsFile::from_file(path)?;
+    let addr = tf.addr()?;
+
+    let cfgs = tf
+        .tenant
+        .iter()
+        .map(|t| {
+            Cfg::for_tenant(&t.config, &t.id).map_err(|e| format!("Tenant {:?}: {}", &t.id, &e))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    check_isolation(tf.tenant.iter().map(|t| t.id.as_str()).zip(cfgs.iter()))?;
+
+    let mut tenants: Vec<Arc<Tenant>> = Vec::with_capacity(tf.tenant.len());
+    let mut hosts: HashMap<String, usize> = HashMap::new();
+    for (n, (t, cfg)) in tf.tenant.into_iter().zip(cfgs).enumerate() {
+        log::info!("Loading tenant {:?} from {:?}.", &t.id, &t.config);
+        cfg.log_settings(&format!("tenant {:?}", &t.id));
+        let glob = config::load_cfg(cfg)
+            .await
+            .map_err(|e| format!("Error loading tenant {:?}: {}", &t.id, &e))?;
+        for h in t.hosts.iter() {
+            hosts.insert(normalize_host(h), n);
+        }
+        tenants.push(Arc::new(Tenant {
+            id: t.id,
+            hosts: t.hosts,
+            config: PathBuf::from(t.config),
+            index: PathBuf::from(t.index.as_deref().unwrap_or(DEFAULT_INDEX)),
+            glob: Arc::new(GlobCell::new(glob)),
+            named: true,
+        }));
+    }
+
+    let default = tf
+        .default_tenant
+        .as_ref()
+        .and_then(|id| tenants.iter().position(|t| &t.id == id));
+    if tf.super_admin_key.is_none() {
+        log::info!("No super_admin_key configured; super-admin API disabled.");
+    }
+
+    Ok(Tenants {
+        tenants,
+        hosts,
+        default,
+        super_admin_key: tf.super_admin_key.map(|k| blake3::hash(k.as_bytes())),
+        super_admin_failures: Mutex::new(Vec::new()),
+        super_admin_throttle: LoginThrottle::default(),
+        addr,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::ensure_logging;
+
+    static TENANTS: &str = "test/env/tenants.toml";
+    static CONFIG: &str = "test/env/config.toml";
+
+    fn entry(id: &str, hosts: &[&str]) -> TenantEntry {
+        TenantEntry {
+            id: id.to_owned(),
+            hosts: hosts.iter().map(|h| h.to_string()).collect(),
+            config: CONFIG.to_owned(),
+            index: None,
+        }
+    }
+
+    fn tenants_file(tenant: Vec<TenantEntry>) -> TenantsFile {
+        TenantsFile {
+            host: None,
+            port: None,
+            super_admin_key: None,
+            default_tenant: None,
+            tenant,
+        }
+    }
+
+    #[test]
+    fn hosts() {
+        assert_eq!(&normalize_host("North.Camp.test:8001"), "north.camp.test");
+        assert_eq!(&normalize_host("camp.test"), "camp.test");
+        assert_eq!(&normalize_host("[::1]:8001"), "[::1]");
+    }
+
+    #[test]
+    fn validate() {
+        let ok = tenants_file(vec![entry("a", &["a.test"]), entry("b", &["b.test"])]);
+        assert!(ok.validate().is_ok());
+
+        let tf = tenants_file(vec![entry("a", &["a.test"]), entry("a", &["b.test"])]);
+        assert!(tf.validate().is_err());
+        let tf = tenants_file(vec![entry("a", &["a.test"]), entry("b", &["A.test:80"])]);
+        assert!(tf.validate().is_err());
+        let tf = tenants_file(vec![entry("a b", &["a.test"])]);
+        assert!(tf.validate().is_err());
+        let tf = tenants_file(vec![entry("a", &[])]);
+        assert!(tf.validate().is_err());
+        assert!(tenants_file(vec![]).validate().is_err());
+
+        let mut tf = tenants_file(vec![entry("a", &["a.test"])]);
+        tf.default_tenant = Some("b".to_owned());
+        assert!(tf.validate().is_err());
+    }
+
+    #[test]
+    fn isolation() {
+        let memory = Cfg {
+            auth_db_connect_string: MEMORY.to_owned(),
+            data_db_connect_string: MEMORY.to_owned(),
+            ..Cfg::default()
+        };
+        let other_memory = Cfg {
+            auth_db_connect_string: MEMORY.to_owned(),
+            data_db_connect_string: MEMORY.to_owned(),
+            ..Cfg::default()
+        };
+        assert!(check_isolation([("a", &memory), ("b", &other_memory)]).is_ok());
+
+        let north = Cfg {
+            auth_db_connect_string: "sqlite:north_auth.db".to_owned(),
+            data_db_connect_string: "sqlite:north_data.db".to_owned(),
+            ..Cfg::default()
+        };
+        let south = Cfg {
+            auth_db_connect_string: "sqlite:south_auth.db".to_owned(),
+            data_db_connect_string: "sqlite:north_data.db".to_owned(),
+            ..Cfg::default()
+        };
+        assert!(check_isolation([("north", &north), ("memory", &memory)]).is_ok());
+        assert!(check_isolation([("north", &north), ("south", &south)]).is_err());
+
+        let confused = Cfg {
+            auth_db_connect_string: "sqlite:one.db".to_owned(),
+            data_db_connect_string: "sqlite:one.db".to_owned(),
+            ..Cfg::default()
+        };
+        assert!(check_isolation([("confused", &confused)]).is_err());
+
+        let elsewhere = Cfg {
+            templates_dir: PathBuf::from("elsewhere/"),
+            ..other_memory
+        };
+        assert!(check_isolation([("a", &memory), ("b", &elsewhere)]).is_ok());
+    }
+
+    #[tokio::test]
+    async fn load_tenants() {
+        ensure_logging();
+
+        assert!(is_tenants_file(TENANTS).unwrap());
+        assert!(!is_tenants_file(CONFIG).unwrap());
+        let files = config_files(TENANTS).unwrap();
+        assert_eq!(
+            files.iter().map(|(id, _)| id.as_deref()).collect::<Vec<_>>(),
+            vec![Some("north"), Some("south")]
+        );
+        assert_eq!(config_files(CONFIG).unwrap(), vec![(None, PathBuf::from(CONFIG))]);
+
+        let tenants = load(TENANTS).await.unwrap();
+        let north = tenants.for_host("NORTH.camp.test:8001").unwrap();
+        let south = tenants.for_host("south.camp.test").unwrap();
+        assert_eq!(&north.id, "north");
+        assert_eq!(&south.id, "south");
+        assert_eq!(&tenants.for_host("camp.test").unwrap().id, "north");
+        assert_eq!(&tenants.for_host("nowhere.test").unwrap().id, "north");
+        assert_eq!(&tenants.get("south").unwrap().id, "south");
+        assert_eq!(south.index, PathBuf::from("test/env/index.html"));
+        assert_eq!(north.index, PathBuf::from(DEFAULT_INDEX));
+
+        assert_eq!(
+            tenants.check_super_admin_key("not a real key", "10.0.0.1").await,
+            AuthResult::Ok
+        );
+        // Three free wrong guesses, and then the backoff starts...
+        for _ in 0..3 {
+            assert_eq!(
+                tenants
+                    .check_super_admin_key("not a real key either", "10.0.0.1")
+                    .await,
+                AuthResult::BadPassword
+            );
+        }
+        // ...during which not even the right key gets in from there...
+        assert_eq!(
+            tenants.check_super_admin_key("not a real key", "10.0.0.1").await,
+            AuthResult::Locked
+        );
+        // ...but it does from anywhere else.
+        assert_eq!(
+            tenants.check_super_admin_key("not a real key", "10.0.0.3").await,
+            AuthResult::Ok
+        );
+
+        // Changes to one tenant's data don't show up in the other's.
+        let day = time::Date::from_calendar_date(2022, time::Month::September, 6).unwrap();
+        south
+            .glob
+            .load()
+            .data()
+            .read()
+            .await
+            .set_calendar(&[day])
+            .await
+            .unwrap();
+        north.reload().await.unwrap();
+        south.reload().await.unwrap();
+        assert!(north.glob.load().calendar.is_empty());
+        assert_eq!(&south.glob.load().calendar, &[day]);
+    }
+
+    #[tokio::test]
+    async fn single_tenant() {
+        ensure_logging();
+
+        let tenants = load(CONFIG).await.unwrap();
+        let t = tenants.for_host("anything.at.all:8001").unwrap();
+        assert_eq!(&t.id, "default");
+        assert_eq!(t.index, PathBuf::from(DEFAULT_INDEX));
+        assert_eq!(
+            tenants.check_super_admin_key("", "10.0.0.1").await,
+            AuthResult::BadPassword
+        );
+    }
+
+    #[tokio::test]
+    async fn super_admin_lockout() {
+        ensure_logging();
+
+        let mut tenants = load(TENANTS).await.unwrap();
+        tenants.super_admin_throttle = LoginThrottle {
+            free_attempts: 1,
+            backoff_seconds: 0,
+            max_attempts: 3,
+            addr_max_attempts: 3,
+            lockout_seconds: 60,
+        };
+
+        for _ in 0..3 {
+            assert_eq!(
+                tenants.check_super_admin_key("wrong", "10.0.0.1").await,
+                AuthResult::BadPassword
+            );
+        }
+        // That address is locked out for good...
+        for _ in 0..3 {
+            assert_eq!(
+                tenants.check_super_admin_key("wrong", "10.0.0.1").await,
+                AuthResult::Locked
+            );
+        }
+        assert_eq!(
+            tenants.check_super_admin_key("not a real key", "10.0.0.1").await,
+            AuthResult::Locked
+        );
+        // ...but no other is.
+        assert_eq!(
+            tenants.check_super_admin_key("wrong", "10.0.0.2").await,
+            AuthResult::BadPassword
+        );
+        assert_eq!(
+            tenants.check_super_admin_key("not a real key", "10.0.0.2").await,
+            AuthResult::Ok
+        );
+    }
+
+    #[tokio::test]
+    async fn separate_templates() {
+        use crate::inter::{reload_templates, render_raw_template, with_templates};
+
+        ensure_logging();
+
+        let dir = std::env::temp_dir().join(format!("camp_templates_{}", std::process::id()));
+        let north = dir.join("north");
+        let south = dir.join("south");
+        for (tdir, school) in [(&north, "North"), (&south, "South")] {
+            std::fs::create_dir_all(tdir).unwrap();
+            std::fs::write(tdir.join("letterhead.md"), format!("{} Academy: {{{{name}}}}", school))
+                .unwrap();
+        }
+        assert_eq!(reload_templates(&[&north, &south, &north]).unwrap(), 2);
+
+        let data = serde_json::json!({ "name": "Report" });
+        let north_text = with_templates(north.clone(), async {
+            render_raw_template("letterhead", &data)
+        })
+        .await
+        .unwrap();
+        let south_text = with_templates(south.clone(), async {
+            render_raw_template("letterhead", &data)
+        })
+        .await
+        .unwrap();
+        assert_eq!(&north_text, "North Academy: Report");
+        assert_eq!(&south_text, "South Academy: Report");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn reconfigure() {
+        ensure_logging();
+
+        let dir = std::env::temp_dir().join(format!("camp_reconfigure_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        let base = std::fs::read_to_string(CONFIG).unwrap();
+        std::fs::write(&path, &base).unwrap();
+
+        let tenants = load(&path).await.unwrap();
+        let t = tenants.get("default").unwrap();
+        let before = t.glob.load();
+
+        std::fs::write(
+            &path,
+            format!(
+                "{}\nsocial_traits = [\"Punctuality\"]\ncache_resync_seconds = 0\n",
+                &base
+            ),
+        )
+        .unwrap();
+        let notes = tenants.reconfigure(None).await.unwrap();
+        assert_eq!(&t.glob.load().social_traits, &["Punctuality".to_owned()]);
+        assert_eq!(t.glob.load().cfg().cache_resync_seconds, 0);
+        // That one only takes effect on restart.
+        assert_eq!(t.glob.load().cache_resync, before.cache_resync);
+        assert_eq!(notes.len(), 1);
+        assert!(notes[0].contains("cache_resync_seconds"));
+        // Snapshots taken before are unchanged.
+        assert_eq!(&before.social_traits, &Cfg::default().social_traits);
+
+        // Nothing changes if the new configuration won't do.
+        std::fs::write(&path, "social_traits = [").unwrap();
+        assert!(tenants.reconfigure(Some("default")).await.is_err());
+        std::fs::write(
+            &path,
+            base.replace(
+                "data_db_connect_string = \"sqlite::memory:\"",
+                "data_db_connect_string = \"sqlite:elsewhere.db\"",
+            ),
+        )
+        .unwrap();
+        assert!(tenants.reconfigure(None).await.is_err());
+        assert_eq!(&t.glob.load().social_traits, &["Punctuality".to_owned()]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
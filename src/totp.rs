@@ -0,0 +1,179 @@
+/*!
+Time-based one-time passwords ([RFC 6238](https://www.rfc-editor.org/rfc/rfc6238)),
+used as a second authentication factor for Admin and Boss accounts.
+
+This is only the arithmetic; storing secrets and deciding who needs to
+present a code is handled in `auth`.
+
+Codes are the common authenticator-app flavor: HMAC-SHA1, six digits,
+thirty-second time steps.
+*/
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha1::Sha1;
+
+/// Length of each time step, in seconds.
+pub const STEP_SECONDS: u64 = 30;
+/// Number of digits in a code.
+pub const DIGITS: u32 = 6;
+/// Length (in bytes) of newly-generated secrets; RFC 4226 recommends 160 bits.
+const SECRET_LENGTH: usize = 20;
+
+const BASE32: base32::Alphabet = base32::Alphabet::RFC4648 { padding: false };
+
+/// Generate a new random shared secret, base32-encoded for storage and for
+/// display to the user.
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; SECRET_LENGTH];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    base32::encode(BASE32, &bytes)
+}
+
+/// Decode a base32-encoded secret.
+fn decode_secret(secret: &str) -> Result<Vec<u8>, String> {
+    base32::decode(BASE32, secret).ok_or_else(|| "Invalid base32 TOTP secret.".to_owned())
+}
+
+/// Percent-encode everything but unreserved characters, for use in the
+/// label and parameters of a provisioning URI.
+fn uri_encode(s: &str) -> String {
+    use std::fmt::Write;
+
+    let mut output = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                output.push(b as char)
+            }
+            _ => write!(&mut output, "%{:02X}", b).unwrap(),
+        }
+    }
+    output
+}
+
+/**
+Generate the `otpauth://` URI from which an authenticator app can set
+itself up (usually by way of a QR code).
+*/
+pub fn provisioning_uri(issuer: &str, account: &str, secret: &str) -> String {
+    let issuer = uri_encode(issuer);
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        &issuer,
+        &uri_encode(account),
+        secret,
+        &issuer,
+        DIGITS,
+        STEP_SECONDS
+    )
+}
+
+/// The HOTP value ([RFC 4226](https://www.rfc-editor.org/rfc/rfc4226)) of
+/// the given `secret` and `counter`, truncated to `digits` digits.
+fn hotp(secret: &[u8], counter: u64, digits: u32) -> u32 {
+    // HMAC can take a key of any length.
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).unwrap();
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let bin = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    bin % 10u32.pow(digits)
+}
+
+/// The time step containing the given Unix time.
+fn step_at(unix_seconds: u64) -> u64 {
+    unix_seconds / STEP_SECONDS
+}
+
+/// The current time step.
+pub fn current_step() -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    step_at(now)
+}
+
+/// The code for the given `secret` at time step `step`, zero-padded.
+pub fn generate(secret: &str, step: u64) -> Result<String, String> {
+    let secret = decode_secret(secret)?;
+    Ok(format!(
+        "{:0width$}",
+        hotp(&secret, step, DIGITS),
+        width = DIGITS as usize
+    ))
+}
+
+/**
+Check a user-supplied `code` against the `secret` for time step `step`,
+allowing for `skew` steps of clock drift in either direction.
+
+Returns the step the code matched, so the caller can refuse to accept the
+same (or an earlier) code again.
+*/
+pub fn verify(secret: &str, code: &str, step: u64, skew: u64) -> Result<Option<u64>, String> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return Ok(None);
+    }
+    let code: u32 = code
+        .parse()
+        .map_err(|e| format!("Unable to parse TOTP code: {}", &e))?;
+    let secret = decode_secret(secret)?;
+
+    let first = step.saturating_sub(skew);
+    Ok((first..=(step + skew)).find(|&s| hotp(&secret, s, DIGITS) == code))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The SHA1 secret from the test vectors in RFC 4226 and RFC 6238.
+    static RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn rfc_vectors() {
+        assert_eq!(hotp(RFC_SECRET, 0, 6), 755224);
+        assert_eq!(hotp(RFC_SECRET, 1, 6), 287082);
+        assert_eq!(hotp(RFC_SECRET, 9, 6), 520489);
+
+        for (time, expected) in [
+            (59_u64, 94287082_u32),
+            (1111111109, 7081804),
+            (1111111111, 14050471),
+            (1234567890, 89005924),
+            (2000000000, 69279037),
+        ] {
+            assert_eq!(hotp(RFC_SECRET, step_at(time), 8), expected);
+        }
+    }
+
+    #[test]
+    fn verify_codes() {
+        let secret = base32::encode(BASE32, RFC_SECRET);
+        let step = step_at(59);
+        // Six-digit truncation of 94287082.
+        assert_eq!(verify(&secret, "287082", step, 0).unwrap(), Some(step));
+        assert_eq!(verify(&secret, " 287082 ", step, 0).unwrap(), Some(step));
+        assert_eq!(verify(&secret, "287082", step + 1, 1).unwrap(), Some(step));
+        assert_eq!(verify(&secret, "287082", step + 2, 1).unwrap(), None);
+        assert_eq!(verify(&secret, "287083", step, 1).unwrap(), None);
+        assert_eq!(verify(&secret, "28708", step, 1).unwrap(), None);
+        assert_eq!(verify(&secret, "abcdef", step, 1).unwrap(), None);
+        assert_eq!(generate(&secret, step).unwrap(), "287082");
+        // 1111111109 is step 37037036, whose code has a leading zero.
+        assert_eq!(generate(&secret, 37037036).unwrap(), "081804");
+
+        let uri = provisioning_uri("CAMP", "the boss", &secret);
+        assert!(uri.starts_with("otpauth://totp/CAMP:the%20boss?secret="));
+    }
+}
@@ -1,5 +1,5 @@
 /*!
-Internal representations of the four types of users of this system:
+Internal representations of the five types of users of this system:
   * [`Admin`](User::Admin): responsible for adding users and courses and updating
     the calendar
   * [`Boss`](User::Boss): can see all students' progress, and autogenerate (and send)
@@ -7,6 +7,7 @@ Internal representations of the four types of users of this system:
   * [`Teacher`]: can see a subset of students' progress (theirs), and
     add and update their goal status
   * [`Student`]: can see their own progress
+  * [`Parent`]: can see the progress of their own children (read-only)
 
 Most of the information contained herein is just directly wrapped data from
 the underlying Postgres store, collected and cross-referenced.
@@ -25,6 +26,7 @@ pub enum Role {
     Boss,
     Teacher,
     Student,
+    Parent,
 }
 
 impl std::fmt::Display for Role {
@@ -34,6 +36,7 @@ impl std::fmt::Display for Role {
             Role::Boss => "Boss",
             Role::Teacher => "Teacher",
             Role::Student => "Student",
+            Role::Parent => "Parent",
         };
 
         write!(f, "{}", token)
@@ -49,6 +52,7 @@ impl std::str::FromStr for Role {
             "Boss" => Ok(Role::Boss),
             "Teacher" => Ok(Role::Teacher),
             "Student" => Ok(Role::Student),
+            "Parent" => Ok(Role::Parent),
             _ => Err(format!("{:?} is not a valid Role.", s)),
         }
     }
@@ -60,7 +64,7 @@ pub struct BaseUser {
     /// Uniquely identifies each user.
     pub uname: String,
     /// Helpful here because the `BaseUser` is information common to all
-    /// four types of users.
+    /// five types of users.
     pub role: Role,
     /// Salt string for comparing a supplied password with the hash on
     /// record in the [auth database](crate::auth::Db).
@@ -97,6 +101,12 @@ impl BaseUser {
             name,
         })
     }
+    pub fn into_parent(self, children: Vec<String>) -> User {
+        User::Parent(Parent {
+            base: self.rerole(Role::Parent),
+            children,
+        })
+    }
     #[allow(clippy::too_many_arguments)]
     pub fn into_student(
         self,
@@ -110,6 +120,7 @@ impl BaseUser {
         spring_exam_fraction: f32,
         fall_notices: i16,
         spring_notices: i16,
+        version: i64,
     ) -> User {
         let s = Student {
             base: self.rerole(Role::Student),
@@ -123,6 +134,7 @@ impl BaseUser {
             spring_exam_fraction,
             fall_notices,
             spring_notices,
+            version,
         };
         User::Student(s)
     }
@@ -137,6 +149,20 @@ pub struct Teacher {
     pub name: String,
 }
 
+/**
+Wraps Parent info.
+
+Parent accounts are created automatically from the parent email column when
+Students are uploaded; Students who share a parent email share a Parent.
+*/
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct Parent {
+    /// uname, salt, email
+    pub base: BaseUser,
+    /// `uname`s of this Parent's Students.
+    pub children: Vec<String>,
+}
+
 /**
 Wraps all information about a student except for pace goals.
 */
@@ -163,6 +189,11 @@ pub struct Student {
     pub fall_notices: i16,
     /// Number of homework notices that "count" for the Spring Semester.
     pub spring_notices: i16,
+    /// Bumped every time the Student's data changes in the database (see
+    /// [`Store::update_student`](crate::store::Store::update_student)).
+    /// Students who haven't been inserted yet have version 0.
+    #[serde(default)]
+    pub version: i64,
 }
 
 impl Student {
@@ -234,6 +265,7 @@ impl Student {
             spring_exam_fraction: 0.2_f32,
             fall_notices: 0,
             spring_notices: 0,
+            version: 0,
         };
         Ok(stud)
     }
@@ -309,13 +341,14 @@ impl Student {
     }
 }
 
-/// Sum type unifying all four types of users.
+/// Sum type unifying all five types of users.
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub enum User {
     Admin(BaseUser),
     Boss(BaseUser),
     Teacher(Teacher),
     Student(Student),
+    Parent(Parent),
 }
 
 impl User {
@@ -325,6 +358,7 @@ impl User {
             User::Boss(base) => &base.uname,
             User::Teacher(t) => &t.base.uname,
             User::Student(s) => &s.base.uname,
+            User::Parent(p) => &p.base.uname,
         }
     }
 
@@ -334,6 +368,7 @@ impl User {
             User::Boss(base) => &base.salt,
             User::Teacher(t) => &t.base.salt,
             User::Student(s) => &s.base.salt,
+            User::Parent(p) => &p.base.salt,
         }
     }
 
@@ -343,6 +378,7 @@ impl User {
             User::Boss(base) => &base.email,
             User::Teacher(t) => &t.base.email,
             User::Student(s) => &s.base.email,
+            User::Parent(p) => &p.base.email,
         }
     }
 
@@ -352,6 +388,7 @@ impl User {
             User::Boss(_) => Role::Boss,
             User::Teacher(_) => Role::Teacher,
             User::Student(_) => Role::Student,
+            User::Parent(_) => Role::Parent,
         }
     }
 }
@@ -370,7 +407,7 @@ impl PartialOrd for User {
             },
             User::Teacher(ref t) => match other {
                 User::Teacher(ref ot) => t.base.uname.cmp(&ot.base.uname),
-                User::Student(_) => Ordering::Less,
+                User::Student(_) | User::Parent(_) => Ordering::Less,
                 _ => Ordering::Greater,
             },
             User::Student(ref s) => match other {
@@ -378,6 +415,11 @@ impl PartialOrd for User {
                     Ordering::Equal => s.rest.cmp(&os.rest),
                     x => x,
                 },
+                User::Parent(_) => Ordering::Less,
+                _ => Ordering::Greater,
+            },
+            User::Parent(ref p) => match other {
+                User::Parent(ref op) => p.base.uname.cmp(&op.base.uname),
                 _ => Ordering::Greater,
             },
         };
@@ -423,6 +465,7 @@ mod tests {
             0.2,
             0,
             0,
+            1,
         );
 
         println!("Debug:\n{:#?}\n{:#?}\n{:#?}\n{:#?}\n\n", &a, &b, &t, &s);
@@ -10,6 +10,9 @@ DEFERred.
 "use strict";
 
 const API_ENDPOINT = "/admin";
+// Time (in ms) to wait for an object to start downloading before its
+// ObjectURL is revoked.
+const DOWNLOAD_DELAY = 5000;
 const STATE = {
     error_count: 0
 };
@@ -34,6 +37,7 @@ const DISPLAY = {
     teacher_tbody: document.querySelector("table#teacher-table > tbody"),
     teacher_edit:  document.getElementById("alter-teacher"),
     student_tbody: document.querySelector("table#student-table > tbody"),
+    parent_tbody:  document.querySelector("table#parent-table > tbody"),
     student_edit:  document.getElementById("alter-student"),
     student_upload: document.getElementById("upload-students-dialog"),
     student_paste: document.getElementById("paste-students-dialog"),
@@ -41,7 +45,19 @@ const DISPLAY = {
     course_edit:   document.getElementById("alter-course"),
     course_upload: document.getElementById("upload-course-dialog"),
     chapter_edit:  document.getElementById("alter-chapter"),
-    student_reset: document.getElementById("reset-students"),
+    sessions:      document.getElementById("sessions-dialog"),
+    sessions_uname: document.getElementById("sessions-uname"),
+    sessions_tbody: document.querySelector("table#sessions-table > tbody"),
+    token_tbody:   document.querySelector("table#token-table > tbody"),
+    token_issue:   document.getElementById("issue-token"),
+    token_new:     document.getElementById("new-token"),
+    token_new_name: document.getElementById("new-token-name"),
+    token_new_value: document.getElementById("new-token-value"),
+    grant_tbody:   document.querySelector("table#grant-table > tbody"),
+    grant_uname:   document.getElementById("grant-uname"),
+    grant_permission: document.getElementById("grant-permission"),
+    grant_options: document.getElementById("grant-permissions"),
+    audit_tbody:   document.querySelector("table#audit-table > tbody"),
     history_year: document.querySelector("tbody#add-completion-history input[name='year']"),
 };
 
@@ -56,9 +72,28 @@ function populate_users(r) {
         UTIL.clear(DISPLAY.boss_tbody);
         UTIL.clear(DISPLAY.teacher_tbody);
         UTIL.clear(DISPLAY.student_tbody);
+        UTIL.clear(DISPLAY.parent_tbody);
         for(const u of j) {
             add_user_to_display(u);
         }
+        request_action("populate-invites", "", "Fetching invitation data...");
+    }).catch(RQ.add_err);
+}
+
+/*
+Fills in the "invite" column of each user table with the status of that
+user's invitation (if they've been sent one).
+*/
+function populate_invites(r) {
+    r.json()
+    .then(j => {
+        console.log("populate-invites response:");
+        console.log(j);
+
+        for(const td of document.querySelectorAll("td.invite")) {
+            const uname = td.getAttribute("data-uname");
+            UTIL.set_text(td, j[uname] || "");
+        }
     }).catch(RQ.add_err);
 }
 
@@ -87,7 +122,12 @@ function update_completion(r) {
 }
 
 function field_response(r) {
-    if(!r.ok) {
+    /*  A 409 means the change was refused because what it was made from has
+        since been changed by someone else. The response still carries the
+        current state, so it gets displayed as usual. */
+    if(r.status == 409) {
+        RQ.add_err("That was changed by someone else since you loaded it, so your change wasn't saved. The current version is now shown; please try again.");
+    } else if(!r.ok) {
         r.text()
         .then(t => {
             const err_txt = `${t}\n(${r.status}: ${r.statusText})`;
@@ -121,6 +161,24 @@ function field_response(r) {
             populate_completion(r); break;
         case "update-completion":
             update_completion(r); break;
+        case "populate-sessions":
+            populate_sessions(r); break;
+        case "populate-invites":
+            populate_invites(r); break;
+        case "populate-tokens":
+            populate_tokens(r); break;
+        case "populate-grants":
+            populate_grants(r); break;
+        case "query-audit":
+            populate_audit(r); break;
+        case "export-audit":
+            save_audit(r); break;
+        case "export-archive":
+            save_archive(r); break;
+        case "reload-config":
+            show_reload_result(r); break;
+        case "impersonate":
+            open_view_as(r); break;
         default:
             const e_n = STATE.next_error();
             const err_txt = `Unrecognized x-camp-action header: ${action}. (See console error #${e_n})`;
@@ -181,6 +239,35 @@ function make_user_edit_button_td(uname, edit_func) {
     return td;
 }
 
+function make_invite_td(uname) {
+    const td = document.createElement("td");
+    td.setAttribute("class", "invite");
+    td.setAttribute("data-uname", uname);
+    return td;
+}
+
+/*
+The "send invitation" checkbox only makes sense when adding a new user,
+so it's checked by default then, and disabled when editing.
+*/
+function set_invite_checkbox(form, uname) {
+    const invite = form.elements['invite'];
+    invite.disabled = Boolean(uname);
+    invite.checked = !uname;
+}
+
+/*
+Extra headers for an "add-user" or "upload-students" request, asking for
+new users to be sent invitations if the form's checkbox is checked.
+*/
+function invite_headers(form) {
+    if(form.elements['invite'].checked) {
+        return { "x-camp-invite": "true" };
+    } else {
+        return {};
+    }
+}
+
 /*
 Add user object to appropriate table. Also insert into the
 DATA.users Map.
@@ -196,6 +283,7 @@ function add_user_to_display(u) {
         tr.setAttribute("data-uname", v.uname);
         tr.appendChild(UTIL.text_td(v.uname));
         tr.appendChild(UTIL.text_td(v.email));
+        tr.appendChild(make_invite_td(v.uname));
         tr.appendChild(make_user_edit_button_td(v.uname, edit_admin));
 
         DISPLAY.admin_tbody.appendChild(tr);
@@ -208,6 +296,7 @@ function add_user_to_display(u) {
         tr.setAttribute("data-uname", v.uname);
         tr.appendChild(UTIL.text_td(v.uname));
         tr.appendChild(UTIL.text_td(v.email));
+        tr.appendChild(make_invite_td(v.uname));
         tr.appendChild(make_user_edit_button_td(v.uname, edit_boss));
 
         DISPLAY.boss_tbody.appendChild(tr);
@@ -221,6 +310,7 @@ function add_user_to_display(u) {
         tr.appendChild(UTIL.text_td(v.uname));
         tr.appendChild(UTIL.text_td(v.email));
         tr.appendChild(UTIL.text_td(u.Teacher.name));
+        tr.appendChild(make_invite_td(v.uname));
         tr.appendChild(make_user_edit_button_td(v.uname, edit_teacher));
 
         DISPLAY.teacher_tbody.appendChild(tr);
@@ -237,10 +327,25 @@ function add_user_to_display(u) {
         tr.appendChild(UTIL.text_td(s.teacher));
         tr.appendChild(UTIL.text_td(v.email));
         tr.appendChild(UTIL.text_td(s.parent));
+        tr.appendChild(make_invite_td(v.uname));
         tr.appendChild(make_user_edit_button_td(v.uname, edit_student));
 
         DISPLAY.student_tbody.appendChild(tr);
 
+    } else if(u.Parent) {
+        const v = u.Parent.base;
+        DATA.users.set(v.uname, u);
+
+        const tr = document.createElement("tr");
+        tr.setAttribute("data-uname", v.uname);
+        tr.appendChild(UTIL.text_td(v.uname));
+        tr.appendChild(UTIL.text_td(v.email));
+        tr.appendChild(UTIL.text_td(u.Parent.children.join(", ")));
+        tr.appendChild(make_invite_td(v.uname));
+        tr.appendChild(make_parent_actions_td(v.uname));
+
+        DISPLAY.parent_tbody.appendChild(tr);
+
     } else {
         console.log("add_user_to_display() not implemented for", u);
     }
@@ -280,6 +385,7 @@ function edit_admin(evt) {
         del.disabled = true;
     }
 
+    set_invite_checkbox(form, uname);
     DISPLAY.admin_edit.showModal();
 }
 
@@ -321,7 +427,7 @@ function edit_admin_submit() {
     if(uname_input.disabled) {
         request_action("update-user", u, `Updating user ${uname}...`);
     } else {
-        request_action("add-user", u, `Adding user ${uname}...`);
+        request_action("add-user", u, `Adding user ${uname}...`, invite_headers(form));
     }
 }
 
@@ -379,6 +485,7 @@ function edit_boss(evt) {
         del.disabled = true;
     }
 
+    set_invite_checkbox(form, uname);
     DISPLAY.boss_edit.showModal();
 }
 
@@ -420,7 +527,7 @@ function edit_boss_submit() {
     if(uname_input.disabled) {
         request_action("update-user", u, `Updating user ${uname}...`);
     } else {
-        request_action("add-user", u, `Adding user ${uname}...`);
+        request_action("add-user", u, `Adding user ${uname}...`, invite_headers(form));
     }
 }
 
@@ -467,6 +574,7 @@ function edit_teacher(evt) {
         del.disabled = true;
     }
 
+    set_invite_checkbox(form, uname);
     DISPLAY.teacher_edit.showModal();
 }
 
@@ -500,7 +608,7 @@ function edit_teacher_submit() {
     if(uname_input.disabled) {
         request_action("update-user", u, `Updating user ${uname}...`);
     } else {
-        request_action("add-user", u, `Adding user ${uname}...`);
+        request_action("add-user", u, `Adding user ${uname}...`, invite_headers(form));
     }
 }
 
@@ -630,6 +738,7 @@ function edit_student(evt) {
 
     display_completion_history(uname);
 
+    set_invite_checkbox(form, uname);
     DISPLAY.student_edit.showModal();
 }
 
@@ -657,6 +766,8 @@ function edit_student_submit() {
     const rest = data.get("rest") || "";
     const teacher = data.get("teacher");
     const parent = (data.get("parent") || "").trim();
+    const old = DATA.users.get(uname);
+    const version = (old && old['Student']) ? old['Student'].version : 0;
 
     let u = {
         "Student": {
@@ -674,6 +785,7 @@ function edit_student_submit() {
             "spring_exam_fraction": 0.2,
             "fall_notices": 0,
             "spring_notices": 0,
+            "version": version,
         }
     };
 
@@ -683,7 +795,7 @@ function edit_student_submit() {
     if(uname_input.disabled) {
         request_action("update-user", u, `Updating user ${uname}...`);
     } else {
-        request_action("add-user", u, `Adding user ${uname}...`);
+        request_action("add-user", u, `Adding user ${uname}...`, invite_headers(form));
     }
 }
 
@@ -707,6 +819,245 @@ async function delete_student_submit(evt) {
 document.getElementById("delete-student")
     .addEventListener("click", delete_student_submit);
 
+/*
+Parents are created automatically when Students are uploaded, so there's
+nothing about them to edit; they can only be sent invitations or deleted.
+*/
+function make_parent_actions_td(uname) {
+    const td = document.createElement("td");
+
+    const invite = document.createElement("button");
+    UTIL.label("send invitation", invite);
+    invite.addEventListener("click", evt => {
+        evt.preventDefault();
+        request_action("resend-invite", uname, `Sending invitation to ${uname}...`);
+    });
+    td.appendChild(invite);
+
+    const view = document.createElement("button");
+    UTIL.label("view as", view);
+    view.addEventListener("click", evt => {
+        evt.preventDefault();
+        view_as(uname);
+    });
+    td.appendChild(view);
+
+    const del = document.createElement("button");
+    UTIL.label("delete", del);
+    del.addEventListener("click", async evt => {
+        evt.preventDefault();
+        const q = `Are you sure you want to delete Parent ${uname}?`;
+        if(await are_you_sure(q)) {
+            request_action("delete-user", uname, `Deleting ${uname}...`);
+        }
+    });
+    td.appendChild(del);
+
+    return td;
+}
+
+/*
+Clears failed login attempts (and thus any lockout) for the user whose
+details are currently being edited. The uname is read from the matching
+"delete" button, which the appropriate edit_xxx() function has already
+set up (and disabled if this is a new user).
+*/
+function unlock_user_submit(evt) {
+    evt.preventDefault();
+    const role = this.getAttribute("data-role");
+    const del = document.getElementById(`delete-${role}`);
+    if(del.disabled) {
+        return;
+    }
+    const uname = del.getAttribute("data-uname");
+    request_action("unlock-user", uname, `Unlocking ${uname}...`);
+}
+
+for(const role of ["admin", "boss", "teacher", "student"]) {
+    document.getElementById(`unlock-${role}`)
+        .addEventListener("click", unlock_user_submit);
+}
+
+/*
+Fills the sessions dialog with the list of a user's active sessions and
+shows it (if it isn't already showing).
+*/
+function populate_sessions(r) {
+    r.json()
+    .then(j => {
+        console.log("populate-sessions response:");
+        console.log(j);
+
+        UTIL.set_text(DISPLAY.sessions_uname, j.uname);
+        DISPLAY.sessions.setAttribute("data-uname", j.uname);
+        UTIL.clear(DISPLAY.sessions_tbody);
+        for(const s of j.sessions) {
+            const tr = document.createElement("tr");
+            tr.appendChild(UTIL.text_td(s.issued || ""));
+            tr.appendChild(UTIL.text_td(s.last_used));
+            tr.appendChild(UTIL.text_td(s.addr || ""));
+            if(s.impersonator) {
+                tr.appendChild(UTIL.text_td(`viewed as by ${s.impersonator}`));
+            } else {
+                tr.appendChild(UTIL.text_td(s.user_agent || ""));
+            }
+            const td = document.createElement("td");
+            const butt = document.createElement("button");
+            UTIL.label("log out", butt);
+            butt.addEventListener("click", (evt) => {
+                evt.preventDefault();
+                const body = { "uname": j.uname, "id": s.id };
+                request_action("revoke-session", body, `Logging out ${j.uname}...`);
+            });
+            td.appendChild(butt);
+            tr.appendChild(td);
+            DISPLAY.sessions_tbody.appendChild(tr);
+        }
+
+        if(!DISPLAY.sessions.open) {
+            DISPLAY.sessions.showModal();
+        }
+    }).catch(RQ.add_err);
+}
+
+/*
+Requests the list of sessions of the user whose details are currently being
+edited. Finds the uname the same way as unlock_user_submit().
+*/
+function list_sessions_submit(evt) {
+    evt.preventDefault();
+    const role = this.getAttribute("data-role");
+    const del = document.getElementById(`delete-${role}`);
+    if(del.disabled) {
+        return;
+    }
+    const uname = del.getAttribute("data-uname");
+    request_action("list-sessions", uname, `Fetching sessions for ${uname}...`);
+}
+
+for(const role of ["admin", "boss", "teacher", "student"]) {
+    document.getElementById(`sessions-${role}`)
+        .addEventListener("click", list_sessions_submit);
+}
+
+/*
+The name of the window (or tab) in which the view of user `uname` gets
+opened.
+*/
+function view_as_target(uname) {
+    return `camp-view-as-${uname}`;
+}
+
+/*
+Requests a read-only key with which to view what user `uname` sees.
+
+The window for the view is opened now, while we're still handling the
+click, so it doesn't get treated as a popup; open_view_as() fills it in
+once the key arrives.
+*/
+function view_as(uname) {
+    window.open("", view_as_target(uname));
+    request_action("impersonate", uname, `Viewing as ${uname}...`);
+}
+
+function view_as_submit(evt) {
+    evt.preventDefault();
+    const role = this.getAttribute("data-role");
+    const del = document.getElementById(`delete-${role}`);
+    if(del.disabled) {
+        return;
+    }
+    view_as(del.getAttribute("data-uname"));
+}
+
+for(const role of ["boss", "teacher", "student"]) {
+    document.getElementById(`view-as-${role}`)
+        .addEventListener("click", view_as_submit);
+}
+
+/*
+Posts the read-only key from an "impersonate" response to /view-as, in the
+window view_as() opened for it.
+*/
+function open_view_as(r) {
+    r.json()
+    .then(j => {
+        const form = document.createElement("form");
+        form.method = "post";
+        form.action = "/view-as";
+        form.target = view_as_target(j.uname);
+        for(const [name, value] of [["uname", j.uname], ["key", j.key]]) {
+            const input = document.createElement("input");
+            input.type = "hidden";
+            input.name = name;
+            input.value = value;
+            form.appendChild(input);
+        }
+        document.body.appendChild(form);
+        form.submit();
+        form.remove();
+    }).catch(RQ.add_err);
+}
+
+document.getElementById("sessions-close")
+    .addEventListener("click", (evt) => {
+        evt.preventDefault();
+        DISPLAY.sessions.close();
+    });
+
+document.getElementById("sessions-revoke-all")
+    .addEventListener("click", async (evt) => {
+        evt.preventDefault();
+        const uname = DISPLAY.sessions.getAttribute("data-uname");
+        const q = `Are you sure you want to log ${uname} out everywhere?`;
+        if(await are_you_sure(q)) {
+            request_action("revoke-sessions", uname, `Logging out ${uname}...`);
+        }
+    });
+
+/*
+Removes the two-factor enrollment of the Admin or Boss currently being
+edited, so they can enroll again (if they've lost their authenticator and
+recovery codes, say). Finds the uname the same way as unlock_user_submit().
+*/
+async function reset_2fa_submit(evt) {
+    evt.preventDefault();
+    const role = this.getAttribute("data-role");
+    const del = document.getElementById(`delete-${role}`);
+    if(del.disabled) {
+        return;
+    }
+    const uname = del.getAttribute("data-uname");
+    const q = `Are you sure you want to remove two-factor authentication for ${uname}?`;
+    if(await are_you_sure(q)) {
+        request_action("reset-2fa", uname, `Resetting two-factor for ${uname}...`);
+    }
+}
+
+for(const role of ["admin", "boss"]) {
+    document.getElementById(`reset-2fa-${role}`)
+        .addEventListener("click", reset_2fa_submit);
+}
+
+/*
+Sends the user currently being edited a new invitation email. Finds the
+uname the same way as unlock_user_submit().
+*/
+function resend_invite_submit(evt) {
+    evt.preventDefault();
+    const role = this.getAttribute("data-role");
+    const del = document.getElementById(`delete-${role}`);
+    if(del.disabled) {
+        return;
+    }
+    const uname = del.getAttribute("data-uname");
+    request_action("resend-invite", uname, `Sending invitation to ${uname}...`);
+}
+
+for(const role of ["admin", "boss", "teacher", "student"]) {
+    document.getElementById(`resend-invite-${role}`)
+        .addEventListener("click", resend_invite_submit);
+}
 
 document.getElementById("upload-students")
     .addEventListener("click", () => {
@@ -721,7 +1072,9 @@ function upload_students_submit(evt) {
     UTIL.get_file_as_text(file)
     .then((text) => {
         DISPLAY.student_upload.close();
-        request_action("upload-students", text, `Uploading new students...`);
+        request_action(
+            "upload-students", text, `Uploading new students...`, invite_headers(form)
+        );
     })
     .catch((err) => {
         RQ.add_err(`Error opening local file: ${err}`);
@@ -731,6 +1084,152 @@ function upload_students_submit(evt) {
 document.getElementById("upload-students-confirm")
     .addEventListener("click",upload_students_submit);
 
+/*
+For issuing and revoking API tokens, for scripts that need to use the
+Admin API without logging in.
+
+The value of a newly-issued token only comes back from the server in the
+response to the "issue-token" request, so it gets shown right then.
+*/
+function populate_tokens(r) {
+    r.json()
+    .then(j => {
+        console.log("populate-tokens response:");
+        console.log(j);
+
+        UTIL.clear(DISPLAY.token_tbody);
+        for(const t of j.tokens) {
+            const tr = document.createElement("tr");
+            tr.appendChild(UTIL.text_td(t.name));
+            tr.appendChild(UTIL.text_td(t.uname));
+            tr.appendChild(UTIL.text_td(t.actions.join(" ")));
+            tr.appendChild(UTIL.text_td(t.created));
+            tr.appendChild(UTIL.text_td(t.expires || "never"));
+            tr.appendChild(UTIL.text_td(t.last_used || ""));
+            const td = document.createElement("td");
+            const butt = document.createElement("button");
+            UTIL.label("revoke", butt);
+            butt.addEventListener("click", async (evt) => {
+                evt.preventDefault();
+                const q = `Are you sure you want to revoke API token "${t.name}"?`;
+                if(await are_you_sure(q)) {
+                    request_action("revoke-token", String(t.id), `Revoking API token ${t.name}...`);
+                }
+            });
+            td.appendChild(butt);
+            tr.appendChild(td);
+            DISPLAY.token_tbody.appendChild(tr);
+        }
+
+        if(j.new_token) {
+            UTIL.set_text(DISPLAY.token_new_name, j.new_token.name);
+            UTIL.set_text(DISPLAY.token_new_value, j.new_token.token);
+            DISPLAY.token_new.showModal();
+        }
+    }).catch(RQ.add_err);
+}
+
+document.getElementById("add-token")
+    .addEventListener("click", () => {
+        const form = document.forms["issue-token"];
+        for(const ipt of form.elements) {
+            ipt.value = "";
+        }
+        DISPLAY.token_issue.showModal();
+    });
+
+function issue_token_submit(evt) {
+    const form = document.forms["issue-token"];
+    const data = new FormData(form);
+
+    const name = (data.get("name") || "").trim();
+    const uname = (data.get("uname") || "").trim();
+    const actions = (data.get("actions") || "")
+        .split(/[\s,]+/)
+        .filter(a => a.length > 0);
+    const expires = data.get("expires") || null;
+
+    const body = {
+        "name": name,
+        "uname": uname,
+        "actions": actions,
+        "expires": expires,
+    };
+
+    DISPLAY.token_issue.close();
+    request_action("issue-token", body, `Issuing API token ${name}...`);
+}
+
+document.getElementById("issue-token-cancel")
+    .addEventListener("click", (evt) => {
+        evt.preventDefault();
+        DISPLAY.token_issue.close();
+    });
+document.getElementById("issue-token-confirm")
+    .addEventListener("click", issue_token_submit);
+/*
+For granting users permissions beyond those of their roles (letting a
+Boss fix Course typos, say).
+*/
+function populate_grants(r) {
+    r.json()
+    .then(j => {
+        console.log("populate-grants response:");
+        console.log(j);
+
+        UTIL.clear(DISPLAY.grant_tbody);
+        const unames = Object.keys(j.grants).sort();
+        for(const uname of unames) {
+            for(const perm of j.grants[uname]) {
+                const tr = document.createElement("tr");
+                tr.appendChild(UTIL.text_td(uname));
+                tr.appendChild(UTIL.text_td(perm));
+                const td = document.createElement("td");
+                const butt = document.createElement("button");
+                UTIL.label("revoke", butt);
+                butt.addEventListener("click", (evt) => {
+                    evt.preventDefault();
+                    const body = { "uname": uname, "permission": perm };
+                    request_action("revoke-permission", body, `Revoking ${perm} from ${uname}...`);
+                });
+                td.appendChild(butt);
+                tr.appendChild(td);
+                DISPLAY.grant_tbody.appendChild(tr);
+            }
+        }
+
+        UTIL.clear(DISPLAY.grant_options);
+        for(const [endpoint, actions] of Object.entries(j.actions)) {
+            for(const action of ["*"].concat(actions)) {
+                const opt = document.createElement("option");
+                opt.value = `${endpoint}:${action}`;
+                DISPLAY.grant_options.appendChild(opt);
+            }
+        }
+    }).catch(RQ.add_err);
+}
+
+document.getElementById("grant-add")
+    .addEventListener("click", (evt) => {
+        evt.preventDefault();
+        const uname = DISPLAY.grant_uname.value.trim();
+        const perm = DISPLAY.grant_permission.value.trim();
+        if(!uname || !perm) {
+            RQ.add_err("Enter both a uname and a permission to grant.");
+            return;
+        }
+        const body = { "uname": uname, "permission": perm };
+        DISPLAY.grant_permission.value = "";
+        request_action("grant-permission", body, `Granting ${perm} to ${uname}...`);
+    });
+
+document.getElementById("new-token-close")
+    .addEventListener("click", (evt) => {
+        evt.preventDefault();
+        UTIL.clear(DISPLAY.token_new_value);
+        DISPLAY.token_new.close();
+    });
+
 
 /*
 
@@ -1143,25 +1642,6 @@ function upload_course_submit(evt) {
 document.getElementById("upload-course-confirm")
     .addEventListener("click", upload_course_submit);
 
-document.getElementById("reset-students-button")
-    .addEventListener("click", () => {
-        DISPLAY.student_reset.showModal();
-    });
-document.getElementById("reset-students-cancel")
-    .addEventListener("click", (evt) => {
-        evt.preventDefault();
-        DISPLAY.student_reset.close();
-    });
-document.getElementById("reset-students-confirm")
-    .addEventListener("click", async function(evt) {
-        evt.preventDefault();
-        const q = "Are you sure you want to exercise the scorced-earth nuclear option on all student Goals and Student User records?";
-        if(await are_you_sure(q)) {
-            DISPLAY.student_reset.close();
-            request_action("reset-students", null, "Deleting all student data.")
-        }
-    });
-
 function add_completion(evt) {
     evt.preventDefault();
 
@@ -1227,4 +1707,107 @@ UTIL.ensure_on_load(() => {
     request_action("populate-users", "", "Fetching User data...");
     request_action("populate-completion", "", "Fetching Course completion history...");
     request_action("populate-courses", "", "Fetching Course data...");
+    request_action("populate-tokens", "", "Fetching API tokens...");
+    request_action("populate-grants", "", "Fetching extra permissions...");
 });
+
+/*
+
+AUDIT LOG section
+
+*/
+
+/*
+Body for a "query-audit" or "export-audit" request, from the values in the
+"audit-query" form. Blank fields match anything.
+*/
+function audit_query_body() {
+    const form = document.forms["audit-query"];
+    const q = {};
+    for(const name of ["actor", "student", "from", "to"]) {
+        q[name] = form.elements[name].value.trim();
+    }
+    return JSON.stringify(q);
+}
+
+function populate_audit(r) {
+    r.json()
+    .then(j => {
+        console.log("query-audit response:");
+        console.log(j);
+
+        UTIL.clear(DISPLAY.audit_tbody);
+        for(const e of j) {
+            const tr = document.createElement("tr");
+            for(const field of ["at", "actor", "role", "action", "target", "student", "before", "after"]) {
+                tr.appendChild(UTIL.text_td(e[field] || ""));
+            }
+            DISPLAY.audit_tbody.appendChild(tr);
+        }
+    }).catch(RQ.add_err);
+}
+
+function save_audit(r) {
+    r.blob()
+    .then(blob => {
+        const file_url = window.URL.createObjectURL(blob);
+        const link = document.createElement("A");
+        link.href = file_url;
+        link.download = "audit.csv";
+        link.click();
+        setTimeout(() => window.URL.revokeObjectURL(file_url), DOWNLOAD_DELAY);
+    }).catch(RQ.add_err);
+}
+
+document.getElementById("audit-query-submit")
+    .addEventListener("click", evt => {
+        evt.preventDefault();
+        request_action("query-audit", audit_query_body(), "Searching audit log...");
+    });
+
+document.getElementById("audit-export")
+    .addEventListener("click", evt => {
+        evt.preventDefault();
+        request_action("export-audit", audit_query_body(), "Exporting audit log...");
+    });
+
+/*
+
+BACKUP section
+
+*/
+
+function save_archive(r) {
+    const disposition = r.headers.get("content-disposition") || "";
+    const match = disposition.match(/filename="([^"]+)"/);
+    const fname = match ? match[1] : "camp.zip";
+
+    r.blob()
+    .then(blob => {
+        const file_url = window.URL.createObjectURL(blob);
+        const link = document.createElement("A");
+        link.href = file_url;
+        link.download = fname;
+        link.click();
+        setTimeout(() => window.URL.revokeObjectURL(file_url), DOWNLOAD_DELAY);
+    }).catch(RQ.add_err);
+}
+
+document.getElementById("archive-export")
+    .addEventListener("click", evt => {
+        evt.preventDefault();
+        request_action("export-archive", null, "Exporting database archive...");
+    });
+
+function show_reload_result(r) {
+    r.text()
+    .then(t => {
+        UTIL.set_text(document.getElementById("config-reload-result"), t);
+    }).catch(RQ.add_err);
+}
+
+document.getElementById("config-reload")
+    .addEventListener("click", evt => {
+        evt.preventDefault();
+        request_action("reload-config", null, "Reloading configuration...");
+    });
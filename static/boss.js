@@ -169,6 +169,12 @@ function field_response(r) {
             display_pdf(r); break;
         case "download-archive":
             save_archive(r); break;
+        case "export-audit":
+            save_archive(r); break;
+        case "populate-years":
+            YEARS.populate_years(r); break;
+        case "populate-year-goals":
+            YEARS.populate_year_goals(r); break;
         case "none": /* No action required, obviously. */
             break;
         default:
@@ -297,6 +303,18 @@ for(const butt of document.querySelectorAll("div#archive-buttons button")) {
     butt.addEventListener("click", download_archive);
 }
 
+/* Download the audit log entries matching the "audit" form as CSV. Blank
+fields match anything. */
+document.getElementById("audit-export").addEventListener("click", evt => {
+    evt.preventDefault();
+    const form = document.forms["audit"];
+    const q = {};
+    for(const name of ["actor", "student", "from", "to"]) {
+        q[name] = form.elements[name].value.trim();
+    }
+    request_action("export-audit", q, "Exporting audit log...");
+});
+
 document.getElementById("show-completion").addEventListener("click", evt => {
     evt.preventDefault();
     DISPLAY.completion_view.showModal();
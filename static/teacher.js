@@ -7,6 +7,7 @@ const API_ENDPOINT = "/teacher";
 const DATA = {
     courses: new Map(),
     chapters: new Map(),
+    customs: new Map(),
     paces: new Map(),
     goals: new Map(),
     traits: [],
@@ -22,6 +23,9 @@ const DISPLAY = {
     goal_edit_meta: document.getElementById("edit-goal-meta"),
     course_input: document.getElementById("edit-goal-course"),
     seq_input: document.getElementById("edit-goal-seq"),
+    custom_input: document.getElementById("edit-goal-custom"),
+    custom_edit: document.getElementById("edit-custom"),
+    custom_list: document.querySelector("table#custom-list > tbody"),
     goal_complete: document.getElementById("complete-goal"),
     goal_complete_meta: document.getElementById("complete-goal-meta"),
     sidecar_edit: document.getElementById("edit-sidecar"),
@@ -92,9 +96,32 @@ function input_label_pair(label, id, name, type) {
 
 const PCAL_COLS = ["course", "chapter", "due", "done", "tries", "score", "edit"];
 
-function row_from_goal(g) {
+/*
+Names to display for the material covered by Goal g, whether it's a course
+chapter or a custom one.
+*/
+function goal_names(g) {
+    if(g.custom) {
+        const cust = DATA.customs.get(g.custom);
+        return {
+            course: "Custom",
+            book: "",
+            chapter: cust ? cust.title : `custom #${g.custom}`,
+            subject: null,
+        };
+    }
     const crs = DATA.courses.get(g.sym);
     const chp = DATA.chapters.get(crs.chapters[g.seq]);
+    return {
+        course: crs.title,
+        book: crs.book,
+        chapter: chp.title,
+        subject: chp.subject,
+    };
+}
+
+function row_from_goal(g) {
+    const names = goal_names(g);
 
     const tr = document.createElement("tr");
     tr.setAttribute("data-id", g.id);
@@ -127,15 +154,15 @@ function row_from_goal(g) {
         }
     }
 
-    const ctd = UTIL.text_td(crs.title);
-    ctd.setAttribute("title", crs.book);
+    const ctd = UTIL.text_td(names.course);
+    ctd.setAttribute("title", names.book);
     tr.appendChild(ctd);
 
-    let chtext = chp.title;
+    let chtext = names.chapter;
     if(g.rev) { chtext = chtext + " R"; }
     if(g.inc) { chtext = chtext + " I"; }
     const chtd = UTIL.text_td(chtext)
-    if(chp.subject) { chtd.setAttribute("title", chp.subject); }
+    if(names.subject) { chtd.setAttribute("title", names.subject); }
     tr.appendChild(chtd);
 
     const duetd = UTIL.text_td(g.due || "")
@@ -612,6 +639,57 @@ function populate_courses(r) {
             }
         }
 
+        request_action("populate-custom", "", "Populating custom chapters.")
+    })
+    .catch(log_numbered_error);
+}
+
+function populate_custom(r) {
+    r.json()
+    .then(j => {
+        console.log("populate-custom response:", j);
+
+        DATA.customs = new Map();
+        UTIL.clear(DISPLAY.custom_input);
+        UTIL.clear(DISPLAY.custom_list);
+        const blank = document.createElement("option");
+        blank.value = "";
+        UTIL.set_text(blank, "(none; use course and chapter)");
+        DISPLAY.custom_input.appendChild(blank);
+
+        for(const cust of j) {
+            DATA.customs.set(cust.id, cust);
+
+            const opt = document.createElement("option");
+            opt.value = cust.id;
+            UTIL.set_text(opt, `${cust.title} (${cust.uname})`);
+            DISPLAY.custom_input.appendChild(opt);
+
+            const tr = document.createElement("tr");
+            tr.appendChild(UTIL.text_td(cust.id));
+            tr.appendChild(UTIL.text_td(cust.title));
+            tr.appendChild(UTIL.text_td(cust.weight));
+            tr.appendChild(UTIL.text_td(cust.uname));
+            const td = document.createElement("td");
+            if(cust.uname == AUTH.uname) {
+                const edit = document.createElement("button");
+                edit.setAttribute("data-id", cust.id);
+                edit.setAttribute("title", "edit custom chapter");
+                UTIL.label("\u270e", edit);
+                edit.addEventListener("click", edit_custom);
+                td.appendChild(edit);
+                const del = document.createElement("button");
+                del.setAttribute("data-id", cust.id);
+                del.setAttribute("title", "delete custom chapter");
+                UTIL.label("\u2716", del);
+                del.addEventListener("click", delete_custom);
+                td.appendChild(del);
+            }
+            tr.appendChild(td);
+            DISPLAY.custom_list.appendChild(tr);
+        }
+
+        // Custom chapter titles and weights show up in the calendars.
         request_action("populate-goals", "", "Populating pace calendars.")
     })
     .catch(log_numbered_error);
@@ -759,6 +837,7 @@ async function show_sidecar(r) {
     const form = document.forms["edit-sidecar"];
 
     form.elements["uname"].value = uname;
+    form.elements["version"].value = car.version || 0;
 
     const name = `${pace.rest} ${pace.last}`;
     UTIL.set_text(document.getElementById("edit-sidecar-meta"), name);
@@ -794,10 +873,9 @@ async function show_sidecar(r) {
     UTIL.clear(goal_rows);
     for(const g of pace.goals) {
         const tr = document.createElement("tr");
-        const course = DATA.courses.get(g.sym);
-        course_symbols.add(g.sym);
-        const chapt = DATA.chapters.get(course.chapters[g.seq]);
-        const ch_name = `${course.title} ${chapt.title}`;
+        if(g.sym) { course_symbols.add(g.sym); }
+        const names = goal_names(g);
+        const ch_name = `${names.course} ${names.chapter}`;
 
         let td = document.createElement("td");
         UTIL.set_text(td, ch_name);
@@ -928,7 +1006,12 @@ document.getElementById("edit-sidecar-summer")
     .addEventListener("click", save_sidecar);
 
 function field_response(r) {
-    if(!r.ok) {
+    /*  A 409 means the change was refused because what it was made from has
+        since been changed by someone else. The response still carries the
+        current state, so it gets displayed as usual. */
+    if(r.status == 409) {
+        RQ.add_err("That was changed by someone else since you loaded it, so your change wasn't saved. The current version is now shown; please try again.");
+    } else if(!r.ok) {
         r.text()
         .then(t => {
             const err_txt = `${t}\n(${r.status}: ${r.statusText})`;
@@ -956,6 +1039,8 @@ function field_response(r) {
     switch(action) {
         case "populate-courses":
             populate_courses(r); break;
+        case "populate-custom":
+            populate_custom(r); break;
         case "populate-goals":
             populate_goals(r); break;
         case "update-pace":
@@ -972,6 +1057,12 @@ function field_response(r) {
             show_pdf(r); break;
         case "student-history":
             show_history(r); break;
+        case "populate-years":
+            YEARS.populate_years(r); break;
+        case "populate-year-goals":
+            YEARS.populate_year_goals(r); break;
+        case "download-pdf":
+            YEARS.show_pdf(r); break;
         case "none":
             /* Don't do anything. This is a success that requires no action. */
             break;
@@ -1079,6 +1170,7 @@ function populate_seq_list(evt) {
     const list = document.getElementById("course-seqs");
     const sym = document.forms["edit-goal"].elements["course"].value;
     const crs = DATA.courses.get(sym);
+    if(!crs) { return; }
 
     UTIL.clear(list);
     const seqs = crs.chapters.filter(x => Boolean(x))
@@ -1105,6 +1197,18 @@ function populate_seq_list(evt) {
 document.getElementById("edit-goal-course")
     .addEventListener("change", populate_seq_list);
 
+/*
+A Goal either covers a course chapter or a custom one, so the course and
+chapter inputs are only in play when no custom chapter is selected.
+*/
+function toggle_custom_goal(evt) {
+    const is_custom = Boolean(DISPLAY.custom_input.value);
+    DISPLAY.course_input.disabled = is_custom;
+    DISPLAY.seq_input.disabled = is_custom;
+}
+
+DISPLAY.custom_input.addEventListener("change", toggle_custom_goal);
+
 function get_previous_goal(uname, goal_id) {
     const goals = DATA.paces.get(uname).goals;
     let prev_g = null;
@@ -1127,8 +1231,9 @@ function edit_goal(evt) {
         id = Number(id);
         const g = DATA.goals.get(id);
         form.elements["id"].value = id;
-        form.elements["course"].value = g.sym;
-        form.elements["seq"].value = g.seq;
+        form.elements["course"].value = g.sym || "";
+        form.elements["seq"].value = g.seq || "";
+        form.elements["custom"].value = g.custom || "";
         form.elements["due"].value = g.due;
         form.elements["review"].checked = g.rev;
         form.elements["incomplete"].checked = g.inc;
@@ -1141,6 +1246,7 @@ function edit_goal(evt) {
             if(ipt.value) { ipt.value = null; }
             if(ipt.checked) { ipt.checked = false; }
         }
+        form.elements["custom"].value = "";
         del.disabled = true;
         del.removeAttribute("data-id");
         const uname = this.getAttribute("data-uname");
@@ -1148,7 +1254,7 @@ function edit_goal(evt) {
 
         const last_g = DATA.paces.get(uname).goals.at(-1);
         console.log(last_g);
-        if(last_g) {
+        if(last_g && !last_g.custom) {
             const sym = last_g.sym;
             const next_seq = last_g.seq + 1;
             console.log(sym, next_seq);
@@ -1161,6 +1267,7 @@ function edit_goal(evt) {
 
     }
 
+    toggle_custom_goal();
     DISPLAY.goal_edit.showModal();
 }
 
@@ -1168,21 +1275,23 @@ function edit_goal_submit(evt) {
     const form = document.forms["edit-goal"];
     const uname = this.getAttribute("data-uname") || "";
     const id = Number(form.elements["id"].value) || 0;
+    const custom = Number(form.elements["custom"].value) || null;
     const sym = form.elements["course"].value?.trim() || "";
     const course = DATA.courses.get(sym);
     const seq = Number(form.elements["seq"].value) || 0;
-    const chapt = course.chapters[seq];
-    if(sym == "") {
-        RQ,add_err("You must select a valid course.");
-        return;
-    } else if(!course) {
-        RQ.add_err(`"${sym} is not a valid course symbol.`);
-        return;
-    }
-    if(!chapt) {
-        const err = `You must select a valid chapter number for course "${sym}": ${course.title} (${course.book}).`
-        RQ.add_err(err);
-        return;
+    if(!custom) {
+        if(sym == "") {
+            RQ.add_err("You must select a valid course.");
+            return;
+        } else if(!course) {
+            RQ.add_err(`"${sym} is not a valid course symbol.`);
+            return;
+        }
+        if(!course.chapters[seq]) {
+            const err = `You must select a valid chapter number for course "${sym}": ${course.title} (${course.book}).`
+            RQ.add_err(err);
+            return;
+        }
     }
 
     // Pre-fill default values for a new goal.
@@ -1202,8 +1311,15 @@ function edit_goal_submit(evt) {
     }
 
     g["id"] = id;
-    g["sym"] = sym;
-    g["seq"] = seq;
+    if(custom) {
+        g["sym"] = null;
+        g["seq"] = null;
+        g["custom"] = custom;
+    } else {
+        g["sym"] = sym;
+        g["seq"] = seq;
+        g["custom"] = null;
+    }
     g["rev"] = form.elements["review"].checked;
     g["inc"] = form.elements["incomplete"].checked;
     g["due"] = form.elements["due"].value || null;
@@ -1212,7 +1328,8 @@ function edit_goal_submit(evt) {
     if(form.elements["id"].value) {
         request_action("update-goal", g, `Updating Goal ${id}`);
     } else {
-        request_action("add-goal", g, `Adding new Goal: ${sym}, ${seq} for ${uname}`);
+        const what = custom ? goal_names(g).chapter : `${sym}, ${seq}`;
+        request_action("add-goal", g, `Adding new Goal: ${what} for ${uname}`);
     }
     
 }
@@ -1228,9 +1345,8 @@ document.getElementById("edit-goal-confirm")
 async function delete_goal_submit(evt) {
     const id = this.getAttribute("data-id");
     const g = DATA.goals.get(Number(id));
-    const crs = DATA.courses.get(g.sym);
-    const chp = DATA.chapters.get(crs.chapters[g.seq]);
-    const q = `Are you sure you want to delete ${crs.title} ${chp.title} for ${g.uname}?.`;
+    const names = goal_names(g);
+    const q = `Are you sure you want to delete ${names.course} ${names.chapter} for ${g.uname}?.`;
     if(await are_you_sure(q)) {
         DISPLAY.goal_edit.close();
         request_action("delete-goal", id, `Deleting Goal #${id}.`);
@@ -1240,6 +1356,82 @@ async function delete_goal_submit(evt) {
 document.getElementById("delete-goal")
     .addEventListener("click", delete_goal_submit);
 
+function show_custom(evt) {
+    evt.preventDefault();
+    clear_custom_form();
+    DISPLAY.custom_edit.showModal();
+}
+
+function clear_custom_form() {
+    const form = document.forms["edit-custom"];
+    form.elements["id"].value = "";
+    form.elements["title"].value = "";
+    form.elements["weight"].value = "";
+}
+
+function edit_custom(evt) {
+    evt.preventDefault();
+    const cust = DATA.customs.get(Number(this.getAttribute("data-id")));
+    const form = document.forms["edit-custom"];
+    form.elements["id"].value = cust.id;
+    form.elements["title"].value = cust.title;
+    form.elements["weight"].value = cust.weight;
+}
+
+function edit_custom_submit(evt) {
+    evt.preventDefault();
+    const form = document.forms["edit-custom"];
+    const id = Number(form.elements["id"].value) || 0;
+    const title = form.elements["title"].value.trim();
+    const weight = Number(form.elements["weight"].value);
+    if(!title) {
+        RQ.add_err("Custom chapters must have titles.");
+        return;
+    }
+    if(!(weight > 0)) {
+        RQ.add_err("Custom chapter weights must be positive numbers.");
+        return;
+    }
+
+    const cust = {
+        "id": id,
+        "uname": AUTH.uname,
+        "title": title,
+        "weight": weight,
+    };
+    clear_custom_form();
+    if(id) {
+        request_action("update-custom", cust, `Updating custom chapter "${title}".`);
+    } else {
+        request_action("add-custom", cust, `Adding custom chapter "${title}".`);
+    }
+}
+
+async function delete_custom(evt) {
+    evt.preventDefault();
+    const id = this.getAttribute("data-id");
+    const cust = DATA.customs.get(Number(id));
+    const q = `Are you sure you want to delete the custom chapter "${cust.title}"?`;
+    if(await are_you_sure(q)) {
+        request_action("delete-custom", id, `Deleting custom chapter "${cust.title}".`);
+    }
+}
+
+document.getElementById("show-custom")
+    .addEventListener("click", show_custom);
+document.getElementById("edit-custom-confirm")
+    .addEventListener("click", edit_custom_submit);
+document.getElementById("edit-custom-clear")
+    .addEventListener("click", evt => {
+        evt.preventDefault();
+        clear_custom_form();
+    });
+document.getElementById("edit-custom-close")
+    .addEventListener("click", evt => {
+        evt.preventDefault();
+        DISPLAY.custom_edit.close();
+    });
+
 function complete_goal(evt) {
     const id = this.getAttribute("data-id");
     const form = document.forms["complete-goal"];
@@ -1372,7 +1564,10 @@ function save_sidecar(evt) {
     const data = new FormData(form);
     const uname = data.get("uname");
 
-    const sc = { "uname": uname };
+    const sc = {
+        "uname": uname,
+        "version": Number(data.get("version")) || 0,
+    };
 
     const fact_fieldset = form.querySelector("fieldset#fact-mastery-container");
     const facts_inputs = fact_fieldset.querySelectorAll("select");
@@ -0,0 +1,188 @@
+/*
+years.js
+
+A look back at earlier academic years' Goals and reports, for Bosses and
+Teachers. Nothing here can change anything; past years are read-only.
+
+The page's own script should define request_action(), and its
+field_response() should hand "populate-years" and "populate-year-goals"
+responses to YEARS.populate_years() and YEARS.populate_year_goals().
+"download-pdf" responses go to a read-only PDF viewer: the page's own, if
+it has one, or YEARS.show_pdf(). This script should be loaded with the DEFER
+attribute, after the page's own script.
+*/
+"use strict";
+
+const YEARS = {
+    TERMS: ["fall", "spring", "summer"],
+    dialog: document.getElementById("years-dialog"),
+    select: document.getElementById("years-select"),
+    students: document.getElementById("years-students"),
+    pdf_view: document.getElementById("years-pdf"),
+    pdf_object: document.getElementById("years-pdf-object"),
+};
+
+YEARS.log_error = function(msg) {
+    return (e) => {
+        console.log(e);
+        RQ.add_err(`${msg}; see console for details.`);
+    };
+}
+
+YEARS.request_years = function(evt) {
+    evt.preventDefault();
+    request_action("populate-years", null, "Retrieving list of academic years.");
+}
+
+YEARS.populate_years = function(r) {
+    r.json()
+    .then(j => {
+        UTIL.clear(YEARS.select);
+        for(const y of j.years) {
+            const opt = document.createElement("option");
+            opt.value = y.year;
+            const text = (y.year == j.current) ? `${y.name} (current)` : y.name;
+            opt.appendChild(document.createTextNode(text));
+            YEARS.select.appendChild(opt);
+        }
+
+        // Start with the most recent year before this one, if there is one.
+        const past = j.years.filter(y => y.year < j.current);
+        if(past.length > 0) {
+            YEARS.select.value = past[past.length - 1].year;
+        } else {
+            YEARS.select.value = j.current;
+        }
+
+        UTIL.clear(YEARS.students);
+        if(!YEARS.dialog.open) {
+            YEARS.dialog.showModal();
+        }
+        YEARS.request_goals();
+    })
+    .catch(YEARS.log_error("Error reading list of academic years"));
+}
+
+YEARS.request_goals = function() {
+    const year = YEARS.select.value;
+    const name = YEARS.select.selectedOptions[0].textContent;
+    request_action(
+        "populate-year-goals", null,
+        `Retrieving Goals from ${name}.`,
+        { "x-camp-year": year }
+    );
+}
+
+YEARS.request_report = function(evt) {
+    evt.preventDefault();
+    const uname = this.getAttribute("data-uname");
+    const term = this.getAttribute("data-term");
+    const year = this.getAttribute("data-year");
+    const extra_headers = {
+        "x-camp-student": uname,
+        "x-camp-term": term,
+        "x-camp-year": year,
+    };
+    request_action(
+        "download-report", null,
+        `Retrieving ${term} report for ${uname}.`,
+        extra_headers
+    );
+}
+
+YEARS.make_pace = function(p, year) {
+    const tab = document.createElement("table");
+    tab.setAttribute("class", "year-pace");
+
+    const thead = document.createElement("thead");
+    const name_tr = document.createElement("tr");
+    const name_th = UTIL.text_th(`${p.last}, ${p.rest} (${p.uname}); ${p.teacher}`);
+    name_th.setAttribute("colspan", "5");
+    name_tr.appendChild(name_th);
+    thead.appendChild(name_tr);
+
+    const report_tr = document.createElement("tr");
+    const report_td = document.createElement("td");
+    report_td.setAttribute("colspan", "5");
+    for(const term of YEARS.TERMS) {
+        const butt = document.createElement("button");
+        butt.setAttribute("data-uname", p.uname);
+        butt.setAttribute("data-term", term);
+        butt.setAttribute("data-year", year);
+        UTIL.label(`${term} report`, butt);
+        butt.addEventListener("click", YEARS.request_report);
+        report_td.appendChild(butt);
+    }
+    report_tr.appendChild(report_td);
+    thead.appendChild(report_tr);
+
+    const head_tr = document.createElement("tr");
+    for(const col of ["course", "chapter", "due", "done", "score"]) {
+        head_tr.appendChild(UTIL.text_th(col));
+    }
+    thead.appendChild(head_tr);
+    tab.appendChild(thead);
+
+    const tbody = document.createElement("tbody");
+    for(const g of p.goals) {
+        const tr = document.createElement("tr");
+        let chapter = g.chapter;
+        if(g.rev) { chapter = `${chapter} (R)`; }
+        if(g.inc) { chapter = `${chapter} (I)`; }
+        tr.appendChild(UTIL.text_td(g.course));
+        tr.appendChild(UTIL.text_td(chapter));
+        tr.appendChild(UTIL.text_td(g.due || ""));
+        tr.appendChild(UTIL.text_td(g.done || ""));
+        tr.appendChild(UTIL.text_td(g.score || ""));
+        tbody.appendChild(tr);
+    }
+    tab.appendChild(tbody);
+
+    return tab;
+}
+
+YEARS.populate_year_goals = function(r) {
+    const year = r.headers.get("x-camp-year");
+    r.json()
+    .then(paces => {
+        // The selection may have changed while this was in flight.
+        if(year != YEARS.select.value) {
+            return;
+        }
+
+        UTIL.clear(YEARS.students);
+        if(paces.length == 0) {
+            const p = document.createElement("p");
+            p.appendChild(document.createTextNode("No Goals were recorded this year."));
+            YEARS.students.appendChild(p);
+        }
+        for(const p of paces) {
+            YEARS.students.appendChild(YEARS.make_pace(p, year));
+        }
+    })
+    .catch(YEARS.log_error("Error reading Goals"));
+}
+
+YEARS.show_pdf = function(r) {
+    r.blob()
+    .then(blob => {
+        const url = window.URL.createObjectURL(blob);
+        YEARS.pdf_object.data = url;
+        YEARS.pdf_view.showModal();
+    })
+    .catch(YEARS.log_error("Error displaying the PDF"));
+}
+
+document.getElementById("show-years")
+    .addEventListener("click", YEARS.request_years);
+YEARS.select.addEventListener("change", YEARS.request_goals);
+document.getElementById("years-close")
+    .addEventListener("click", evt => {
+        evt.preventDefault();
+        YEARS.dialog.close();
+    });
+document.getElementById("years-pdf-close")
+    .addEventListener("click", evt => {
+        evt.preventDefault();
+        YEARS.pdf_view.close();
+    });